@@ -1 +0,0 @@
-//! The non-blocking api with async-std
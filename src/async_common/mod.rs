@@ -0,0 +1,5 @@
+//! The runtime-agnostic part of the non-blocking api, which is shared by the `tokio_async` and
+//! the `async_std` modules, only the http client of the S3 pool is different.
+pub mod primitives;
+pub(crate) mod signer;
+pub mod traits;
@@ -1,16 +1,100 @@
+#[cfg(not(target_arch = "wasm32"))]
 use super::file::FilePool;
+use crate::async_common::traits::{DataPool, Filter, S3Folder};
 use crate::error::Error;
-use crate::tokio_async::traits::{DataPool, Filter, S3Folder};
+use crate::utils::glob::Rules;
+use crate::utils::sync::{check_key, SyncOptions, SyncPlan};
 use crate::utils::S3Object;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
 use url::Url;
 
-#[derive(Debug)]
+/// Transfer the object between the pools, the content type, the modified time and the user
+/// metadata of the source are kept, if they are not set on the destination object.
+async fn transfer(
+    from: &dyn DataPool,
+    to: &dyn DataPool,
+    source: S3Object,
+    mut dest: S3Object,
+) -> Result<(), Error> {
+    // there is no content in the marker of a folder, only the folder is created
+    if source.is_dir_marker() {
+        return to.push(folder_of(dest), Bytes::new()).await;
+    }
+    let (source, b) = from.pull_with_meta(source).await?;
+    dest.mime = dest.mime.or(source.mime);
+    dest.mtime = dest.mtime.or(source.mtime);
+    if dest.metadata.is_empty() {
+        dest.metadata = source.metadata;
+    }
+    to.push(dest, b).await
+}
+
+/// The destination of a folder marker is a folder, even it is given without the trailing `/`,
+/// ex: `s3://bucket/photos/` is downloaded to `/tmp/photos`
+fn folder_of(mut dest: S3Object) -> S3Object {
+    if let Some(key) = dest.key.as_mut().filter(|k| !k.ends_with('/')) {
+        key.push('/');
+    }
+    dest
+}
+
+/// The prefix of the keys under the index of a sync, ex: `backup/`
+fn sync_prefix(index: &S3Object) -> String {
+    match index.key.as_deref().map(|k| k.trim_matches('/')) {
+        Some("") | None => String::new(),
+        Some(p) => format!("{}/", p),
+    }
+}
+
+/// The objects under the index by the keys relative to it, the markers of the folders are not
+/// synced
+async fn sync_objects(
+    pool: &dyn DataPool,
+    index: &S3Object,
+) -> Result<BTreeMap<String, S3Object>, Error> {
+    let prefix = sync_prefix(index);
+    let index = S3Object {
+        bucket: index.bucket.clone(),
+        key: (!prefix.is_empty()).then(|| format!("/{}", prefix)),
+        ..Default::default()
+    };
+    let mut objects = BTreeMap::new();
+    for obj in pool.list_all(Some(index)).await? {
+        let key = match obj
+            .key
+            .as_deref()
+            .and_then(|k| k.trim_start_matches('/').strip_prefix(prefix.as_str()))
+        {
+            Some(k) => k.to_string(),
+            None => continue,
+        };
+        if !key.is_empty() && !key.ends_with('/') {
+            objects.insert(key, obj);
+        }
+    }
+    Ok(objects)
+}
+
+/// The object of the relative key under the index of a sync, the key escaping the index is
+/// refused
+fn sync_object(index: &S3Object, key: &str) -> Result<S3Object, Error> {
+    check_key(key)?;
+    Ok(S3Object {
+        bucket: index.bucket.clone(),
+        key: Some(format!("/{}{}", sync_prefix(index), key)),
+        ..Default::default()
+    })
+}
+
+#[derive(Clone, Debug)]
 pub enum PoolType {
     UpPool,
     DownPool,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Canal {
     pub up_pool: Option<Box<dyn DataPool>>,
     pub upstream_object: Option<S3Object>,
@@ -18,6 +102,11 @@ pub struct Canal {
     pub downstream_object: Option<S3Object>,
     pub(crate) default: PoolType,
     pub filter: Option<Filter>,
+    /// Keep the modified time of the files, please set it before the file pool is set up
+    pub preserve_mtime: bool,
+    /// Keep the prefix in the keys of the objects pulled into the down pool, default the prefix
+    /// is stripped, ex: `logs/2021/a.log` is saved as `a.log` with the prefix `logs/2021/`
+    pub keep_prefix: bool,
     // TODO: feature: data transformer
     // it may do encrypt, or format transformation here
     // upstream_obj_lambda:
@@ -39,21 +128,56 @@ pub struct Canal {
 /// so there are several similar methods help you to setup things.
 /// If you down want these duplicate functions, you can enable the `slim` feature.
 impl Canal {
+    /// Keep the modified time of the files, the time is recorded in the `x-amz-meta-mtime` when
+    /// uploading, and restored from it, or the `Last-Modified`, when downloading.
+    /// Please set it before the file pool is set up by `toward` or `from`.
+    pub fn preserve_mtime(mut self, enable: bool) -> Self {
+        self.preserve_mtime = enable;
+        self
+    }
+
+    /// Keep the prefix in the keys of the objects pulled by `pull_obj`, default disabled
+    pub fn keep_prefix(mut self, enable: bool) -> Self {
+        self.keep_prefix = enable;
+        self
+    }
+
+    /// The object in the down pool for the object listed under the prefix, only the folders of
+    /// the prefix are stripped, ex: `logs/app-1.txt` is saved as `app-1.txt` with `logs/app*`
+    fn pulled_object(&self, mut obj: S3Object) -> S3Object {
+        let prefix = self.filter.as_ref().map(|f| f.prefix()).unwrap_or_default();
+        let prefix = prefix.rfind('/').map(|i| &prefix[..=i]).unwrap_or_default();
+        if !self.keep_prefix && !prefix.is_empty() {
+            obj.key = obj.key.map(|k| {
+                let key = k.trim_start_matches('/');
+                format!("/{}", key.strip_prefix(prefix).unwrap_or(key))
+            });
+        }
+        obj
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn file_pool(&self, path: &str) -> Result<FilePool, Error> {
+        Ok(FilePool::new(path)?.preserve_mtime(self.preserve_mtime))
+    }
+
     /// Check the two pools are set or not
     pub fn is_connect(&self) -> bool {
         self.up_pool.is_some() && self.down_pool.is_some()
     }
 
     /// Set downd pool as file pool, and toward to the `resource_location`
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn toward(mut self, resource_location: &str) -> Result<Self, Error> {
-        self.toward_pool(Box::new(FilePool::new(resource_location)?));
+        self.toward_pool(Box::new(self.file_pool(resource_location)?));
         self.upstream_object = Some(resource_location.into());
         Ok(self)
     }
 
     /// Set up pool as file pool, and from to the `resource_location`
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn from(mut self, resource_location: &str) -> Result<Self, Error> {
-        self.from_pool(Box::new(FilePool::new(resource_location)?));
+        self.from_pool(Box::new(self.file_pool(resource_location)?));
         self.downstream_object = Some(resource_location.into());
         Ok(self)
     }
@@ -62,11 +186,12 @@ impl Canal {
     /// This function set file pool as down pool and s3 pool as up pool
     /// then toward to the `resource_location`,
     /// pull the object from uppool into down pool.
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn download_file(mut self, resource_location: &str) -> Result<(), Error> {
         if let Ok(r) = Url::parse(resource_location) {
-            self.toward_pool(Box::new(FilePool::new(&r.scheme())?)); // for C://
+            self.toward_pool(Box::new(self.file_pool(r.scheme())?)); // for C://
         } else {
-            self.toward_pool(Box::new(FilePool::new("/")?));
+            self.toward_pool(Box::new(self.file_pool("/")?));
         }
         self.downstream_object = Some(resource_location.into());
         match self.downstream_object.take() {
@@ -84,18 +209,48 @@ impl Canal {
                 panic!("never be here")
             }
         }
-        Ok(self.pull().await?)
+        // stream the object into the file, rather than buffering the whole object
+        match (self.up_pool, self.down_pool, self.upstream_object) {
+            (Some(_), Some(down_pool), Some(upstream_object))
+                if upstream_object.is_dir_marker() =>
+            {
+                down_pool
+                    .push(
+                        folder_of(self.downstream_object.unwrap_or(upstream_object)),
+                        Bytes::new(),
+                    )
+                    .await
+            }
+            (Some(up_pool), Some(down_pool), Some(upstream_object)) => {
+                let mut downstream_object = self
+                    .downstream_object
+                    .unwrap_or_else(|| upstream_object.clone());
+                let object = if self.preserve_mtime {
+                    let (upstream_object, object) =
+                        up_pool.pull_stream_with_meta(upstream_object).await?;
+                    downstream_object.mtime = upstream_object.mtime;
+                    downstream_object.metadata = upstream_object.metadata;
+                    object
+                } else {
+                    up_pool.pull_stream(upstream_object).await?
+                };
+                down_pool.push_stream(downstream_object, object).await
+            }
+            (Some(_), Some(_), None) => Err(Error::NoObject()),
+            _ => Err(Error::PoolUninitializeError()),
+        }
     }
 
     /// Upload object from file pool to s3 pool
     /// This function set file pool as down pool and s3 pool as up pool
     /// then toward to the `resource_location`,
     /// push the object from uppool into down pool.
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn upload_file(mut self, resource_location: &str) -> Result<(), Error> {
         if let Ok(r) = Url::parse(resource_location) {
-            self.toward_pool(Box::new(FilePool::new(&r.scheme())?)); // for C://
+            self.toward_pool(Box::new(self.file_pool(r.scheme())?)); // for C://
         } else {
-            self.toward_pool(Box::new(FilePool::new("/")?));
+            self.toward_pool(Box::new(self.file_pool("/")?));
         }
         self.downstream_object = Some(resource_location.into());
         match self.downstream_object.take() {
@@ -188,8 +343,28 @@ impl Canal {
         self._bucket(folder_name)
     }
 
+    /// The prefix listed by the server, and stripped from the keys of the objects pulled by
+    /// `pull_obj` unless `keep_prefix` is enabled, ex: `bucket.prefix("logs/2021/")`
     pub fn prefix(mut self, prefix_str: &str) -> Self {
-        self.filter = Some(Filter::Prefix(prefix_str.into()));
+        self.filter = Some(match self.filter.take() {
+            Some(Filter::Rules { rules, .. }) => Filter::Rules {
+                prefix: prefix_str.into(),
+                rules,
+            },
+            _ => Filter::Prefix(prefix_str.into()),
+        });
+        self
+    }
+
+    /// Keep the objects by the include and exclude rules when listing, the keys are checked
+    /// relative to the prefix set before, ex: `bucket.prefix("logs/").rules(rules)`
+    pub fn rules(mut self, rules: Rules) -> Self {
+        let prefix = self
+            .filter
+            .as_ref()
+            .map(|f| f.prefix().to_string())
+            .unwrap_or_default();
+        self.filter = Some(Filter::Rules { prefix, rules });
         self
     }
 
@@ -292,11 +467,10 @@ impl Canal {
         match (self.up_pool, self.down_pool) {
             (Some(up_pool), Some(down_pool)) => {
                 if let Some(downstream_object) = self.downstream_object {
-                    let b = down_pool.pull(downstream_object.clone()).await?;
-                    up_pool
-                        .push(self.upstream_object.unwrap_or(downstream_object), b)
-                        .await?;
-                    Ok(())
+                    let upstream_object = self
+                        .upstream_object
+                        .unwrap_or_else(|| downstream_object.clone());
+                    transfer(&*down_pool, &*up_pool, downstream_object, upstream_object).await
                 } else {
                     Err(Error::NoObject())
                 }
@@ -309,9 +483,7 @@ impl Canal {
     pub async fn push_obj(&self, obj: S3Object) -> Result<(), Error> {
         match (&self.up_pool, &self.down_pool) {
             (Some(up_pool), Some(down_pool)) => {
-                let b = down_pool.pull(obj.clone()).await?;
-                up_pool.push(obj, b).await?;
-                Ok(())
+                transfer(&**down_pool, &**up_pool, obj.clone(), obj).await
             }
             _ => Err(Error::PoolUninitializeError()),
         }
@@ -322,11 +494,10 @@ impl Canal {
         match (self.up_pool, self.down_pool) {
             (Some(up_pool), Some(down_pool)) => {
                 if let Some(upstream_object) = self.upstream_object {
-                    let b = up_pool.pull(upstream_object.clone()).await?;
-                    down_pool
-                        .push(self.downstream_object.unwrap_or(upstream_object), b)
-                        .await?;
-                    Ok(())
+                    let downstream_object = self
+                        .downstream_object
+                        .unwrap_or_else(|| upstream_object.clone());
+                    transfer(&*up_pool, &*down_pool, upstream_object, downstream_object).await
                 } else {
                     Err(Error::NoObject())
                 }
@@ -335,13 +506,13 @@ impl Canal {
         }
     }
 
-    /// Pull a specified object from up pool to down pool
+    /// Pull a specified object from up pool to down pool, the prefix of the canal is stripped
+    /// from the key in the down pool, unless `keep_prefix` is enabled.
     pub async fn pull_obj(&self, obj: S3Object) -> Result<(), Error> {
         match (&self.up_pool, &self.down_pool) {
             (Some(up_pool), Some(down_pool)) => {
-                let b = up_pool.pull(obj.clone()).await?;
-                down_pool.push(obj, b).await?;
-                Ok(())
+                let dest = self.pulled_object(obj.clone());
+                transfer(&**up_pool, &**down_pool, obj, dest).await
             }
             _ => Err(Error::PoolUninitializeError()),
         }
@@ -415,6 +586,39 @@ impl Canal {
         }
     }
 
-    // pub async fn sync(self)
+    /// Mirror the objects under the upstream object into the down pool under the downstream
+    /// object, ex: a folder of a file pool to a prefix of a s3 pool, the keys missing or changed
+    /// in the down pool are transferred, the extra ones are removed if `delete` is set, and the
+    /// planned keys are returned. The ETags are compared only if both pools list them.
+    pub async fn sync(&self, options: &SyncOptions) -> Result<SyncPlan, Error> {
+        let (up_pool, down_pool) = match (&self.up_pool, &self.down_pool) {
+            (Some(up_pool), Some(down_pool)) => (up_pool, down_pool),
+            _ => return Err(Error::PoolUninitializeError()),
+        };
+        let src_index = self.upstream_object.clone().ok_or(Error::NoObject())?;
+        let dest_index = self
+            .downstream_object
+            .clone()
+            .unwrap_or_else(|| src_index.clone());
+        let src = sync_objects(&**up_pool, &src_index).await?;
+        let dest = sync_objects(&**down_pool, &dest_index).await?;
+        let plan = SyncPlan::new(&src, &dest, options.checksum, options.delete);
+        // The keys are checked before anything is transferred or removed
+        let transfers = plan
+            .transfer
+            .iter()
+            .map(|k| Ok((src[k].clone(), sync_object(&dest_index, k)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if options.dry_run {
+            return Ok(plan);
+        }
+        for (source, dest) in transfers {
+            transfer(&**up_pool, &**down_pool, source, dest).await?;
+        }
+        for key in plan.delete.iter() {
+            down_pool.remove(dest[key].clone()).await?;
+        }
+        Ok(plan)
+    }
     // End of IO api
 }
@@ -0,0 +1,886 @@
+use std::path::{Component, Path};
+
+use async_fs::{
+    create_dir, create_dir_all, read, read_dir, read_link, remove_dir_all, remove_file,
+    symlink_metadata, write, File, ReadDir,
+};
+use async_trait::async_trait;
+use blocking::unblock;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{AsyncWriteExt, StreamExt, TryStreamExt};
+use mime_guess::from_path;
+use url::Url;
+
+use crate::async_common::traits::{ByteStream, DataPool, Filter, S3Folder};
+use crate::error::Error;
+use crate::utils::walk::{walk_dir, LocalEntry, SymlinkPolicy, SYMLINK_METADATA};
+use crate::utils::{mtime_metadata, S3Object, MTIME_METADATA};
+
+#[async_trait]
+impl S3Folder for ReadDir {
+    async fn next_object(&mut self) -> Result<Option<S3Object>, Error> {
+        Ok(self.try_next().await?.map(|e| S3Object {
+            key: e.path().to_str().map(|s| s.to_string()),
+            ..Default::default()
+        }))
+    }
+}
+
+/// The entries of a folder which names start with the prefix, and the empty folders are listed
+/// as the markers with a key ending in `/` if `dir_markers` is enabled
+#[derive(Debug)]
+struct FileFolder {
+    entries: ReadDir,
+    prefix: String,
+    dir_markers: bool,
+    symlink_policy: SymlinkPolicy,
+}
+
+#[async_trait]
+impl S3Folder for FileFolder {
+    async fn next_object(&mut self) -> Result<Option<S3Object>, Error> {
+        let mut obj = loop {
+            let mut obj = match self.entries.next_object().await? {
+                Some(obj) => obj,
+                None => return Ok(None),
+            };
+            let name = obj.key.as_deref().and_then(|k| Path::new(k).file_name());
+            if !name.is_some_and(|n| n.to_string_lossy().starts_with(&self.prefix)) {
+                continue;
+            }
+            let target = match (&obj.key, self.symlink_policy) {
+                (_, SymlinkPolicy::Follow) | (None, _) => None,
+                (Some(k), _) => symlink_target(k).await,
+            };
+            match (target, self.symlink_policy) {
+                (Some(_), SymlinkPolicy::Skip) => continue,
+                (Some(t), _) => {
+                    obj.metadata.insert(SYMLINK_METADATA.to_string(), t);
+                    return Ok(Some(obj));
+                }
+                (None, _) => break obj,
+            }
+        };
+        if self.dir_markers {
+            if let Some(k) = obj.key.as_mut() {
+                if let Ok(mut entries) = read_dir(&k).await {
+                    if entries.try_next().await?.is_none() {
+                        k.push('/');
+                    }
+                }
+            }
+        }
+        Ok(Some(obj))
+    }
+}
+
+/// The target of the path if it is a symbolic link
+async fn symlink_target(path: &str) -> Option<String> {
+    match symlink_metadata(path).await {
+        Ok(meta) if meta.file_type().is_symlink() => read_link(path)
+            .await
+            .ok()
+            .map(|t| t.to_string_lossy().to_string()),
+        _ => None,
+    }
+}
+
+/// The target of the link created from the metadata stays in the root, such that the later
+/// writes through the link will not reach the other files, the absolute target or the one
+/// climbing out of the root is refused, ex: `../../.bashrc`
+fn check_symlink_target(root: &str, path: &str, target: &str) -> Result<(), Error> {
+    let escape = Err(Error::UserError(
+        "the target of the link escapes the folder",
+    ));
+    let mut depth = match Path::new(path)
+        .parent()
+        .and_then(|p| p.strip_prefix(root).ok())
+    {
+        Some(folder) => folder.components().count(),
+        None => return escape,
+    };
+    for c in Path::new(target).components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return escape,
+        }
+    }
+    Ok(())
+}
+
+/// Create the folders of the file, there is no folder for the key in the object storages,
+/// ex: `a/b.txt` is pushed without the object `a/`
+async fn create_parent(path: &str) -> Result<(), Error> {
+    if let Some(folder) = Path::new(path).parent() {
+        create_dir_all(folder).await?;
+    }
+    Ok(())
+}
+
+/// The files under the root in all the folders, the keys are the paths relative to the base,
+/// and the entry failing to read, ex: a dangling link, is logged and skipped.
+fn list_files(
+    bucket: Option<String>,
+    base: &Path,
+    root: &Path,
+    policy: SymlinkPolicy,
+) -> Result<Vec<S3Object>, Error> {
+    let mut objects = Vec::new();
+    if !root.exists() {
+        return Ok(objects);
+    }
+    for (path, entry) in walk_dir(root, policy)? {
+        let key: Vec<String> = path
+            .strip_prefix(base)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let mut obj = S3Object {
+            bucket: bucket.clone(),
+            key: Some(format!("/{}", key.join("/"))),
+            ..Default::default()
+        };
+        match entry {
+            Ok(LocalEntry::File(file)) => {
+                let meta = std::fs::metadata(&file)?;
+                let mtime: DateTime<Utc> = meta.modified()?.into();
+                obj.size = Some(meta.len());
+                obj.mtime = Some(mtime.to_rfc3339());
+            }
+            Ok(LocalEntry::Symlink(target)) => {
+                obj.size = Some(0);
+                obj.metadata.insert(
+                    SYMLINK_METADATA.to_string(),
+                    target.to_string_lossy().to_string(),
+                );
+            }
+            Err(e) => {
+                log::error!("fail to list {}: {}", path.display(), e);
+                continue;
+            }
+        }
+        objects.push(obj);
+    }
+    Ok(objects)
+}
+
+/// The user metadata keeping the mode of the file in decimal, which is the same as s3fs and rclone
+pub const MODE_METADATA: &str = "mode";
+
+#[derive(Clone, Debug)]
+pub struct FilePool {
+    /// use "/" for *nix, "C://" for windows (not tested)
+    pub drive: String,
+    /// The bucket is the first folder under the drive, else the key is put under the drive
+    /// directly, ex: `s3://mybucket/a/b.txt` is at `<drive>/a/b.txt`
+    pub bucket_as_directory: bool,
+    /// Keep the permission bits of the file in the `mode` user metadata, ex: the executable bit
+    /// of a binary, the mode is read when uploading and set when downloading, default disabled.
+    pub preserve_mode: bool,
+    /// Write through the symbolic link on the path of the object, default enabled,
+    /// the download is refused if it is disabled and the path is a symbolic link.
+    pub follow_symlinks: bool,
+    /// List the empty folders as the markers with a key ending in `/`, such that they are kept
+    /// when uploading, default disabled.
+    pub dir_markers: bool,
+    /// Keep the modified time of the file in the `mtime` user metadata, the time is read when
+    /// uploading, and set from it, or the `Last-Modified`, when downloading, default disabled.
+    pub preserve_mtime: bool,
+    /// The way to handle the symbolic links when reading, default follows them, the links are
+    /// listed with the target in the `symlink-target` user metadata if they are preserved,
+    /// and the links are created from the metadata when writing.
+    pub symlink_policy: SymlinkPolicy,
+}
+impl Default for FilePool {
+    fn default() -> Self {
+        Self {
+            drive: "/".into(),
+            bucket_as_directory: true,
+            preserve_mode: false,
+            follow_symlinks: true,
+            dir_markers: false,
+            preserve_mtime: false,
+            symlink_policy: SymlinkPolicy::Follow,
+        }
+    }
+}
+
+impl FilePool {
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let mut fp = FilePool::default();
+        if path.starts_with('/') {
+            fp.drive = path.to_string();
+        } else if let Ok(r) = Url::parse(path) {
+            if ["s3", "S3"].contains(&r.scheme()) {
+                return Err(Error::SchemeError());
+            }
+        }
+        Ok(fp)
+    }
+
+    /// Treat the drive as the base directory and put the keys under it without the bucket folder
+    /// ```
+    /// use s3handler::none_blocking::primitives::FilePool;
+    ///
+    /// let pool = FilePool::new("/tmp/mirror").unwrap().bucket_as_directory(false);
+    /// ```
+    pub fn bucket_as_directory(mut self, enable: bool) -> Self {
+        self.bucket_as_directory = enable;
+        self
+    }
+
+    pub fn preserve_mode(mut self, enable: bool) -> Self {
+        self.preserve_mode = enable;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, enable: bool) -> Self {
+        self.follow_symlinks = enable;
+        self
+    }
+
+    pub fn preserve_mtime(mut self, enable: bool) -> Self {
+        self.preserve_mtime = enable;
+        self
+    }
+
+    pub fn dir_markers(mut self, enable: bool) -> Self {
+        self.dir_markers = enable;
+        self
+    }
+
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// The target of the symbolic link on the path if the links are preserved
+    async fn preserved_symlink(&self, path: &str) -> Option<String> {
+        if self.symlink_policy == SymlinkPolicy::Preserve {
+            symlink_target(path).await
+        } else {
+            None
+        }
+    }
+
+    /// Create the symbolic link kept in the `symlink-target` user metadata if the links are
+    /// preserved, and return it is created or not, the target out of the bucket folder is refused
+    async fn push_symlink(&self, bucket: &str, path: &str, desc: &S3Object) -> Result<bool, Error> {
+        let target = match desc.metadata.get(SYMLINK_METADATA) {
+            Some(t) if self.symlink_policy == SymlinkPolicy::Preserve => t,
+            _ => return Ok(false),
+        };
+        check_symlink_target(&self.bucket_dir(bucket), path, target)?;
+        if symlink_metadata(path).await.is_ok() {
+            remove_file(path).await?;
+        }
+        #[cfg(unix)]
+        {
+            async_fs::unix::symlink(target, path).await?;
+            Ok(true)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = target;
+            Err(Error::Unsupported("symbolic links"))
+        }
+    }
+
+    /// Refuse to write the path of a symbolic link if the links are not followed
+    async fn check_symlink(&self, path: &str) -> Result<(), Error> {
+        if !self.follow_symlinks {
+            if let Ok(meta) = symlink_metadata(path).await {
+                if meta.file_type().is_symlink() {
+                    return Err(Error::UserError("refuse to overwrite a symbolic link"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the permission bits of the file kept in the `mode` user metadata
+    async fn set_mode(&self, path: &str, desc: &S3Object) -> Result<(), Error> {
+        #[cfg(unix)]
+        if self.preserve_mode {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = desc
+                .metadata
+                .get(MODE_METADATA)
+                .and_then(|m| m.parse::<u32>().ok())
+            {
+                async_fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the modified time of the file kept in the `mtime` user metadata or the `Last-Modified`
+    async fn set_mtime(&self, path: &str, desc: &S3Object) -> Result<(), Error> {
+        if let Some(mtime) = desc.modified_time().filter(|_| self.preserve_mtime) {
+            let path = path.to_string();
+            unblock(move || {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_modified(mtime.into())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// The path of the object on the file system, the key climbing out of the folder is refused,
+    /// ex: `/../.bashrc`
+    fn path(&self, bucket: &str, key: &str) -> Result<String, Error> {
+        if !Path::new(key.trim_start_matches('/'))
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(Error::UserError("the key escapes the folder"));
+        }
+        Ok(if !self.bucket_as_directory {
+            format!(
+                "{}/{}",
+                self.drive.trim_end_matches('/'),
+                key.trim_start_matches('/')
+            )
+        } else if key.starts_with('/') {
+            format!("{}{}{}", self.drive, bucket, key)
+        } else {
+            format!("{}/{}{}", self.drive, bucket, key)
+        })
+    }
+
+    /// The folder of the bucket on the file system
+    fn bucket_dir(&self, bucket: &str) -> String {
+        if self.bucket_as_directory {
+            format!("{}{}", self.drive, bucket)
+        } else {
+            self.drive.clone()
+        }
+    }
+}
+
+unsafe impl Send for FilePool {}
+unsafe impl Sync for FilePool {}
+
+#[async_trait]
+impl DataPool for FilePool {
+    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error> {
+        match (&desc.bucket, &desc.key) {
+            (Some(b), Some(k)) if desc.is_dir_marker() => {
+                Ok(create_dir_all(self.path(b, k)?).await?)
+            }
+            (Some(b), Some(k)) => {
+                let path = self.path(b, k)?;
+                create_parent(&path).await?;
+                if self.push_symlink(b, &path, &desc).await? {
+                    return Ok(());
+                }
+                self.check_symlink(&path).await?;
+                write(Path::new(&path), object).await?;
+                self.set_mode(&path, &desc).await?;
+                self.set_mtime(&path, &desc).await
+            }
+            (Some(b), None) => Ok(create_dir(Path::new(b)).await?),
+            (None, _) => Err(Error::ModifyEmptyBucketError()),
+        }
+    }
+
+    /// Write the chunks into the file as they arrive
+    async fn push_stream(&self, desc: S3Object, mut object: ByteStream) -> Result<(), Error> {
+        if desc.is_dir_marker() {
+            return self.push(desc, Bytes::new()).await;
+        }
+        if let S3Object {
+            bucket: Some(b),
+            key: Some(k),
+            ..
+        } = &desc
+        {
+            let path = self.path(b, k)?;
+            create_parent(&path).await?;
+            if self.push_symlink(b, &path, &desc).await? {
+                return Ok(());
+            }
+            self.check_symlink(&path).await?;
+            let mut file = File::create(Path::new(&path)).await?;
+            while let Some(chunk) = object.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            self.set_mode(&path, &desc).await?;
+            return self.set_mtime(&path, &desc).await;
+        }
+        self.push(desc, Bytes::new()).await
+    }
+
+    async fn pull(&self, desc: S3Object) -> Result<Bytes, Error> {
+        if let S3Object {
+            bucket: Some(b),
+            key: Some(k),
+            ..
+        } = desc
+        {
+            let path = self.path(&b, &k)?;
+            // The content of a preserved link is the target in the metadata
+            if self.preserved_symlink(&path).await.is_some() {
+                return Ok(Bytes::new());
+            }
+            return match read(Path::new(&path)).await {
+                // TODO: figure ouput how to use Bytes in tokio
+                Ok(c) => Ok(Bytes::copy_from_slice(&c)),
+                Err(e) => Err(e.into()),
+            };
+        }
+        Err(Error::PullEmptyObjectError())
+    }
+
+    async fn list(
+        &self,
+        index: Option<S3Object>,
+        filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error> {
+        let prefix = match filter {
+            Some(Filter::Prefix(prefix)) => prefix.clone(),
+            Some(_) => {
+                return Err(Error::Unsupported(
+                    "the filter except prefix on a file pool",
+                ))
+            }
+            None => String::new(),
+        };
+        let entries = match index {
+            Some(S3Object {
+                bucket: Some(b),
+                key: None,
+                ..
+            }) => read_dir(Path::new(&self.bucket_dir(&b))).await?,
+            Some(S3Object {
+                bucket: Some(b),
+                key: Some(k),
+                ..
+            }) => read_dir(Path::new(&self.path(&b, &k)?)).await?,
+            Some(S3Object { bucket: None, .. }) | None => {
+                read_dir(Path::new(&self.drive.to_string())).await?
+            }
+        };
+        Ok(Box::new(FileFolder {
+            entries,
+            prefix,
+            dir_markers: self.dir_markers,
+            symlink_policy: self.symlink_policy,
+        }))
+    }
+
+    /// The files under the folder in all the subfolders, the keys are relative to the folder of
+    /// the bucket, and the links are handled by the `symlink_policy`
+    async fn list_all(&self, index: Option<S3Object>) -> Result<Vec<S3Object>, Error> {
+        let index = index.unwrap_or_default();
+        let bucket = index.bucket.clone().unwrap_or_default();
+        let base = self.bucket_dir(&bucket);
+        let root = match &index.key {
+            Some(k) => self.path(&bucket, k)?,
+            None => base.clone(),
+        };
+        let policy = self.symlink_policy;
+        unblock(move || list_files(index.bucket, Path::new(&base), Path::new(&root), policy)).await
+    }
+
+    async fn remove(&self, desc: S3Object) -> Result<(), Error> {
+        if let Some(b) = desc.bucket {
+            let r = if let Some(k) = desc.key {
+                remove_file(Path::new(&self.path(&b, &k)?)).await
+            } else {
+                remove_dir_all(Path::new(&b)).await
+            };
+            r.map_err(|e| e.into())
+        } else {
+            Err(Error::ModifyEmptyBucketError())
+        }
+    }
+
+    /// The content type is guessed from the extension of the file, and the modified time and
+    /// the mode of the file are kept in the user metadata if they are preserved.
+    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
+        if desc.mime.is_none() {
+            desc.mime = desc
+                .key
+                .as_ref()
+                .and_then(|k| from_path(k).first_raw())
+                .map(|m| m.to_string());
+        }
+        if let (Some(b), Some(k)) = (&desc.bucket, &desc.key) {
+            if let Some(target) = self.preserved_symlink(&self.path(b, k)?).await {
+                desc.metadata.insert(SYMLINK_METADATA.to_string(), target);
+                return Ok(());
+            }
+        }
+        if self.preserve_mtime {
+            if let (Some(b), Some(k)) = (&desc.bucket, &desc.key) {
+                let mtime = async_fs::metadata(self.path(b, k)?).await?.modified()?;
+                desc.metadata
+                    .insert(MTIME_METADATA.to_string(), mtime_metadata(&mtime.into()));
+            }
+        }
+        #[cfg(unix)]
+        if self.preserve_mode {
+            use std::os::unix::fs::PermissionsExt;
+            if let (Some(b), Some(k)) = (&desc.bucket, &desc.key) {
+                let mode = async_fs::metadata(self.path(b, k)?)
+                    .await?
+                    .permissions()
+                    .mode();
+                desc.metadata
+                    .insert(MODE_METADATA.to_string(), mode.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_scheme(&self, _scheme: &str) -> Result<(), Error> {
+        panic!("file pool use new to create a valid, without this function")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+
+    /// The file pool is not bound to a runtime, so the tests are run on each runtime enabled
+    fn on_runtimes<F, Fut>(test: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        #[cfg(feature = "tokio-async")]
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(test());
+        #[cfg(feature = "async-std")]
+        ::async_std::task::block_on(test());
+    }
+
+    #[test]
+    fn test_file_pool() {
+        on_runtimes(|| async {
+            let dir = std::env::temp_dir().join("s3handler-file-pool");
+            std::fs::create_dir_all(&dir).unwrap();
+            let pool = FilePool::default();
+            let mut desc = S3Object {
+                bucket: Some(dir.to_string_lossy()[1..].to_string()),
+                key: Some("/index.html".to_string()),
+                ..Default::default()
+            };
+            pool.push(desc.clone(), Bytes::from("<html></html>"))
+                .await
+                .unwrap();
+            assert_eq!(
+                pool.pull(desc.clone()).await.unwrap(),
+                Bytes::from("<html></html>")
+            );
+            pool.fetch_meta(&mut desc).await.unwrap();
+            assert_eq!(desc.mime.as_deref(), Some("text/html"));
+
+            let index = S3Object {
+                bucket: desc.bucket.clone(),
+                ..Default::default()
+            };
+            for (prefix, listed) in [("index", 1), ("other", 0)] {
+                let mut folder = pool
+                    .list(Some(index.clone()), &Some(Filter::Prefix(prefix.into())))
+                    .await
+                    .unwrap();
+                let mut count = 0;
+                while folder.next_object().await.unwrap().is_some() {
+                    count += 1;
+                }
+                assert_eq!(count, listed);
+            }
+            pool.remove(desc.clone()).await.unwrap();
+            assert!(pool.pull(desc).await.is_err());
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_pool_preserve_mode() {
+        on_runtimes(|| async {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = std::env::temp_dir().join("s3handler-file-pool-mode");
+            std::fs::create_dir_all(&dir).unwrap();
+            let pool = FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false)
+                .preserve_mode(true);
+            let mut desc = S3Object::from("s3://bucket/run.sh");
+            desc.metadata
+                .insert(MODE_METADATA.to_string(), 0o100755.to_string());
+            pool.push(desc, Bytes::from("#!/bin/sh")).await.unwrap();
+            let mode = std::fs::metadata(dir.join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+
+            let mut uploaded = S3Object::from("s3://bucket/run.sh");
+            pool.fetch_meta(&mut uploaded).await.unwrap();
+            assert_eq!(uploaded.metadata[MODE_METADATA], 0o100755.to_string());
+
+            // the mode is not changed by default
+            let mut desc = S3Object::from("s3://bucket/data");
+            desc.metadata
+                .insert(MODE_METADATA.to_string(), 0o100755.to_string());
+            FilePool {
+                preserve_mode: false,
+                ..pool.clone()
+            }
+            .push(desc, Bytes::from("data"))
+            .await
+            .unwrap();
+            let mode = std::fs::metadata(dir.join("data"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o111, 0);
+
+            let link = dir.join("link");
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(dir.join("data"), &link).unwrap();
+            let err = pool
+                .follow_symlinks(false)
+                .push(S3Object::from("s3://bucket/link"), Bytes::from("other"))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::UserError(_)));
+            assert_eq!(std::fs::read(dir.join("data")).unwrap(), b"data");
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_file_pool_without_bucket_directory() {
+        on_runtimes(|| async {
+            let dir = std::env::temp_dir().join("s3handler-file-pool-base");
+            std::fs::create_dir_all(dir.join("a")).unwrap();
+            let pool = FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false);
+            let desc = S3Object::from("s3://mybucket/a/b.txt");
+            pool.push(desc.clone(), Bytes::from("content"))
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read(dir.join("a/b.txt")).unwrap(), b"content");
+            assert!(!dir.join("mybucket").exists());
+            assert_eq!(
+                pool.pull(desc.clone()).await.unwrap(),
+                Bytes::from("content")
+            );
+            pool.remove(desc).await.unwrap();
+            assert!(!dir.join("a/b.txt").exists());
+
+            // The key climbing out of the folder is refused
+            let escape = S3Object {
+                bucket: Some("mybucket".to_string()),
+                key: Some("/a/../../s3handler-file-pool-escape.txt".to_string()),
+                ..Default::default()
+            };
+            assert!(matches!(
+                pool.push(escape.clone(), Bytes::from("content")).await,
+                Err(Error::UserError(_))
+            ));
+            assert!(pool.pull(escape).await.is_err());
+            assert!(!std::env::temp_dir()
+                .join("s3handler-file-pool-escape.txt")
+                .exists());
+        });
+    }
+
+    #[test]
+    fn test_file_pool_preserve_mtime() {
+        on_runtimes(|| async {
+            let dir = std::env::temp_dir().join("s3handler-file-pool-mtime");
+            std::fs::create_dir_all(&dir).unwrap();
+            let pool = FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false)
+                .preserve_mtime(true);
+            let mut desc = S3Object::from("s3://bucket/a.txt");
+            desc.metadata
+                .insert(MTIME_METADATA.to_string(), "1600527886".to_string());
+            pool.push(desc, Bytes::from("a")).await.unwrap();
+            let modified = std::fs::metadata(dir.join("a.txt"))
+                .unwrap()
+                .modified()
+                .unwrap();
+            assert_eq!(
+                modified,
+                std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600527886)
+            );
+
+            let mut uploaded = S3Object::from("s3://bucket/a.txt");
+            pool.fetch_meta(&mut uploaded).await.unwrap();
+            assert_eq!(uploaded.metadata[MTIME_METADATA], "1600527886");
+
+            // the modified time is not changed by default
+            let mut desc = S3Object::from("s3://bucket/b.txt");
+            desc.mtime = Some("Sat, 19 Sep 2020 15:04:46 GMT".to_string());
+            FilePool {
+                preserve_mtime: false,
+                ..pool.clone()
+            }
+            .push(desc.clone(), Bytes::from("b"))
+            .await
+            .unwrap();
+            let modified = |path| {
+                std::fs::metadata(dir.join(path))
+                    .unwrap()
+                    .modified()
+                    .unwrap()
+            };
+            assert!(
+                modified("b.txt")
+                    > std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600527886)
+            );
+            pool.push_stream(
+                desc,
+                Box::pin(futures::stream::once(async { Ok(Bytes::from("b")) })),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                modified("b.txt"),
+                std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600527886)
+            );
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_file_pool_dir_markers() {
+        on_runtimes(|| async {
+            let dir = std::env::temp_dir().join("s3handler-file-pool-dir-markers");
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let pool = FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false);
+            pool.push(S3Object::from("s3://bucket/photos/"), Bytes::new())
+                .await
+                .unwrap();
+            pool.push_stream(
+                S3Object::from("s3://bucket/empty/"),
+                Box::pin(futures::stream::empty()),
+            )
+            .await
+            .unwrap();
+            pool.push(
+                S3Object::from("s3://bucket/photos/a.jpg"),
+                Bytes::from("jpg"),
+            )
+            .await
+            .unwrap();
+            assert!(dir.join("empty").is_dir());
+            assert_eq!(std::fs::read(dir.join("photos/a.jpg")).unwrap(), b"jpg");
+
+            for (dir_markers, empty) in [(false, "empty"), (true, "empty/")] {
+                let mut folder = pool
+                    .clone()
+                    .dir_markers(dir_markers)
+                    .list(None, &None)
+                    .await
+                    .unwrap();
+                let mut keys = Vec::new();
+                while let Some(obj) = folder.next_object().await.unwrap() {
+                    keys.push(obj.key.unwrap());
+                }
+                keys.sort();
+                assert_eq!(
+                    keys,
+                    vec![
+                        format!("{}/{}", dir.to_string_lossy(), empty),
+                        format!("{}/photos", dir.to_string_lossy()),
+                    ]
+                );
+            }
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_file_pool_symlink_policy() {
+        on_runtimes(|| async {
+            let dir = std::env::temp_dir().join("s3handler-file-pool-symlink");
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("a.txt"), b"content").unwrap();
+            std::os::unix::fs::symlink("a.txt", dir.join("link.txt")).unwrap();
+            let pool = FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false);
+
+            let list = |policy| {
+                let pool = pool.clone().symlink_policy(policy);
+                async move {
+                    let mut folder = pool.list(None, &None).await.unwrap();
+                    let mut objects = Vec::new();
+                    while let Some(obj) = folder.next_object().await.unwrap() {
+                        objects.push((obj.key.unwrap(), obj.metadata));
+                    }
+                    objects.sort_by(|a, b| a.0.cmp(&b.0));
+                    objects
+                }
+            };
+            assert_eq!(list(SymlinkPolicy::Follow).await.len(), 2);
+            assert_eq!(list(SymlinkPolicy::Skip).await.len(), 1);
+            let objects = list(SymlinkPolicy::Preserve).await;
+            assert_eq!(objects[1].0, format!("{}/link.txt", dir.to_string_lossy()));
+            assert_eq!(objects[1].1[SYMLINK_METADATA], "a.txt");
+
+            let link = S3Object::from("s3://bucket/link.txt");
+            let preserved = pool.clone().symlink_policy(SymlinkPolicy::Preserve);
+            assert_eq!(
+                pool.pull(link.clone()).await.unwrap(),
+                Bytes::from("content")
+            );
+            assert!(preserved.pull(link.clone()).await.unwrap().is_empty());
+            let mut desc = link.clone();
+            preserved.fetch_meta(&mut desc).await.unwrap();
+            assert_eq!(desc.metadata[SYMLINK_METADATA], "a.txt");
+
+            // The link is created from the metadata
+            desc.key = Some("/copy.txt".to_string());
+            preserved.push(desc.clone(), Bytes::new()).await.unwrap();
+            assert_eq!(
+                std::fs::read_link(dir.join("copy.txt")).unwrap(),
+                Path::new("a.txt")
+            );
+            assert_eq!(std::fs::read(dir.join("copy.txt")).unwrap(), b"content");
+
+            // The target out of the folder is refused
+            desc.key = Some("/sub/inner.txt".to_string());
+            desc.metadata
+                .insert(SYMLINK_METADATA.to_string(), "../a.txt".to_string());
+            preserved.push(desc.clone(), Bytes::new()).await.unwrap();
+            assert_eq!(
+                std::fs::read(dir.join("sub/inner.txt")).unwrap(),
+                b"content"
+            );
+            for target in ["/etc/passwd", "../outside.txt", "sub/../../outside.txt"] {
+                desc.key = Some("/escape.txt".to_string());
+                desc.metadata
+                    .insert(SYMLINK_METADATA.to_string(), target.to_string());
+                assert!(preserved.push(desc.clone(), Bytes::new()).await.is_err());
+                assert!(std::fs::symlink_metadata(dir.join("escape.txt")).is_err());
+            }
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+}
@@ -0,0 +1,179 @@
+use async_trait::async_trait;
+use log::warn;
+use std::fmt::Debug;
+
+use crate::async_common::traits::{Filter, S3Folder};
+use crate::error::Error;
+use crate::utils::{xml_list_page, S3Object};
+
+/// The settings of the listings of a S3 pool
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ListSettings {
+    /// Request `encoding-type=url` and decode the keys
+    pub encoding_type_url: bool,
+    /// Request `fetch-owner=true` in the V2 listings
+    pub fetch_owner: bool,
+    /// Stop after the number of objects
+    pub limit: Option<usize>,
+    /// Stop after the number of requests
+    pub max_requests: Option<usize>,
+}
+
+/// The S3 pool requesting the pages of a listing with the http client of its runtime,
+/// such that the pagination of `S3ObjectList` is shared by the runtimes.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait ListPages: Send + Sync + Debug {
+    /// The settings of the listings
+    fn list_settings(&self) -> ListSettings;
+
+    /// Request a page of the listing of the bucket with the query, and return the body
+    async fn list_page(&self, index: S3Object, query: &[(&str, &str)]) -> Result<String, Error>;
+}
+
+/// The objects listed from a S3 pool, the listing keeps the state of the pagination and the
+/// pool is not changed.
+#[derive(Debug)]
+pub struct S3ObjectList<P: ListPages> {
+    pool: P,
+    settings: ListSettings,
+    /// The bucket to list
+    index: S3Object,
+    filter: Option<Filter>,
+    buffer: Vec<S3Object>,
+    is_truncated: bool,
+    /// The key to list after for the next page of the listing
+    marker: Option<String>,
+    /// The objects handed over, which are counted for the limit of the pool
+    listed: usize,
+    /// The pages requested, which are counted for the max requests of the pool
+    requests: usize,
+}
+
+impl<P: ListPages> S3ObjectList<P> {
+    pub(crate) fn new(pool: P, index: S3Object, filter: Option<Filter>) -> Self {
+        S3ObjectList {
+            settings: pool.list_settings(),
+            pool,
+            index,
+            filter,
+            buffer: Vec::new(),
+            is_truncated: false,
+            marker: None,
+            listed: 0,
+            requests: 0,
+        }
+    }
+
+    /// Request the first page of the listing
+    pub(crate) async fn start(mut self) -> Result<Self, Error> {
+        self.request_page().await?;
+        Ok(self)
+    }
+
+    fn handle_list_response(&mut self, body: String) -> Result<(), Error> {
+        (self.buffer, self.is_truncated) = xml_list_page(&body, &mut self.marker)?;
+        Ok(())
+    }
+
+    /// The listed object is handed over or not
+    fn is_kept(&self, obj: &S3Object) -> bool {
+        obj.key.is_some() && self.filter.as_ref().is_none_or(|f| f.matches(obj))
+    }
+
+    /// Request the page after the marker, the first page is requested without it
+    async fn request_page(&mut self) -> Result<(), Error> {
+        self.requests += 1;
+        let mut params = Vec::new();
+        if let Some(marker) = &self.marker {
+            params.push(("list-type", "2"));
+            params.push(("start-after", marker.as_str()));
+            if self.settings.fetch_owner {
+                params.push(("fetch-owner", "true"));
+            }
+        }
+        if let Some(filter) = &self.filter {
+            params.push(("prefix", filter.prefix()));
+        }
+        if self.settings.encoding_type_url {
+            params.push(("encoding-type", "url"));
+        }
+        let body = self.pool.list_page(self.index.clone(), &params).await?;
+        // TODO: validate start-after
+        self.handle_list_response(body)
+    }
+
+    async fn update_list(&mut self) -> Result<S3Object, Error> {
+        let last_object = self.buffer.remove(0);
+        if self
+            .settings
+            .max_requests
+            .is_some_and(|m| self.requests >= m)
+        {
+            warn!(
+                "listing stops after {} requests, it is not complete",
+                self.requests
+            );
+            self.is_truncated = false;
+            return Ok(last_object);
+        }
+        self.request_page().await?;
+        Ok(last_object)
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<P: ListPages> S3Folder for S3ObjectList<P> {
+    async fn next_object(&mut self) -> Result<Option<S3Object>, Error> {
+        loop {
+            if self.buffer.is_empty() || self.settings.limit.is_some_and(|l| self.listed >= l) {
+                return Ok(None);
+            } else {
+                // The next page is not requested if the last object reaches the limit
+                let is_last = self.settings.limit.is_some_and(|l| self.listed + 1 >= l)
+                    && self.is_kept(&self.buffer[0]);
+                let obj = if self.is_truncated && self.buffer.len() == 1 && !is_last {
+                    self.update_list().await?
+                } else {
+                    self.buffer.remove(0)
+                };
+                // The keys are in lexicographic order, so every page is requested even if
+                // none of the objects on it matches the filter
+                if self.is_kept(&obj) {
+                    self.listed += 1;
+                    return Ok(Some(obj));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct NoPages;
+
+    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+    impl ListPages for NoPages {
+        fn list_settings(&self) -> ListSettings {
+            ListSettings::default()
+        }
+
+        async fn list_page(&self, _: S3Object, _: &[(&str, &str)]) -> Result<String, Error> {
+            Err(Error::Unsupported("the pages of the listing"))
+        }
+    }
+
+    #[test]
+    fn test_handle_list_response() {
+        let s = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>ant-lab</Name><Prefix></Prefix><Marker></Marker><MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated><Contents><Key>14M</Key><LastModified>2020-01-31T14:58:45.000Z</LastModified><ETag>&quot;8ff43d748637d249d80d6f45e15c7663-3&quot;</ETag><Size>14336000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>7M</Key><LastModified>2020-11-21T09:50:46.000Z</LastModified><ETag>&quot;cbe4f29b8b099989ae49afc02aa1c618-2&quot;</ETag><Size>7168000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>7M.json</Key><LastModified>2020-09-19T14:59:23.000Z</LastModified><ETag>&quot;d34bd3f9aff10629ac49353312a42b0f-2&quot;</ETag><Size>7168000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>get</Key><LastModified>2020-08-11T06:10:11.000Z</LastModified><ETag>&quot;f895d74af5106ce0c3d6cb008fb3b98d&quot;</ETag><Size>304</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>t</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>10</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>t.txt</Key><LastModified>2020-09-19T15:04:46.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>10</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test-orig</Key><LastModified>2020-11-21T09:48:29.000Z</LastModified><ETag>&quot;c059dadd468de1835bc99dab6e3b2cee-3&quot;</ETag><Size>11534336</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test-s3handle</Key><LastModified>2020-11-21T10:09:39.000Z</LastModified><ETag>&quot;5dd39cab1c53c2c77cd352983f9641e1&quot;</ETag><Size>20</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test.json</Key><LastModified>2020-08-11T09:54:42.000Z</LastModified><ETag>&quot;f895d74af5106ce0c3d6cb008fb3b98d&quot;</ETag><Size>304</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>";
+        let mut list = S3ObjectList::new(NoPages, "ant-lab".into(), None);
+        list.handle_list_response(s.to_string()).unwrap();
+        assert!(!list.buffer.is_empty());
+        assert!(!list.is_truncated);
+    }
+}
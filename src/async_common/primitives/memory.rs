@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::async_common::traits::{DataPool, Filter, S3Folder};
+use crate::error::Error;
+use crate::utils::S3Object;
+
+/// The objects indexed by bucket and key
+type Objects = BTreeMap<(String, String), Bytes>;
+
+/// The objects listed from a memory pool
+#[derive(Debug)]
+pub struct MemoryFolder {
+    objects: Vec<S3Object>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl S3Folder for MemoryFolder {
+    async fn next_object(&mut self) -> Result<Option<S3Object>, Error> {
+        if self.objects.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(self.objects.remove(0)))
+        }
+    }
+}
+
+/// A pool keeps the objects in memory, which is useful for testing or caching.
+/// The clones of the pool share the same objects.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryPool {
+    objects: Arc<Mutex<Objects>>,
+}
+
+impl MemoryPool {
+    fn lock(&self) -> Result<MutexGuard<'_, Objects>, Error> {
+        self.objects
+            .lock()
+            .map_err(|e| Error::RequestPoolError(format!("lock is not acquired: {:?}", e)))
+    }
+}
+
+fn bucket_and_key(desc: S3Object) -> Result<(String, String), Error> {
+    match desc {
+        S3Object {
+            bucket: Some(b),
+            key: Some(k),
+            ..
+        } => Ok((b, k)),
+        S3Object { bucket: None, .. } => Err(Error::ModifyEmptyBucketError()),
+        _ => Err(Error::PullEmptyObjectError()),
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl DataPool for MemoryPool {
+    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error> {
+        self.lock()?.insert(bucket_and_key(desc)?, object);
+        Ok(())
+    }
+
+    async fn pull(&self, desc: S3Object) -> Result<Bytes, Error> {
+        let path = bucket_and_key(desc)?;
+        self.lock()?
+            .get(&path)
+            .cloned()
+            .ok_or_else(|| Error::ResourceUrlError(format!("{}{} not found", path.0, path.1)))
+    }
+
+    async fn list(
+        &self,
+        index: Option<S3Object>,
+        filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error> {
+        let index = index.unwrap_or_default();
+        let prefix = match filter {
+            Some(f) => format!("/{}", f.prefix().trim_start_matches('/')),
+            None => index.key.clone().unwrap_or_default(),
+        };
+        let objects = self
+            .lock()?
+            .iter()
+            .filter(|((b, k), _)| {
+                index.bucket.as_ref().map(|i| i == b).unwrap_or(true) && k.starts_with(&prefix)
+            })
+            .map(|((b, k), v)| S3Object {
+                bucket: Some(b.clone()),
+                key: Some(k.clone()),
+                size: Some(v.len() as u64),
+                ..Default::default()
+            })
+            .filter(|obj| filter.as_ref().is_none_or(|f| f.matches(obj)))
+            .collect();
+        Ok(Box::new(MemoryFolder { objects }))
+    }
+
+    async fn remove(&self, desc: S3Object) -> Result<(), Error> {
+        match desc {
+            S3Object {
+                bucket: Some(b),
+                key: None,
+                ..
+            } => self.lock()?.retain(|(bucket, _), _| bucket != &b),
+            desc => {
+                self.lock()?.remove(&bucket_and_key(desc)?);
+            }
+        }
+        Ok(())
+    }
+
+    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
+        desc.size = Some(self.pull(desc.clone()).await?.len() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_pool() {
+        let pool = MemoryPool::default();
+        for key in ["/a/1", "/a/2", "/b/1"] {
+            pool.push(
+                S3Object::from(format!("s3://bucket{}", key).as_str()),
+                Bytes::from(key),
+            )
+            .await
+            .unwrap();
+        }
+        let mut folder = pool
+            .list(
+                Some(S3Object::from("s3://bucket")),
+                &Some(Filter::Prefix("a/".to_string())),
+            )
+            .await
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(o) = folder.next_object().await.unwrap() {
+            keys.push(o.key.unwrap());
+        }
+        assert_eq!(keys, vec!["/a/1", "/a/2"]);
+
+        pool.remove(S3Object::from("s3://bucket/a/1"))
+            .await
+            .unwrap();
+        assert!(pool.pull(S3Object::from("s3://bucket/a/1")).await.is_err());
+        assert_eq!(
+            pool.clone()
+                .pull(S3Object::from("s3://bucket/b/1"))
+                .await
+                .unwrap(),
+            Bytes::from("/b/1")
+        );
+    }
+}
@@ -0,0 +1,11 @@
+pub use canal::{Canal, PoolType};
+#[cfg(not(target_arch = "wasm32"))]
+pub use file::FilePool;
+pub use list::{ListPages, ListSettings, S3ObjectList};
+pub use memory::{MemoryFolder, MemoryPool};
+
+mod canal;
+#[cfg(not(target_arch = "wasm32"))]
+mod file;
+mod list;
+mod memory;
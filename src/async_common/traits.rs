@@ -0,0 +1,456 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use dyn_clone::DynClone;
+use futures::{Stream, StreamExt};
+use std::fmt::Debug;
+use std::pin::Pin;
+use url::Url;
+
+use super::primitives::{Canal, PoolType};
+use crate::error::Error;
+use crate::utils::glob::{Glob, Rules};
+use crate::utils::S3Object;
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Prefix(String),
+    /// The objects under the prefix modified at or after the time, the prefix is listed by the
+    /// server and the mtime is checked on the client side page by page.
+    ModifiedSince {
+        prefix: String,
+        since: DateTime<Utc>,
+    },
+    /// The objects matching the glob, the literal part before the first wildcard is listed as
+    /// the prefix by the server, and the rest is checked on the client side page by page.
+    Glob(Glob),
+    /// The objects under the prefix kept by the include and exclude rules, which are checked
+    /// with the keys relative to the prefix on the client side page by page.
+    Rules {
+        prefix: String,
+        rules: Rules,
+    },
+}
+
+impl Filter {
+    /// The objects matching the glob, ex: `**/*.parquet`
+    pub fn glob(pattern: &str) -> Self {
+        Filter::Glob(Glob::new(pattern))
+    }
+
+    /// The objects with the suffix in any folder, ex: `.log`
+    pub fn suffix(suffix: &str) -> Self {
+        Filter::Glob(Glob::suffix(suffix))
+    }
+
+    /// The prefix of the keys to list
+    pub fn prefix(&self) -> &str {
+        match self {
+            Filter::Prefix(prefix)
+            | Filter::ModifiedSince { prefix, .. }
+            | Filter::Rules { prefix, .. } => prefix,
+            Filter::Glob(glob) => glob.prefix(),
+        }
+    }
+
+    /// The listed object is kept or not
+    pub fn matches(&self, obj: &S3Object) -> bool {
+        match self {
+            Filter::Prefix(_) => true,
+            Filter::ModifiedSince { since, .. } => obj.is_modified_since(since),
+            Filter::Glob(glob) => obj.key.as_deref().is_none_or(|k| glob.is_match(k)),
+            Filter::Rules { prefix, rules } => obj.key.as_deref().is_none_or(|k| {
+                let key = k.trim_start_matches('/');
+                rules.is_included(key.strip_prefix(prefix.as_str()).unwrap_or(key))
+            }),
+        }
+    }
+}
+
+/// A stream of chunks for the object which size is unknown before pushing
+#[cfg(not(target_arch = "wasm32"))]
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>;
+
+/// The responses of the fetch api are not `Send` on wasm32
+#[cfg(target_arch = "wasm32")]
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, Error>>>>;
+
+/// A stream of the objects listed in a folder
+#[cfg(not(target_arch = "wasm32"))]
+pub type ObjectStream = Pin<Box<dyn Stream<Item = Result<S3Object, Error>> + Send>>;
+
+#[cfg(target_arch = "wasm32")]
+pub type ObjectStream = Pin<Box<dyn Stream<Item = Result<S3Object, Error>>>>;
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait S3Folder: Send + Debug {
+    async fn next_object(&mut self) -> Result<Option<S3Object>, Error>;
+
+    /// Turn the folder into a stream of objects, such that the combinators of `StreamExt` and
+    /// `TryStreamExt` can be used on the listing.
+    fn into_stream(self: Box<Self>) -> ObjectStream
+    where
+        Self: 'static,
+    {
+        Box::pin(futures::stream::try_unfold(self, |mut folder| async move {
+            Ok(folder.next_object().await?.map(|obj| (obj, folder)))
+        }))
+    }
+}
+
+/// The pool is cloneable behind `Box<dyn DataPool>`, such that a configured canal can be cloned
+/// to drive the transfers in parallel.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait DataPool: Send + Sync + DynClone + Debug {
+    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error>;
+    async fn pull(&self, desc: S3Object) -> Result<Bytes, Error>;
+    /// Push an object from a stream of chunks.
+    /// The default implementation collects the whole stream then push it,
+    /// the pool supporting multipart should override this to keep the memory bounded.
+    async fn push_stream(&self, desc: S3Object, mut object: ByteStream) -> Result<(), Error> {
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = object.next().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        self.push(desc, buf.freeze()).await
+    }
+    /// Pull an object as a stream of chunks.
+    /// The default implementation pulls the whole object,
+    /// the pool supporting ranged reads should override this to keep the memory bounded.
+    async fn pull_stream(&self, desc: S3Object) -> Result<ByteStream, Error> {
+        let object = self.pull(desc).await?;
+        Ok(Box::pin(futures::stream::once(async { Ok(object) })))
+    }
+    /// The index will be treated as a folder object to filter the list results
+    async fn list(
+        &self,
+        index: Option<S3Object>,
+        filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error>;
+    /// The objects under the index in all the folders, which are compared by `Canal::sync`.
+    /// The default implementation collects the listing, which is recursive for the object
+    /// storages, the pool with nested folders should override this.
+    async fn list_all(&self, index: Option<S3Object>) -> Result<Vec<S3Object>, Error> {
+        let mut folder = self.list(index, &None).await?;
+        let mut objects = Vec::new();
+        while let Some(obj) = folder.next_object().await? {
+            objects.push(obj);
+        }
+        Ok(objects)
+    }
+    async fn remove(&self, desc: S3Object) -> Result<(), Error>;
+    /// Fill the metadata of the object, ex: size, content type and user metadata,
+    /// which are kept when the object is transferred by canal.
+    /// The pool without metadata leaves the object as it is.
+    async fn fetch_meta(&self, _desc: &mut S3Object) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Pull the object with its metadata filled as `fetch_meta`, which is used by canal.
+    /// The default implementation fetches the metadata before the pull, the pool reading the
+    /// metadata in the pull should override this to save the request.
+    async fn pull_with_meta(&self, mut desc: S3Object) -> Result<(S3Object, Bytes), Error> {
+        self.fetch_meta(&mut desc).await?;
+        let object = self.pull(desc.clone()).await?;
+        Ok((desc, object))
+    }
+    /// Pull the object as a stream with its metadata filled as `fetch_meta`.
+    /// The default implementation fetches the metadata before the pull.
+    async fn pull_stream_with_meta(
+        &self,
+        mut desc: S3Object,
+    ) -> Result<(S3Object, ByteStream), Error> {
+        self.fetch_meta(&mut desc).await?;
+        let object = self.pull_stream(desc.clone()).await?;
+        Ok((desc, object))
+    }
+    fn check_scheme(&self, _scheme: &str) -> Result<(), Error> {
+        Err(Error::SchemeError())
+    }
+    fn base_from(self, resource_location: &str) -> Result<Canal, Error>
+    where
+        Self: Sized + 'static,
+    {
+        match Url::parse(resource_location) {
+            Ok(r) if self.check_scheme(r.scheme()).is_err() => Err(Error::SchemeError()),
+            _ => Ok(Canal {
+                up_pool: Some(Box::new(self)),
+                down_pool: None,
+                upstream_object: None,
+                downstream_object: Some(resource_location.into()),
+                default: PoolType::DownPool,
+                filter: None,
+                preserve_mtime: false,
+                keep_prefix: false,
+            }),
+        }
+    }
+    fn target_to(self, resource_location: &str) -> Result<Canal, Error>
+    where
+        Self: Sized + 'static,
+    {
+        match Url::parse(resource_location) {
+            Ok(r) if self.check_scheme(r.scheme()).is_err() => Err(Error::SchemeError()),
+            _ => Ok(Canal {
+                up_pool: Some(Box::new(self)),
+                down_pool: None,
+                upstream_object: Some(resource_location.into()),
+                downstream_object: None,
+                default: PoolType::UpPool,
+                filter: None,
+                preserve_mtime: false,
+                keep_prefix: false,
+            }),
+        }
+    }
+}
+
+dyn_clone::clone_trait_object!(DataPool);
+
+#[cfg(test)]
+mod tests {
+    use crate::async_common::primitives::{Canal, FilePool, MemoryPool, PoolType};
+    use crate::async_common::traits::DataPool;
+    use crate::utils::sync::SyncOptions;
+    use crate::utils::S3Object;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    #[test]
+    fn test_canal_connect() {
+        let resource = FilePool::default();
+        let folder = resource.base_from("/path/to/a/folder").unwrap();
+        assert!(!folder.is_connect());
+        let canal = folder.toward("/path/to/another/folder").unwrap();
+        assert!(canal.is_connect());
+    }
+
+    #[tokio::test]
+    async fn test_folder_into_stream() {
+        let pool = MemoryPool::default();
+        for key in ["a.txt", "b.csv", "c.txt"] {
+            pool.push(
+                S3Object::from(format!("s3://bucket/{}", key).as_str()),
+                Bytes::from(key),
+            )
+            .await
+            .unwrap();
+        }
+        let folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+        let keys: Vec<String> = folder
+            .into_stream()
+            .try_filter(|o| futures::future::ready(o.key.as_deref().unwrap().ends_with(".txt")))
+            .map_ok(|o| o.key.unwrap())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(keys, vec!["/a.txt", "/c.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_clone_canal() {
+        let up_pool = MemoryPool::default();
+        let down_pool = MemoryPool::default();
+        for key in ["a.txt", "b.txt"] {
+            up_pool
+                .push(
+                    S3Object::from(format!("s3://bucket/{}", key).as_str()),
+                    Bytes::from(key),
+                )
+                .await
+                .unwrap();
+        }
+        let canal = Canal {
+            up_pool: Some(Box::new(up_pool)),
+            upstream_object: None,
+            down_pool: Some(Box::new(down_pool.clone())),
+            downstream_object: None,
+            default: PoolType::UpPool,
+            filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
+        };
+        let another = canal.clone();
+        let (a, b) = futures::join!(
+            canal.pull_obj("s3://bucket/a.txt".into()),
+            another.pull_obj("s3://bucket/b.txt".into())
+        );
+        a.unwrap();
+        b.unwrap();
+        for key in ["a.txt", "b.txt"] {
+            assert_eq!(
+                down_pool
+                    .pull(S3Object::from(format!("s3://bucket/{}", key).as_str()))
+                    .await
+                    .unwrap(),
+                Bytes::from(key)
+            );
+        }
+    }
+
+    /// The canal from the pool to the other pool for a sync
+    fn sync_canal(
+        up_pool: Box<dyn DataPool>,
+        upstream_object: S3Object,
+        down_pool: Box<dyn DataPool>,
+        downstream_object: S3Object,
+    ) -> Canal {
+        Canal {
+            up_pool: Some(up_pool),
+            upstream_object: Some(upstream_object),
+            down_pool: Some(down_pool),
+            downstream_object: Some(downstream_object),
+            default: PoolType::UpPool,
+            filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_canal_sync_to_memory() {
+        let dir = std::env::temp_dir().join("s3handler_test_canal_sync_to_memory");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), b"changed").unwrap();
+        std::fs::write(dir.join("same.txt"), b"content").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), b"new").unwrap();
+        let pool = MemoryPool::default();
+        for (key, content) in [
+            ("backup/a.txt", "old"),
+            ("backup/same.txt", "content"),
+            ("backup/extra.txt", "e"),
+            ("other.txt", "o"),
+        ] {
+            pool.push(
+                S3Object::from(format!("s3://bucket/{}", key).as_str()),
+                Bytes::from(content),
+            )
+            .await
+            .unwrap();
+        }
+        let canal = sync_canal(
+            Box::new(
+                FilePool::new(dir.to_str().unwrap())
+                    .unwrap()
+                    .bucket_as_directory(false),
+            ),
+            S3Object {
+                bucket: Some("local".to_string()),
+                ..Default::default()
+            },
+            Box::new(pool.clone()),
+            "s3://bucket/backup/".into(),
+        );
+
+        let options = SyncOptions {
+            delete: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let plan = canal.sync(&options).await.unwrap();
+        assert_eq!(plan.transfer, vec!["a.txt", "sub/b.txt"]);
+        assert_eq!(plan.delete, vec!["extra.txt"]);
+        assert_eq!(
+            pool.pull("s3://bucket/backup/a.txt".into()).await.unwrap(),
+            Bytes::from("old")
+        );
+
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        canal.sync(&options).await.unwrap();
+        for (key, content) in [
+            ("backup/a.txt", "changed"),
+            ("backup/same.txt", "content"),
+            ("backup/sub/b.txt", "new"),
+            ("other.txt", "o"),
+        ] {
+            assert_eq!(
+                pool.pull(S3Object::from(format!("s3://bucket/{}", key).as_str()))
+                    .await
+                    .unwrap(),
+                Bytes::from(content)
+            );
+        }
+        assert!(pool
+            .pull("s3://bucket/backup/extra.txt".into())
+            .await
+            .is_err());
+        assert!(canal.sync(&options).await.unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_canal_sync_from_memory() {
+        let dir = std::env::temp_dir().join("s3handler_test_canal_sync_from_memory");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep.txt"), b"kept").unwrap();
+        std::fs::write(dir.join("stale.txt"), b"stale").unwrap();
+        let pool = MemoryPool::default();
+        for (key, content) in [
+            ("docs/a.txt", "content"),
+            ("docs/keep.txt", "keep"),
+            ("docs/sub/b.txt", "new"),
+        ] {
+            pool.push(
+                S3Object::from(format!("s3://bucket/{}", key).as_str()),
+                Bytes::from(content),
+            )
+            .await
+            .unwrap();
+        }
+        let local = S3Object {
+            bucket: Some("local".to_string()),
+            ..Default::default()
+        };
+        let canal = sync_canal(
+            Box::new(pool.clone()),
+            "s3://bucket/docs/".into(),
+            Box::new(
+                FilePool::new(dir.to_str().unwrap())
+                    .unwrap()
+                    .bucket_as_directory(false),
+            ),
+            local,
+        );
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        let plan = canal.sync(&options).await.unwrap();
+        assert_eq!(plan.transfer, vec!["a.txt", "sub/b.txt"]);
+        assert_eq!(plan.delete, vec!["stale.txt"]);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"content");
+        assert_eq!(std::fs::read(dir.join("sub/b.txt")).unwrap(), b"new");
+        // The file of the same size is not transferred without the times or the ETags
+        assert_eq!(std::fs::read(dir.join("keep.txt")).unwrap(), b"kept");
+        assert!(!dir.join("stale.txt").exists());
+
+        // The key escaping the folder is refused before anything is transferred
+        for key in [
+            "/docs/../s3handler_test_canal_sync_escape.txt",
+            "/docs/z.txt",
+        ] {
+            pool.push(
+                S3Object {
+                    bucket: Some("bucket".to_string()),
+                    key: Some(key.to_string()),
+                    ..Default::default()
+                },
+                Bytes::from("escape"),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(canal.sync(&options).await.is_err());
+        assert!(!dir.join("z.txt").exists());
+        assert!(!std::env::temp_dir()
+            .join("s3handler_test_canal_sync_escape.txt")
+            .exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+//! The non-blocking api with async-std, which shares the traits, the canal, the signers and the
+//! memory and file pools with the `tokio_async` module for the users not on tokio, only the http
+//! client of the S3 pool is different.
+pub mod primitives;
+pub use crate::async_common::traits;
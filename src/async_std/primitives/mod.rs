@@ -0,0 +1,6 @@
+pub use crate::async_common::primitives::{
+    Canal, FilePool, ListPages, ListSettings, MemoryFolder, MemoryPool, PoolType,
+};
+pub use s3::{S3ObjectList, S3Pool};
+
+mod s3;
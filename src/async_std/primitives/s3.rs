@@ -0,0 +1,630 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::prelude::*;
+use chrono::Duration;
+use log::warn;
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode, Url,
+};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use surf::{Client, Request, Response};
+
+use super::{Canal, ListPages, ListSettings, PoolType};
+use crate::async_common::signer::{DummySigner, Signer, V2AuthSigner, V4AuthSigner};
+use crate::async_common::traits::{DataPool, Filter, S3Folder};
+use crate::error::Error;
+use crate::utils::credentials::CredentialProvider;
+use crate::utils::sign::PayloadHash;
+use crate::utils::{clock_skew, fill_meta, s3_error, S3Object, UrlStyle, METADATA_PREFIX};
+
+type UTCTime = DateTime<Utc>;
+
+/// The value of the header in the request, ex: the host or the user metadata
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value).map_err(|_| Error::HeaderParsingError())
+}
+
+/// The headers of the response in the type shared with the other pools
+fn header_map(r: &Response) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (k, v) in r.iter() {
+        if let (Ok(k), Ok(v)) = (
+            HeaderName::from_bytes(k.as_str().as_bytes()),
+            HeaderValue::from_str(v.last().as_str()),
+        ) {
+            headers.insert(k, v);
+        }
+    }
+    headers
+}
+
+/// The request signed in the type of reqwest is sent by surf
+fn surf_request(signed: reqwest::Request, body: &Bytes) -> Result<Request, Error> {
+    let method = signed
+        .method()
+        .as_str()
+        .parse()
+        .map_err(|_| Error::UserError("the method is not supported by surf"))?;
+    let mut request = Request::new(method, signed.url().clone());
+    for (name, value) in signed.headers() {
+        let value = value.to_str().map_err(|_| Error::HeaderParsingError())?;
+        request.append_header(name.as_str(), value);
+    }
+    if !body.is_empty() {
+        request.set_body(body.to_vec());
+    }
+    Ok(request)
+}
+
+/// Turn the failed response into `Error::S3Error` with the request ids for support
+async fn validate(mut r: Response) -> Result<Response, Error> {
+    let status = r.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = r.body_bytes().await.unwrap_or_default();
+        Err(s3_error(
+            StatusCode::from_u16(status.into()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            &header_map(&r),
+            &body,
+        ))
+    } else {
+        Ok(r)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct S3Pool {
+    pub host: String,
+    /// To use https or not, please note that integrity is secured by S3 protocol.
+    /// If the confidentiality is not under concerned, the http is good.
+    pub secure: bool,
+    /// Default will be Path style,
+    /// because Virtual hosted URLs may be supported for non-SSL requests only.
+    pub url_style: UrlStyle,
+
+    client: Client,
+
+    /// The signer to adapt different protocol of data source
+    pub signer: Box<dyn Signer>,
+
+    /// The offset of the server clock in milliseconds, shared with the clones of the pool
+    clock_offset: Arc<AtomicI64>,
+
+    /// Request `encoding-type=url` in the listings and decode the keys, default disabled.
+    pub encoding_type_url: bool,
+
+    /// Request `fetch-owner=true` in the V2 listings, default disabled.
+    pub fetch_owner: bool,
+
+    /// Stop the listings after the number of objects, default unlimited.
+    pub list_limit: Option<usize>,
+
+    /// Stop the listings after the number of requests, default unlimited.
+    pub list_max_requests: Option<usize>,
+}
+
+impl S3Pool {
+    pub fn bucket(self, bucket_name: &str) -> Canal {
+        Canal {
+            up_pool: Some(Box::new(self)),
+            down_pool: None,
+            upstream_object: Some(bucket_name.into()),
+            downstream_object: None,
+            default: PoolType::UpPool,
+            filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
+        }
+    }
+
+    pub fn resource(self, s3_object: S3Object) -> Canal {
+        Canal {
+            up_pool: Some(Box::new(self)),
+            down_pool: None,
+            upstream_object: Some(s3_object),
+            downstream_object: None,
+            default: PoolType::UpPool,
+            filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
+        }
+    }
+
+    pub fn new(host: String) -> Self {
+        S3Pool {
+            host,
+            secure: false,
+            url_style: UrlStyle::PATH,
+            client: Client::new(),
+            signer: Box::new(DummySigner {}),
+            clock_offset: Arc::new(AtomicI64::new(0)),
+            encoding_type_url: false,
+            fetch_owner: false,
+            list_limit: None,
+            list_max_requests: None,
+        }
+    }
+
+    /// Use https for the endpoints or not
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Request the keys url encoded in the listings, and the keys are decoded when parsing,
+    /// such that the keys with the characters invalid in XML, ex: `\n`, can be listed.
+    pub fn encoding_type_url(mut self, enable: bool) -> Self {
+        self.encoding_type_url = enable;
+        self
+    }
+
+    /// Request the owner of the objects in the V2 listings, which are the pages after the first
+    pub fn fetch_owner(mut self, enable: bool) -> Self {
+        self.fetch_owner = enable;
+        self
+    }
+
+    /// Stop the listings after the number of objects, ex: the first 200 keys,
+    /// and the pages are not requested after the limit is reached.
+    pub fn list_limit(mut self, limit: usize) -> Self {
+        self.list_limit = Some(limit);
+        self
+    }
+
+    /// Stop the listings after the number of requests, ex: for the buckets with plenty of
+    /// delete markers, which may return the empty pages.
+    pub fn list_max_requests(mut self, max_requests: usize) -> Self {
+        self.list_max_requests = Some(max_requests);
+        self
+    }
+
+    pub fn aws_v2(mut self, access_key: String, secret_key: String) -> Self {
+        self.signer = Box::new(V2AuthSigner::new(access_key, secret_key));
+        self.url_style = UrlStyle::PATH;
+        self
+    }
+
+    pub fn aws_v4(mut self, access_key: String, secret_key: String, region: String) -> Self {
+        self.signer = Box::new(V4AuthSigner::new(access_key, secret_key, region));
+        self.url_style = UrlStyle::HOST;
+        self
+    }
+
+    /// Sign with AWS signature v2 by the credentials from the provider, ex: the rotated ones
+    pub fn aws_v2_with_provider(mut self, credentials: Arc<dyn CredentialProvider>) -> Self {
+        self.signer = Box::new(V2AuthSigner::with_provider(credentials));
+        self.url_style = UrlStyle::PATH;
+        self
+    }
+
+    /// Sign with AWS signature v4 by the credentials from the provider, ex: the rotated ones
+    pub fn aws_v4_with_provider(
+        mut self,
+        credentials: Arc<dyn CredentialProvider>,
+        region: String,
+    ) -> Self {
+        self.signer = Box::new(V4AuthSigner::with_provider(credentials, region));
+        self.url_style = UrlStyle::HOST;
+        self
+    }
+
+    pub fn endpoint_and_virturalhost(&self, desc: S3Object) -> (String, Option<String>) {
+        self.url_style.endpoint(&self.host, self.secure, &desc)
+    }
+
+    /// The offset of the server clock to the local clock, which is detected when the server
+    /// rejects a request with `RequestTimeTooSkewed`, and added to the timestamp for signing.
+    pub fn clock_offset(&self) -> Duration {
+        Duration::milliseconds(self.clock_offset.load(Ordering::Relaxed))
+    }
+
+    /// The time for signing, which is adjusted with the clock offset
+    fn now(&self) -> UTCTime {
+        Utc::now() + self.clock_offset()
+    }
+
+    pub fn init_headers(
+        &self,
+        headers: &mut HeaderMap,
+        now: &UTCTime,
+        virturalhost: Option<String>,
+    ) -> Result<(), Error> {
+        let host = virturalhost.unwrap_or_else(|| self.host.clone());
+        headers.insert(header::DATE, header_value(&now.to_rfc2822())?);
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("Rust S3 Handler"),
+        );
+        headers.insert(header::HOST, header_value(&host)?);
+        Ok(())
+    }
+
+    /// Sign and send a request for the resource, the failed response will be turned into error
+    async fn send(
+        &self,
+        method: Method,
+        desc: S3Object,
+        query: &[(&str, &str)],
+        body: Bytes,
+    ) -> Result<Response, Error> {
+        let mime = desc.mime.clone();
+        // The user metadata is only kept when the object is written
+        let metadata = if method == Method::PUT {
+            desc.metadata.clone()
+        } else {
+            Default::default()
+        };
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
+        let url = if query.is_empty() {
+            Url::parse(&endpoint)?
+        } else {
+            Url::parse_with_params(&endpoint, query)?
+        };
+        let payload = PayloadHash::of(&body);
+        let request = |now: UTCTime| {
+            let mut request = reqwest::Request::new(method.clone(), url.clone());
+            let headers = request.headers_mut();
+            self.init_headers(headers, &now, virturalhost.clone())?;
+            if let Some(mime) = &mime {
+                headers.insert(header::CONTENT_TYPE, header_value(mime)?);
+            }
+            for (key, value) in &metadata {
+                headers.insert(
+                    HeaderName::from_bytes(format!("{}{}", METADATA_PREFIX, key).as_bytes())
+                        .map_err(|_| Error::HeaderParsingError())?,
+                    header_value(value)?,
+                );
+            }
+            self.signer.sign(&mut request, &now, &payload)?;
+            surf_request(request, &body)
+        };
+
+        // re-sign and retry once if the request is rejected for the skewed clock
+        let r = self.client.send(request(self.now())?).await?;
+        let headers = header_map(&r);
+        match validate(r).await {
+            Err(e) => match clock_skew(&e, &headers) {
+                Some(offset) => {
+                    warn!(
+                        "the clock is skewed {}ms from the server, re-sign the request",
+                        offset.num_milliseconds()
+                    );
+                    self.clock_offset
+                        .store(offset.num_milliseconds(), Ordering::Relaxed);
+                    validate(self.client.send(request(self.now())?).await?).await
+                }
+                None => Err(e),
+            },
+            r => r,
+        }
+    }
+}
+
+#[async_trait]
+impl DataPool for S3Pool {
+    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error> {
+        self.send(Method::PUT, desc, &[], object).await?;
+        Ok(())
+    }
+
+    async fn pull(&self, desc: S3Object) -> Result<Bytes, Error> {
+        let mut r = self.send(Method::GET, desc, &[], Bytes::new()).await?;
+        let expected_size = r.len();
+        let output = r.body_bytes().await?;
+        // A truncated transfer should not be returned as a short object silently
+        if let Some(expected) = expected_size {
+            if output.len() != expected {
+                return Err(Error::IntegrityError {
+                    expected: expected as u64,
+                    received: output.len() as u64,
+                });
+            }
+        }
+        Ok(output.into())
+    }
+
+    async fn list(
+        &self,
+        index: Option<S3Object>,
+        filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error> {
+        let mut index = index.unwrap_or_default();
+        // The key of the index is listed as the prefix, if there is no prefix filter
+        let filter = match (filter, index.key.take()) {
+            (Some(f), _) => Some(f.clone()),
+            (None, Some(key)) => Some(Filter::Prefix(key.trim_start_matches('/').to_string())),
+            (None, None) => None,
+        };
+        let list = S3ObjectList::new(self.clone(), index, filter)
+            .start()
+            .await?;
+        Ok(Box::new(list))
+    }
+
+    async fn remove(&self, desc: S3Object) -> Result<(), Error> {
+        self.send(Method::DELETE, desc, &[], Bytes::new()).await?;
+        Ok(())
+    }
+
+    fn check_scheme(&self, scheme: &str) -> Result<(), Error> {
+        if scheme.to_lowercase() != "s3" {
+            Err(Error::SchemeError())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
+        let r = self
+            .send(Method::HEAD, desc.clone(), &[], Bytes::new())
+            .await?;
+        fill_meta(desc, &header_map(&r))
+    }
+}
+
+/// The objects listed from a S3 pool, the listing keeps the state of the pagination and the
+/// pool is not changed.
+pub type S3ObjectList = crate::async_common::primitives::S3ObjectList<S3Pool>;
+
+#[async_trait]
+impl ListPages for S3Pool {
+    fn list_settings(&self) -> ListSettings {
+        ListSettings {
+            encoding_type_url: self.encoding_type_url,
+            fetch_owner: self.fetch_owner,
+            limit: self.list_limit,
+            max_requests: self.list_max_requests,
+        }
+    }
+
+    async fn list_page(&self, index: S3Object, query: &[(&str, &str)]) -> Result<String, Error> {
+        Ok(self
+            .send(Method::GET, index, query, Bytes::new())
+            .await?
+            .body_string()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_std::primitives::MemoryPool;
+    use crate::utils::sign::{v2_signature, v2_string_to_sign};
+    use crate::utils::test_server::{list_page, TestServer};
+    use std::convert::TryInto;
+
+    /// The test server closes the connection after each response, so the connections of the
+    /// client should not be kept alive and reused.
+    fn test_pool(server: &TestServer) -> S3Pool {
+        let mut pool = S3Pool::new(server.host.clone()).aws_v2("akey".into(), "skey".into());
+        pool.client = surf::Config::new()
+            .set_http_keep_alive(false)
+            .try_into()
+            .unwrap();
+        pool
+    }
+
+    #[test]
+    fn test_secure_endpoints() {
+        for (secure, scheme) in [(false, "http"), (true, "https")] {
+            let s3_pool = S3Pool::new("127.0.0.1:9000".to_string()).secure(secure);
+            assert_eq!(
+                s3_pool.endpoint_and_virturalhost(S3Object::from("s3://bucket/key")),
+                (format!("{}://127.0.0.1:9000/bucket/key", scheme), None)
+            );
+            let s3_pool = s3_pool.aws_v4("akey".into(), "skey".into(), "us-east-1".into());
+            assert_eq!(
+                s3_pool.endpoint_and_virturalhost(S3Object::from("s3://bucket/key")),
+                (
+                    format!("{}://bucket.127.0.0.1:9000/key", scheme),
+                    Some("bucket.127.0.0.1:9000".to_string())
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_s3_pool() {
+        ::async_std::task::block_on(async {
+            let server = TestServer::start();
+            let pool = test_pool(&server);
+
+            let mut desc = S3Object::from("s3://bucket/key.txt");
+            desc.mime = Some("text/plain".to_string());
+            pool.push(desc, Bytes::from("content")).await.unwrap();
+
+            server.respond(200, &[], b"content");
+            assert_eq!(
+                pool.pull(S3Object::from("s3://bucket/key.txt"))
+                    .await
+                    .unwrap(),
+                Bytes::from("content")
+            );
+
+            server.respond(200, &[], b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>key.txt</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>");
+            let mut folder = pool
+                .list(Some(S3Object::from("s3://bucket/key")), &None)
+                .await
+                .unwrap();
+            let obj = folder.next_object().await.unwrap().unwrap();
+            assert_eq!(obj.key.as_deref(), Some("/key.txt"));
+            assert!(folder.next_object().await.unwrap().is_none());
+
+            pool.remove(S3Object::from("s3://bucket/key.txt"))
+                .await
+                .unwrap();
+
+            server.respond(
+                404,
+                &[("x-amz-request-id", "4442587FB7D0A2F9")],
+                b"<Error><Code>NoSuchKey</Code><Message>The resource you requested does not exist</Message></Error>",
+            );
+            match pool.pull(S3Object::from("s3://bucket/key.txt")).await {
+                Err(Error::S3Error {
+                    status, request_id, ..
+                }) => {
+                    assert_eq!(status, 404);
+                    assert_eq!(request_id.as_deref(), Some("4442587FB7D0A2F9"));
+                }
+                r => panic!("unexpected result: {:?}", r),
+            }
+
+            let requests = server.requests();
+            let paths: Vec<(&str, &str)> = requests
+                .iter()
+                .map(|r| (r.method.as_str(), r.path.as_str()))
+                .collect();
+            assert_eq!(
+                paths,
+                vec![
+                    ("PUT", "/bucket/key.txt"),
+                    ("GET", "/bucket/key.txt"),
+                    ("GET", "/bucket/?prefix=key"),
+                    ("DELETE", "/bucket/key.txt"),
+                    ("GET", "/bucket/key.txt"),
+                ]
+            );
+            let put = &requests[0];
+            assert_eq!(put.body, b"content");
+            assert_eq!(put.headers["content-type"], "text/plain");
+            let expected = v2_string_to_sign(
+                "PUT",
+                "",
+                "text/plain",
+                &put.headers["date"],
+                &[],
+                "/bucket/key.txt",
+            );
+            assert_eq!(
+                put.headers["authorization"],
+                format!("AWS akey:{}", v2_signature("skey", &expected))
+            );
+        });
+    }
+
+    #[test]
+    fn test_canal_round_trip() {
+        ::async_std::task::block_on(async {
+            let server = TestServer::start();
+            let memory = MemoryPool::default();
+            memory
+                .push(
+                    S3Object::from("s3://bucket/key.txt"),
+                    Bytes::from("content"),
+                )
+                .await
+                .unwrap();
+            let canal = |object: &str| Canal {
+                up_pool: Some(Box::new(test_pool(&server))),
+                down_pool: Some(Box::new(memory.clone())),
+                upstream_object: Some(object.into()),
+                downstream_object: Some(object.into()),
+                default: PoolType::UpPool,
+                filter: None,
+                preserve_mtime: false,
+                keep_prefix: false,
+            };
+
+            // push from the memory pool to the S3 pool
+            canal("s3://bucket/key.txt").push().await.unwrap();
+            let requests = server.requests();
+            assert_eq!(requests[0].method, "PUT");
+            assert_eq!(requests[0].path, "/bucket/key.txt");
+            assert_eq!(requests[0].body, b"content");
+
+            // pull from the S3 pool back to the memory pool
+            server.route("/bucket/copied.txt", 200, &[], b"copied");
+            canal("s3://bucket/copied.txt").pull().await.unwrap();
+            assert_eq!(
+                memory
+                    .pull(S3Object::from("s3://bucket/copied.txt"))
+                    .await
+                    .unwrap(),
+                Bytes::from("copied")
+            );
+        });
+    }
+
+    #[test]
+    fn test_resign_on_clock_skew() {
+        ::async_std::task::block_on(async {
+            let server = TestServer::start();
+            let pool = test_pool(&server);
+            let server_time = (Utc::now() + Duration::hours(1)).to_rfc2822();
+            server.respond(
+                403,
+                &[("date", &server_time)],
+                b"<Error><Code>RequestTimeTooSkewed</Code></Error>",
+            );
+            pool.remove(S3Object::from("s3://bucket/key.txt"))
+                .await
+                .unwrap();
+            assert_eq!(server.requests().len(), 2);
+            assert!(
+                (pool.clock_offset() - Duration::hours(1))
+                    .num_seconds()
+                    .abs()
+                    <= 1
+            );
+        });
+    }
+
+    #[test]
+    fn test_list_with_encoding_type_url() {
+        ::async_std::task::block_on(async {
+            let server = TestServer::start();
+            let pool = test_pool(&server).encoding_type_url(true).fetch_owner(true);
+            server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType><IsTruncated>true</IsTruncated><Contents><Key>a%0Ab.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>");
+            server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType><IsTruncated>false</IsTruncated><Contents><Key>c+d.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size><Owner><ID>owner-id</ID></Owner></Contents></ListBucketResult>");
+            let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+            let mut objects = Vec::new();
+            while let Some(obj) = folder.next_object().await.unwrap() {
+                objects.push((obj.key.unwrap(), obj.owner));
+            }
+            assert_eq!(
+                objects,
+                vec![
+                    ("/a\nb.txt".to_string(), None),
+                    ("/c d.txt".to_string(), Some("owner-id".to_string())),
+                ]
+            );
+            let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+            assert_eq!(
+                paths,
+                vec![
+                    "/bucket/?encoding-type=url",
+                    "/bucket/?list-type=2&start-after=a%0Ab.txt&fetch-owner=true&encoding-type=url",
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_list_with_budget() {
+        ::async_std::task::block_on(async {
+            for (limit, max_requests, count, requests) in
+                [(Some(1500), None, 1500, 2), (None, Some(2), 2000, 2)]
+            {
+                let server = TestServer::start();
+                for (start, truncated) in [(0, true), (1000, true), (2000, false)] {
+                    server.respond(200, &[], list_page(start, truncated).as_bytes());
+                }
+                let pool = S3Pool {
+                    list_limit: limit,
+                    list_max_requests: max_requests,
+                    ..test_pool(&server)
+                };
+                let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+                let mut keys = Vec::new();
+                while let Some(obj) = folder.next_object().await.unwrap() {
+                    keys.push(obj.key.unwrap());
+                }
+                assert_eq!(keys.len(), count);
+                assert_eq!(keys.last().unwrap(), &format!("/{:04}", count - 1));
+                assert_eq!(server.requests().len(), requests);
+            }
+        });
+    }
+}
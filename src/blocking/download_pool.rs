@@ -1,15 +1,22 @@
+use std::collections::HashMap;
 use std::default::Default;
-use std::fmt::Debug;
-use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::io::Write;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
 use std::{thread, time};
 
-use crate::blocking::aws::{AWS2Client, AWS4Client};
-use crate::blocking::{AuthType, S3Client};
+use crate::blocking::aws::{AWS2Client, AWS4Client, HttpClient};
+use crate::blocking::{acquire, record_request, AuthType, S3Client};
 use crate::error::Error;
+use crate::utils::credentials::CredentialProvider;
+use crate::utils::metrics::Metrics;
+use crate::utils::{buffer_size, s3_error};
+use chrono::Duration;
 use log::{debug, error, info};
 
+/// The byte range of a part, the start is included and the end is excluded
 #[derive(Default, Debug, Clone)]
-pub struct MultiDownloadParameters(pub usize, pub usize);
+pub struct MultiDownloadParameters(pub u64, pub u64);
 
 pub struct DownloadRequestPool {
     ch_data: Option<mpsc::Sender<Box<MultiDownloadParameters>>>,
@@ -19,32 +26,21 @@ pub struct DownloadRequestPool {
     data: Vec<u8>,
 }
 
-#[allow(clippy::needless_lifetimes)]
-fn acquire<'a, T>(s: &'a Arc<Mutex<T>>) -> MutexGuard<'a, T>
-where
-    T: Debug,
-{
-    let mut l = s.lock();
-    while l.is_err() {
-        thread::sleep(time::Duration::from_millis(1000));
-        info!("sleep and wait for lock... error: {:?}", l);
-        l = s.lock();
-    }
-    l.expect("lock acuired")
-}
-
 #[allow(clippy::too_many_arguments)]
 impl DownloadRequestPool {
     pub fn new(
         auth_type: AuthType,
         secure: bool,
-        access_key: String,
-        secret_key: String,
+        credentials: Arc<dyn CredentialProvider>,
         host: String,
         uri: String,
         region: String,
         totoal_size: usize,
         total_worker: usize,
+        clock_offset: Duration,
+        http: HttpClient,
+        default_headers: Vec<(String, String)>,
+        metrics: Arc<dyn Metrics>,
     ) -> Self {
         let (ch_s, ch_r) = mpsc::channel();
         let a_ch_r = Arc::new(Mutex::new(ch_r));
@@ -55,37 +51,47 @@ impl DownloadRequestPool {
         for _ in 0..total_worker {
             let a_ch_r2 = a_ch_r.clone();
             let a_ch_result_s2 = a_ch_result_s.clone();
-            let akey = access_key.clone();
-            let skey = secret_key.clone();
+            let credentials = credentials.clone();
             let h = host.clone();
             let u = uri.clone();
             let r = region.clone();
+            let http = http.clone();
+            let hs = default_headers.clone();
+            let metrics = metrics.clone();
 
             std::thread::spawn(move || loop {
                 let s3_client: Box<dyn S3Client> = match auth_type {
                     AuthType::AWS2 => Box::new(AWS2Client {
                         tls: secure,
-                        access_key: &akey,
-                        secret_key: &skey,
+                        credentials: credentials.clone(),
+                        clock_offset,
+                        http: http.clone(),
                     }),
                     AuthType::AWS4 => Box::new(AWS4Client {
                         tls: secure,
-                        access_key: &akey,
-                        secret_key: &skey,
-                        host: &h,
+                        credentials: credentials.clone(),
+                        host: h.clone(),
                         region: r.to_string(),
+                        clock_offset,
+                        http: http.clone(),
                     }),
                 };
                 let recv_end = a_ch_r2.lock().expect("worker recv end is expected");
-                let result_send_back_ch = acquire(&a_ch_result_s2);
+                let result_send_back_ch = match acquire(&a_ch_result_s2) {
+                    Ok(ch) => ch,
+                    Err(e) => {
+                        error!("worker can not send back result: {}", e);
+                        return;
+                    }
+                };
                 loop {
                     let p: Box<MultiDownloadParameters> = match recv_end.recv() {
                         Ok(p) => p,
                         Err(e) => {
-                            let r = acquire(&a_ch_result_s2);
-                            r.send(Err(Error::RequestPoolError(format!("{:?}", e))))
-                                .ok();
-                            drop(r);
+                            if let Ok(r) = acquire(&a_ch_result_s2) {
+                                r.send(Err(Error::RequestPoolError(format!("{:?}", e))))
+                                    .ok();
+                            }
                             return;
                         }
                     };
@@ -97,16 +103,31 @@ impl DownloadRequestPool {
                     }
 
                     info!("Range ({}, {}) downloading...", p.0, p.1);
-                    match s3_client.request(
+                    let range = format!("bytes={}-{}", p.0, p.1 - 1);
+                    let mut headers = vec![("range", range.as_str())];
+                    headers.extend(hs.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                    let start = Instant::now();
+                    let result = s3_client.request(
                         "GET",
                         &h,
                         &u,
                         &mut Vec::new(),
-                        &mut vec![("range", &format!("bytes={}-{}", p.0, p.1 - 1))],
+                        &mut headers,
                         &Vec::new(),
-                    ) {
+                    );
+                    record_request(metrics.as_ref(), "GET", &h, start, 0, &result);
+                    match result {
+                        Ok((status, body, headers))
+                            if status.is_client_error() || status.is_server_error() =>
+                        {
+                            info!("Error on downloading Range ({}, {}): {}", p.0, p.1, status);
+                            // the pool may be dropped already, ex: a pipe stopped by a failed part
+                            result_send_back_ch
+                                .send(Err(s3_error(status, &headers, &body)))
+                                .ok();
+                        }
                         Ok(result) => {
-                            if result.1.len() == p.1 - p.0 {
+                            if result.1.len() as u64 == p.1 - p.0 {
                                 let mut send_result =
                                     result_send_back_ch.send(Ok(((*p).clone(), result.1.clone())));
                                 while send_result.is_err() {
@@ -122,15 +143,19 @@ impl DownloadRequestPool {
                                     p.1,
                                     result.1.len()
                                 );
+                                result_send_back_ch
+                                    .send(Err(Error::IntegrityError {
+                                        expected: p.1 - p.0,
+                                        received: result.1.len() as u64,
+                                    }))
+                                    .ok();
                             }
                             info!("Range ({}, {}) download executed", p.0, p.1);
                         }
                         Err(err) => {
                             info!("Error on downloading Range ({}, {}): {}", p.0, p.1, err);
-                            let rs = acquire(&a_ch_result_s2);
-                            rs.send(Err(err))
-                                .expect("channel is full to handle messages");
-                            drop(rs);
+                            // the sender is already held by this worker
+                            result_send_back_ch.send(Err(err)).ok();
                         }
                     };
                 }
@@ -167,9 +192,57 @@ impl DownloadRequestPool {
             }
         }
     }
+    /// Write the parts to `out` in the order of `parts`, because a pipe is not seekable,
+    /// at most `window` parts are downloading or waiting for the former parts at once.
+    pub fn pipe(
+        mut self,
+        parts: Vec<MultiDownloadParameters>,
+        window: usize,
+        out: &mut dyn Write,
+    ) -> Result<u64, Error> {
+        let window = window.max(1);
+        let mut buffered = HashMap::new();
+        let mut sent = 0;
+        let mut written = 0;
+        let mut total = 0;
+        while written < parts.len() {
+            while sent < parts.len() && sent < written + window {
+                self.run(parts[sent].clone());
+                sent += 1;
+            }
+            match self
+                .ch_result
+                .recv()
+                .map_err(|e| Error::RequestPoolError(format!("{:?}", e)))?
+            {
+                Ok((para, data)) => {
+                    buffered.insert(para.0, data);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(e);
+                }
+            }
+            while let Some(data) = parts.get(written).and_then(|p| buffered.remove(&p.0)) {
+                out.write_all(&data)?;
+                total += data.len() as u64;
+                written += 1;
+            }
+        }
+        out.flush()?;
+        // the workers are stopped when the channel is closed
+        self.ch_data.take();
+        Ok(total)
+    }
     pub fn wait(mut self) -> Result<Vec<u8>, Error> {
         let mut results = 0;
+        let mut received = 0;
+        let mut failure = None;
         self.ch_data.take();
+        // no result will be sent back without any part, ex: an empty object
+        if self.total_jobs == 0 {
+            return Ok(self.data);
+        }
         loop {
             thread::sleep(time::Duration::from_millis(1000));
             let result = self
@@ -177,13 +250,16 @@ impl DownloadRequestPool {
                 .recv()
                 .expect("channel is full to handle messages");
 
-            match result {
-                Ok((para, data)) => {
-                    self.data[para.0..para.1].copy_from_slice(&data);
+            // the buffer is indexed in usize, which is checked for the 32-bit targets
+            match result.and_then(|(para, data)| Ok((buffer_size(para.0)?, para, data))) {
+                Ok((start, para, data)) => {
+                    self.data[start..start + data.len()].copy_from_slice(&data);
+                    received += data.len();
                     debug!("{:?}", para);
                 }
                 Err(e) => {
                     error!("{}", e);
+                    failure.get_or_insert(e);
                 }
             }
             results += 1;
@@ -191,6 +267,15 @@ impl DownloadRequestPool {
 
             if results == self.total_jobs {
                 self.close();
+                if let Some(e) = failure {
+                    return Err(e);
+                }
+                if received != self.data.len() {
+                    return Err(Error::IntegrityError {
+                        expected: self.data.len() as u64,
+                        received: received as u64,
+                    });
+                }
                 return Ok(self.data);
             }
         }
@@ -8,65 +8,74 @@
 //!     region: None, // default is us-east-1
 //!     s3_type: None, // default will try to config as AWS S3 handler
 //!     secure: None, // dafault is false, because the integrity protect by HMAC
+//!     part_size: None, // default is automatic for downloads, 5MB for uploads
+//!     worker_count: None, // default is 10
 //! };
 //! let mut handler = s3handler::Handler::from(&config);
 //! let _ = handler.la();
 //! ```
 
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::From;
-use std::fs::{metadata, write, File};
+use std::fmt::Debug;
+use std::fs::{write, File};
+use std::hash::{BuildHasher, Hasher};
 use std::io::prelude::*;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use std::{thread, time};
 
 use crate::error::Error;
 pub use crate::utils::UrlStyle;
-use aws::{AWS2Client, AWS4Client};
+use aws::{AWS2Client, AWS4Client, HttpClient};
 use download_pool::{DownloadRequestPool, MultiDownloadParameters};
+use recording::RecordingClient;
 use upload_pool::{MultiUploadParameters, UploadRequestPool};
 
+use crate::utils::credentials::{CredentialProvider, StaticProvider};
+use crate::utils::glob::{Glob, Rules};
+use crate::utils::metrics::{Metrics, NoopMetrics};
+use crate::utils::notification::{notification_xml_parser, NotificationConfiguration};
+use crate::utils::sign::{RecordedRequest, SignDebug};
+pub use crate::utils::sync::SyncOptions;
+use crate::utils::sync::{check_key, SyncPlan};
+use crate::utils::walk::{walk_dir, LocalEntry, SymlinkPolicy};
+pub use crate::utils::CredentialConfig;
 use crate::utils::{
-    s3object_list_xml_parser, upload_id_xml_parser, S3Convert, S3Object, DEFAULT_REGION,
+    advance_marker, auto_part_size, buffer_size, clock_skew, complete_multipart_xml, content_md5,
+    copy_part_etag_xml_parser, delete_errors_xml_parser, delete_objects_xml, gunzip, is_gzip,
+    is_mutating, mtime_metadata, multipart_etag, multipart_uploads_xml_parser, normalize_region,
+    object_attributes_xml_parser, parts_xml_parser, s3_error, s3object_list_json_parser,
+    s3object_list_xml_parser, tagging_header, upload_id_xml_parser, url_encode, user_metadata,
+    validate_bucket_name, versions_xml_parser, xml_list_page, xml_next_marker, Action,
+    ClientConfig, DuSummary, ObjectAttributes, PartInfo, S3Convert, S3Object, UploadInfo,
+    UploadMarkers, VersionEntry, VersionMarkers, CANNED_ACLS, DEFAULT_REGION, DELETE_BATCH_SIZE,
+    MAX_COPY_SIZE, MAX_PART_COUNT, METADATA_PREFIX, MTIME_METADATA, TAGGING_HEADER,
 };
-use log::{debug, error, info};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info, warn};
 use mime_guess::from_path;
-use quick_xml::{events::Event, Reader};
 use regex::Regex;
 use reqwest::{blocking::Response, StatusCode};
-use serde_derive::Deserialize;
 
 pub mod aws;
 mod download_pool;
+mod recording;
 mod upload_pool;
 
-static RESPONSE_CONTENT_FORMAT: &str =
-    r#""Contents":\["([^"]+?)","([^"]+?)","\\"([^"]+?)\\"",([^"]+?),"([^"]+?)"(.*?)\]"#;
-static RESPONSE_MARKER_FORMAT: &str = r#""NextMarker":"([^"]+?)","#;
 static DEFAULT_PREPART_SIZE: u64 = 5242880;
-
-/// # The struct for credential config for each S3 cluster
-/// - host is a parameter for the server you want to link
-///     - it can be s3.us-east-1.amazonaws.com or a ip, ex 10.1.1.100, for a ceph node
-/// - user name is not required, because it only show in the prompt of shell
-/// - access_key and secret_key are keys to connect to the cluster providing S3
-/// - region is a paramter for the S3 cluster location
-///     - if region is not specified, it will take default value us-east-1
-/// - s3 type is a shortcut to set up auth type, format, url style for aws or ceph
-///     - if s3_type is not specified, it will take aws as default value, aws
-/// - secure is the request will send via https or not.  The integrity of requests is provided by
-/// HMAC, and the https requests can provid the confidentiality.
-///
-#[derive(Debug, Clone, Deserialize)]
-pub struct CredentialConfig {
-    pub host: String,
-    pub user: Option<String>,
-    pub access_key: String,
-    pub secret_key: String,
-    pub region: Option<String>,
-    pub s3_type: Option<String>,
-    pub secure: Option<bool>,
-}
+// Once we have retry mechanism in workers, we can make this bigger
+// Magic number, I do not tune on this currently
+static DEFAULT_WORKER_COUNT: usize = 10;
+static ACQUIRE_RETRY: u32 = 5;
+static SYMLINK_HEADER: &str = "x-amz-meta-symlink-target";
+static ACQUIRE_BACKOFF_MS: u64 = 10;
 
 /// # The signature type of Authentication
 /// AWS2, AWS4 represent for AWS signature v2 and AWS signature v4
@@ -81,24 +90,50 @@ pub struct CredentialConfig {
 /// - Asia Pacific (Singapore) Region
 /// - Asia Pacific (Sydney) Region
 /// - South America (So Paulo) Region
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum AuthType {
     AWS4,
     AWS2,
 }
 
+impl FromStr for AuthType {
+    type Err = Error;
+
+    /// Parse `aws2` or `aws4`, `aws` is treated as `aws4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "aws2" => Ok(AuthType::AWS2),
+            "aws4" | "aws" => Ok(AuthType::AWS4),
+            _ => Err(Error::UserError("auth type should be aws4 or aws2")),
+        }
+    }
+}
+
 /// # The response format
 /// AWS only support XML format (default)
 /// CEPH support JSON and XML
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Format {
     JSON,
     XML,
 }
 
+impl FromStr for Format {
+    type Err = Error;
+
+    /// Parse `xml` or `json`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "xml" => Ok(Format::XML),
+            "json" => Ok(Format::JSON),
+            _ => Err(Error::UserError("format should be xml or json")),
+        }
+    }
+}
+
 /// # The trait for S3Client
 /// - handle a valid request
-pub(crate) trait S3Client {
+pub(crate) trait S3Client: Send {
     fn request(
         &self,
         method: &str,
@@ -112,9 +147,54 @@ pub(crate) trait S3Client {
         payload: &[u8],
     ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error>;
 
+    /// Send the request with the file streamed as the body
+    fn request_file(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        file: &File,
+    ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error>;
+
     fn redirect_parser(&self, body: Vec<u8>, format: Format) -> Result<String, Error>;
     fn update(&mut self, region: String, secure: bool);
+    fn update_clock_offset(&mut self, offset: Duration);
     fn current_region(&self) -> Option<String>;
+
+    /// Sign the request and return the url and the headers should be sent
+    #[allow(clippy::too_many_arguments)]
+    fn sign_request(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+        utc: DateTime<Utc>,
+    ) -> Result<(String, reqwest::header::HeaderMap), Error>;
+
+    /// Sign the request as `request` does without sending it
+    #[allow(clippy::too_many_arguments)]
+    fn debug_sign(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+        utc: DateTime<Utc>,
+    ) -> Result<SignDebug, Error>;
+}
+
+/// The body of a request, the file is streamed instead of being read into memory
+#[derive(Clone, Copy)]
+enum Payload<'p> {
+    Bytes(&'p [u8]),
+    File(&'p File, u64),
 }
 
 /// # The struct for generate the request
@@ -136,13 +216,20 @@ pub(crate) trait S3Client {
 ///     region: None, // default is us-east-1
 ///     s3_type: None, // default will try to config as AWS S3 handler
 ///     secure: None, // dafault is false, because the integrity protect by HMAC
+///     part_size: None, // default is automatic for downloads, 5MB for uploads
+///     worker_count: None, // default is 10
 /// };
 /// let mut handler = s3handler::Handler::from(&config);
+/// // or move the config into the handler, such that the handler does not borrow it
+/// let handler: s3handler::HandlerOwned = s3handler::Handler::from(config.clone());
 /// ```
 pub struct Handler<'a> {
-    pub access_key: &'a str,
-    pub secret_key: &'a str,
-    pub host: &'a str,
+    pub access_key: Cow<'a, str>,
+    pub secret_key: Cow<'a, str>,
+    pub host: Cow<'a, str>,
+
+    // The provider of the credentials for signing, the keys above are provided by default
+    credentials: Arc<dyn CredentialProvider>,
 
     s3_client: Box<dyn S3Client + 'a>,
     pub auth_type: AuthType,
@@ -153,11 +240,194 @@ pub struct Handler<'a> {
     // redirect related paramters
     domain_name: String,
 
+    // the base domain of the buckets in virtual-host style, ex: a CNAME of the buckets
+    base_domain: Option<String>,
+
     // https for switch s3_client
     secure: bool,
 
     // The chunck size for multipart
     part_size: u64,
+
+    // The max number of workers for multipart
+    worker_count: usize,
+
+    // Decompress the object with gzip content encoding when downloading
+    decompress: bool,
+
+    // Keep the modified time of the files in the `mtime` user metadata
+    preserve_mtime: bool,
+
+    // Request the keys url encoded in the listings, for the keys with the control characters
+    encoding_type_url: bool,
+
+    // Pick the part size of downloads by the object size instead of `part_size`
+    auto_part_size: bool,
+
+    // Append the `format` query param for JSON format, only CEPH supports it
+    format_query: bool,
+
+    // The offset of the server clock detected from `RequestTimeTooSkewed` error
+    clock_offset: Duration,
+
+    // The certificate verification of https, for the self-signed S3 services
+    client_config: ClientConfig,
+
+    // The http client of `client_config`, shared by the S3 clients and the workers
+    http: HttpClient,
+
+    // The headers sent and signed with every request, ex: the tenant header of a gateway
+    default_headers: Vec<(String, String)>,
+
+    // The hooks to record the requests
+    metrics: Arc<dyn Metrics>,
+
+    // Skip the mutating requests and plan them as actions
+    dry_run: bool,
+
+    // The actions planned in the dry-run mode
+    planned: Vec<Action>,
+
+    // The signed requests recorded instead of being sent, shared with the forked handlers
+    recorded: Option<Arc<Mutex<Vec<RecordedRequest>>>>,
+
+    // The regions and the endpoints of the buckets discovered from the redirects
+    bucket_regions: HashMap<String, (String, String)>,
+}
+
+/// The handler owning its keys, which is initialized from the config by value, ex:
+/// `HashMap<String, HandlerOwned>` to keep the handlers of several S3 clusters.
+pub type HandlerOwned = Handler<'static>;
+
+/// # The conditions for server side copy
+/// - copy_source_if_match - copy only if the ETag of the source object matches
+/// - copy_source_if_modified_since - copy only if the source object is modified since the time,
+///   ex: "Wed, 21 Oct 2015 07:28:00 GMT"
+/// - tags - replace the tags of the source by `x-amz-tagging-directive: REPLACE`,
+///   ex: `("project", "s3rs")`, the tags of the source are copied if it is None
+///
+/// If the condition is not met, the copy will fail with `Error::PreconditionFailed`.
+/// The source refused by the single copy for larger than 5GB is copied in parts, the content type
+/// and the metadata of the source are kept, but only the replaced tags are kept on it.
+/// ```
+/// let options = s3handler::CopyOptions {
+///     copy_source_if_match: Some("d41d8cd98f00b204e9800998ecf8427e".to_string()),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    pub copy_source_if_match: Option<String>,
+    pub copy_source_if_modified_since: Option<String>,
+    pub tags: Option<Vec<(String, String)>>,
+}
+
+/// # The response headers overridden by S3 when downloading
+/// - response_content_type - the `Content-Type` of the response, ex: "text/csv"
+/// - response_content_disposition - the `Content-Disposition` of the response
+///     - ex: "attachment; filename=\"report.csv\""
+///
+/// The overrides are sent as the query params, so they are signed with the request.
+/// ```
+/// let options = s3handler::GetOptions {
+///     response_content_type: Some("text/csv".to_string()),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GetOptions {
+    pub response_content_type: Option<String>,
+    pub response_content_disposition: Option<String>,
+}
+
+impl GetOptions {
+    fn query_strings(&self) -> Vec<(&str, &str)> {
+        let mut query_strings = Vec::new();
+        if let Some(content_type) = &self.response_content_type {
+            query_strings.push(("response-content-type", content_type.as_str()));
+        }
+        if let Some(disposition) = &self.response_content_disposition {
+            query_strings.push(("response-content-disposition", disposition.as_str()));
+        }
+        query_strings
+    }
+}
+
+/// # The settings of the uploaded object
+/// - tags - the tags set with the upload by `x-amz-tagging` header, ex: `("project", "s3rs")`
+/// - content_type - the `Content-Type` header, default guessed by the extension of the file
+///
+/// The object is tagged atomically, and never left untagged after the upload.
+/// ```
+/// let options = s3handler::PutOptions {
+///     tags: vec![("project".to_string(), "s3rs".to_string())],
+///     content_type: s3handler::ContentType::Omit,
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    pub tags: Vec<(String, String)>,
+    pub content_type: ContentType,
+}
+
+/// The content type of the upload
+/// - Guess - guess the type by the extension of the file, this is the default
+/// - Fixed - the given type, ex: "application/vnd.apache.parquet"
+/// - Omit - send no `Content-Type` header, and the server picks the default one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ContentType {
+    #[default]
+    Guess,
+    Fixed(String),
+    Omit,
+}
+
+/// # The ACL settings for bucket creation
+/// - acl - the canned ACL, ex: "public-read"
+/// - grant_* - the grantees, ex: `id="111122223333", emailAddress="user@example.com"`
+/// ```
+/// let options = s3handler::BucketOptions {
+///     acl: Some("public-read".to_string()),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BucketOptions {
+    pub acl: Option<String>,
+    pub grant_full_control: Option<String>,
+    pub grant_read: Option<String>,
+    pub grant_read_acp: Option<String>,
+    pub grant_write: Option<String>,
+    pub grant_write_acp: Option<String>,
+}
+
+/// # The budget of a listing
+/// - limit - stop after the number of objects, and the last page is trimmed
+/// - max_requests - stop after the number of requests, ex: for the buckets with plenty of
+///   delete markers, which may return the empty pages
+///
+/// The listing is not complete if it is stopped by the budget.
+/// ```
+/// let options = s3handler::ListOptions {
+///     limit: Some(200),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub limit: Option<usize>,
+    pub max_requests: Option<usize>,
+}
+
+/// # The result of a listing
+/// - objects - the objects listed
+/// - complete - false if the listing stopped before the last page
+///     - a page after the first one failed
+///     - the server truncated the page without a marker to continue
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub objects: Vec<S3Object>,
+    pub complete: bool,
 }
 
 trait ResponseHandler {
@@ -186,113 +456,671 @@ impl ResponseHandler for Response {
     }
 }
 
+/// Turn the failed response into `Error::PreconditionFailed` or `Error::S3Error`
+fn check_status(
+    status_code: StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    body: &[u8],
+) -> Result<(), Error> {
+    if status_code == StatusCode::PRECONDITION_FAILED {
+        return Err(Error::PreconditionFailed());
+    }
+    if status_code.is_client_error() || status_code.is_server_error() {
+        return Err(s3_error(status_code, headers, body));
+    }
+    Ok(())
+}
+
+/// Record the finished request of the S3 client in the metrics
+pub(crate) fn record_request(
+    metrics: &dyn Metrics,
+    method: &str,
+    host: &str,
+    start: Instant,
+    sent: u64,
+    result: &Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error>,
+) {
+    match result {
+        Ok((status, body, _)) => {
+            metrics.on_host_request(method, host, Some(status.as_u16()), start.elapsed());
+            metrics.on_bytes(sent, body.len() as u64);
+        }
+        Err(_) => metrics.on_host_request(method, host, None, start.elapsed()),
+    }
+}
+
+/// The ranges of the parts to download an object of `size` bytes
+fn download_parts(size: u64, part_size: u64) -> Result<Vec<MultiDownloadParameters>, Error> {
+    if part_size == 0 {
+        return Err(Error::UserError("part size should be larger than 0"));
+    }
+    // the offsets are counted in u64, such that the ranges over 4GB are kept on 32-bit targets
+    Ok((0..size.div_ceil(part_size))
+        .map(|i| i * part_size)
+        .map(|start| MultiDownloadParameters(start, cmp::min(size, start + part_size)))
+        .collect())
+}
+
+/// The location of a sync is a S3 prefix, ex: `s3://bucket/backup/`, else it is a local folder
+fn is_s3_location(location: &str) -> bool {
+    location.trim_start().to_lowercase().starts_with("s3://")
+}
+
+/// The file or the object of the relative key under the location of a sync, the key escaping
+/// the location is refused
+fn sync_path(location: &str, key: &str) -> Result<String, Error> {
+    check_key(key)?;
+    if is_s3_location(location) {
+        Ok(format!("{}/{}", location.trim_end_matches('/'), key))
+    } else {
+        Ok(Path::new(location).join(key).to_string_lossy().to_string())
+    }
+}
+
+/// Lock the mutex shared between the workers of request pools.
+/// Retry with a jittered exponential backoff, such that the workers will not wake up at the same
+/// time, and return error if the lock is still poisoned after `ACQUIRE_RETRY` times.
+pub(crate) fn acquire<T>(s: &Arc<Mutex<T>>) -> Result<MutexGuard<'_, T>, Error>
+where
+    T: Debug,
+{
+    for retry in 0..ACQUIRE_RETRY {
+        match s.lock() {
+            Ok(l) => return Ok(l),
+            Err(e) => {
+                let backoff = ACQUIRE_BACKOFF_MS << retry;
+                let jitter = RandomState::new().build_hasher().finish() % backoff;
+                info!(
+                    "sleep {}ms and wait for lock... error: {:?}",
+                    backoff + jitter,
+                    e
+                );
+                thread::sleep(time::Duration::from_millis(backoff + jitter));
+            }
+        }
+    }
+    s.lock()
+        .map_err(|e| Error::RequestPoolError(format!("lock is not acquired: {:?}", e)))
+}
+
 impl Handler<'_> {
     pub fn is_secure(&self) -> bool {
         self.secure
     }
 
+    /// Use https or not, the S3 client is rebuilt with the setting,
+    /// such that the handler can be switched to https without constructing a new one.
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+        self.renew_client();
+    }
+
+    /// Accept the invalid certificates of https, ex: the self-signed certificate,
+    /// the connection is open to man-in-the-middle attacks, please prefer `root_ca`.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn danger_accept_invalid_certs(&mut self, accept: bool) {
+        self.client_config.danger_accept_invalid_certs = accept;
+        self.renew_client();
+    }
+
+    /// Trust the root CA certificate in PEM, ex: the CA signing the certificate of a ceph cluster
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn root_ca(&mut self, pem: &[u8]) -> Result<(), Error> {
+        // validate the certificate here, rather than failing on every request
+        reqwest::blocking::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(pem)?)
+            .build()?;
+        self.client_config.root_ca = Some(pem.to_vec());
+        self.renew_client();
+        Ok(())
+    }
+
+    /// Keep at most `max` idle connections for a host
+    pub fn pool_max_idle_per_host(&mut self, max: usize) {
+        self.client_config.pool_max_idle_per_host = Some(max);
+        self.renew_client();
+    }
+
+    /// Close the idle connections after the timeout, ex: 10 seconds for a haproxy dropping the
+    /// idle keep-alive connections after 15 seconds
+    pub fn pool_idle_timeout(&mut self, timeout: std::time::Duration) {
+        self.client_config.pool_idle_timeout = Some(timeout);
+        self.renew_client();
+    }
+
+    /// Negotiate HTTP/2 with the server by ALPN, or use HTTP/1 only if disabled
+    pub fn http2(&mut self, enable: bool) {
+        self.client_config.http2 = Some(enable);
+        self.client_config.http2_prior_knowledge &= enable;
+        self.renew_client();
+    }
+
+    /// Use HTTP/2 without the negotiation, only for the servers known to speak HTTP/2,
+    /// the requests to AWS S3 fail with it
+    pub fn http2_prior_knowledge(&mut self, enable: bool) {
+        self.client_config.http2_prior_knowledge = enable;
+        self.renew_client();
+    }
+
+    /// Sign the requests with the credentials from the provider, ex: the rotated ones of an
+    /// instance profile, then `access_key` and `secret_key` are not used for signing.
+    pub fn credential_provider(&mut self, provider: Arc<dyn CredentialProvider>) {
+        self.credentials = provider;
+        self.renew_client();
+    }
+
+    /// Rebuild the S3 client with the current settings, the http client is built again on the
+    /// next request and reused by the later ones
+    fn renew_client(&mut self) {
+        self.http = HttpClient::new(self.client_config.clone(), self.metrics.clone());
+        self.s3_client = self.new_client();
+    }
+
+    /// A new S3 client with the current settings
+    fn new_client(&self) -> Box<dyn S3Client> {
+        let secure = self.secure;
+        let client: Box<dyn S3Client> = match self.auth_type {
+            AuthType::AWS2 => Box::new(AWS2Client {
+                tls: secure,
+                credentials: self.credentials.clone(),
+                clock_offset: self.clock_offset,
+                http: self.http.clone(),
+            }),
+            AuthType::AWS4 => Box::new(AWS4Client {
+                tls: secure,
+                credentials: self.credentials.clone(),
+                host: self.host.to_string(),
+                region: self
+                    .region
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+                clock_offset: self.clock_offset,
+                http: self.http.clone(),
+            }),
+        };
+        match &self.recorded {
+            Some(recorded) => Box::new(RecordingClient {
+                inner: client,
+                recorded: recorded.clone(),
+                clock_offset: self.clock_offset,
+            }),
+            None => client,
+        }
+    }
+
+    /// A handler with the same settings and a new client, which can be moved to a worker
+    fn fork(&self) -> HandlerOwned {
+        Handler {
+            access_key: Cow::Owned(self.access_key.to_string()),
+            secret_key: Cow::Owned(self.secret_key.to_string()),
+            host: Cow::Owned(self.host.to_string()),
+            credentials: self.credentials.clone(),
+            s3_client: self.new_client(),
+            auth_type: self.auth_type,
+            format: self.format.clone(),
+            url_style: self.url_style.clone(),
+            region: self.region.clone(),
+            domain_name: self.domain_name.clone(),
+            base_domain: self.base_domain.clone(),
+            secure: self.secure,
+            part_size: self.part_size,
+            worker_count: self.worker_count,
+            decompress: self.decompress,
+            preserve_mtime: self.preserve_mtime,
+            encoding_type_url: self.encoding_type_url,
+            auto_part_size: self.auto_part_size,
+            format_query: self.format_query,
+            clock_offset: self.clock_offset,
+            client_config: self.client_config.clone(),
+            http: self.http.clone(),
+            default_headers: self.default_headers.clone(),
+            metrics: self.metrics.clone(),
+            dry_run: self.dry_run,
+            planned: Vec::new(),
+            recorded: self.recorded.clone(),
+            bucket_regions: self.bucket_regions.clone(),
+        }
+    }
+
+    /// Change the signature version, the client is renewed with the same keys and region
+    pub fn set_auth_type(&mut self, auth_type: AuthType) {
+        self.auth_type = auth_type;
+        self.renew_client();
+    }
+
+    /// Change the response format of the listings, only CEPH supports json
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Change the request url style
+    pub fn set_url_style(&mut self, url_style: UrlStyle) {
+        self.url_style = url_style;
+    }
+
+    /// Set the base domain of the buckets in virtual-host style, ex: `cdn.example.com` for
+    /// `bucket.cdn.example.com` with CNAME, default is the host of the handler.
+    pub fn set_base_domain(&mut self, domain: &str) {
+        self.base_domain = Some(domain.to_string());
+    }
+
+    /// The domain of the buckets in virtual-host style, the connection host is kept for the
+    /// path style.
+    fn virtual_host_domain(&self) -> String {
+        self.base_domain
+            .clone()
+            .unwrap_or_else(|| self.domain_name.clone())
+    }
+
+    /// Decompress the downloaded object if it is stored with `Content-Encoding: gzip`,
+    /// default is false, and the object will be kept as it stored.
+    pub fn decompress(&mut self, decompress: bool) {
+        self.decompress = decompress;
+    }
+
+    /// Record the modified time of the file in the `x-amz-meta-mtime` when uploading, and restore
+    /// it, or the `Last-Modified` of the object, to the file when downloading, default is false.
+    pub fn preserve_mtime(&mut self, enable: bool) {
+        self.preserve_mtime = enable;
+    }
+
+    /// Request `encoding-type=url` in the listings, and the keys are decoded when parsing,
+    /// such that the keys with the characters invalid in XML, ex: `\n`, can be listed.
+    pub fn encoding_type_url(&mut self, enable: bool) {
+        self.encoding_type_url = enable;
+    }
+
+    /// Pick the part size of downloads by the object size and the `worker_count`,
+    /// it is recommended for the large objects or the links with high latency,
+    /// default is true unless the `part_size` is set in the config.
+    pub fn auto_part_size(&mut self, auto: bool) {
+        self.auto_part_size = auto;
+    }
+
+    /// Append the `format=json` query param when the format is JSON,
+    /// it is enabled for CEPH by default, and other S3 servers may reject the unknown param.
+    pub fn format_query(&mut self, enable: bool) {
+        self.format_query = enable;
+    }
+
+    /// The offset of the server clock to the local clock, which is detected when the server
+    /// rejects a request with `RequestTimeTooSkewed`, and added to the timestamp for signing.
+    pub fn clock_offset(&self) -> Duration {
+        self.clock_offset
+    }
+
+    /// Add a header sent and signed with every request, ex: `x-tenant-id` for a storage gateway,
+    /// this can be called repeatedly for more headers.
+    pub fn default_header(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| Error::HeaderParsingError())?;
+        reqwest::header::HeaderValue::from_str(value).map_err(|_| Error::HeaderParsingError())?;
+        self.default_headers
+            .push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Record the requests, the retries and the transferred bytes with the hooks,
+    /// including the requests of the multipart workers.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = metrics;
+        self.renew_client();
+    }
+
+    /// Skip the mutating requests, ex: PUT, POST and DELETE, and plan them as actions,
+    /// the listings are still requested, so the plan is accurate.
+    /// Please check out the plan with `take_planned` before running the operation for real.
+    pub fn dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Take the actions planned in the dry-run mode
+    pub fn take_planned(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.planned)
+    }
+
+    /// Sign the requests and record them instead of sending, every request is answered with an
+    /// empty 200 response, ex: to debug the signatures or to snapshot the requests for the tests.
+    /// The parts of the multipart transfers are not recorded, and the client is renewed when
+    /// the mode is changed.
+    pub fn record(&mut self, enable: bool) {
+        if enable {
+            self.recorded.get_or_insert_with(Default::default);
+        } else {
+            self.recorded = None;
+        }
+        self.renew_client();
+    }
+
+    /// The requests recorded by the pools converted from the handler in the recording mode
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn recorded(&self) -> Option<Arc<Mutex<Vec<RecordedRequest>>>> {
+        self.recorded.clone()
+    }
+
+    /// Take the requests recorded in the recording mode
+    pub fn take_recorded(&mut self) -> Vec<RecordedRequest> {
+        match &self.recorded {
+            Some(recorded) => acquire(recorded)
+                .map(|mut r| std::mem::take(&mut *r))
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn base_domain(&self) -> Option<&str> {
+        self.base_domain.as_deref()
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn part_size(&self) -> u64 {
+        self.part_size
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn is_decompress(&self) -> bool {
+        self.decompress
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn is_auto_part_size(&self) -> bool {
+        self.auto_part_size
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn worker_count(&self) -> usize {
+        self.worker_count
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn is_encoding_type_url(&self) -> bool {
+        self.encoding_type_url
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn recorder(&self) -> Arc<dyn Metrics> {
+        self.metrics.clone()
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn default_headers(&self) -> &[(String, String)] {
+        &self.default_headers
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn credentials(&self) -> Arc<dyn CredentialProvider> {
+        self.credentials.clone()
+    }
+
+    #[cfg(feature = "tokio-async")]
+    pub(crate) fn client_config(&self) -> &ClientConfig {
+        &self.client_config
+    }
+
+    /// Send the request with the S3 client, and record it in the metrics
+    fn send(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: Payload,
+    ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error> {
+        let start = Instant::now();
+        let (result, sent) = match payload {
+            Payload::Bytes(payload) => (
+                self.s3_client
+                    .request(method, host, uri, query_strings, headers, payload),
+                payload.len() as u64,
+            ),
+            Payload::File(file, size) => (
+                self.s3_client
+                    .request_file(method, host, uri, query_strings, headers, file),
+                size,
+            ),
+        };
+        record_request(self.metrics.as_ref(), method, host, start, sent, &result);
+        result
+    }
+
+    /// Send the request, and re-sign it once if it is rejected for the skewed clock
+    fn send_with_retry(
+        &mut self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: Payload,
+    ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error> {
+        let (status_code, body, response_headers) =
+            self.send(method, host, uri, query_strings, headers, payload)?;
+        if status_code.is_client_error() {
+            let error = s3_error(status_code, &response_headers, &body);
+            if let Some(offset) = clock_skew(&error, &response_headers) {
+                warn!(
+                    "the clock is skewed {}ms from the server, re-sign the request",
+                    offset.num_milliseconds()
+                );
+                self.clock_offset = offset;
+                self.s3_client.update_clock_offset(offset);
+                self.metrics.on_retry(method);
+                return self.send(method, host, uri, query_strings, headers, payload);
+            }
+        }
+        Ok((status_code, body, response_headers))
+    }
+
     fn request(
         &mut self,
         method: &str,
         s3_object: &S3Object,
         qs: &[(&str, &str)],
-        headers: &mut Vec<(&str, &str)>,
+        headers: &[(&str, &str)],
         payload: &[u8],
     ) -> Result<(Vec<u8>, reqwest::header::HeaderMap), Error> {
+        self.request_payload(method, s3_object, qs, headers, Payload::Bytes(payload))
+    }
+
+    fn request_payload(
+        &mut self,
+        method: &str,
+        s3_object: &S3Object,
+        qs: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        payload: Payload,
+    ) -> Result<(Vec<u8>, reqwest::header::HeaderMap), Error> {
+        if self.dry_run && is_mutating(method) {
+            self.planned.push(Action::new(method, s3_object, qs));
+            return Ok((Vec::new(), reqwest::header::HeaderMap::new()));
+        }
         let mut query_strings = vec![];
-        if let Format::JSON = self.format {
-            query_strings.push(("format", "json"));
+        if let (Format::JSON, true) = (&self.format, self.format_query) {
+            if !qs.iter().any(|(k, _)| *k == "format") {
+                query_strings.push(("format", "json"));
+            }
         }
         query_strings.extend(qs.iter().cloned());
+        // The default headers are cloned, so the handler can be updated when retrying
+        let default_headers = self.default_headers.clone();
+        let mut headers = headers.to_vec();
+        headers.extend(
+            default_headers
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        );
+        let headers = &mut headers;
 
-        let (request_host, uri) = match self.url_style {
-            UrlStyle::HOST => s3_object.virtural_host_style_links(self.domain_name.to_string()),
+        let (mut request_host, uri) = match self.url_style {
+            UrlStyle::HOST => s3_object.virtural_host_style_links(self.virtual_host_domain()),
             UrlStyle::PATH => s3_object.path_style_links(self.domain_name.to_string()),
         };
 
+        // Go straight to the region of the bucket discovered from a previous redirect
+        let cached = s3_object
+            .bucket
+            .as_ref()
+            .and_then(|b| self.bucket_regions.get(b))
+            .cloned();
+        let origin_region = self.s3_client.current_region();
+        if let Some((region, host)) = cached.clone() {
+            self.s3_client.update(region, self.secure);
+            request_host = host;
+        }
+
         debug!("method: {}", method);
         debug!("request_host: {}", request_host);
         debug!("uri: {}", uri);
 
-        let (status_code, body, response_headers) = self.s3_client.request(
+        let result = self.send_with_retry(
             method,
             &request_host,
             &uri,
             &mut query_strings,
             headers,
             payload,
-        )?;
+        );
+        if let (Some(_), Some(region)) = (&cached, origin_region) {
+            self.s3_client.update(region, self.secure);
+        }
+        let (status_code, body, response_headers) = result?;
+        check_status(status_code, &response_headers, &body)?;
         match status_code.is_redirection() {
             true => {
-                self.region = Some(
+                self.region = Some(normalize_region(
                     response_headers["x-amz-bucket-region"]
                         .to_str()
-                        .unwrap_or("")
-                        .to_string(),
-                );
+                        .unwrap_or(""),
+                ));
                 // TODO: This should be better
                 // Change the region and request once
                 let origin_region = self.s3_client.current_region();
                 self.s3_client
                     .update(self.region.clone().unwrap(), self.secure);
-                let (_status_code, body, response_headers) = self.s3_client.request(
+                let redirect_host = self.s3_client.redirect_parser(body, self.format.clone())?;
+                if let Some(bucket) = &s3_object.bucket {
+                    self.bucket_regions.insert(
+                        bucket.to_string(),
+                        (self.region.clone().unwrap(), redirect_host.clone()),
+                    );
+                }
+                let result = self.send(
                     method,
-                    &self.s3_client.redirect_parser(body, self.format.clone())?,
+                    &redirect_host,
                     &uri,
                     &mut query_strings,
                     headers,
                     payload,
-                )?;
+                );
                 self.s3_client.update(origin_region.unwrap(), self.secure);
+                let (status_code, body, response_headers) = result?;
+                check_status(status_code, &response_headers, &body)?;
                 Ok((body, response_headers))
             }
             false => Ok((body, response_headers)),
         }
     }
-    fn next_marker_xml_parser(&self, body: &str) -> Option<String> {
-        // let result = std::str::from_utf8(body).unwrap_or("");
-        let mut reader = Reader::from_str(body);
-        let mut in_tag = false;
-        let mut buf = Vec::new();
-        let mut output = "".to_string();
-        loop {
-            match reader.read_event(&mut buf) {
-                Ok(Event::Start(ref e)) if e.name() == b"NextMarker" => {
-                    in_tag = true;
-                }
-                Ok(Event::End(ref _e)) => {}
-                Ok(Event::Text(e)) => {
-                    if in_tag {
-                        output = e.unescape_and_decode(&reader).unwrap();
-                        break;
-                    }
-                }
-                Ok(Event::Eof) => break,
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                _ => (),
+
+    /// Sign a request in the way it will be sent, and return the canonical request,
+    /// the string to sign and the signature without sending anything.
+    /// These can be compared with the ones in a `SignatureDoesNotMatch` error from the server.
+    /// ```
+    /// # let config = s3handler::CredentialConfig{
+    /// #     host: "s3.us-east-1.amazonaws.com".to_string(),
+    /// #     access_key: "akey".to_string(),
+    /// #     secret_key: "skey".to_string(),
+    /// #     user: None,
+    /// #     region: None,
+    /// #     s3_type: None,
+    /// #     secure: None,
+    /// #     part_size: None,
+    /// #     worker_count: None,
+    /// # };
+    /// let handler = s3handler::Handler::from(&config);
+    /// let debug = handler
+    ///     .debug_sign("GET", "s3://bucket/key", &[], &[("range", "bytes=0-9")], &[])
+    ///     .unwrap();
+    /// println!("{:?}\n{}", debug.canonical_request, debug.string_to_sign);
+    /// ```
+    pub fn debug_sign(
+        &self,
+        method: &str,
+        target: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        payload: &[u8],
+    ) -> Result<SignDebug, Error> {
+        let s3_object = S3Object::from(target);
+        let mut query_strings = vec![];
+        if let (Format::JSON, true) = (&self.format, self.format_query) {
+            if !query.iter().any(|(k, _)| *k == "format") {
+                query_strings.push(("format", "json"));
             }
-            buf.clear();
-        }
-        if output.is_empty() {
-            None
-        } else {
-            Some(output)
         }
+        query_strings.extend(query.iter().cloned());
+        let mut headers = headers.to_vec();
+        headers.extend(
+            self.default_headers
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        );
+        let (request_host, uri) = match self.url_style {
+            UrlStyle::HOST => s3_object.virtural_host_style_links(self.virtual_host_domain()),
+            UrlStyle::PATH => s3_object.path_style_links(self.domain_name.to_string()),
+        };
+        self.s3_client.debug_sign(
+            method,
+            &request_host,
+            &uri,
+            &mut query_strings,
+            &mut headers,
+            payload,
+            Utc::now() + self.clock_offset,
+        )
     }
 
-    /// List all objects in a bucket
+    /// List all objects of all buckets, the buckets are listed in parallel by `worker_count`
+    /// workers, and the error of the first bucket failed to list is returned, please use
+    /// `la_by_bucket` to keep the objects of the other buckets.
     pub fn la(&mut self) -> Result<Vec<S3Object>, Box<dyn std::error::Error>> {
         let mut output = Vec::new();
-        let content_re = Regex::new(RESPONSE_CONTENT_FORMAT).unwrap();
-        let next_marker_re = Regex::new(RESPONSE_MARKER_FORMAT).unwrap();
+        let mut failure = None;
+        for (bucket, listed) in self.la_by_bucket()? {
+            match listed {
+                Ok(objects) => output.extend(objects),
+                Err(e) => {
+                    error!("fail to list {}: {}", bucket, e);
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e.into()),
+            None => Ok(output),
+        }
+    }
+
+    /// List all objects of all buckets in parallel by `worker_count` workers, and return the
+    /// result of each bucket in the order of the bucket names.
+    #[allow(clippy::type_complexity)]
+    pub fn la_by_bucket(
+        &mut self,
+    ) -> Result<Vec<(String, Result<Vec<S3Object>, Error>)>, Box<dyn std::error::Error>> {
         let s3_object = S3Object::from("s3://");
         let res = &self
-            .request("GET", &s3_object, &Vec::new(), &mut Vec::new(), &Vec::new())?
+            .request("GET", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?
             .0;
         let mut buckets = Vec::new();
         match self.format {
             Format::JSON => {
-                let result: serde_json::Value = serde_json::from_slice(res).unwrap();
+                let result: serde_json::Value = serde_json::from_slice(res)?;
                 if let Some(bucket_list) = result[1].as_array() {
                     buckets.extend(
                         bucket_list
@@ -311,51 +1139,49 @@ impl Handler<'_> {
                 );
             }
         }
-        for bucket in buckets {
-            let s3_object = S3Object::from(format!("s3://{}", bucket).as_str());
-            let mut next_marker = Some("".to_string());
-            while next_marker.is_some() {
-                let body = &self
-                    .request(
-                        "GET",
-                        &s3_object,
-                        &[("marker", &next_marker.clone().unwrap())],
-                        &mut Vec::new(),
-                        &Vec::new(),
-                    )?
-                    .0;
+        buckets.sort();
 
-                match self.format {
-                    Format::JSON => {
-                        next_marker = next_marker_re
-                            .captures_iter(std::str::from_utf8(body).unwrap_or(""))
-                            .next()
-                            .map(|c| c[1].to_string());
-                        output.extend(
-                            content_re
-                                .captures_iter(std::str::from_utf8(body).unwrap_or(""))
-                                .map(|cap| {
-                                    S3Convert::new(
-                                        Some(bucket.clone()),
-                                        Some(cap[1].to_string()),
-                                        Some(cap[2].to_string()),
-                                        Some(cap[3].to_string()),
-                                        Some(cap[5].to_string()),
-                                        None, // TODO: test with cech
-                                    )
-                                }),
-                        );
-                    }
-                    Format::XML => {
-                        next_marker =
-                            self.next_marker_xml_parser(std::str::from_utf8(body).unwrap_or(""));
-                        output.extend(
-                            s3object_list_xml_parser(std::str::from_utf8(body).unwrap_or(""))?.0,
-                        );
+        let next = AtomicUsize::new(0);
+        let workers: Vec<HandlerOwned> = (0..cmp::min(self.worker_count, buckets.len()))
+            .map(|_| self.fork())
+            .collect();
+        let mut results: Vec<Result<Vec<S3Object>, Error>> = buckets
+            .iter()
+            .map(|_| Err(Error::RequestPoolError("the worker panicked".to_string())))
+            .collect();
+        thread::scope(|s| {
+            let handles: Vec<_> = workers
+                .into_iter()
+                .map(|mut worker| {
+                    let (buckets, next) = (&buckets, &next);
+                    s.spawn(move || {
+                        let mut listed = Vec::new();
+                        while let Some(bucket) = buckets.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            listed.push((bucket, worker.list_bucket(bucket)));
+                        }
+                        (worker.bucket_regions, listed)
+                    })
+                })
+                .collect();
+            for handle in handles {
+                if let Ok((regions, listed)) = handle.join() {
+                    self.bucket_regions.extend(regions);
+                    for (bucket, objects) in listed {
+                        if let Ok(i) = buckets.binary_search(bucket) {
+                            results[i] = objects;
+                        }
                     }
                 }
             }
-        }
+        });
+        Ok(buckets.into_iter().zip(results).collect())
+    }
+
+    /// List all objects in a bucket page by page
+    fn list_bucket(&mut self, bucket: &str) -> Result<Vec<S3Object>, Error> {
+        let mut output = Vec::new();
+        let s3_bucket = S3Object::from(format!("s3://{}", bucket).as_str());
+        self.list_pages(&s3_bucket, "", |o| output.push(o))?;
         Ok(output)
     }
 
@@ -363,101 +1189,273 @@ impl Handler<'_> {
     pub fn ls(
         &mut self,
         prefix: Option<&str>,
+    ) -> Result<Vec<S3Object>, Box<dyn std::error::Error>> {
+        self.ls_with(prefix, |_| true)
+    }
+
+    /// List the objects modified at or after the time, ex: for an incremental backup.
+    /// The keys are listed in lexicographic order, so every page is still requested,
+    /// but only the matching objects of each page are kept.
+    pub fn ls_since(
+        &mut self,
+        prefix: Option<&str>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<S3Object>, Box<dyn std::error::Error>> {
+        self.ls_with(prefix, |o| o.is_modified_since(&since))
+    }
+
+    /// List the objects of the bucket matching the glob, ex: `**/*.parquet`,
+    /// the literal part before the first wildcard is listed as the prefix by the server,
+    /// and only the matching objects of each page are kept.
+    pub fn ls_filtered(
+        &mut self,
+        bucket: &str,
+        glob: &Glob,
+    ) -> Result<Vec<S3Object>, Box<dyn std::error::Error>> {
+        let bucket = match S3Object::from(bucket).bucket {
+            Some(b) => b,
+            None => return Err(Error::UserError("please specific the bucket name").into()),
+        };
+        self.ls_with(Some(&format!("s3://{}/{}", bucket, glob.prefix())), |o| {
+            o.key.as_deref().is_none_or(|k| glob.is_match(k))
+        })
+    }
+
+    /// Summarize the count and the size of the objects under the prefix, ex: `s3://bucket/logs/`,
+    /// the pages of the listing are counted one by one without keeping the objects.
+    pub fn du(&mut self, prefix: &str) -> Result<DuSummary, Box<dyn std::error::Error>> {
+        self.du_with_delimiter(prefix, None)
+    }
+
+    /// Summarize the objects under the prefix and each sub-prefix split by the delimiter,
+    /// ex: `/` to summarize every folder under the prefix like `du -d1`
+    pub fn du_with_delimiter(
+        &mut self,
+        prefix: &str,
+        delimiter: Option<&str>,
+    ) -> Result<DuSummary, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(prefix);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        let key_prefix = s3_object.key.unwrap_or_default();
+        let key_prefix = key_prefix.strip_prefix('/').unwrap_or(&key_prefix);
+        let mut summary = DuSummary::default();
+        self.ls_each(Some(prefix), |o| summary.add(&o, key_prefix, delimiter))?;
+        Ok(summary)
+    }
+
+    fn ls_with(
+        &mut self,
+        prefix: Option<&str>,
+        keep: impl Fn(&S3Object) -> bool,
     ) -> Result<Vec<S3Object>, Box<dyn std::error::Error>> {
         let mut output = Vec::new();
-        let mut res: String;
+        self.ls_each(prefix, |o| {
+            if keep(&o) {
+                output.push(o);
+            }
+        })?;
+        Ok(output)
+    }
+
+    /// Hand over the listed objects page by page, such that the objects are not collected
+    fn ls_each(
+        &mut self,
+        prefix: Option<&str>,
+        mut each: impl FnMut(S3Object),
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let s3_object = S3Object::from(prefix.unwrap_or("s3://"));
         let s3_bucket = S3Object::new(s3_object.bucket, None, None, None, None, None);
         match s3_bucket.bucket.clone() {
-            Some(b) => {
-                let re = Regex::new(RESPONSE_CONTENT_FORMAT).unwrap();
-                let next_marker_re = Regex::new(RESPONSE_MARKER_FORMAT).unwrap();
-                let mut next_marker = Some("".to_string());
-                while next_marker.is_some() {
-                    res = std::str::from_utf8(
-                        &self
-                            .request(
-                                "GET",
-                                &s3_bucket,
-                                &[
-                                    (
-                                        "prefix",
-                                        &s3_object.key.clone().unwrap_or_else(|| "/".to_string())
-                                            [1..],
-                                    ),
-                                    ("marker", &next_marker.clone().unwrap()),
-                                ],
-                                &mut Vec::new(),
-                                &Vec::new(),
-                            )?
-                            .0,
-                    )
-                    .unwrap_or("")
-                    .to_string();
-                    match self.format {
-                        Format::JSON => {
-                            next_marker_re
-                                .captures_iter(&res)
-                                .next()
-                                .map(|c| c[1].to_string());
-                            output.extend(re.captures_iter(&res).map(|cap| {
-                                S3Convert::new(
-                                    Some(b.to_string()),
-                                    Some(cap[1].to_string()),
-                                    Some(cap[2].to_string()),
-                                    Some(cap[3].to_string()),
-                                    Some(cap[5].to_string()),
-                                    None, // TODO: test with ceph server
-                                )
-                            }));
-                        }
-                        Format::XML => {
-                            next_marker = self.next_marker_xml_parser(&res);
-                            output.extend(s3object_list_xml_parser(&res)?.0);
-                        }
-                    }
-                }
+            Some(_) => {
+                let key_prefix = s3_object.key.unwrap_or_else(|| "/".to_string())[1..].to_string();
+                self.list_pages(&s3_bucket, &key_prefix, &mut each)?;
             }
             None => {
                 let s3_object = S3Object::from("s3://");
                 let body = &self
-                    .request("GET", &s3_object, &Vec::new(), &mut Vec::new(), &Vec::new())?
+                    .request("GET", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?
                     .0;
                 match self.format {
                     Format::JSON => {
-                        let result: serde_json::Value =
-                            serde_json::from_str(std::str::from_utf8(body).unwrap_or("")).unwrap();
+                        let result: serde_json::Value = serde_json::from_slice(body)?;
                         if let Some(bucket_list) = result[1].as_array() {
-                            output.extend(bucket_list.iter().map(|b| {
-                                S3Convert::new(
-                                    Some(b["Name"].as_str().unwrap().to_string()),
-                                    None,
-                                    None,
-                                    None,
-                                    None,
-                                    None,
-                                )
-                            }))
+                            bucket_list
+                                .iter()
+                                .map(|b| {
+                                    S3Convert::new(
+                                        Some(b["Name"].as_str().unwrap().to_string()),
+                                        None,
+                                        None,
+                                        None,
+                                        None,
+                                        None,
+                                    )
+                                })
+                                .for_each(&mut each)
                         };
                     }
                     Format::XML => {
-                        output.extend(
-                            s3object_list_xml_parser(std::str::from_utf8(body).unwrap_or(""))?.0,
-                        );
+                        s3object_list_xml_parser(std::str::from_utf8(body).unwrap_or(""))?
+                            .0
+                            .into_iter()
+                            .for_each(&mut each);
                     }
                 }
             }
         };
-        Ok(output)
+        Ok(())
+    }
+
+    /// Request the pages of the objects under the key prefix of the bucket, a truncated page is
+    /// continued by the `NextMarker`, or the last key when the `NextMarker` is left out, and the
+    /// listing fails rather than stopping early if the marker does not advance.
+    fn list_pages(
+        &mut self,
+        s3_bucket: &S3Object,
+        key_prefix: &str,
+        mut each: impl FnMut(S3Object),
+    ) -> Result<(), Error> {
+        let mut next_marker = Some("".to_string());
+        while let Some(marker) = next_marker.clone() {
+            let mut query = vec![("prefix", key_prefix), ("marker", &marker)];
+            if self.encoding_type_url {
+                query.push(("encoding-type", "url"));
+            }
+            let body = self
+                .request("GET", s3_bucket, &query, &Vec::new(), &Vec::new())?
+                .0;
+            match self.format {
+                Format::JSON => {
+                    let (page, truncated, marker) = s3object_list_json_parser(&body)?;
+                    if truncated {
+                        // The truncated page without a marker fails as the one not advancing
+                        let marker = marker.or_else(|| next_marker.clone());
+                        advance_marker(&mut next_marker, marker)?;
+                    } else {
+                        next_marker = None;
+                    }
+                    page.into_iter().for_each(&mut each);
+                }
+                Format::XML => {
+                    let res = std::str::from_utf8(&body).unwrap_or("");
+                    let (page, truncated) = xml_list_page(res, &mut next_marker)?;
+                    if !truncated {
+                        next_marker = None;
+                    }
+                    page.into_iter().for_each(&mut each);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// List all bucket of an account or List all object of an bucket, and tell the listing is
+    /// complete or not.  The objects listed before the pagination stops are kept in the `Listing`.
+    pub fn ls_complete(
+        &mut self,
+        prefix: Option<&str>,
+    ) -> Result<Listing, Box<dyn std::error::Error>> {
+        self.ls_with_options(prefix, &ListOptions::default())
+    }
+
+    /// List the objects of a bucket in the budget of the options, ex: the first 200 keys,
+    /// the pages are not requested after the limit is reached.
+    pub fn ls_with_options(
+        &mut self,
+        prefix: Option<&str>,
+        options: &ListOptions,
+    ) -> Result<Listing, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(prefix.unwrap_or("s3://"));
+        let s3_bucket = S3Object::new(s3_object.bucket, None, None, None, None, None);
+        if s3_bucket.bucket.is_none() {
+            return Ok(Listing {
+                objects: self.ls(None)?,
+                complete: true,
+            });
+        }
+        let key_prefix = s3_object.key.unwrap_or_else(|| "/".to_string())[1..].to_string();
+        let mut objects = Vec::new();
+        let mut marker = String::new();
+        let mut requests = 0;
+        // The bucket of each page is listed without a key, and it is not counted in the limit
+        let mut listed = 0;
+        let complete = loop {
+            if options.max_requests.is_some_and(|m| requests >= m) {
+                break false;
+            }
+            requests += 1;
+            let mut query = vec![("prefix", key_prefix.as_str()), ("marker", &marker)];
+            if self.encoding_type_url {
+                query.push(("encoding-type", "url"));
+            }
+            let body = match self.request("GET", &s3_bucket, &query, &Vec::new(), &Vec::new()) {
+                Ok((body, _)) => body,
+                Err(e) if !marker.is_empty() => {
+                    warn!("listing stops after marker {}: {}", marker, e);
+                    break false;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let res = std::str::from_utf8(&body).unwrap_or("");
+            let (page, truncated, next_marker) = match self.format {
+                Format::JSON => s3object_list_json_parser(&body)?,
+                Format::XML => {
+                    let (page, truncated) = s3object_list_xml_parser(res)?;
+                    let next_marker = xml_next_marker(res, &page)?;
+                    (page, truncated, next_marker)
+                }
+            };
+            listed += page.iter().filter(|o| o.key.is_some()).count();
+            objects.extend(page);
+            let next_marker = match (truncated, next_marker) {
+                (false, _) => break true,
+                (true, Some(m)) if m != marker => m,
+                (true, _) => {
+                    warn!("listing is truncated without a marker to continue");
+                    break false;
+                }
+            };
+            if options.limit.is_some_and(|l| listed >= l) {
+                break false;
+            }
+            marker = next_marker;
+        };
+        // The last page is trimmed to the limit
+        let complete = complete && options.limit.is_none_or(|l| listed <= l);
+        if let Some(limit) = options.limit {
+            let mut kept = 0;
+            objects.retain(|o| {
+                kept += o.key.is_some() as usize;
+                o.key.is_none() || kept <= limit
+            });
+        }
+        Ok(Listing { objects, complete })
+    }
+
+    /// Parse the upload id of the initiated multipart upload in the format of the handler
+    fn upload_id(&self, res: &str) -> Result<String, Error> {
+        match self.format {
+            Format::JSON => Regex::new(r#""UploadId":"(?P<upload_id>[^"]+)""#)
+                .unwrap()
+                .captures(res)
+                .map(|caps| caps["upload_id"].to_string())
+                .ok_or(Error::FieldNotFound("UploadId")),
+            Format::XML => upload_id_xml_parser(res),
+        }
     }
 
     fn multipart_uplodad(
         &mut self,
-        file: &str,
+        mut fin: File,
         file_size: u64,
         s3_object: S3Object,
         headers: Vec<(&str, &str)>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.part_size == 0 {
+            return Err(Error::UserError("part size should be larger than 0").into());
+        }
         let total_part_number = (file_size / self.part_size + 1) as usize;
         debug!("upload file in {} parts", total_part_number);
         let res = std::str::from_utf8(
@@ -466,52 +1464,45 @@ impl Handler<'_> {
                     "POST",
                     &s3_object,
                     &[("uploads", "")],
-                    &mut headers.clone(),
+                    &headers.clone(),
                     &Vec::new(),
                 )?
                 .0,
         )
         .unwrap_or("")
         .to_string();
-        let upload_id = match self.format {
-            Format::JSON => {
-                let re = Regex::new(r#""UploadId":"(?P<upload_id>[^"]+)""#).unwrap();
-                let caps = re.captures(&res).expect("Upload ID missing");
-                caps["upload_id"].to_string()
-            }
-            Format::XML => upload_id_xml_parser(&res)?,
-        };
+        let upload_id = self.upload_id(&res)?;
 
         info!("upload id: {}", upload_id);
 
         let mut part = 0usize;
-        let mut fin = File::open(file)?;
-        // Once we have retry mechanism in workers, we can make this bigger
-        // Magic number, I do not tune on this currently
-        let worker_number = cmp::min(10, total_part_number);
+        let worker_number = cmp::min(self.worker_count, total_part_number);
         info!(
             "{} part and {} workers to upload",
             total_part_number, worker_number
         );
         let (host, uri) = match self.url_style {
-            UrlStyle::HOST => s3_object.virtural_host_style_links(self.domain_name.to_string()),
+            UrlStyle::HOST => s3_object.virtural_host_style_links(self.virtual_host_domain()),
             UrlStyle::PATH => s3_object.path_style_links(self.domain_name.to_string()),
         };
         let mut rp = UploadRequestPool::new(
             self.auth_type,
             self.secure,
-            self.access_key.to_string(),
-            self.secret_key.to_string(),
+            self.credentials.clone(),
             host,
             uri,
-            self.region.clone().unwrap_or_else(|| "".to_string()),
+            self.region.clone().unwrap_or_default(),
             upload_id.clone(),
             worker_number,
+            self.clock_offset,
+            self.http.clone(),
+            self.default_headers.clone(),
+            self.metrics.clone(),
         );
         loop {
             part += 1;
 
-            let mut buffer = vec![0; self.part_size as usize];
+            let mut buffer = vec![0; buffer_size(self.part_size)?];
             let mut tail_buffer = Vec::new();
             if part == total_part_number {
                 fin.read_to_end(&mut tail_buffer)?;
@@ -536,11 +1527,16 @@ impl Handler<'_> {
         }
 
         let content = rp.wait()?;
+        // the object is tagged when the upload is initiated
+        let headers: Vec<(&str, &str)> = headers
+            .into_iter()
+            .filter(|(k, _)| *k != TAGGING_HEADER)
+            .collect();
         let _ = self.request(
             "POST",
             &s3_object,
             &[("uploadId", upload_id.as_str())],
-            &mut headers.clone(),
+            &headers,
             &content.into_bytes(),
         )?;
         info!("complete multipart");
@@ -549,6 +1545,87 @@ impl Handler<'_> {
 
     /// Upload a file to a S3 bucket
     pub fn put(&mut self, file: &str, dest: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.put_with_options(file, dest, &PutOptions::default())
+    }
+
+    /// Upload the files in the directory recursively under the prefix, ex: `s3://bucket/backup/`,
+    /// the symbolic links are handled by the policy, and the result of each key is returned,
+    /// such that a dangling link or an unreadable file does not abort the others.
+    #[allow(clippy::type_complexity)]
+    pub fn put_dir(
+        &mut self,
+        dir: &str,
+        dest: &str,
+        policy: SymlinkPolicy,
+    ) -> Result<Vec<(String, Result<(), Box<dyn std::error::Error>>)>, Box<dyn std::error::Error>>
+    {
+        self.put_dir_filtered(dir, dest, policy, &Rules::default())
+    }
+
+    /// Upload the files in the directory kept by the include and exclude rules, the paths
+    /// relative to the directory are checked, ex: `Rules::default().exclude("*.tmp")`
+    #[allow(clippy::type_complexity)]
+    pub fn put_dir_filtered(
+        &mut self,
+        dir: &str,
+        dest: &str,
+        policy: SymlinkPolicy,
+        rules: &Rules,
+    ) -> Result<Vec<(String, Result<(), Box<dyn std::error::Error>>)>, Box<dyn std::error::Error>>
+    {
+        let s3_object = S3Object::from(dest);
+        let bucket = match s3_object.bucket {
+            Some(b) => b,
+            None => return Err(Error::UserError("please specific the bucket name").into()),
+        };
+        let prefix = s3_object.key.unwrap_or_default();
+        let prefix = prefix.trim_matches('/');
+        let root = Path::new(dir);
+        let mut results = Vec::new();
+        for (path, entry) in walk_dir(root, policy)? {
+            let relative: Vec<String> = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            if !rules.is_included(&relative.join("/")) {
+                continue;
+            }
+            let key = if prefix.is_empty() {
+                format!("s3://{}/{}", bucket, relative.join("/"))
+            } else {
+                format!("s3://{}/{}/{}", bucket, prefix, relative.join("/"))
+            };
+            let result = match entry {
+                Ok(LocalEntry::File(file)) => self.put(&file.to_string_lossy(), &key),
+                Ok(LocalEntry::Symlink(target)) => self
+                    .request(
+                        "PUT",
+                        &S3Object::from(key.as_str()),
+                        &[],
+                        &[(SYMLINK_HEADER, &target.to_string_lossy())],
+                        &[],
+                    )
+                    .map(|_| ())
+                    .map_err(|e| e.into()),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = &result {
+                error!("fail to upload {}: {}", path.display(), e);
+            }
+            results.push((key, result));
+        }
+        Ok(results)
+    }
+
+    /// Upload a file to a S3 bucket with the tags
+    pub fn put_with_options(
+        &mut self,
+        file: &str,
+        dest: &str,
+        options: &PutOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         // TODO: handle XCOPY
         if file.is_empty() || dest.is_empty() {
             return Err(Error::UserError("please specify the file and the destiney").into());
@@ -556,54 +1633,70 @@ impl Handler<'_> {
 
         let mut s3_object = S3Object::from(dest);
 
-        let mut content: Vec<u8>;
-
-        let gusess_mime = from_path(Path::new(file)).first_raw();
-        let mut headers = if let Some(mime) = gusess_mime {
+        let mime = match &options.content_type {
+            ContentType::Guess => from_path(Path::new(file)).first_raw(),
+            ContentType::Fixed(mime) => Some(mime.as_str()),
+            ContentType::Omit => None,
+        };
+        let mut headers = if let Some(mime) = mime {
             vec![(reqwest::header::CONTENT_TYPE.as_str(), mime)]
         } else {
             Vec::new()
         };
+        let tags: Vec<(&str, &str)> = options
+            .tags
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let tagging = tagging_header(&tags);
+        if !tags.is_empty() {
+            headers.push((TAGGING_HEADER, tagging.as_str()));
+        }
 
         if s3_object.key.is_none() {
             let file_name = Path::new(file).file_name().unwrap().to_string_lossy();
             s3_object.key = Some(format!("/{}", file_name));
         }
 
-        if !Path::new(file).exists() && file == "test" {
-            // TODO: add time info in the test file
-            content = vec![83, 51, 82, 83, 32, 116, 101, 115, 116, 10]; // S3RS test/n
-            let _ = self.request(
+        // the size is taken from the opened file, such that it is the file uploaded
+        let fin = File::open(file).map_err(Error::LoadError)?;
+        let file_meta = fin.metadata().map_err(Error::LoadError)?;
+        let file_size = file_meta.len();
+        let mtime_header = format!("{}{}", METADATA_PREFIX, MTIME_METADATA);
+        let mtime;
+        if self.preserve_mtime {
+            mtime = mtime_metadata(&file_meta.modified().map_err(Error::LoadError)?.into());
+            headers.push((mtime_header.as_str(), mtime.as_str()));
+        }
+        debug!("upload file size: {}", file_size);
+        if self.dry_run {
+            self.planned.push(Action::new("PUT", &s3_object, &[]));
+        } else if file_size > self.part_size {
+            self.multipart_uplodad(fin, file_size, s3_object, headers)?;
+        } else {
+            let _ = self.request_payload(
                 "PUT",
                 &s3_object,
-                &Vec::new(),
-                &mut vec![(reqwest::header::CONTENT_TYPE.as_str(), "text/plain")],
-                &content,
-            );
-        } else {
-            let file_size = match metadata(Path::new(file)) {
-                Ok(m) => m.len(),
-                Err(e) => {
-                    error!("file meta error: {}", e);
-                    0
-                }
-            };
-
-            debug!("upload file size: {}", file_size);
-            if file_size > self.part_size {
-                self.multipart_uplodad(file, file_size, s3_object, headers)?;
-            } else {
-                content = Vec::new();
-                let mut fin = File::open(file)?;
-                let _ = fin.read_to_end(&mut content);
-                let _ = self.request("PUT", &s3_object, &Vec::new(), &mut headers, &content)?;
-            };
-        }
+                &[],
+                &headers,
+                Payload::File(&fin, file_size),
+            )?;
+        };
         Ok(())
     }
 
     /// Download an object from S3 service
     pub fn get(&mut self, src: &str, file: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        self.get_with_options(src, file, &GetOptions::default())
+    }
+
+    /// Download an object from S3 service with the response headers overridden
+    pub fn get_with_options(
+        &mut self,
+        src: &str,
+        file: Option<&str>,
+        options: &GetOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let s3_object = S3Object::from(src);
         if s3_object.key.is_none() {
             return Err(Error::UserError("Please specific the object").into());
@@ -617,130 +1710,631 @@ impl Handler<'_> {
                 .to_str()
                 .unwrap_or("s3download"),
         };
+        // there is no content in the marker of a folder, only the folder is created
+        if s3_object.is_dir_marker() {
+            std::fs::create_dir_all(fout)?;
+            return Ok(());
+        }
         // TODO fetch size then multipart
         let headers = self
-            .request(
-                "HEAD",
-                &s3_object,
-                &Vec::new(),
-                &mut Vec::new(),
-                &Vec::new(),
-            )?
+            .request("HEAD", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?
             .1;
-        let size = if headers.contains_key(reqwest::header::CONTENT_LENGTH) {
-            headers[reqwest::header::CONTENT_LENGTH]
-                .to_str()?
-                .parse::<u64>()
-                .unwrap_or_default()
+        let expected_size = match headers.get(reqwest::header::CONTENT_LENGTH) {
+            Some(length) => length.to_str()?.parse::<u64>().ok(),
+            None => None,
+        };
+        let size = expected_size.unwrap_or_default();
+        let capacity = buffer_size(size)?;
+        let part_size = if self.auto_part_size {
+            auto_part_size(size, self.worker_count)
         } else {
-            0
+            self.part_size
         };
 
-        let data = if size > 0 && size > self.part_size {
-            let total_part_number = (size / self.part_size + 1) as usize;
-            let worker_number = cmp::min(10, total_part_number);
-            let (host, uri) = match self.url_style {
-                UrlStyle::HOST => s3_object.virtural_host_style_links(self.domain_name.to_string()),
-                UrlStyle::PATH => s3_object.path_style_links(self.domain_name.to_string()),
-            };
-            let mut dp = DownloadRequestPool::new(
-                self.auth_type,
-                self.secure,
-                self.access_key.to_string(),
-                self.secret_key.to_string(),
-                host,
-                uri,
-                self.region.clone().unwrap_or_else(|| "".to_string()),
-                size as usize,
-                worker_number,
-            );
-            let mut part = 0;
-            while part * self.part_size < size {
-                let end = cmp::min(size, (part + 1) * self.part_size) as usize;
-                let start = (part * self.part_size) as usize;
-                dp.run(MultiDownloadParameters(start, end));
-                part += 1;
+        let data = if size > 0 && size > part_size {
+            let parts = download_parts(size, part_size)?;
+            let worker_number = cmp::min(self.worker_count, parts.len());
+            let mut dp = self.download_pool(&s3_object, capacity, worker_number);
+            for p in parts {
+                dp.run(p);
             }
             dp.wait()?
         } else {
-            self.request("GET", &s3_object, &Vec::new(), &mut Vec::new(), &Vec::new())?
-                .0
+            self.request(
+                "GET",
+                &s3_object,
+                &options.query_strings(),
+                &Vec::new(),
+                &Vec::new(),
+            )?
+            .0
+        };
+        // A truncated transfer should not be written as a short file silently
+        if let Some(expected) = expected_size {
+            if data.len() as u64 != expected {
+                return Err(Error::IntegrityError {
+                    expected,
+                    received: data.len() as u64,
+                }
+                .into());
+            }
+        }
+        let data = if self.decompress
+            && is_gzip(
+                headers
+                    .get(reqwest::header::CONTENT_ENCODING)
+                    .and_then(|v| v.to_str().ok()),
+            ) {
+            gunzip(&data)?
+        } else {
+            data
         };
         write(fout, data)?;
+        if self.preserve_mtime {
+            let object = S3Object {
+                mtime: headers
+                    .get(reqwest::header::LAST_MODIFIED)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string()),
+                metadata: user_metadata(&headers),
+                ..Default::default()
+            };
+            if let Some(mtime) = object.modified_time() {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(fout)?
+                    .set_modified(mtime.into())?;
+            }
+        }
 
         Ok(())
     }
 
-    /// Show the content and the content type of an object
-    pub fn cat(
+    /// Stream an object to a writer without a file, ex: stdout or a pipe,
+    /// the parts are downloaded in parallel and written in order with a small window of
+    /// `worker_count` parts buffered, and the content is written as it is stored.
+    /// ```no_run
+    /// # let config = s3handler::CredentialConfig {
+    /// #     host: "s3.us-east-1.amazonaws.com".to_string(),
+    /// #     access_key: "akey".to_string(),
+    /// #     secret_key: "skey".to_string(),
+    /// #     user: None,
+    /// #     region: None,
+    /// #     s3_type: None,
+    /// #     secure: None,
+    /// #     part_size: None,
+    /// #     worker_count: None,
+    /// # };
+    /// # let mut handler = s3handler::Handler::from(&config);
+    /// handler.pipe("s3://bucket/logs.gz", &mut std::io::stdout()).unwrap();
+    /// ```
+    pub fn pipe(
         &mut self,
         src: &str,
-    ) -> Result<(String, Option<String>), Box<dyn std::error::Error>> {
+        out: &mut dyn Write,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let s3_object = S3Object::from(src);
         if s3_object.key.is_none() {
             return Err(Error::UserError("Please specific the object").into());
         }
-        let (output, content_type) = self
-            .request("GET", &s3_object, &Vec::new(), &mut Vec::new(), &Vec::new())
-            .map(|r| {
-                (
-                    std::str::from_utf8(&r.0).unwrap_or("").to_string(),
-                    r.1.get(reqwest::header::CONTENT_TYPE)
-                        .and_then(|v| std::str::from_utf8(v.as_bytes()).ok())
-                        .map(|s| s.to_string()),
-                )
-            })?;
-        Ok((output, content_type))
-    }
+        let headers = self
+            .request("HEAD", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?
+            .1;
+        let expected_size = match headers.get(reqwest::header::CONTENT_LENGTH) {
+            Some(length) => length.to_str()?.parse::<u64>().ok(),
+            None => None,
+        };
+        let size = expected_size.unwrap_or_default();
+        let part_size = if self.auto_part_size {
+            auto_part_size(size, self.worker_count)
+        } else {
+            self.part_size
+        };
 
-    /// Delete with header flags for some deletion features
-    /// - AWS - delete-marker
-    /// - Bigtera - secure-delete
-    pub fn del_with_flag(
-        &mut self,
-        src: &str,
-        headers: &mut Vec<(&str, &str)>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        debug!("headers: {:?}", headers);
-        let s3_object = S3Object::from(src);
-        if s3_object.key.is_none() {
-            return Err(Error::UserError("Please specific the object").into());
+        let received = if size > 0 && size > part_size {
+            let parts = download_parts(size, part_size)?;
+            let worker_number = cmp::min(self.worker_count, parts.len());
+            self.download_pool(&s3_object, 0, worker_number)
+                .pipe(parts, worker_number, out)?
+        } else {
+            let data = self
+                .request("GET", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?
+                .0;
+            out.write_all(&data)?;
+            out.flush()?;
+            data.len() as u64
+        };
+        if let Some(expected) = expected_size {
+            if received != expected {
+                return Err(Error::IntegrityError { expected, received }.into());
+            }
         }
-        self.request("DELETE", &s3_object, &Vec::new(), headers, &Vec::new())?;
         Ok(())
     }
 
-    /// Delete an object
-    pub fn del(&mut self, src: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.del_with_flag(src, &mut Vec::new())
+    fn download_pool(
+        &self,
+        s3_object: &S3Object,
+        capacity: usize,
+        worker_number: usize,
+    ) -> DownloadRequestPool {
+        let (host, uri) = match self.url_style {
+            UrlStyle::HOST => s3_object.virtural_host_style_links(self.virtual_host_domain()),
+            UrlStyle::PATH => s3_object.path_style_links(self.domain_name.to_string()),
+        };
+        DownloadRequestPool::new(
+            self.auth_type,
+            self.secure,
+            self.credentials.clone(),
+            host,
+            uri,
+            self.region.clone().unwrap_or_default(),
+            capacity,
+            worker_number,
+            self.clock_offset,
+            self.http.clone(),
+            self.default_headers.clone(),
+            self.metrics.clone(),
+        )
     }
 
-    /// Make a new bucket
-    pub fn mb(&mut self, bucket: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let s3_object = S3Object::from(bucket);
-        if s3_object.bucket.is_none() {
-            return Err(Error::UserError("please specific the bucket name").into());
-        }
-        self.request("PUT", &s3_object, &Vec::new(), &mut Vec::new(), &Vec::new())?;
-        Ok(())
+    /// Copy an object in S3 service, the object is copied by server without download
+    pub fn cp(&mut self, src: &str, dest: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.cp_with_options(src, dest, &CopyOptions::default())
     }
 
-    /// Remove a bucket
-    pub fn rb(&mut self, bucket: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let s3_object = S3Object::from(bucket);
-        if s3_object.bucket.is_none() {
-            return Err(Error::UserError("please specific the bucket name").into());
-        }
+    /// Copy an object in S3 service only if the conditions in `CopyOptions` are met
+    pub fn cp_with_options(
+        &mut self,
+        src: &str,
+        dest: &str,
+        options: &CopyOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let src_object = S3Object::from(src);
+        let mut dest_object = S3Object::from(dest);
+        let (src_bucket, src_key) = match (src_object.bucket.clone(), src_object.key.clone()) {
+            (Some(b), Some(k)) => (b, k),
+            _ => return Err(Error::UserError("Please specific the source object").into()),
+        };
+        if dest_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the destination bucket").into());
+        }
+        if dest_object.key.is_none() {
+            dest_object.key = Some(src_key.clone());
+        }
+
+        let copy_source = url_encode(&format!("/{}{}", src_bucket, src_key));
+        let mut headers = vec![("x-amz-copy-source", copy_source.as_str())];
+        if let Some(etag) = &options.copy_source_if_match {
+            headers.push(("x-amz-copy-source-if-match", etag));
+        }
+        if let Some(time) = &options.copy_source_if_modified_since {
+            headers.push(("x-amz-copy-source-if-modified-since", time));
+        }
+        let tagging = options.tags.as_ref().map(|tags| {
+            tagging_header(
+                &tags
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+        });
+        let mut put_headers = headers.clone();
+        if let Some(tagging) = &tagging {
+            put_headers.push(("x-amz-tagging-directive", "REPLACE"));
+            put_headers.push((TAGGING_HEADER, tagging));
+        }
+        match self.request("PUT", &dest_object, &Vec::new(), &put_headers, &Vec::new()) {
+            Ok(_) => Ok(()),
+            // The source larger than 5GB is refused by the single copy, and copied in parts
+            Err(e)
+                if matches!(&e, Error::S3Error { status: 400, code, .. }
+                    if code == "InvalidRequest" || code == "EntityTooLarge") =>
+            {
+                let src_headers = self
+                    .request("HEAD", &src_object, &Vec::new(), &Vec::new(), &Vec::new())?
+                    .1;
+                let size = src_headers
+                    .get(reqwest::header::CONTENT_LENGTH)
+                    .and_then(|l| l.to_str().ok()?.parse::<u64>().ok())
+                    .unwrap_or_default();
+                if size <= MAX_COPY_SIZE {
+                    return Err(e.into());
+                }
+                self.multipart_copy(
+                    &dest_object,
+                    headers,
+                    &src_headers,
+                    tagging.as_deref(),
+                    size,
+                )
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Copy the object in parts with `x-amz-copy-source-range`, the parts are copied by server,
+    /// and the upload is aborted if any part fails.
+    fn multipart_copy(
+        &mut self,
+        dest_object: &S3Object,
+        headers: Vec<(&str, &str)>,
+        src_headers: &reqwest::header::HeaderMap,
+        tagging: Option<&str>,
+        size: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let part_size = cmp::max(
+            auto_part_size(size, self.worker_count),
+            size.div_ceil(MAX_PART_COUNT),
+        );
+        // The content type and the metadata are copied by server only in the single copy
+        let mut initiate_headers: Vec<(&str, &str)> = src_headers
+            .iter()
+            .filter(|(k, _)| {
+                *k == reqwest::header::CONTENT_TYPE || k.as_str().starts_with(METADATA_PREFIX)
+            })
+            .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?)))
+            .collect();
+        if let Some(tagging) = tagging {
+            initiate_headers.push((TAGGING_HEADER, tagging));
+        }
+        let res = self
+            .request(
+                "POST",
+                dest_object,
+                &[("uploads", "")],
+                &initiate_headers,
+                &Vec::new(),
+            )?
+            .0;
+        let upload_id = self.upload_id(std::str::from_utf8(&res).unwrap_or(""))?;
+        info!(
+            "copy in parts of {} bytes, upload id: {}",
+            part_size, upload_id
+        );
+
+        let mut parts = Vec::new();
+        let mut start = 0;
+        while start < size {
+            let end = cmp::min(size, start + part_size) - 1;
+            let part_number = (parts.len() + 1).to_string();
+            let range = format!("bytes={}-{}", start, end);
+            let mut part_headers = headers.clone();
+            part_headers.push(("x-amz-copy-source-range", range.as_str()));
+            let etag = self
+                .request(
+                    "PUT",
+                    dest_object,
+                    &[("partNumber", &part_number), ("uploadId", &upload_id)],
+                    &part_headers,
+                    &Vec::new(),
+                )
+                .and_then(|r| copy_part_etag_xml_parser(std::str::from_utf8(&r.0).unwrap_or("")));
+            match etag {
+                Ok(etag) => parts.push((parts.len() + 1, etag)),
+                Err(e) => {
+                    let _ = self.request(
+                        "DELETE",
+                        dest_object,
+                        &[("uploadId", &upload_id)],
+                        &Vec::new(),
+                        &Vec::new(),
+                    );
+                    return Err(e.into());
+                }
+            }
+            start = end + 1;
+        }
         self.request(
-            "DELETE",
-            &s3_object,
-            &Vec::new(),
-            &mut Vec::new(),
+            "POST",
+            dest_object,
+            &[("uploadId", &upload_id)],
             &Vec::new(),
+            complete_multipart_xml(&parts).as_bytes(),
         )?;
         Ok(())
     }
 
+    /// Show the content and the content type of an object
+    pub fn cat(
+        &mut self,
+        src: &str,
+    ) -> Result<(String, Option<String>), Box<dyn std::error::Error>> {
+        self.cat_with_options(src, &GetOptions::default())
+    }
+
+    /// Show the content and the content type of an object with the response headers overridden
+    pub fn cat_with_options(
+        &mut self,
+        src: &str,
+        options: &GetOptions,
+    ) -> Result<(String, Option<String>), Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(src);
+        if s3_object.key.is_none() {
+            return Err(Error::UserError("Please specific the object").into());
+        }
+        let (output, content_type) = self
+            .request(
+                "GET",
+                &s3_object,
+                &options.query_strings(),
+                &Vec::new(),
+                &Vec::new(),
+            )
+            .map(|r| {
+                (
+                    std::str::from_utf8(&r.0).unwrap_or("").to_string(),
+                    r.1.get(reqwest::header::CONTENT_TYPE)
+                        .and_then(|v| std::str::from_utf8(v.as_bytes()).ok())
+                        .map(|s| s.to_string()),
+                )
+            })?;
+        Ok((output, content_type))
+    }
+
+    /// Delete with header flags for some deletion features
+    /// - AWS - delete-marker
+    /// - Bigtera - secure-delete
+    ///
+    /// The object is deleted only if its ETag matches `if_match`, else `Error::PreconditionFailed`,
+    /// the header is ignored by the gateways without conditional deletes.
+    pub fn del_with_flag<'a>(
+        &mut self,
+        src: &str,
+        headers: &mut Vec<(&'a str, &'a str)>,
+        if_match: Option<&'a str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        debug!("headers: {:?}", headers);
+        let s3_object = S3Object::from(src);
+        if s3_object.key.is_none() {
+            return Err(Error::UserError("Please specific the object").into());
+        }
+        if let Some(etag) = if_match {
+            headers.push(("If-Match", etag));
+        }
+        self.request("DELETE", &s3_object, &Vec::new(), headers, &Vec::new())?;
+        Ok(())
+    }
+
+    /// Delete an object
+    pub fn del(&mut self, src: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.del_with_flag(src, &mut Vec::new(), None)
+    }
+
+    /// Delete the objects under the prefix with the multi-object delete API, ex: `s3://bucket/logs/`,
+    /// and return the number of the objects removed
+    pub fn del_prefix(&mut self, prefix: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(prefix);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        let s3_bucket = S3Object::new(s3_object.bucket, None, None, None, None, None);
+        let mut attempted = HashSet::new();
+        let mut removed = 0;
+        // The prefix is listed again after each round until no key is left to delete, the keys
+        // failed to delete are not retried
+        loop {
+            let keys: Vec<String> = self
+                .ls(Some(prefix))?
+                .into_iter()
+                .filter_map(|o| o.key)
+                .filter(|k| attempted.insert(k.clone()))
+                .collect();
+            if keys.is_empty() {
+                break;
+            }
+            for batch in keys.chunks(DELETE_BATCH_SIZE) {
+                let content = delete_objects_xml(batch);
+                let md5 = content_md5(content.as_bytes());
+                let body = self
+                    .request(
+                        "POST",
+                        &s3_bucket,
+                        &[("delete", "")],
+                        &[("Content-MD5", &md5)],
+                        content.as_bytes(),
+                    )?
+                    .0;
+                let failed = delete_errors_xml_parser(std::str::from_utf8(&body).unwrap_or(""))?;
+                for (key, code) in failed.iter() {
+                    error!("fail to delete {}: {}", key, code);
+                }
+                removed += batch.len() - failed.len();
+            }
+            // Nothing is removed in the dry run, the same keys would be listed again
+            if self.dry_run {
+                break;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Mirror the source to the destination, each of them is a local folder or a S3 prefix,
+    /// ex: `s3://bucket/backup/`, the keys missing or changed on the destination are transferred,
+    /// the extra ones are removed if `delete` is set, and the planned keys are returned.
+    pub fn sync(
+        &mut self,
+        src: &str,
+        dest: &str,
+        options: SyncOptions,
+    ) -> Result<SyncPlan, Box<dyn std::error::Error>> {
+        let (src_objects, src_complete) = self.sync_objects(src, options.checksum)?;
+        let (dest_objects, dest_complete) = self.sync_objects(dest, options.checksum)?;
+        // The keys missing from an incomplete listing would be removed by mistake
+        if options.delete && !(src_complete && dest_complete) {
+            return Err(Error::RequestPoolError(
+                "the listing is incomplete, nothing is pruned".to_string(),
+            )
+            .into());
+        }
+        let plan = SyncPlan::new(
+            &src_objects,
+            &dest_objects,
+            options.checksum,
+            options.delete,
+        );
+        // The paths are checked before anything is transferred or removed
+        let transfers = plan
+            .transfer
+            .iter()
+            .map(|k| Ok((sync_path(src, k)?, sync_path(dest, k)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let removals = plan
+            .delete
+            .iter()
+            .map(|k| sync_path(dest, k))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if options.dry_run {
+            return Ok(plan);
+        }
+        for (from, to) in transfers {
+            if !is_s3_location(dest) {
+                if let Some(folder) = Path::new(&to).parent() {
+                    std::fs::create_dir_all(folder)?;
+                }
+            }
+            match (is_s3_location(src), is_s3_location(dest)) {
+                (false, true) => self.put(&from, &to)?,
+                (true, false) => self.get(&from, Some(&to))?,
+                (true, true) => self.cp(&from, &to)?,
+                (false, false) => {
+                    std::fs::copy(&from, &to)?;
+                }
+            }
+        }
+        for target in removals {
+            if is_s3_location(dest) {
+                self.del(&target)?;
+            } else {
+                std::fs::remove_file(&target)?;
+            }
+        }
+        Ok(plan)
+    }
+
+    /// The files or the objects under the folder or the prefix by the relative keys, and the
+    /// listing of the prefix is complete or not, the ETags of the files are only calculated for
+    /// the checksum
+    fn sync_objects(
+        &mut self,
+        location: &str,
+        checksum: bool,
+    ) -> Result<(BTreeMap<String, S3Object>, bool), Box<dyn std::error::Error>> {
+        let mut objects = BTreeMap::new();
+        if is_s3_location(location) {
+            let s3_object = S3Object::from(location);
+            let bucket = match s3_object.bucket {
+                Some(b) => b,
+                None => return Err(Error::UserError("please specific the bucket name").into()),
+            };
+            let prefix = s3_object.key.unwrap_or_default();
+            let prefix = match prefix.trim_matches('/') {
+                "" => String::new(),
+                p => format!("{}/", p),
+            };
+            let listing = self.ls_complete(Some(&format!("s3://{}/{}", bucket, prefix)))?;
+            for o in listing.objects {
+                let key = match o.key.as_deref().map(|k| k.trim_start_matches('/')) {
+                    Some(k) => k.strip_prefix(prefix.as_str()).unwrap_or(k).to_string(),
+                    None => continue,
+                };
+                // The markers of the folders are not synced
+                if !key.is_empty() && !key.ends_with('/') {
+                    objects.insert(key, o);
+                }
+            }
+            return Ok((objects, listing.complete));
+        }
+        let root = Path::new(location);
+        if !root.exists() {
+            return Ok((objects, true));
+        }
+        for (path, entry) in walk_dir(root, SymlinkPolicy::Follow)? {
+            let file = match entry {
+                Ok(LocalEntry::File(file)) => file,
+                Ok(LocalEntry::Symlink(_)) => continue,
+                Err(e) => {
+                    error!("fail to sync {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let key: Vec<String> = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect();
+            let meta = std::fs::metadata(&file)?;
+            let etag = if !checksum {
+                None
+            } else if meta.len() > self.part_size {
+                Some(multipart_etag(&file.to_string_lossy(), self.part_size)?)
+            } else {
+                Some(format!("{:x}", md5::compute(std::fs::read(&file)?)))
+            };
+            let mtime: DateTime<Utc> = meta.modified()?.into();
+            objects.insert(
+                key.join("/"),
+                S3Object {
+                    key: Some(file.to_string_lossy().to_string()),
+                    mtime: Some(mtime.to_rfc3339()),
+                    etag,
+                    size: Some(meta.len()),
+                    ..Default::default()
+                },
+            );
+        }
+        Ok((objects, true))
+    }
+
+    /// Make a new bucket
+    pub fn mb(&mut self, bucket: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.mb_with_options(bucket, &BucketOptions::default())
+    }
+
+    /// Make a new bucket with the canned ACL or the grants in `BucketOptions`
+    pub fn mb_with_options(
+        &mut self,
+        bucket: &str,
+        options: &BucketOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(bucket);
+        match &s3_object.bucket {
+            Some(b) => validate_bucket_name(b)?,
+            None => return Err(Error::UserError("please specific the bucket name").into()),
+        }
+        let mut headers = Vec::new();
+        if let Some(acl) = &options.acl {
+            if !CANNED_ACLS.contains(&acl.as_str()) {
+                return Err(Error::UserError("please specific a valid canned ACL").into());
+            }
+            headers.push(("x-amz-acl", acl.as_str()));
+        }
+        for (name, grant) in [
+            ("x-amz-grant-full-control", &options.grant_full_control),
+            ("x-amz-grant-read", &options.grant_read),
+            ("x-amz-grant-read-acp", &options.grant_read_acp),
+            ("x-amz-grant-write", &options.grant_write),
+            ("x-amz-grant-write-acp", &options.grant_write_acp),
+        ] {
+            if let Some(grant) = grant {
+                headers.push((name, grant.as_str()));
+            }
+        }
+        self.request("PUT", &s3_object, &Vec::new(), &headers, &Vec::new())?;
+        Ok(())
+    }
+
+    /// Remove a bucket
+    pub fn rb(&mut self, bucket: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(bucket);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        self.request("DELETE", &s3_object, &Vec::new(), &Vec::new(), &Vec::new())?;
+        Ok(())
+    }
+
     /// list all tags of an object
     pub fn list_tag(&mut self, target: &str) -> Result<(), Box<dyn std::error::Error>> {
         let res: String;
@@ -752,13 +2346,7 @@ impl Handler<'_> {
         let query_string = vec![("tagging", "")];
         res = std::str::from_utf8(
             &self
-                .request(
-                    "GET",
-                    &s3_object,
-                    &query_string,
-                    &mut Vec::new(),
-                    &Vec::new(),
-                )?
+                .request("GET", &s3_object, &query_string, &Vec::new(), &Vec::new())?
                 .0,
         )
         .unwrap_or("")
@@ -796,7 +2384,7 @@ impl Handler<'_> {
             "PUT",
             &s3_object,
             &query_string,
-            &mut Vec::new(),
+            &Vec::new(),
             &content.into_bytes(),
         )?;
         Ok(())
@@ -814,12 +2402,198 @@ impl Handler<'_> {
             "DELETE",
             &s3_object,
             &query_string,
-            &mut Vec::new(),
             &Vec::new(),
+            &Vec::new(),
+        )?;
+        Ok(())
+    }
+
+    /// Get the notification configuration of a bucket
+    pub fn get_notification(
+        &mut self,
+        bucket: &str,
+    ) -> Result<NotificationConfiguration, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(bucket);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        let res = self
+            .request(
+                "GET",
+                &s3_object,
+                &[("notification", "")],
+                &Vec::new(),
+                &Vec::new(),
+            )?
+            .0;
+        Ok(notification_xml_parser(
+            std::str::from_utf8(&res).unwrap_or(""),
+        )?)
+    }
+
+    /// Replace the notification configuration of a bucket,
+    /// an empty configuration will disable the notifications of the bucket
+    pub fn put_notification(
+        &mut self,
+        bucket: &str,
+        config: &NotificationConfiguration,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(bucket);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        let content = config.to_xml();
+        debug!("payload: {:?}", content);
+        self.request(
+            "PUT",
+            &s3_object,
+            &[("notification", "")],
+            &Vec::new(),
+            &content.into_bytes(),
         )?;
         Ok(())
     }
 
+    /// List the uploaded parts of an in-progress multipart upload
+    pub fn list_parts(
+        &mut self,
+        dest: &str,
+        upload_id: &str,
+    ) -> Result<Vec<PartInfo>, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(dest);
+        if s3_object.key.is_none() {
+            return Err(Error::UserError("Please specific the object").into());
+        }
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query_strings = vec![("uploadId", upload_id)];
+            if let Some(marker) = &marker {
+                query_strings.push(("part-number-marker", marker.as_str()));
+            }
+            let res = self
+                .request("GET", &s3_object, &query_strings, &Vec::new(), &Vec::new())?
+                .0;
+            let (page, next_marker) = parts_xml_parser(std::str::from_utf8(&res).unwrap_or(""))?;
+            parts.extend(page);
+            match next_marker {
+                Some(m) if m.is_empty() => {
+                    return Err(Error::FieldNotFound("NextPartNumberMarker").into())
+                }
+                Some(m) => advance_marker(&mut marker, Some(m))?,
+                None => return Ok(parts),
+            }
+        }
+    }
+
+    /// Get the attributes of an object without downloading it, ex: the checksums of the parts to
+    /// verify a multipart upload, the fields are picked from "ETag", "Checksum", "ObjectParts",
+    /// "StorageClass" and "ObjectSize".
+    pub fn get_attributes(
+        &mut self,
+        src: &str,
+        fields: &[&str],
+    ) -> Result<ObjectAttributes, Box<dyn std::error::Error>> {
+        let s3_object = S3Object::from(src);
+        if s3_object.key.is_none() {
+            return Err(Error::UserError("Please specific the object").into());
+        }
+        if fields.is_empty() {
+            return Err(Error::UserError("Please specific the attributes").into());
+        }
+        let fields = fields.join(",");
+        let res = self
+            .request(
+                "GET",
+                &s3_object,
+                &[("attributes", "")],
+                &[("x-amz-object-attributes", fields.as_str())],
+                &Vec::new(),
+            )?
+            .0;
+        Ok(object_attributes_xml_parser(
+            std::str::from_utf8(&res).unwrap_or(""),
+        )?)
+    }
+
+    /// List the in-progress multipart uploads of a bucket, the uploads can be filtered by the
+    /// prefix of the keys.
+    pub fn list_multipart_uploads(
+        &mut self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<UploadInfo>, Box<dyn std::error::Error>> {
+        let mut s3_object = S3Object::from(bucket);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        s3_object.key = None;
+        let mut uploads = Vec::new();
+        let mut markers: Option<UploadMarkers> = None;
+        loop {
+            let mut query_strings = vec![("uploads", "")];
+            if let Some(prefix) = prefix {
+                query_strings.push(("prefix", prefix));
+            }
+            if let Some((key_marker, upload_id_marker)) = &markers {
+                query_strings.push(("key-marker", key_marker.as_str()));
+                query_strings.push(("upload-id-marker", upload_id_marker.as_str()));
+            }
+            let res = self
+                .request("GET", &s3_object, &query_strings, &Vec::new(), &Vec::new())?
+                .0;
+            let (page, next_markers) =
+                multipart_uploads_xml_parser(std::str::from_utf8(&res).unwrap_or(""))?;
+            uploads.extend(page);
+            match next_markers {
+                Some((k, _)) if k.is_empty() => {
+                    return Err(Error::FieldNotFound("NextKeyMarker").into())
+                }
+                Some(m) => advance_marker(&mut markers, Some(m))?,
+                None => return Ok(uploads),
+            }
+        }
+    }
+
+    /// List the versions and the delete markers of the objects in a versioning bucket, the
+    /// entries can be filtered by the prefix of the keys.
+    pub fn list_versions(
+        &mut self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<VersionEntry>, Box<dyn std::error::Error>> {
+        let mut s3_object = S3Object::from(bucket);
+        if s3_object.bucket.is_none() {
+            return Err(Error::UserError("please specific the bucket name").into());
+        }
+        s3_object.key = None;
+        let mut entries = Vec::new();
+        let mut markers: Option<VersionMarkers> = None;
+        loop {
+            let mut query_strings = vec![("versions", "")];
+            if let Some(prefix) = prefix {
+                query_strings.push(("prefix", prefix));
+            }
+            if let Some((key_marker, version_id_marker)) = &markers {
+                query_strings.push(("key-marker", key_marker.as_str()));
+                query_strings.push(("version-id-marker", version_id_marker.as_str()));
+            }
+            let res = self
+                .request("GET", &s3_object, &query_strings, &Vec::new(), &Vec::new())?
+                .0;
+            let (page, next_markers) =
+                versions_xml_parser(std::str::from_utf8(&res).unwrap_or(""))?;
+            entries.extend(page);
+            match next_markers {
+                Some((k, _)) if k.is_empty() => {
+                    return Err(Error::FieldNotFound("NextKeyMarker").into())
+                }
+                Some(m) => advance_marker(&mut markers, Some(m))?,
+                None => return Ok(entries),
+            }
+        }
+    }
+
     /// Show the usage of a bucket (CEPH only)
     pub fn usage(
         &mut self,
@@ -834,28 +2608,21 @@ impl Handler<'_> {
         };
         let bucket = s3_object.bucket.unwrap();
         query_strings.push(("bucket", &bucket));
+        // The admin api of CEPH is always in JSON, no matter the format of the handler
+        query_strings.push(("format", "json"));
         let result = self.request(
             "GET",
             &s3_admin_bucket_object,
             &query_strings,
-            &mut Vec::new(),
+            &Vec::new(),
             &Vec::new(),
         )?;
-        match self.format {
-            Format::JSON => {
-                let json: serde_json::Value;
-                json = serde_json::from_str(std::str::from_utf8(&result.0).unwrap_or("")).unwrap();
-                println!(
-                    "{}",
-                    serde_json::to_string_pretty(&json["usage"]).unwrap_or_else(|_| "".to_string())
-                );
-            }
-            Format::XML => {
-                // TODO:
-                // Ceph Ops api may not support xml
-                unimplemented!();
-            }
-        };
+        let json: serde_json::Value =
+            serde_json::from_str(std::str::from_utf8(&result.0).unwrap_or(""))?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json["usage"]).unwrap_or_else(|_| "".to_string())
+        );
         Ok(())
     }
 
@@ -884,13 +2651,7 @@ impl Handler<'_> {
             }
         }
 
-        let result = self.request(
-            "GET",
-            &s3_object,
-            &query_strings,
-            &mut Vec::new(),
-            &Vec::new(),
-        )?;
+        let result = self.request("GET", &s3_object, &query_strings, &Vec::new(), &Vec::new())?;
         println!("{}", std::str::from_utf8(&result.0).unwrap_or(""));
         Ok(())
     }
@@ -900,25 +2661,29 @@ impl Handler<'_> {
         if command.ends_with("aws") {
             self.auth_type = AuthType::AWS4;
             self.format = Format::XML;
+            self.format_query = false;
             self.url_style = UrlStyle::HOST;
             self.s3_client = Box::new(AWS4Client {
                 tls: self.secure,
-                access_key: self.access_key,
-                secret_key: self.secret_key,
-                host: self.host,
+                credentials: self.credentials.clone(),
+                host: self.host.to_string(),
                 region: self.region.clone().unwrap(),
+                clock_offset: self.clock_offset,
+                http: self.http.clone(),
             });
             println!("using aws verion 4 signature, xml format, and host style url");
         } else if command.ends_with("ceph") {
             self.auth_type = AuthType::AWS4;
             self.format = Format::JSON;
+            self.format_query = true;
             self.url_style = UrlStyle::PATH;
             self.s3_client = Box::new(AWS4Client {
                 tls: self.secure,
-                access_key: self.access_key,
-                secret_key: self.secret_key,
-                host: self.host,
+                credentials: self.credentials.clone(),
+                host: self.host.to_string(),
                 region: self.region.clone().unwrap(),
+                clock_offset: self.clock_offset,
+                http: self.http.clone(),
             });
             println!("using aws verion 4 signature, json format, and path style url");
         } else {
@@ -939,22 +2704,10 @@ impl Handler<'_> {
     /// - South America (So Paulo) Region
     pub fn change_auth_type(&mut self, command: &str) {
         if command.ends_with("aws2") {
-            self.auth_type = AuthType::AWS2;
-            self.s3_client = Box::new(AWS2Client {
-                tls: self.secure,
-                access_key: self.access_key,
-                secret_key: self.secret_key,
-            });
+            self.set_auth_type(AuthType::AWS2);
             println!("using aws version 2 signature");
         } else if command.ends_with("aws4") || command.ends_with("aws") {
-            self.auth_type = AuthType::AWS4;
-            self.s3_client = Box::new(AWS4Client {
-                tls: self.secure,
-                access_key: self.access_key,
-                secret_key: self.secret_key,
-                host: self.host,
-                region: self.region.clone().unwrap(),
-            });
+            self.set_auth_type(AuthType::AWS4);
             println!("using aws verion 4 signature");
         } else {
             println!("usage: auth_type [aws4/aws2]");
@@ -966,10 +2719,10 @@ impl Handler<'_> {
     /// AWS only support xml
     pub fn change_format_type(&mut self, command: &str) {
         if command.ends_with("xml") {
-            self.format = Format::XML;
+            self.set_format(Format::XML);
             println!("using xml format");
         } else if command.ends_with("json") {
-            self.format = Format::JSON;
+            self.set_format(Format::JSON);
             println!("using json format");
         } else {
             println!("usage: format_type [xml/json]");
@@ -979,10 +2732,10 @@ impl Handler<'_> {
     /// Change request url style
     pub fn change_url_style(&mut self, command: &str) {
         if command.ends_with("path") {
-            self.url_style = UrlStyle::PATH;
+            self.set_url_style(UrlStyle::PATH);
             println!("using path style url");
         } else if command.ends_with("host") {
-            self.url_style = UrlStyle::HOST;
+            self.set_url_style(UrlStyle::HOST);
             println!("using host style url");
         } else {
             println!("usage: url_style [path/host]");
@@ -992,77 +2745,175 @@ impl Handler<'_> {
 
 impl<'a> From<&'a CredentialConfig> for Handler<'a> {
     fn from(credential: &'a CredentialConfig) -> Self {
-        debug!("host: {}", credential.host);
+        Handler::with_keys(
+            credential,
+            Cow::Borrowed(&credential.access_key),
+            Cow::Borrowed(&credential.secret_key),
+            Cow::Borrowed(&credential.host),
+        )
+    }
+}
+
+/// The handler owns the keys, so it can be kept without the config, ex: in a registry of
+/// named endpoints.
+impl From<CredentialConfig> for Handler<'static> {
+    fn from(credential: CredentialConfig) -> Self {
+        Handler::with_keys(
+            &credential,
+            Cow::Owned(credential.access_key.clone()),
+            Cow::Owned(credential.secret_key.clone()),
+            Cow::Owned(credential.host.clone()),
+        )
+    }
+}
+
+impl<'a> Handler<'a> {
+    fn with_keys(
+        credential: &CredentialConfig,
+        access_key: Cow<'a, str>,
+        secret_key: Cow<'a, str>,
+        host: Cow<'a, str>,
+    ) -> Self {
+        let host = if host.is_empty() {
+            Cow::Owned(credential.endpoint())
+        } else {
+            host
+        };
+        let endpoint = host.to_string();
+        debug!("host: {}", endpoint);
         debug!("access key: {}", credential.access_key);
         debug!("secret key: {}", credential.secret_key);
+        let credentials: Arc<dyn CredentialProvider> = Arc::new(StaticProvider::new(
+            &credential.access_key,
+            &credential.secret_key,
+        ));
+        let http = HttpClient::new(ClientConfig::default(), Arc::new(NoopMetrics));
 
-        match credential
-            .clone()
-            .s3_type
-            .unwrap_or_else(|| "".to_string())
-            .as_str()
-        {
+        match credential.clone().s3_type.unwrap_or_default().as_str() {
             "aws" => Handler {
-                access_key: &credential.access_key,
-                secret_key: &credential.secret_key,
-                host: &credential.host,
+                access_key,
+                secret_key,
+                host,
+                credentials: credentials.clone(),
 
                 s3_client: Box::new(AWS4Client {
                     tls: credential.secure.unwrap_or(false),
-                    access_key: &credential.access_key,
-                    secret_key: &credential.secret_key,
-                    host: &credential.host,
+                    credentials: credentials.clone(),
+                    host: endpoint.clone(),
                     region: credential.region.clone().unwrap(),
+                    clock_offset: Duration::zero(),
+                    http: http.clone(),
                 }),
                 auth_type: AuthType::AWS4,
                 format: Format::XML,
                 url_style: UrlStyle::HOST,
                 region: credential.region.clone(),
                 secure: credential.secure.unwrap_or(false),
-                domain_name: credential.host.to_string(),
-                part_size: DEFAULT_PREPART_SIZE,
+                domain_name: endpoint.clone(),
+                base_domain: None,
+                part_size: credential.part_size.unwrap_or(DEFAULT_PREPART_SIZE),
+                worker_count: credential
+                    .worker_count
+                    .unwrap_or(DEFAULT_WORKER_COUNT)
+                    .max(1),
+                decompress: false,
+                preserve_mtime: false,
+                encoding_type_url: false,
+                auto_part_size: credential.part_size.is_none(),
+                format_query: false,
+                clock_offset: Duration::zero(),
+                client_config: ClientConfig::default(),
+                http: http.clone(),
+                default_headers: Vec::new(),
+                metrics: Arc::new(NoopMetrics),
+                dry_run: false,
+                planned: Vec::new(),
+                recorded: None,
+                bucket_regions: HashMap::new(),
             },
             "ceph" => Handler {
-                access_key: &credential.access_key,
-                secret_key: &credential.secret_key,
-                host: &credential.host,
+                access_key,
+                secret_key,
+                host,
+                credentials: credentials.clone(),
 
                 s3_client: Box::new(AWS4Client {
                     tls: credential.secure.unwrap_or(false),
-                    access_key: &credential.access_key,
-                    secret_key: &credential.secret_key,
-                    host: &credential.host,
+                    credentials: credentials.clone(),
+                    host: endpoint.clone(),
                     region: credential.region.clone().unwrap(),
+                    clock_offset: Duration::zero(),
+                    http: http.clone(),
                 }),
                 auth_type: AuthType::AWS4,
                 format: Format::JSON,
                 url_style: UrlStyle::PATH,
                 region: credential.region.clone(),
                 secure: credential.secure.unwrap_or(false),
-                domain_name: credential.host.to_string(),
-                part_size: DEFAULT_PREPART_SIZE,
+                domain_name: endpoint.clone(),
+                base_domain: None,
+                part_size: credential.part_size.unwrap_or(DEFAULT_PREPART_SIZE),
+                worker_count: credential
+                    .worker_count
+                    .unwrap_or(DEFAULT_WORKER_COUNT)
+                    .max(1),
+                decompress: false,
+                preserve_mtime: false,
+                encoding_type_url: false,
+                auto_part_size: credential.part_size.is_none(),
+                format_query: true,
+                clock_offset: Duration::zero(),
+                client_config: ClientConfig::default(),
+                http: http.clone(),
+                default_headers: Vec::new(),
+                metrics: Arc::new(NoopMetrics),
+                dry_run: false,
+                planned: Vec::new(),
+                recorded: None,
+                bucket_regions: HashMap::new(),
             },
             _ => Handler {
-                access_key: &credential.access_key,
-                secret_key: &credential.secret_key,
-                host: &credential.host,
+                access_key,
+                secret_key,
+                host,
+                credentials: credentials.clone(),
                 auth_type: AuthType::AWS4,
                 format: Format::XML,
                 url_style: UrlStyle::PATH,
                 region: credential.region.clone(),
                 secure: credential.secure.unwrap_or(false),
-                domain_name: credential.host.to_string(),
+                domain_name: endpoint.clone(),
+                base_domain: None,
                 s3_client: Box::new(AWS4Client {
                     tls: credential.secure.unwrap_or(false),
-                    access_key: &credential.access_key,
-                    secret_key: &credential.secret_key,
-                    host: &credential.host,
+                    credentials: credentials.clone(),
+                    host: endpoint.clone(),
                     region: credential
                         .region
                         .clone()
                         .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+                    clock_offset: Duration::zero(),
+                    http: http.clone(),
                 }),
-                part_size: DEFAULT_PREPART_SIZE,
+                part_size: credential.part_size.unwrap_or(DEFAULT_PREPART_SIZE),
+                worker_count: credential
+                    .worker_count
+                    .unwrap_or(DEFAULT_WORKER_COUNT)
+                    .max(1),
+                decompress: false,
+                preserve_mtime: false,
+                encoding_type_url: false,
+                auto_part_size: credential.part_size.is_none(),
+                format_query: false,
+                clock_offset: Duration::zero(),
+                client_config: ClientConfig::default(),
+                http: http.clone(),
+                default_headers: Vec::new(),
+                metrics: Arc::new(NoopMetrics),
+                dry_run: false,
+                planned: Vec::new(),
+                recorded: None,
+                bucket_regions: HashMap::new(),
             },
         }
     }
@@ -1071,6 +2922,1385 @@ impl<'a> From<&'a CredentialConfig> for Handler<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::metrics::CounterMetrics;
+    use crate::utils::sign::{hash_payload, EMPTY_PAYLOAD_HASH};
+    use crate::utils::test_server::{list_page, TestServer, TEST_CA_PEM};
+    use std::collections::VecDeque;
+
+    type MockResponse = (StatusCode, Vec<u8>, reqwest::header::HeaderMap);
+
+    /// The request received by the mock client
+    #[derive(Debug, Clone)]
+    struct MockRequest {
+        method: String,
+        host: String,
+        uri: String,
+        query_strings: Vec<(String, String)>,
+        headers: Vec<(String, String)>,
+        payload: Vec<u8>,
+    }
+
+    /// A S3 client records the requests and replies the prepared responses in order,
+    /// if there is no prepared response, an empty 200 response will be replied.
+    #[derive(Default, Clone)]
+    struct MockClient {
+        responses: Arc<Mutex<VecDeque<MockResponse>>>,
+        requests: Arc<Mutex<Vec<MockRequest>>>,
+    }
+
+    impl MockClient {
+        fn respond(&self, status: StatusCode, body: &[u8], headers: &[(&'static str, &str)]) {
+            let mut header_map = reqwest::header::HeaderMap::new();
+            for (k, v) in headers {
+                header_map.insert(*k, v.parse().unwrap());
+            }
+            self.responses
+                .lock()
+                .unwrap()
+                .push_back((status, body.to_vec(), header_map));
+        }
+        fn requests(&self) -> Vec<MockRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl S3Client for MockClient {
+        fn request(
+            &self,
+            method: &str,
+            host: &str,
+            uri: &str,
+            query_strings: &mut Vec<(&str, &str)>,
+            headers: &mut Vec<(&str, &str)>,
+            payload: &[u8],
+        ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error> {
+            self.requests.lock().unwrap().push(MockRequest {
+                method: method.to_string(),
+                host: host.to_string(),
+                uri: uri.to_string(),
+                query_strings: query_strings
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                headers: headers
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                payload: payload.to_vec(),
+            });
+            Ok(self.responses.lock().unwrap().pop_front().unwrap_or((
+                StatusCode::OK,
+                Vec::new(),
+                reqwest::header::HeaderMap::new(),
+            )))
+        }
+        fn request_file(
+            &self,
+            method: &str,
+            host: &str,
+            uri: &str,
+            query_strings: &mut Vec<(&str, &str)>,
+            headers: &mut Vec<(&str, &str)>,
+            file: &File,
+        ) -> Result<(StatusCode, Vec<u8>, reqwest::header::HeaderMap), Error> {
+            let mut payload = Vec::new();
+            let mut fin = file.try_clone()?;
+            fin.seek(std::io::SeekFrom::Start(0))?;
+            fin.read_to_end(&mut payload)?;
+            self.request(method, host, uri, query_strings, headers, &payload)
+        }
+        /// The body of the redirect is taken as the endpoint
+        fn redirect_parser(&self, body: Vec<u8>, _format: Format) -> Result<String, Error> {
+            Ok(String::from_utf8(body).unwrap_or_default())
+        }
+        fn update(&mut self, _region: String, _secure: bool) {}
+        fn update_clock_offset(&mut self, _offset: Duration) {}
+        fn current_region(&self) -> Option<String> {
+            Some(DEFAULT_REGION.to_string())
+        }
+        fn debug_sign(
+            &self,
+            _method: &str,
+            _host: &str,
+            _uri: &str,
+            _query_strings: &mut Vec<(&str, &str)>,
+            _headers: &mut Vec<(&str, &str)>,
+            _payload: &[u8],
+            _utc: DateTime<Utc>,
+        ) -> Result<SignDebug, Error> {
+            Ok(SignDebug::default())
+        }
+        fn sign_request(
+            &self,
+            _method: &str,
+            host: &str,
+            uri: &str,
+            _query_strings: &mut Vec<(&str, &str)>,
+            _headers: &mut Vec<(&str, &str)>,
+            _payload: &[u8],
+            _utc: DateTime<Utc>,
+        ) -> Result<(String, reqwest::header::HeaderMap), Error> {
+            Ok((
+                format!("http://{}{}", host, uri),
+                reqwest::header::HeaderMap::new(),
+            ))
+        }
+    }
+
+    fn mock_handler(client: &MockClient) -> Handler<'static> {
+        Handler {
+            access_key: "akey".into(),
+            secret_key: "skey".into(),
+            host: "s3.mock".into(),
+            credentials: Arc::new(StaticProvider::new("akey", "skey")),
+            s3_client: Box::new(client.clone()),
+            auth_type: AuthType::AWS4,
+            format: Format::XML,
+            url_style: UrlStyle::PATH,
+            region: None,
+            domain_name: "s3.mock".to_string(),
+            base_domain: None,
+            secure: false,
+            part_size: DEFAULT_PREPART_SIZE,
+            worker_count: DEFAULT_WORKER_COUNT,
+            decompress: false,
+            preserve_mtime: false,
+            encoding_type_url: false,
+            auto_part_size: false,
+            format_query: false,
+            clock_offset: Duration::zero(),
+            client_config: ClientConfig::default(),
+            http: HttpClient::default(),
+            default_headers: Vec::new(),
+            metrics: Arc::new(NoopMetrics),
+            dry_run: false,
+            planned: Vec::new(),
+            recorded: None,
+            bucket_regions: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_get_gzip_object() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"This is a test file\n").unwrap();
+        let data = encoder.finish().unwrap();
+        let length = data.len().to_string();
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        for decompress in [false, true] {
+            client.respond(
+                StatusCode::OK,
+                &[],
+                &[("content-length", &length), ("content-encoding", "gzip")],
+            );
+            client.respond(StatusCode::OK, &data, &[("content-encoding", "gzip")]);
+            handler.decompress(decompress);
+            handler
+                .get("s3://bucket/test.txt", Some("/tmp/s3handler-gzip-test"))
+                .unwrap();
+            let content = std::fs::read("/tmp/s3handler-gzip-test").unwrap();
+            if decompress {
+                assert_eq!(content, b"This is a test file\n");
+            } else {
+                assert_eq!(content, data);
+            }
+        }
+        assert_eq!(client.requests()[0].method, "HEAD");
+        assert_eq!(client.requests()[1].method, "GET");
+        assert_eq!(client.requests()[1].uri, "/bucket/test.txt");
+    }
+    #[test]
+    fn test_get_truncated_object() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(StatusCode::OK, &[], &[("content-length", "50")]);
+        client.respond(StatusCode::OK, &[0; 20], &[]);
+        let err = handler
+            .get(
+                "s3://bucket/test.txt",
+                Some("/tmp/s3handler-truncated-test"),
+            )
+            .unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::IntegrityError { expected, received }) => {
+                assert_eq!((*expected, *received), (50, 20))
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+
+        // the last part is truncated in multipart download
+        let server = TestServer::start();
+        server.serve_object(&[0; 45]);
+        handler.domain_name = server.host.clone();
+        handler.part_size = 25;
+        client.respond(StatusCode::OK, &[], &[("content-length", "50")]);
+        let err = handler
+            .get(
+                "s3://bucket/test.txt",
+                Some("/tmp/s3handler-truncated-test"),
+            )
+            .unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::IntegrityError { expected, received }) => {
+                assert_eq!((*expected, *received), (25, 20))
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+    #[test]
+    fn test_zero_part_size() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.part_size = 0;
+        client.respond(StatusCode::OK, &[], &[("content-length", "50")]);
+        let err = handler.get("s3://bucket/test.txt", None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UserError(_))
+        ));
+
+        let file = std::env::temp_dir().join("s3handler_test_zero_part_size");
+        std::fs::write(&file, b"content").unwrap();
+        let err = handler
+            .put(file.to_str().unwrap(), "s3://bucket/test.txt")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UserError(_))
+        ));
+        // Only the HEAD of the download is sent
+        assert_eq!(client.requests().len(), 1);
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn test_default_header() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        assert!(handler.default_header("x tenant", "tenant").is_err());
+        handler.default_header("x-tenant-id", "tenant").unwrap();
+        handler
+            .default_header("traceparent", "00-trace-01")
+            .unwrap();
+        handler.del("s3://bucket/key").unwrap();
+        handler
+            .cp("s3://src-bucket/key", "s3://dest-bucket")
+            .unwrap();
+        for request in client.requests() {
+            assert!(request
+                .headers
+                .contains(&("x-tenant-id".to_string(), "tenant".to_string())));
+            assert!(request
+                .headers
+                .contains(&("traceparent".to_string(), "00-trace-01".to_string())));
+        }
+    }
+    #[test]
+    fn test_set_base_domain() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.url_style = UrlStyle::HOST;
+        handler.del("s3://bucket/key").unwrap();
+        handler.set_base_domain("cdn.example.com");
+        handler.del("s3://bucket/key").unwrap();
+        let requests = client.requests();
+        assert_eq!(requests[0].host, "bucket.s3.mock");
+        assert_eq!(requests[1].host, "bucket.cdn.example.com");
+        assert_eq!(requests[1].uri, "/key");
+
+        // the connection host is kept in path style
+        handler.url_style = UrlStyle::PATH;
+        handler.del("s3://bucket/key").unwrap();
+        let request = client.requests().pop().unwrap();
+        assert_eq!(request.host, "s3.mock");
+        assert_eq!(request.uri, "/bucket/key");
+    }
+    #[test]
+    fn test_empty_object() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.part_size = 25;
+        let file = std::env::temp_dir().join("s3handler_test_empty_object_blocking");
+        std::fs::write(&file, b"").unwrap();
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/_SUCCESS")
+            .unwrap();
+        let put = client.requests().pop().unwrap();
+        assert_eq!(put.method, "PUT");
+        assert!(put.payload.is_empty());
+
+        std::fs::write(&file, b"stale").unwrap();
+        client.respond(StatusCode::OK, &[], &[("content-length", "0")]);
+        handler.get("s3://bucket/_SUCCESS", file.to_str()).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"");
+        std::fs::remove_file(&file).unwrap();
+
+        client.respond(StatusCode::OK, &[], &[("content-length", "0")]);
+        let mut out = Vec::new();
+        handler.pipe("s3://bucket/_SUCCESS", &mut out).unwrap();
+        assert!(out.is_empty());
+
+        // the marker of a folder is created as a folder without requests
+        let requests = client.requests().len();
+        let folder = std::env::temp_dir().join("s3handler_test_empty_object_folder");
+        handler.get("s3://bucket/photos/", folder.to_str()).unwrap();
+        assert!(folder.is_dir());
+        assert_eq!(client.requests().len(), requests);
+        std::fs::remove_dir(&folder).unwrap();
+
+        // the pools without any part return at once, rather than waiting for the results
+        let object = S3Object::from("s3://bucket/_SUCCESS");
+        assert!(handler
+            .download_pool(&object, 0, 1)
+            .wait()
+            .unwrap()
+            .is_empty());
+        let upload = UploadRequestPool::new(
+            AuthType::AWS4,
+            false,
+            Arc::new(StaticProvider::new("akey", "skey")),
+            "s3.mock".to_string(),
+            "/bucket/_SUCCESS".to_string(),
+            DEFAULT_REGION.to_string(),
+            "upload-id".to_string(),
+            1,
+            Duration::zero(),
+            HttpClient::default(),
+            Vec::new(),
+            Arc::new(NoopMetrics),
+        );
+        assert!(matches!(upload.wait(), Err(Error::UserError(_))));
+        assert_eq!(client.requests().len(), requests);
+    }
+    #[test]
+    fn test_preserve_mtime() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let file = std::env::temp_dir().join("s3handler_test_preserve_mtime");
+        std::fs::write(&file, b"content").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600527886))
+            .unwrap();
+        for enable in [false, true] {
+            handler.preserve_mtime(enable);
+            handler
+                .put(file.to_str().unwrap(), "s3://bucket/test")
+                .unwrap();
+            let put = client.requests().pop().unwrap();
+            assert_eq!(
+                put.headers
+                    .contains(&("x-amz-meta-mtime".to_string(), "1600527886".to_string())),
+                enable
+            );
+        }
+
+        let modified = |file: &std::path::Path| {
+            DateTime::<Utc>::from(std::fs::metadata(file).unwrap().modified().unwrap())
+        };
+        client.respond(
+            StatusCode::OK,
+            &[],
+            &[
+                ("content-length", "7"),
+                ("x-amz-meta-mtime", "1500000000.25"),
+                ("last-modified", "Sat, 19 Sep 2020 15:04:46 GMT"),
+            ],
+        );
+        client.respond(StatusCode::OK, b"content", &[]);
+        handler.get("s3://bucket/test", file.to_str()).unwrap();
+        assert_eq!(modified(&file).timestamp_millis(), 1500000000250);
+
+        // the `Last-Modified` is taken for the object uploaded by other tools
+        client.respond(
+            StatusCode::OK,
+            &[],
+            &[
+                ("content-length", "7"),
+                ("last-modified", "Sat, 19 Sep 2020 15:04:46 GMT"),
+            ],
+        );
+        client.respond(StatusCode::OK, b"content", &[]);
+        handler.get("s3://bucket/test", file.to_str()).unwrap();
+        assert_eq!(modified(&file).timestamp(), 1600527886);
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_put_dir() {
+        let dir = std::env::temp_dir().join("s3handler_test_put_dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/a.txt"), b"content").unwrap();
+        std::os::unix::fs::symlink("sub/a.txt", dir.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", dir.join("dangling.txt")).unwrap();
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let results = handler
+            .put_dir(
+                dir.to_str().unwrap(),
+                "s3://bucket/backup/",
+                SymlinkPolicy::Follow,
+            )
+            .unwrap();
+        let keys: Vec<&str> = results.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "s3://bucket/backup/dangling.txt",
+                "s3://bucket/backup/link.txt",
+                "s3://bucket/backup/sub/a.txt"
+            ]
+        );
+        assert!(results[0].1.is_err());
+        assert!(results[1].1.is_ok() && results[2].1.is_ok());
+        let puts: Vec<(String, Vec<u8>)> = client
+            .requests()
+            .into_iter()
+            .map(|r| (r.uri, r.payload))
+            .collect();
+        assert_eq!(
+            puts,
+            vec![
+                ("/bucket/backup/link.txt".to_string(), b"content".to_vec()),
+                ("/bucket/backup/sub/a.txt".to_string(), b"content".to_vec()),
+            ]
+        );
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let results = handler
+            .put_dir(
+                dir.to_str().unwrap(),
+                "s3://bucket",
+                SymlinkPolicy::Preserve,
+            )
+            .unwrap();
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].uri, "/bucket/dangling.txt");
+        assert!(requests[0].payload.is_empty());
+        assert!(requests[0].headers.contains(&(
+            "x-amz-meta-symlink-target".to_string(),
+            "missing.txt".to_string()
+        )));
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let results = handler
+            .put_dir_filtered(
+                dir.to_str().unwrap(),
+                "s3://bucket",
+                SymlinkPolicy::Follow,
+                &Rules::default().exclude("dangling.txt").exclude("sub/**"),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "s3://bucket/link.txt");
+        assert!(results[0].1.is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pipe() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(StatusCode::OK, &[], &[("content-length", "7")]);
+        client.respond(StatusCode::OK, b"content", &[]);
+        let mut out = Vec::new();
+        handler.pipe("s3://bucket/test.txt", &mut out).unwrap();
+        assert_eq!(out, b"content");
+
+        // the parts are written in order
+        let content: Vec<u8> = (0..60).collect();
+        let server = TestServer::start();
+        server.serve_object(&content);
+        handler.domain_name = server.host.clone();
+        handler.part_size = 25;
+        handler.worker_count = 2;
+        client.respond(StatusCode::OK, &[], &[("content-length", "60")]);
+        let mut out = Vec::new();
+        handler.pipe("s3://bucket/test.txt", &mut out).unwrap();
+        assert_eq!(out, content);
+        let mut ranges: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| r.headers["range"].clone())
+            .collect();
+        ranges.sort();
+        assert_eq!(ranges, ["bytes=0-24", "bytes=25-49", "bytes=50-59"]);
+    }
+    #[test]
+    fn test_get_failed_part() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let server = TestServer::start();
+        for _ in 0..2 {
+            server.respond(
+                503,
+                &[("x-amz-request-id", "request-id")],
+                b"<Error><Code>SlowDown</Code></Error>",
+            );
+        }
+        handler.domain_name = server.host.clone();
+        handler.part_size = 25;
+        client.respond(StatusCode::OK, &[], &[("content-length", "50")]);
+        let err = handler
+            .get(
+                "s3://bucket/test.txt",
+                Some("/tmp/s3handler-failed-part-test"),
+            )
+            .unwrap_err();
+        let err = err.downcast_ref::<Error>().unwrap();
+        assert_eq!(err.status(), Some(503));
+        assert_eq!(err.request_id(), Some("request-id"));
+    }
+    #[test]
+    fn test_pipe_ranges_over_4gb() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let server = TestServer::start();
+        server.route(
+            "/bucket/large",
+            503,
+            &[],
+            b"<Error><Code>SlowDown</Code></Error>",
+        );
+        handler.domain_name = server.host.clone();
+        handler.part_size = 2 << 30;
+        handler.worker_count = 3;
+        // a 5GB object, the ranges after 4GB are not wrapped on the 32-bit targets
+        client.respond(StatusCode::OK, &[], &[("content-length", "5368709120")]);
+        assert!(handler.pipe("s3://bucket/large", &mut Vec::new()).is_err());
+
+        // the pipe is stopped by the first failed part, the others are still sent
+        let start = std::time::Instant::now();
+        while server.requests().len() < 3 && start.elapsed() < time::Duration::from_secs(5) {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        let mut ranges: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| r.headers["range"].clone())
+            .collect();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            [
+                "bytes=0-2147483647",
+                "bytes=2147483648-4294967295",
+                "bytes=4294967296-5368709119"
+            ]
+        );
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_upload_pool_bounded() {
+        /// The offset of the file opened by the upload, which is the bytes read from it
+        fn read_position(file: &Path) -> u64 {
+            std::fs::read_dir("/proc/self/fd")
+                .unwrap()
+                .flatten()
+                .filter(|fd| std::fs::read_link(fd.path()).ok().as_deref() == Some(file))
+                .filter_map(|fd| {
+                    let info = std::fs::read_to_string(
+                        Path::new("/proc/self/fdinfo").join(fd.file_name()),
+                    )
+                    .ok()?;
+                    info.lines()
+                        .find_map(|l| l.strip_prefix("pos:"))?
+                        .trim()
+                        .parse()
+                        .ok()
+                })
+                .max()
+                .unwrap_or_default()
+        }
+
+        /// The slow network, which records the most parts read from the file but not uploaded
+        #[derive(Debug)]
+        struct SlowNetwork {
+            file: std::path::PathBuf,
+            part_size: u64,
+            uploaded: AtomicUsize,
+            peak: AtomicUsize,
+        }
+        impl Metrics for SlowNetwork {
+            fn on_request(&self, method: &str, _status: Option<u16>, _latency: time::Duration) {
+                if method != "PUT" {
+                    return;
+                }
+                let uploaded = self.uploaded.fetch_add(1, Ordering::SeqCst) + 1;
+                let read = (read_position(&self.file) / self.part_size) as usize;
+                self.peak
+                    .fetch_max(read.saturating_sub(uploaded), Ordering::SeqCst);
+                thread::sleep(time::Duration::from_millis(20));
+            }
+        }
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let file = std::env::temp_dir().join("s3handler_test_upload_pool_bounded");
+        std::fs::write(&file, [0; 80]).unwrap();
+        let metrics = Arc::new(SlowNetwork {
+            file: file.canonicalize().unwrap(),
+            part_size: 5,
+            uploaded: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        });
+        handler.metrics(metrics.clone());
+        handler.s3_client = Box::new(client.clone());
+        let server = TestServer::start();
+        server.route("/bucket/key", 200, &[("etag", "\"e\"")], b"");
+        handler.domain_name = server.host.clone();
+        handler.part_size = 5;
+        handler.worker_count = 2;
+        client.respond(
+            StatusCode::OK,
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+            &[],
+        );
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/key")
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(metrics.uploaded.load(Ordering::SeqCst), 16);
+        // the parts in the channel, the ones taken by the workers, and the one being sent
+        assert!(metrics.peak.load(Ordering::SeqCst) <= handler.worker_count * 3 + 1);
+    }
+    #[test]
+    fn test_http_client_reused() {
+        let server = TestServer::start();
+        let config = CredentialConfig {
+            host: server.host.clone(),
+            access_key: "akey".to_string(),
+            secret_key: "skey".to_string(),
+            user: None,
+            region: None,
+            s3_type: None,
+            secure: None,
+            part_size: Some(25),
+            worker_count: Some(2),
+        };
+        let mut handler = Handler::from(&config);
+        let metrics = Arc::new(CounterMetrics::default());
+        handler.metrics(metrics.clone());
+        for key in ["a", "b", "c"] {
+            handler.del(&format!("s3://bucket/{}", key)).unwrap();
+        }
+        assert_eq!(metrics.clients.load(Ordering::Relaxed), 1);
+
+        // The workers of the download share the client
+        let content: Vec<u8> = (0..60).collect();
+        server.serve_object(&content);
+        server.respond(200, &[("content-length", "60")], b"");
+        let file = std::env::temp_dir().join("s3handler_test_http_client_reused");
+        handler
+            .get("s3://bucket/a", Some(file.to_str().unwrap()))
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), content);
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(server.requests().len(), 7);
+        assert_eq!(metrics.counts().0, 7);
+        assert_eq!(metrics.clients.load(Ordering::Relaxed), 1);
+
+        // The client is built again with the new settings
+        handler.http2(false);
+        handler.del("s3://bucket/a").unwrap();
+        assert_eq!(metrics.clients.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let metrics = Arc::new(CounterMetrics::default());
+        handler.metrics(metrics.clone());
+        // the mock is put back after the client is renewed with the metrics
+        handler.s3_client = Box::new(client.clone());
+        let file = std::env::temp_dir().join("s3handler_test_metrics.txt");
+        std::fs::write(&file, b"S3RS test\n").unwrap();
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/test")
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+        client.respond(StatusCode::OK, &[], &[("content-length", "7")]);
+        client.respond(StatusCode::OK, b"content", &[]);
+        handler
+            .get("s3://bucket/test", Some("/tmp/s3handler-metrics-test"))
+            .unwrap();
+        assert_eq!(metrics.counts(), (3, 10, 7, 0));
+
+        client.respond(
+            StatusCode::FORBIDDEN,
+            b"<Error><Code>RequestTimeTooSkewed</Code></Error>",
+            &[("date", &chrono::Utc::now().to_rfc2822())],
+        );
+        handler.del("s3://bucket/test").unwrap();
+        assert_eq!(metrics.counts().0, 5);
+        assert_eq!(metrics.counts().3, 1);
+        assert_eq!(metrics.errors.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.latency_histogram().iter().sum::<usize>(), 5);
+        assert_eq!(metrics.hosts()[&handler.domain_name], 5);
+    }
+    #[test]
+    fn test_list_parts_with_pagination() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListPartsResult><NextPartNumberMarker>1</NextPartNumberMarker><IsTruncated>true</IsTruncated><Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag><Size>5</Size></Part></ListPartsResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListPartsResult><IsTruncated>false</IsTruncated><Part><PartNumber>2</PartNumber><ETag>\"b\"</ETag><Size>3</Size></Part></ListPartsResult>",
+            &[],
+        );
+        let parts = handler.list_parts("s3://bucket/key", "upload-id").unwrap();
+        assert_eq!(
+            parts.iter().map(|p| (p.number, p.size)).collect::<Vec<_>>(),
+            vec![(1, 5), (2, 3)]
+        );
+        let requests = client.requests();
+        assert_eq!(requests[0].uri, "/bucket/key");
+        assert_eq!(
+            requests[1].query_strings,
+            vec![
+                ("uploadId".to_string(), "upload-id".to_string()),
+                ("part-number-marker".to_string(), "1".to_string())
+            ]
+        );
+
+        // The truncated list without a marker or with the same marker again is not continued
+        let truncated = b"<ListPartsResult><IsTruncated>true</IsTruncated><Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag><Size>5</Size></Part></ListPartsResult>";
+        client.respond(StatusCode::OK, truncated, &[]);
+        assert!(handler.list_parts("s3://bucket/key", "upload-id").is_err());
+        let repeated = b"<ListPartsResult><NextPartNumberMarker>1</NextPartNumberMarker><IsTruncated>true</IsTruncated></ListPartsResult>";
+        client.respond(StatusCode::OK, repeated, &[]);
+        client.respond(StatusCode::OK, repeated, &[]);
+        assert!(handler.list_parts("s3://bucket/key", "upload-id").is_err());
+        assert_eq!(client.requests().len(), 5);
+    }
+    #[test]
+    fn test_get_attributes() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<GetObjectAttributesResponse><ETag>\"a\"</ETag><ObjectSize>5</ObjectSize></GetObjectAttributesResponse>",
+            &[],
+        );
+        let attributes = handler
+            .get_attributes("s3://bucket/key", &["ETag", "ObjectSize"])
+            .unwrap();
+        assert_eq!(attributes.etag.as_deref(), Some("a"));
+        assert_eq!(attributes.object_size, Some(5));
+        let requests = client.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(
+            requests[0].query_strings,
+            vec![("attributes".to_string(), "".to_string())]
+        );
+        assert!(requests[0].headers.contains(&(
+            "x-amz-object-attributes".to_string(),
+            "ETag,ObjectSize".to_string()
+        )));
+        assert!(handler.get_attributes("s3://bucket/key", &[]).is_err());
+        assert!(handler.get_attributes("s3://bucket", &["ETag"]).is_err());
+    }
+    #[test]
+    fn test_list_multipart_uploads_with_pagination() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListMultipartUploadsResult><NextKeyMarker>a</NextKeyMarker><NextUploadIdMarker>1</NextUploadIdMarker><IsTruncated>true</IsTruncated><Upload><Key>a</Key><UploadId>1</UploadId></Upload></ListMultipartUploadsResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListMultipartUploadsResult><IsTruncated>false</IsTruncated><Upload><Key>b</Key><UploadId>2</UploadId></Upload></ListMultipartUploadsResult>",
+            &[],
+        );
+        let uploads = handler
+            .list_multipart_uploads("s3://bucket", Some("pre"))
+            .unwrap();
+        assert_eq!(
+            uploads
+                .iter()
+                .map(|u| (u.key.as_str(), u.upload_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("a", "1"), ("b", "2")]
+        );
+        let requests = client.requests();
+        assert_eq!(requests[1].uri, "/bucket/");
+        assert_eq!(
+            requests[1].query_strings,
+            vec![
+                ("uploads".to_string(), "".to_string()),
+                ("prefix".to_string(), "pre".to_string()),
+                ("key-marker".to_string(), "a".to_string()),
+                ("upload-id-marker".to_string(), "1".to_string())
+            ]
+        );
+
+        let repeated = b"<ListMultipartUploadsResult><NextKeyMarker>a</NextKeyMarker><NextUploadIdMarker>1</NextUploadIdMarker><IsTruncated>true</IsTruncated></ListMultipartUploadsResult>";
+        client.respond(StatusCode::OK, repeated, &[]);
+        client.respond(StatusCode::OK, repeated, &[]);
+        assert!(handler.list_multipart_uploads("s3://bucket", None).is_err());
+        client.respond(
+            StatusCode::OK,
+            b"<ListMultipartUploadsResult><IsTruncated>true</IsTruncated></ListMultipartUploadsResult>",
+            &[],
+        );
+        assert!(handler.list_multipart_uploads("s3://bucket", None).is_err());
+        assert_eq!(client.requests().len(), 5);
+    }
+    #[test]
+    fn test_list_versions_with_pagination() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let page = b"<ListVersionsResult><IsTruncated>true</IsTruncated><NextKeyMarker>a</NextKeyMarker><NextVersionIdMarker>v1</NextVersionIdMarker><Version><Key>a</Key><VersionId>v1</VersionId><IsLatest>true</IsLatest><ETag>\"a\"</ETag><Size>1</Size></Version></ListVersionsResult>";
+        client.respond(StatusCode::OK, page, &[]);
+        client.respond(
+            StatusCode::OK,
+            b"<ListVersionsResult><IsTruncated>false</IsTruncated><DeleteMarker><Key>b</Key><VersionId>v2</VersionId><IsLatest>true</IsLatest></DeleteMarker></ListVersionsResult>",
+            &[],
+        );
+        assert_eq!(handler.list_versions("s3://bucket", None).unwrap().len(), 2);
+        assert_eq!(
+            client.requests()[1].query_strings[1..],
+            [
+                ("key-marker".to_string(), "a".to_string()),
+                ("version-id-marker".to_string(), "v1".to_string())
+            ]
+        );
+
+        // The markers not advanced or missing stop the listing with an error
+        client.respond(StatusCode::OK, page, &[]);
+        client.respond(StatusCode::OK, page, &[]);
+        assert!(handler.list_versions("s3://bucket", None).is_err());
+        client.respond(
+            StatusCode::OK,
+            b"<ListVersionsResult><IsTruncated>true</IsTruncated></ListVersionsResult>",
+            &[],
+        );
+        assert!(handler.list_versions("s3://bucket", None).is_err());
+        assert_eq!(client.requests().len(), 5);
+    }
+    #[test]
+    fn test_conditional_copy() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler
+            .cp_with_options(
+                "s3://src-bucket/key",
+                "s3://dest-bucket",
+                &CopyOptions {
+                    copy_source_if_match: Some("etag".to_string()),
+                    copy_source_if_modified_since: Some(
+                        "Wed, 21 Oct 2015 07:28:00 GMT".to_string(),
+                    ),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let request = &client.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.uri, "/dest-bucket/key");
+        assert_eq!(
+            request.headers,
+            vec![
+                (
+                    "x-amz-copy-source".to_string(),
+                    "/src-bucket/key".to_string()
+                ),
+                ("x-amz-copy-source-if-match".to_string(), "etag".to_string()),
+                (
+                    "x-amz-copy-source-if-modified-since".to_string(),
+                    "Wed, 21 Oct 2015 07:28:00 GMT".to_string()
+                ),
+            ]
+        );
+
+        client.respond(StatusCode::PRECONDITION_FAILED, &[], &[]);
+        let err = handler
+            .cp_with_options(
+                "s3://src-bucket/key",
+                "s3://dest-bucket/key2",
+                &CopyOptions {
+                    copy_source_if_match: Some("other-etag".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::PreconditionFailed())
+        ));
+    }
+    #[test]
+    fn test_copy_with_replaced_tags() {
+        let mut handler = mock_handler(&MockClient::default());
+        let server = TestServer::start();
+        server.respond(200, &[], b"<CopyObjectResult></CopyObjectResult>");
+        handler.domain_name = server.host.clone();
+        handler.region = Some(DEFAULT_REGION.to_string());
+        handler.renew_client();
+        handler
+            .cp_with_options(
+                "s3://src-bucket/key",
+                "s3://dest-bucket",
+                &CopyOptions {
+                    tags: Some(vec![("project".to_string(), "s3 handler".to_string())]),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].headers["x-amz-tagging-directive"], "REPLACE");
+        assert_eq!(requests[0].headers["x-amz-tagging"], "project=s3%20handler");
+        assert!(requests[0].headers["authorization"]
+            .contains("x-amz-copy-source;x-amz-date;x-amz-tagging;x-amz-tagging-directive"));
+    }
+    #[test]
+    fn test_multipart_copy() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        // 6GB is refused by the single copy, and copied in 12 parts of 512MB
+        let too_large = b"<Error><Code>InvalidRequest</Code><Message>The specified copy source is larger than the maximum allowable size for a copy source: 5368709120</Message></Error>";
+        client.respond(StatusCode::BAD_REQUEST, too_large, &[]);
+        client.respond(
+            StatusCode::OK,
+            &[],
+            &[
+                ("content-length", "6442450944"),
+                ("content-type", "video/mp4"),
+                ("x-amz-meta-camera", "front"),
+            ],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+            &[],
+        );
+        for part in 1..=12 {
+            client.respond(
+                StatusCode::OK,
+                format!(
+                    "<CopyPartResult><ETag>\"etag-{}\"</ETag></CopyPartResult>",
+                    part
+                )
+                .as_bytes(),
+                &[],
+            );
+        }
+        handler
+            .cp("s3://src-bucket/large", "s3://dest-bucket")
+            .unwrap();
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 16);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[1].method, "HEAD");
+        assert_eq!(requests[1].uri, "/src-bucket/large");
+        assert_eq!(requests[2].method, "POST");
+        assert_eq!(
+            requests[2].query_strings,
+            vec![("uploads".to_string(), "".to_string())]
+        );
+        assert!(requests[2]
+            .headers
+            .contains(&("content-type".to_string(), "video/mp4".to_string())));
+        assert!(requests[2]
+            .headers
+            .contains(&("x-amz-meta-camera".to_string(), "front".to_string())));
+        let parts = &requests[3..15];
+        assert!(parts.iter().all(|r| r.method == "PUT"
+            && r.uri == "/dest-bucket/large"
+            && r.headers.contains(&(
+                "x-amz-copy-source".to_string(),
+                "/src-bucket/large".to_string()
+            ))));
+        assert_eq!(
+            parts[0].query_strings,
+            vec![
+                ("partNumber".to_string(), "1".to_string()),
+                ("uploadId".to_string(), "upload-id".to_string())
+            ]
+        );
+        assert!(parts[0].headers.contains(&(
+            "x-amz-copy-source-range".to_string(),
+            "bytes=0-536870911".to_string()
+        )));
+        assert!(parts[11].headers.contains(&(
+            "x-amz-copy-source-range".to_string(),
+            "bytes=5905580032-6442450943".to_string()
+        )));
+        let complete = String::from_utf8(requests[15].payload.clone()).unwrap();
+        assert!(complete.starts_with(
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"etag-1\"</ETag></Part>"
+        ));
+        assert!(complete.ends_with(
+            "<Part><PartNumber>12</PartNumber><ETag>\"etag-12\"</ETag></Part></CompleteMultipartUpload>"
+        ));
+
+        // the upload is aborted if a part fails
+        client.respond(StatusCode::BAD_REQUEST, too_large, &[]);
+        client.respond(StatusCode::OK, &[], &[("content-length", "6442450944")]);
+        client.respond(
+            StatusCode::OK,
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+            &[],
+        );
+        client.respond(StatusCode::INTERNAL_SERVER_ERROR, b"", &[]);
+        assert!(handler
+            .cp("s3://src-bucket/large", "s3://dest-bucket")
+            .is_err());
+        let abort = client.requests().pop().unwrap();
+        assert_eq!(abort.method, "DELETE");
+        assert_eq!(
+            abort.query_strings,
+            vec![("uploadId".to_string(), "upload-id".to_string())]
+        );
+    }
+    #[test]
+    fn test_mb_with_acl() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler
+            .mb_with_options(
+                "s3://website",
+                &BucketOptions {
+                    acl: Some("public-read".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let request = &client.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.uri, "/website/");
+        assert_eq!(
+            request.headers,
+            vec![("x-amz-acl".to_string(), "public-read".to_string())]
+        );
+
+        let signer = AWS4Client {
+            tls: false,
+            host: "s3.mock".to_string(),
+            credentials: Arc::new(StaticProvider::new("akey", "skey")),
+            region: DEFAULT_REGION.to_string(),
+            clock_offset: Duration::zero(),
+            http: HttpClient::default(),
+        };
+        let (_url, headers) = signer
+            .sign_request(
+                "PUT",
+                "s3.mock",
+                &request.uri,
+                &mut Vec::new(),
+                &mut request
+                    .headers
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect(),
+                &[],
+                chrono::Utc::now(),
+            )
+            .unwrap();
+        assert_eq!(headers["x-amz-acl"], "public-read");
+        assert!(headers[reqwest::header::AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .contains("SignedHeaders=host;x-amz-acl;x-amz-date,"));
+
+        assert!(handler
+            .mb_with_options(
+                "s3://website",
+                &BucketOptions {
+                    acl: Some("public".to_string()),
+                    ..Default::default()
+                },
+            )
+            .is_err());
+        assert_eq!(client.requests().len(), 1);
+
+        assert!(handler.mb("s3://Website_2021").is_err());
+        assert_eq!(client.requests().len(), 1);
+    }
+    #[test]
+    fn test_request_ids_in_error() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::FORBIDDEN,
+            b"<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+            &[
+                ("x-amz-request-id", "318BC8BC148832E5"),
+                ("x-amz-id-2", "eftixk72aD6Ap51TnqcoF8eFidJG9Z"),
+            ],
+        );
+        let err = handler.del("s3://bucket/key").unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::S3Error {
+                status,
+                code,
+                request_id,
+                host_id,
+                ..
+            }) => {
+                assert_eq!(*status, 403);
+                assert_eq!(code, "AccessDenied");
+                assert_eq!(request_id.as_deref(), Some("318BC8BC148832E5"));
+                assert_eq!(host_id.as_deref(), Some("eftixk72aD6Ap51TnqcoF8eFidJG9Z"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+    #[test]
+    fn test_format_query() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.change_format_type("json");
+        handler.cat("s3://bucket/key").unwrap();
+        assert!(client.requests()[0].query_strings.is_empty());
+
+        handler.region = Some(DEFAULT_REGION.to_string());
+        handler.change_s3_type("ceph");
+        handler.s3_client = Box::new(client.clone());
+        handler.cat("s3://bucket/key").unwrap();
+        assert_eq!(
+            client.requests()[1].query_strings,
+            vec![("format".to_string(), "json".to_string())]
+        );
+
+        handler.format_query(false);
+        handler.cat("s3://bucket/key").unwrap();
+        assert!(client.requests()[2].query_strings.is_empty());
+    }
+    #[test]
+    fn test_put_with_tags() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let file = std::env::temp_dir().join("s3handler_test_put_with_tags.txt");
+        std::fs::write(&file, b"content").unwrap();
+        let options = PutOptions {
+            tags: vec![("project".to_string(), "s3 handler".to_string())],
+            ..Default::default()
+        };
+        handler
+            .put_with_options(file.to_str().unwrap(), "s3://bucket/key", &options)
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(client.requests()[0].headers.contains(&(
+            "x-amz-tagging".to_string(),
+            "project=s3%20handler".to_string()
+        )));
+    }
+    #[test]
+    fn test_put_file() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let file = std::env::temp_dir().join("s3handler_test_put_file.txt");
+        std::fs::write(&file, b"content").unwrap();
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/key")
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let put = &client.requests()[0];
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.uri, "/bucket/key");
+        assert_eq!(put.payload, b"content");
+        assert!(put
+            .headers
+            .contains(&("content-type".to_string(), "text/plain".to_string())));
+    }
+    #[test]
+    fn test_record() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.record(true);
+        let file = std::env::temp_dir().join("s3handler_test_record.txt");
+        std::fs::write(&file, b"content").unwrap();
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/key")
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+        handler.del("s3://bucket/key").unwrap();
+        assert!(client.requests().is_empty());
+
+        let recorded = handler.take_recorded();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].method, "PUT");
+        assert!(recorded[0].url.starts_with("http://s3.mock/bucket/key"));
+        assert_eq!(recorded[0].payload_hash, hash_payload(b"content"));
+        assert_eq!(
+            recorded[0].header("x-amz-content-sha256"),
+            Some(recorded[0].payload_hash.as_str())
+        );
+        assert_eq!(recorded[0].header("content-type"), Some("text/plain"));
+        assert!(recorded[0]
+            .header("authorization")
+            .unwrap()
+            .starts_with("AWS4-HMAC-SHA256 Credential=akey/"));
+        assert_eq!(recorded[1].method, "DELETE");
+        assert_eq!(recorded[1].payload_hash, EMPTY_PAYLOAD_HASH);
+        assert!(handler.take_recorded().is_empty());
+
+        handler.record(false);
+        assert!(handler.take_recorded().is_empty());
+    }
+    #[test]
+    fn test_put_content_type() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let file = std::env::temp_dir().join("s3handler_test_put_content_type.txt");
+        std::fs::write(&file, b"content").unwrap();
+        for content_type in [
+            ContentType::Fixed("application/octet-stream".to_string()),
+            ContentType::Omit,
+        ] {
+            let options = PutOptions {
+                content_type,
+                ..Default::default()
+            };
+            handler
+                .put_with_options(file.to_str().unwrap(), "s3://bucket/key", &options)
+                .unwrap();
+        }
+        std::fs::remove_file(&file).unwrap();
+        let requests = client.requests();
+        assert!(requests[0].headers.contains(&(
+            "content-type".to_string(),
+            "application/octet-stream".to_string()
+        )));
+        assert!(requests[1]
+            .headers
+            .iter()
+            .all(|(k, _)| !k.eq_ignore_ascii_case("content-type")));
+    }
+    #[test]
+    fn test_put_nonexistent_file() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        for file in ["test", "/tmp/s3handler-nonexistent-file"] {
+            let err = handler.put(file, "s3://bucket/key").unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::LoadError(_))
+            ));
+        }
+        // a path under a file can not be opened
+        let file = std::env::temp_dir().join("s3handler_test_put_unreadable.txt");
+        std::fs::write(&file, b"content").unwrap();
+        let err = handler
+            .put(file.join("unreadable").to_str().unwrap(), "s3://bucket/key")
+            .unwrap_err();
+        std::fs::remove_file(&file).unwrap();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::LoadError(_))
+        ));
+        handler.dry_run(true);
+        assert!(handler.put("test", "s3://bucket/key").is_err());
+        assert!(handler.take_planned().is_empty());
+        assert!(client.requests().is_empty());
+    }
+    #[test]
+    fn test_format_query_for_admin_api() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.change_format_type("json");
+        handler.cat("s3://bucket/key").unwrap();
+        assert!(client.requests()[0].query_strings.is_empty());
+
+        client.respond(StatusCode::OK, br#"{"usage":{}}"#, &[]);
+        handler.usage("s3://bucket", &[]).unwrap();
+        assert_eq!(
+            client.requests()[1].query_strings,
+            vec![
+                ("bucket".to_string(), "bucket".to_string()),
+                ("format".to_string(), "json".to_string())
+            ]
+        );
+
+        handler.region = Some(DEFAULT_REGION.to_string());
+        handler.change_s3_type("ceph");
+        handler.s3_client = Box::new(client.clone());
+        client.respond(StatusCode::OK, br#"{"usage":{}}"#, &[]);
+        handler.usage("s3://bucket", &[]).unwrap();
+        assert_eq!(
+            client.requests()[2].query_strings,
+            vec![
+                ("bucket".to_string(), "bucket".to_string()),
+                ("format".to_string(), "json".to_string())
+            ]
+        );
+    }
+    #[test]
+    fn test_resign_on_clock_skew() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let server_time = (chrono::Utc::now() + Duration::hours(1)).to_rfc2822();
+        client.respond(
+            StatusCode::FORBIDDEN,
+            b"<Error><Code>RequestTimeTooSkewed</Code><Message>The difference between the request time and the server's time is too large.</Message></Error>",
+            &[("date", &server_time)],
+        );
+        handler.del("s3://bucket/key").unwrap();
+        assert_eq!(client.requests().len(), 2);
+        assert!(
+            (handler.clock_offset() - Duration::hours(1))
+                .num_seconds()
+                .abs()
+                <= 1
+        );
+
+        // retry only once
+        for _ in 0..2 {
+            client.respond(
+                StatusCode::FORBIDDEN,
+                b"<Error><Code>RequestTimeTooSkewed</Code></Error>",
+                &[("date", &server_time)],
+            );
+        }
+        assert!(handler.del("s3://bucket/key").is_err());
+        assert_eq!(client.requests().len(), 4);
+    }
+    #[test]
+    fn test_ls_on_html_error_page() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::BAD_GATEWAY,
+            b"<html><head><title>502 Bad Gateway</title></head></html>",
+            &[("content-type", "text/html")],
+        );
+        let err = handler.ls(Some("s3://bucket")).unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::S3Error {
+                status, message, ..
+            }) => {
+                assert_eq!(*status, 502);
+                assert!(message.contains("502 Bad Gateway"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        client.respond(StatusCode::OK, b"<html>maintenance</html>", &[]);
+        handler.change_format_type("json");
+        assert!(handler.ls(None).is_err());
+    }
     #[test]
     fn test_s3object_for_dummy_folder() {
         let s3_object = S3Object::from("s3://bucket/dummy_folder/");
@@ -1117,4 +4347,1156 @@ mod tests {
         let s3_object: S3Object = S3Convert::new_from_uri("bucket");
         assert_eq!("s3://bucket".to_string(), String::from(s3_object));
     }
+    #[test]
+    fn test_s3object_for_root_from_uri() {
+        for uri in ["", "/", " "] {
+            let s3_object: S3Object = S3Object::from(uri);
+            assert_eq!(s3_object.bucket, None);
+            assert_eq!(s3_object.key, None);
+        }
+    }
+    #[test]
+    fn test_ls_root_bucket_and_prefix() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        for prefix in [
+            "",
+            "s3://",
+            "bucket",
+            "bucket/",
+            "s3://bucket/",
+            "bucket/prefix",
+            "s3://bucket/prefix",
+        ] {
+            handler.ls(Some(prefix)).unwrap();
+        }
+        let requests: Vec<(String, Vec<(String, String)>)> = client
+            .requests()
+            .into_iter()
+            .map(|r| {
+                (
+                    r.uri,
+                    r.query_strings
+                        .into_iter()
+                        .filter(|(k, _)| k == "prefix")
+                        .collect(),
+                )
+            })
+            .collect();
+        let root = ("/".to_string(), vec![]);
+        let bucket = (
+            "/bucket/".to_string(),
+            vec![("prefix".to_string(), "".to_string())],
+        );
+        let prefix = (
+            "/bucket/".to_string(),
+            vec![("prefix".to_string(), "prefix".to_string())],
+        );
+        assert_eq!(
+            requests,
+            vec![
+                root.clone(),
+                root,
+                bucket.clone(),
+                bucket.clone(),
+                bucket,
+                prefix.clone(),
+                prefix
+            ]
+        );
+    }
+    #[test]
+    fn test_acquire_poisoned_lock() {
+        let lock = Arc::new(Mutex::new(0));
+        let poison_lock = lock.clone();
+        let _ = thread::spawn(move || {
+            let _l = poison_lock.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(lock.is_poisoned());
+        assert!(matches!(acquire(&lock), Err(Error::RequestPoolError(_))));
+    }
+    #[test]
+    fn test_acquire_lock() {
+        let lock = Arc::new(Mutex::new(0));
+        *acquire(&lock).unwrap() += 1;
+        assert_eq!(*lock.lock().unwrap(), 1);
+    }
+    #[test]
+    fn test_zero_worker_count() {
+        let server = TestServer::start();
+        let config = CredentialConfig {
+            host: server.host.clone(),
+            access_key: "akey".to_string(),
+            secret_key: "skey".to_string(),
+            user: None,
+            region: None,
+            s3_type: None,
+            secure: None,
+            part_size: Some(5),
+            worker_count: Some(0),
+        };
+        let mut handler = Handler::from(&config);
+        assert_eq!(handler.worker_count, 1);
+
+        let content: Vec<u8> = (0..12).collect();
+        let file = std::env::temp_dir().join("s3handler_test_zero_worker_count");
+        std::fs::write(&file, &content).unwrap();
+        server.respond(
+            200,
+            &[],
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+        );
+        for etag in ["\"a\"", "\"b\"", "\"c\""] {
+            server.respond(200, &[("etag", etag)], b"");
+        }
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/key")
+            .unwrap();
+        assert_eq!(server.requests().len(), 5);
+
+        // The download is split into the parts as well
+        server.serve_object(&content);
+        server.respond(200, &[("content-length", "12")], b"");
+        std::fs::remove_file(&file).unwrap();
+        handler
+            .get("s3://bucket/key", Some(file.to_str().unwrap()))
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), content);
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn test_transfer_config_from_credential() {
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "127.0.0.1", "access_key": "akey", "secret_key": "skey", "part_size": 10485760, "worker_count": 4}"#,
+        )
+        .unwrap();
+        assert_eq!(config.part_size, Some(10485760));
+        assert_eq!(config.worker_count, Some(4));
+        let handler = Handler::from(&config);
+        assert_eq!(handler.part_size, 10485760);
+        assert_eq!(handler.worker_count, 4);
+
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "127.0.0.1", "access_key": "akey", "secret_key": "skey"}"#,
+        )
+        .unwrap();
+        let handler = Handler::from(&config);
+        assert_eq!(handler.part_size, DEFAULT_PREPART_SIZE);
+        assert_eq!(handler.worker_count, DEFAULT_WORKER_COUNT);
+    }
+    #[test]
+    fn test_endpoint_of_region() {
+        for (region, host) in [
+            ("cn-north-1", "s3.cn-north-1.amazonaws.com.cn"),
+            ("us-gov-west-1", "s3.us-gov-west-1.amazonaws.com"),
+        ] {
+            let config: CredentialConfig = serde_json::from_str(&format!(
+                r#"{{"host": "", "access_key": "akey", "secret_key": "skey", "region": "{}", "s3_type": "aws"}}"#,
+                region
+            ))
+            .unwrap();
+            let handler = Handler::from(&config);
+            assert_eq!(handler.domain_name, host);
+            assert_eq!(handler.host, host);
+        }
+
+        // the host is kept if it is given
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "127.0.0.1", "access_key": "akey", "secret_key": "skey", "region": "cn-north-1"}"#,
+        )
+        .unwrap();
+        assert_eq!(Handler::from(&config).domain_name, "127.0.0.1");
+    }
+    #[test]
+    fn test_handler_owned() {
+        fn registry() -> std::collections::HashMap<String, HandlerOwned> {
+            let mut handlers = std::collections::HashMap::new();
+            for (name, host) in [
+                ("lab", "ceph.lab:9000"),
+                ("prod", "s3.us-east-1.amazonaws.com"),
+            ] {
+                let config: CredentialConfig = serde_json::from_str(&format!(
+                    r#"{{"host": "{}", "access_key": "akey-{}", "secret_key": "skey", "region": "us-east-1"}}"#,
+                    host, name
+                ))
+                .unwrap();
+                // the config is dropped, and the handler keeps the keys
+                handlers.insert(name.to_string(), Handler::from(config));
+            }
+            handlers
+        }
+        let handlers = registry();
+        assert_eq!(handlers["lab"].host, "ceph.lab:9000");
+        assert_eq!(handlers["lab"].access_key, "akey-lab");
+        assert_eq!(handlers["prod"].host, "s3.us-east-1.amazonaws.com");
+        assert_eq!(handlers["prod"].access_key, "akey-prod");
+        assert_eq!(
+            handlers["prod"].s3_client.current_region(),
+            Some("us-east-1".to_string())
+        );
+    }
+    #[test]
+    fn test_set_secure() {
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "127.0.0.1:9000", "access_key": "akey", "secret_key": "skey", "region": "eu-west-1"}"#,
+        )
+        .unwrap();
+        let mut handler = Handler::from(&config);
+        assert!(!handler.is_secure());
+        handler.set_secure(true);
+        assert!(handler.is_secure());
+        assert_eq!(
+            handler.s3_client.current_region(),
+            Some("eu-west-1".to_string())
+        );
+        handler.set_secure(false);
+        assert!(!handler.is_secure());
+    }
+    #[test]
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn test_tls_config() {
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "127.0.0.1:9000", "access_key": "akey", "secret_key": "skey"}"#,
+        )
+        .unwrap();
+        let mut handler = Handler::from(&config);
+        assert!(handler
+            .root_ca(b"-----BEGIN CERTIFICATE-----\nnot a certificate\n-----END CERTIFICATE-----\n")
+            .is_err());
+        assert!(handler.client_config.root_ca.is_none());
+        handler.root_ca(TEST_CA_PEM.as_bytes()).unwrap();
+        handler.danger_accept_invalid_certs(true);
+        assert!(handler.client_config.root_ca.is_some());
+        assert!(handler.client_config.danger_accept_invalid_certs);
+    }
+    #[test]
+    fn test_ls_json() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.set_format(Format::JSON);
+        client.respond(
+            StatusCode::OK,
+            br#"{"Name":"bucket","Prefix":"","Marker":"","NextMarker":"a.txt","MaxKeys":1,"IsTruncated":true,"Contents":["a.txt","2020-09-19T15:10:08.000Z","\"5050ef3558233dc04b3fac50eff68de1\"",7,"STANDARD",{"ID":"tester","DisplayName":"Tester"}]}"#,
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            br#"{"Name":"bucket","Prefix":"","Marker":"a.txt","MaxKeys":1,"IsTruncated":false,"Contents":["b.txt","2020-09-20T15:10:08.000Z","\"6050ef3558233dc04b3fac50eff68de1\"",8,"GLACIER",{"ID":"tester","DisplayName":"Tester"}]}"#,
+            &[],
+        );
+        let objects = handler.ls(Some("s3://bucket")).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[1].key.as_deref(), Some("/b.txt"));
+        assert_eq!(objects[1].size, Some(8));
+        assert_eq!(objects[1].storage_class.as_deref(), Some("GLACIER"));
+        assert_eq!(
+            client.requests()[1].query_strings,
+            vec![
+                ("prefix".to_string(), "".to_string()),
+                ("marker".to_string(), "a.txt".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ls_complete() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>a.txt</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>",
+            &[],
+        );
+        let listing = handler.ls_complete(Some("s3://bucket")).unwrap();
+        assert!(listing.complete);
+        assert_eq!(listing.objects.len(), 2);
+
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>a.txt</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(StatusCode::INTERNAL_SERVER_ERROR, b"", &[]);
+        let listing = handler.ls_complete(Some("s3://bucket")).unwrap();
+        assert!(!listing.complete);
+        assert_eq!(listing.objects.len(), 2);
+        assert_eq!(
+            client.requests().last().unwrap().query_strings,
+            vec![
+                ("prefix".to_string(), "".to_string()),
+                ("marker".to_string(), "a.txt".to_string())
+            ]
+        );
+
+        client.respond(StatusCode::INTERNAL_SERVER_ERROR, b"", &[]);
+        assert!(handler.ls_complete(Some("s3://bucket")).is_err());
+    }
+    #[test]
+    fn test_ls_complete_json() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.set_format(Format::JSON);
+        // The repeated NextMarker stops the listing as an incomplete one
+        let page = br#"{"Name":"bucket","NextMarker":"a.txt","IsTruncated":true,"Contents":[{"Key":"a.txt","Size":7}]}"#;
+        client.respond(StatusCode::OK, page, &[]);
+        client.respond(StatusCode::OK, page, &[]);
+        let listing = handler.ls_complete(Some("s3://bucket")).unwrap();
+        assert!(!listing.complete);
+        assert_eq!(client.requests().len(), 2);
+
+        // The truncated page without a content and a marker is incomplete
+        let page = br#"{"Name":"bucket","IsTruncated":true,"Contents":[]}"#;
+        client.respond(StatusCode::OK, page, &[]);
+        let listing = handler.ls_complete(Some("s3://bucket")).unwrap();
+        assert!(!listing.complete);
+        assert!(listing.objects.is_empty());
+        client.respond(StatusCode::OK, page, &[]);
+        assert!(handler.ls(Some("s3://bucket")).is_err());
+    }
+
+    #[test]
+    fn test_ls_with_options() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let respond_pages = || {
+            for (start, truncated) in [(0, true), (1000, true), (2000, false)] {
+                client.respond(StatusCode::OK, list_page(start, truncated).as_bytes(), &[]);
+            }
+        };
+        let keys = |listing: &Listing| listing.objects.iter().filter(|o| o.key.is_some()).count();
+
+        respond_pages();
+        let listing = handler
+            .ls_with_options(
+                Some("s3://bucket"),
+                &ListOptions {
+                    limit: Some(2500),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!listing.complete);
+        assert_eq!(keys(&listing), 2500);
+        assert_eq!(
+            listing.objects.last().unwrap().key.as_deref(),
+            Some("/2499")
+        );
+        assert_eq!(client.requests().len(), 3);
+
+        // the limit is not reached
+        respond_pages();
+        let listing = handler
+            .ls_with_options(
+                Some("s3://bucket"),
+                &ListOptions {
+                    limit: Some(3000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(listing.complete);
+        assert_eq!(keys(&listing), 3000);
+
+        // the pages are not requested after the limit is reached
+        client.respond(StatusCode::OK, list_page(0, true).as_bytes(), &[]);
+        let listing = handler
+            .ls_with_options(
+                Some("s3://bucket"),
+                &ListOptions {
+                    limit: Some(1000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!listing.complete);
+        assert_eq!(keys(&listing), 1000);
+        assert_eq!(client.requests().len(), 7);
+
+        respond_pages();
+        let listing = handler
+            .ls_with_options(
+                Some("s3://bucket"),
+                &ListOptions {
+                    max_requests: Some(2),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!listing.complete);
+        assert_eq!(keys(&listing), 2000);
+        assert_eq!(client.requests().len(), 9);
+    }
+
+    #[test]
+    fn test_parse_types() {
+        assert_eq!("aws2".parse::<AuthType>().unwrap(), AuthType::AWS2);
+        assert_eq!("AWS4".parse::<AuthType>().unwrap(), AuthType::AWS4);
+        assert_eq!("aws".parse::<AuthType>().unwrap(), AuthType::AWS4);
+        assert!("aws3".parse::<AuthType>().is_err());
+        assert_eq!(" json".parse::<Format>().unwrap(), Format::JSON);
+        assert_eq!("xml".parse::<Format>().unwrap(), Format::XML);
+        assert!("yaml".parse::<Format>().is_err());
+        assert_eq!("host".parse::<UrlStyle>().unwrap(), UrlStyle::HOST);
+        assert_eq!("Path".parse::<UrlStyle>().unwrap(), UrlStyle::PATH);
+        assert!(matches!(
+            "virtual".parse::<UrlStyle>(),
+            Err(Error::UserError(_))
+        ));
+    }
+    #[test]
+    fn test_set_types() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.set_format(Format::JSON);
+        handler.set_url_style(UrlStyle::HOST);
+        assert_eq!(handler.format, Format::JSON);
+        assert_eq!(handler.url_style, UrlStyle::HOST);
+
+        handler.set_auth_type(AuthType::AWS2);
+        assert_eq!(handler.auth_type, AuthType::AWS2);
+        // the client is renewed with the signature version 2
+        assert!(handler
+            .debug_sign("GET", "s3://bucket/key", &[], &[], &[])
+            .unwrap()
+            .canonical_request
+            .is_none());
+        handler.set_auth_type(AuthType::AWS4);
+        assert!(handler
+            .debug_sign("GET", "s3://bucket/key", &[], &[], &[])
+            .unwrap()
+            .canonical_request
+            .is_some());
+    }
+    #[test]
+    fn test_la_in_parallel() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let server = TestServer::start();
+        handler.domain_name = server.host.clone();
+        handler.worker_count = 2;
+        client.respond(
+            StatusCode::OK,
+            b"<ListAllMyBucketsResult><Buckets><Bucket><Name>c</Name></Bucket><Bucket><Name>a</Name></Bucket><Bucket><Name>b</Name></Bucket></Buckets></ListAllMyBucketsResult>",
+            &[],
+        );
+        // the first matched route replies, so the next page is routed first
+        server.route(
+            "/a/?marker=1",
+            200,
+            &[],
+            b"<ListBucketResult><Name>a</Name><IsTruncated>false</IsTruncated><Contents><Key>2</Key><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+        );
+        server.route(
+            "/a/?marker=",
+            200,
+            &[],
+            b"<ListBucketResult><Name>a</Name><IsTruncated>true</IsTruncated><NextMarker>1</NextMarker><Contents><Key>1</Key><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+        );
+        server.route(
+            "/b/",
+            403,
+            &[],
+            b"<Error><Code>AccessDenied</Code><Message>denied</Message></Error>",
+        );
+        server.route(
+            "/c/",
+            200,
+            &[],
+            b"<ListBucketResult><Name>c</Name><IsTruncated>false</IsTruncated><Contents><Key>3</Key><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+        );
+
+        let listed = handler.la_by_bucket().unwrap();
+        assert_eq!(
+            listed.iter().map(|(b, _)| b.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        let keys = |objects: &Vec<S3Object>| -> Vec<String> {
+            objects.iter().filter_map(|o| o.key.clone()).collect()
+        };
+        assert_eq!(keys(listed[0].1.as_ref().unwrap()), vec!["/1", "/2"]);
+        assert!(matches!(
+            listed[1].1,
+            Err(Error::S3Error { status: 403, .. })
+        ));
+        assert_eq!(keys(listed[2].1.as_ref().unwrap()), vec!["/3"]);
+
+        // the failed bucket fails the whole listing
+        client.respond(
+            StatusCode::OK,
+            b"<ListAllMyBucketsResult><Buckets><Bucket><Name>c</Name></Bucket><Bucket><Name>b</Name></Bucket></Buckets></ListAllMyBucketsResult>",
+            &[],
+        );
+        assert!(handler.la().is_err());
+        client.respond(
+            StatusCode::OK,
+            b"<ListAllMyBucketsResult><Buckets><Bucket><Name>c</Name></Bucket></Buckets></ListAllMyBucketsResult>",
+            &[],
+        );
+        assert_eq!(keys(&handler.la().unwrap()), vec!["/3"]);
+    }
+    #[test]
+    fn test_cache_bucket_region() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::MOVED_PERMANENTLY,
+            b"bucket.s3.eu-west-1.mock",
+            &[("x-amz-bucket-region", "eu-west-1")],
+        );
+        handler.del("s3://bucket/key").unwrap();
+        handler.del("s3://bucket/key2").unwrap();
+        handler.del("s3://other/key").unwrap();
+        let hosts: Vec<String> = client.requests().into_iter().map(|r| r.host).collect();
+        // the second request to the bucket skips the redirect
+        assert_eq!(
+            hosts,
+            vec![
+                "s3.mock",
+                "bucket.s3.eu-west-1.mock",
+                "bucket.s3.eu-west-1.mock",
+                "s3.mock"
+            ]
+        );
+        assert_eq!(
+            handler.bucket_regions["bucket"],
+            (
+                "eu-west-1".to_string(),
+                "bucket.s3.eu-west-1.mock".to_string()
+            )
+        );
+
+        // the failure after the redirect is returned as an error
+        client.respond(
+            StatusCode::MOVED_PERMANENTLY,
+            b"forbidden.s3.eu-west-1.mock",
+            &[("x-amz-bucket-region", "eu-west-1")],
+        );
+        client.respond(
+            StatusCode::FORBIDDEN,
+            b"<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+            &[],
+        );
+        let err = handler.del("s3://forbidden/key").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::S3Error { status: 403, .. })
+        ));
+    }
+    #[test]
+    fn test_conditional_delete() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler
+            .del_with_flag("s3://bucket/key", &mut Vec::new(), Some("etag"))
+            .unwrap();
+        let request = &client.requests()[0];
+        assert_eq!(request.method, "DELETE");
+        assert_eq!(request.uri, "/bucket/key");
+        assert_eq!(
+            request.headers,
+            vec![("If-Match".to_string(), "etag".to_string())]
+        );
+
+        client.respond(StatusCode::PRECONDITION_FAILED, &[], &[]);
+        let err = handler
+            .del_with_flag("s3://bucket/key", &mut Vec::new(), Some("other-etag"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::PreconditionFailed())
+        ));
+    }
+    #[test]
+    fn test_sync_to_s3() {
+        let dir = std::env::temp_dir().join("s3handler_test_sync_to_s3");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), b"changed").unwrap();
+        std::fs::write(dir.join("same.txt"), b"content").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), b"new").unwrap();
+        let listing = b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>backup/a.txt</Key><LastModified>2100-01-01T00:00:00.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>3</Size></Contents><Contents><Key>backup/extra.txt</Key><LastModified>2100-01-01T00:00:00.000Z</LastModified><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents><Contents><Key>backup/same.txt</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified><ETag>&quot;9a0364b9e99bb480dd25e1f0284c8555&quot;</ETag><Size>7</Size></Contents></ListBucketResult>";
+        let dir = dir.to_str().unwrap();
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(StatusCode::OK, listing, &[]);
+        let options = SyncOptions {
+            delete: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let plan = handler.sync(dir, "s3://bucket/backup", options).unwrap();
+        // The files modified after the objects are transferred
+        assert_eq!(plan.transfer, vec!["a.txt", "same.txt", "sub/b.txt"]);
+        assert_eq!(plan.delete, vec!["extra.txt"]);
+        assert_eq!(client.requests().len(), 1);
+        assert_eq!(
+            client.requests()[0].query_strings[0],
+            ("prefix".to_string(), "backup/".to_string())
+        );
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        // The file modified before the object is not transferred
+        client.respond(
+            StatusCode::OK,
+            &String::from_utf8_lossy(listing)
+                .replace("2000-01-01", "2100-01-01")
+                .into_bytes(),
+            &[],
+        );
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        handler.sync(dir, "s3://bucket/backup/", options).unwrap();
+        let requests: Vec<(String, String, Vec<u8>)> = client
+            .requests()
+            .into_iter()
+            .skip(1)
+            .map(|r| (r.method, r.uri, r.payload))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                (
+                    "PUT".to_string(),
+                    "/bucket/backup/a.txt".to_string(),
+                    b"changed".to_vec()
+                ),
+                (
+                    "PUT".to_string(),
+                    "/bucket/backup/sub/b.txt".to_string(),
+                    b"new".to_vec()
+                ),
+                (
+                    "DELETE".to_string(),
+                    "/bucket/backup/extra.txt".to_string(),
+                    Vec::new()
+                ),
+            ]
+        );
+
+        // The objects are compared by the ETags, and the extra one is kept without `delete`
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            &String::from_utf8_lossy(listing)
+                .replace(
+                    "5050ef3558233dc04b3fac50eff68de1",
+                    "9a0364b9e99bb480dd25e1f0284c8555",
+                )
+                .replace("<Size>3</Size>", "<Size>7</Size>")
+                .into_bytes(),
+            &[],
+        );
+        std::fs::write(format!("{}/a.txt", dir), b"content").unwrap();
+        let options = SyncOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        let plan = handler.sync(dir, "s3://bucket/backup/", options).unwrap();
+        assert_eq!(plan.transfer, vec!["sub/b.txt"]);
+        assert!(plan.delete.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sync_from_s3() {
+        let dir = std::env::temp_dir().join("s3handler_test_sync_from_s3");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep.txt"), b"keep").unwrap();
+        std::fs::write(dir.join("stale.txt"), b"stale").unwrap();
+
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>docs/</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified><ETag>&quot;d41d8cd98f00b204e9800998ecf8427e&quot;</ETag><Size>0</Size></Contents><Contents><Key>docs/a.txt</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified><ETag>&quot;9a0364b9e99bb480dd25e1f0284c8555&quot;</ETag><Size>7</Size></Contents><Contents><Key>keep.txt</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified><ETag>&quot;e&quot;</ETag><Size>4</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(StatusCode::OK, b"", &[("content-length", "7")]);
+        client.respond(StatusCode::OK, b"content", &[]);
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        let plan = handler
+            .sync("s3://bucket", dir.to_str().unwrap(), options)
+            .unwrap();
+        assert_eq!(plan.transfer, vec!["docs/a.txt"]);
+        assert_eq!(plan.delete, vec!["stale.txt"]);
+        assert_eq!(std::fs::read(dir.join("docs/a.txt")).unwrap(), b"content");
+        assert_eq!(std::fs::read(dir.join("keep.txt")).unwrap(), b"keep");
+        assert!(!dir.join("stale.txt").exists());
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].method, "GET");
+        assert_eq!(requests[2].uri, "/bucket/docs/a.txt");
+
+        // The missing folder is synced as an empty one
+        std::fs::remove_dir_all(&dir).unwrap();
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>keep.txt</Key><ETag>&quot;e&quot;</ETag><Size>4</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(StatusCode::OK, b"", &[("content-length", "4")]);
+        client.respond(StatusCode::OK, b"keep", &[]);
+        let plan = handler
+            .sync(
+                "s3://bucket/",
+                dir.to_str().unwrap(),
+                SyncOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(plan.transfer, vec!["keep.txt"]);
+        assert_eq!(std::fs::read(dir.join("keep.txt")).unwrap(), b"keep");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The key escaping the folder is refused before anything is downloaded
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>docs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>1</Size></Contents><Contents><Key>docs/../../s3handler_test_sync_escape.txt</Key><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        assert!(handler
+            .sync(
+                "s3://bucket/docs/",
+                dir.to_str().unwrap(),
+                SyncOptions::default(),
+            )
+            .is_err());
+        assert_eq!(client.requests().len(), 1);
+        assert!(!dir.exists());
+        assert!(!std::env::temp_dir()
+            .join("s3handler_test_sync_escape.txt")
+            .exists());
+    }
+
+    #[test]
+    fn test_sync_prune_with_truncated_listing() {
+        let dir = std::env::temp_dir().join("s3handler_test_sync_truncated");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), b"a").unwrap();
+        std::fs::write(dir.join("b.txt"), b"b").unwrap();
+        let options = SyncOptions {
+            delete: true,
+            dry_run: true,
+            ..Default::default()
+        };
+
+        // The truncated page without the NextMarker is continued by the last key
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>a.txt</Key><ETag>&quot;a&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>b.txt</Key><ETag>&quot;b&quot;</ETag><Size>1</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        let plan = handler
+            .sync("s3://bucket", dir.to_str().unwrap(), options.clone())
+            .unwrap();
+        assert!(plan.delete.is_empty());
+        assert_eq!(
+            client.requests()[1].query_strings[1],
+            ("marker".to_string(), "a.txt".to_string())
+        );
+
+        // Nothing is pruned if the listing can not continue
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated></ListBucketResult>",
+            &[],
+        );
+        let options = SyncOptions {
+            dry_run: false,
+            ..options
+        };
+        assert!(handler
+            .sync("s3://bucket", dir.to_str().unwrap(), options.clone())
+            .is_err());
+        assert!(dir.join("a.txt").exists());
+        assert!(dir.join("b.txt").exists());
+
+        // The same for the JSON listing of Ceph
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.set_format(Format::JSON);
+        client.respond(
+            StatusCode::OK,
+            br#"{"Name":"bucket","IsTruncated":true,"Contents":[]}"#,
+            &[],
+        );
+        assert!(handler
+            .sync("s3://bucket", dir.to_str().unwrap(), options)
+            .is_err());
+        assert!(dir.join("a.txt").exists());
+        assert!(dir.join("b.txt").exists());
+        assert_eq!(client.requests().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_del_prefix() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>logs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/b&amp;c.txt</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<DeleteResult><Error><Key>logs/b&amp;c.txt</Key><Code>AccessDenied</Code></Error></DeleteResult>",
+            &[],
+        );
+        // The key failed to delete is listed again, but not retried
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/b&amp;c.txt</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        assert_eq!(handler.del_prefix("s3://bucket/logs/").unwrap(), 1);
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(
+            requests[0].query_strings[0],
+            ("prefix".to_string(), "logs/".to_string())
+        );
+        // The truncated page without the NextMarker is continued by the last key
+        assert_eq!(
+            requests[1].query_strings[1],
+            ("marker".to_string(), "logs/a.txt".to_string())
+        );
+        let delete = &requests[2];
+        assert_eq!(delete.method, "POST");
+        assert_eq!(
+            delete.query_strings,
+            vec![("delete".to_string(), "".to_string())]
+        );
+        assert_eq!(
+            std::str::from_utf8(&delete.payload).unwrap(),
+            "<Delete><Quiet>true</Quiet><Object><Key>logs/a.txt</Key></Object><Object><Key>logs/b&amp;c.txt</Key></Object></Delete>"
+        );
+        assert_eq!(
+            delete.headers,
+            vec![("Content-MD5".to_string(), content_md5(&delete.payload))]
+        );
+
+        // The key written during the deletion is removed in the next round
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(StatusCode::OK, b"<DeleteResult></DeleteResult>", &[]);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/d.txt</Key><ETag>&quot;d&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(StatusCode::OK, b"<DeleteResult></DeleteResult>", &[]);
+        assert_eq!(handler.del_prefix("s3://bucket/logs/").unwrap(), 2);
+        assert_eq!(
+            std::str::from_utf8(&client.requests()[3].payload).unwrap(),
+            "<Delete><Quiet>true</Quiet><Object><Key>logs/d.txt</Key></Object></Delete>"
+        );
+        assert_eq!(client.requests().len(), 5);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        handler.dry_run(true);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        assert_eq!(handler.del_prefix("s3://bucket/logs/").unwrap(), 1);
+        handler.del("s3://bucket/b.txt").unwrap();
+        let file = std::env::temp_dir().join("s3handler_test_dry_run.txt");
+        std::fs::write(&file, b"content").unwrap();
+        handler
+            .put(file.to_str().unwrap(), "s3://bucket/c.txt")
+            .unwrap();
+        std::fs::remove_file(&file).unwrap();
+        handler.rb("s3://bucket").unwrap();
+
+        assert!(client.requests().iter().all(|r| r.method == "GET"));
+        let planned = handler.take_planned();
+        assert_eq!(
+            planned
+                .iter()
+                .map(|a| (a.method.as_str(), a.target.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("POST", "s3://bucket"),
+                ("DELETE", "s3://bucket/b.txt"),
+                ("PUT", "s3://bucket/c.txt"),
+                ("DELETE", "s3://bucket"),
+            ]
+        );
+        assert_eq!(
+            planned[0].query,
+            vec![("delete".to_string(), "".to_string())]
+        );
+        assert!(handler.take_planned().is_empty());
+    }
+
+    #[test]
+    fn test_ls_since() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>logs/b</NextMarker><Contents><Key>logs/a</Key><LastModified>2020-09-19T15:04:45.999Z</LastModified><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>logs/b</Key><LastModified>2020-09-19T15:04:46.000Z</LastModified><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/c</Key><LastModified>2020-01-01T00:00:00.000Z</LastModified><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents><Contents><Key>logs/d</Key><LastModified>2021-01-01T00:00:00.000Z</LastModified><ETag>&quot;d&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        let since = "2020-09-19T15:04:46Z".parse::<DateTime<Utc>>().unwrap();
+        let keys: Vec<String> = handler
+            .ls_since(Some("s3://bucket/logs/"), since)
+            .unwrap()
+            .into_iter()
+            .filter_map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["/logs/b", "/logs/d"]);
+        assert_eq!(
+            client.requests().last().unwrap().query_strings,
+            vec![
+                ("prefix".to_string(), "logs/".to_string()),
+                ("marker".to_string(), "logs/b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_du() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>logs/2021/a.log</Key><ETag>&quot;a&quot;</ETag><Size>3000000000</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/2022/b.log</Key><ETag>&quot;b&quot;</ETag><Size>3000000000</Size><StorageClass>GLACIER</StorageClass></Contents><Contents><Key>logs/c.log</Key><ETag>&quot;c&quot;</ETag><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>",
+            &[],
+        );
+        let summary = handler
+            .du_with_delimiter("s3://bucket/logs/", Some("/"))
+            .unwrap();
+        assert_eq!(summary.objects, 3);
+        assert_eq!(summary.total_bytes, 6_000_000_007);
+        assert_eq!(summary.by_storage_class["GLACIER"], 3_000_000_000);
+        assert_eq!(
+            summary.sub_prefixes["logs/2021/"].total_bytes,
+            3_000_000_000
+        );
+        assert_eq!(summary.sub_prefixes["logs/2022/"].objects, 1);
+        assert_eq!(summary.sub_prefixes.len(), 2);
+        assert_eq!(
+            client.requests()[0].query_strings,
+            vec![
+                ("prefix".to_string(), "logs/".to_string()),
+                ("marker".to_string(), "".to_string())
+            ]
+        );
+        // AWS leaves out the NextMarker, so the last key continues the listing
+        assert_eq!(
+            client.requests()[1].query_strings[1],
+            ("marker".to_string(), "logs/2021/a.log".to_string())
+        );
+        assert!(handler.du("s3://").is_err());
+    }
+
+    #[test]
+    fn test_ls_filtered() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>data/a/1.parquet.crc</NextMarker><Contents><Key>data/a/1.parquet</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>data/a/1.parquet.crc</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>data/b.parquet</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        let keys: Vec<String> = handler
+            .ls_filtered("s3://bucket", &Glob::new("data/**/*.parquet"))
+            .unwrap()
+            .into_iter()
+            .filter_map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["/data/a/1.parquet", "/data/b.parquet"]);
+        assert_eq!(
+            client.requests()[0].query_strings,
+            vec![
+                ("prefix".to_string(), "data/".to_string()),
+                ("marker".to_string(), "".to_string())
+            ]
+        );
+        assert!(handler.ls_filtered("s3://", &Glob::suffix(".log")).is_err());
+    }
+
+    #[test]
+    fn test_ls_with_empty_truncated_page() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker><Contents><Key>a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>b.txt</NextMarker></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>c.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        let keys: Vec<String> = handler
+            .ls(Some("s3://bucket"))
+            .unwrap()
+            .into_iter()
+            .filter_map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["/a.txt", "/c.txt"]);
+        assert_eq!(
+            client.requests().last().unwrap().query_strings,
+            vec![
+                ("prefix".to_string(), "".to_string()),
+                ("marker".to_string(), "b.txt".to_string())
+            ]
+        );
+
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker></ListBucketResult>",
+            &[],
+        );
+        let err = handler.ls(Some("s3://bucket")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error in RequestPool: listing did not advance"
+        );
+    }
+
+    #[test]
+    fn test_ls_without_next_marker() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        // AWS leaves out the NextMarker of a truncated page without a delimiter
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>b.txt</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>c.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+            &[],
+        );
+        let keys: Vec<String> = handler
+            .ls(Some("s3://bucket"))
+            .unwrap()
+            .into_iter()
+            .filter_map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["/a.txt", "/b.txt", "/c.txt"]);
+        assert_eq!(
+            client.requests().last().unwrap().query_strings,
+            vec![
+                ("prefix".to_string(), "".to_string()),
+                ("marker".to_string(), "b.txt".to_string())
+            ]
+        );
+
+        // A truncated page without a marker and a key to continue fails the listing
+        client.respond(
+            StatusCode::OK,
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated></ListBucketResult>",
+            &[],
+        );
+        assert!(handler.ls(Some("s3://bucket")).is_err());
+    }
+
+    #[test]
+    fn test_get_with_response_overrides() {
+        let client = MockClient::default();
+        let mut handler = mock_handler(&client);
+        let options = GetOptions {
+            response_content_type: Some("text/csv".to_string()),
+            response_content_disposition: Some("attachment; filename=\"report.csv\"".to_string()),
+        };
+        client.respond(StatusCode::OK, b"a,b", &[("content-type", "text/csv")]);
+        let (content, content_type) = handler
+            .cat_with_options("s3://bucket/report.bin", &options)
+            .unwrap();
+        assert_eq!(content, "a,b");
+        assert_eq!(content_type.as_deref(), Some("text/csv"));
+
+        let file = "/tmp/s3handler-get-with-options-test.bin";
+        client.respond(StatusCode::OK, b"", &[]);
+        client.respond(StatusCode::OK, b"a,b", &[]);
+        handler
+            .get_with_options("s3://bucket/report.bin", Some(file), &options)
+            .unwrap();
+        assert_eq!(std::fs::read(file).unwrap(), b"a,b");
+
+        let requests = client.requests();
+        let expected = vec![
+            ("response-content-type".to_string(), "text/csv".to_string()),
+            (
+                "response-content-disposition".to_string(),
+                "attachment; filename=\"report.csv\"".to_string(),
+            ),
+        ];
+        assert_eq!(requests[0].query_strings, expected);
+        assert_eq!(requests[1].method, "HEAD");
+        assert!(requests[1].query_strings.is_empty());
+        assert_eq!(requests[2].query_strings, expected);
+    }
 }
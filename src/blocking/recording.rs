@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use chrono::Duration;
+use reqwest::{header::HeaderMap, StatusCode};
+
+use crate::blocking::{acquire, Format, S3Client};
+use crate::error::Error;
+use crate::utils::sign::{hash_payload, RecordedRequest, SignDebug};
+
+/// The client signing the requests with the inner client and recording them instead of sending,
+/// every request is answered with an empty 200 response.
+pub(crate) struct RecordingClient<'a> {
+    pub inner: Box<dyn S3Client + 'a>,
+    pub recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+    /// The offset of the server clock added to the timestamp for signing
+    pub clock_offset: Duration,
+}
+
+impl RecordingClient<'_> {
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+    ) -> Result<(StatusCode, Vec<u8>, HeaderMap), Error> {
+        let (url, request_headers) = self.inner.sign_request(
+            method,
+            host,
+            uri,
+            query_strings,
+            headers,
+            payload,
+            Utc::now() + self.clock_offset,
+        )?;
+        acquire(&self.recorded)?.push(RecordedRequest::new(
+            method,
+            &url,
+            &request_headers,
+            &hash_payload(payload),
+        ));
+        Ok((StatusCode::OK, Vec::new(), HeaderMap::new()))
+    }
+}
+
+impl S3Client for RecordingClient<'_> {
+    fn request(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+    ) -> Result<(StatusCode, Vec<u8>, HeaderMap), Error> {
+        self.record(method, host, uri, query_strings, headers, payload)
+    }
+    fn request_file(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        file: &File,
+    ) -> Result<(StatusCode, Vec<u8>, HeaderMap), Error> {
+        // the file is smaller than a part, so it is read into memory for signing
+        let mut fin = file.try_clone()?;
+        fin.seek(SeekFrom::Start(0))?;
+        let mut payload = Vec::new();
+        fin.read_to_end(&mut payload)?;
+        self.record(method, host, uri, query_strings, headers, &payload)
+    }
+    fn sign_request(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+        utc: DateTime<Utc>,
+    ) -> Result<(String, HeaderMap), Error> {
+        self.inner
+            .sign_request(method, host, uri, query_strings, headers, payload, utc)
+    }
+    fn debug_sign(
+        &self,
+        method: &str,
+        host: &str,
+        uri: &str,
+        query_strings: &mut Vec<(&str, &str)>,
+        headers: &mut Vec<(&str, &str)>,
+        payload: &[u8],
+        utc: DateTime<Utc>,
+    ) -> Result<SignDebug, Error> {
+        self.inner
+            .debug_sign(method, host, uri, query_strings, headers, payload, utc)
+    }
+    fn redirect_parser(&self, body: Vec<u8>, format: Format) -> Result<String, Error> {
+        self.inner.redirect_parser(body, format)
+    }
+    fn update(&mut self, region: String, secure: bool) {
+        self.inner.update(region, secure)
+    }
+    fn update_clock_offset(&mut self, offset: Duration) {
+        self.clock_offset = offset;
+        self.inner.update_clock_offset(offset)
+    }
+    fn current_region(&self) -> Option<String> {
+        self.inner.current_region()
+    }
+}
@@ -1,12 +1,16 @@
 use std::default::Default;
-use std::fmt::Debug;
-use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
 use std::{thread, time};
 
-use crate::blocking::aws::{AWS2Client, AWS4Client};
-use crate::blocking::{AuthType, S3Client};
+use crate::blocking::aws::{AWS2Client, AWS4Client, HttpClient};
+use crate::blocking::{acquire, record_request, AuthType, S3Client};
 use crate::error::Error;
-use log::{debug, info};
+use crate::utils::credentials::CredentialProvider;
+use crate::utils::metrics::Metrics;
+use crate::utils::{complete_multipart_xml, s3_error};
+use chrono::Duration;
+use log::{debug, error, info};
 
 #[derive(Default)]
 pub struct MultiUploadParameters {
@@ -15,40 +19,31 @@ pub struct MultiUploadParameters {
 }
 
 pub struct UploadRequestPool {
-    ch_data: Option<mpsc::Sender<Box<MultiUploadParameters>>>,
+    ch_data: Option<mpsc::SyncSender<Box<MultiUploadParameters>>>,
     ch_result: mpsc::Receiver<Result<(usize, reqwest::header::HeaderMap), Error>>,
     total_worker: usize,
     total_jobs: usize,
 }
 
-#[allow(clippy::needless_lifetimes)]
-fn acquire<'a, T>(s: &'a Arc<Mutex<T>>) -> MutexGuard<'a, T>
-where
-    T: Debug,
-{
-    let mut l = s.lock();
-    while l.is_err() {
-        thread::sleep(time::Duration::from_millis(1000));
-        info!("sleep and wait for lock... error: {:?}", l);
-        l = s.lock();
-    }
-    l.expect("lock acuired")
-}
-
 #[allow(clippy::too_many_arguments)]
 impl UploadRequestPool {
     pub fn new(
         auth_type: AuthType,
         secure: bool,
-        access_key: String,
-        secret_key: String,
+        credentials: Arc<dyn CredentialProvider>,
         host: String,
         uri: String,
         region: String,
         upload_id: String,
         total_worker: usize,
+        clock_offset: Duration,
+        http: HttpClient,
+        default_headers: Vec<(String, String)>,
+        metrics: Arc<dyn Metrics>,
     ) -> Self {
-        let (ch_s, ch_r) = mpsc::channel();
+        // The parts read ahead are bounded, so a fast disk with a slow network does not buffer
+        // the whole file, and `run` is blocked until a worker takes a part.
+        let (ch_s, ch_r) = mpsc::sync_channel(total_worker * 2);
         let a_ch_r = Arc::new(Mutex::new(ch_r));
         let (ch_result_s, ch_result_r) = mpsc::channel();
         let a_ch_result_s = Arc::new(Mutex::new(ch_result_s));
@@ -57,37 +52,47 @@ impl UploadRequestPool {
             let a_ch_r2 = a_ch_r.clone();
             let a_ch_result_s2 = a_ch_result_s.clone();
             let upload = upload_id.clone();
-            let akey = access_key.clone();
-            let skey = secret_key.clone();
+            let credentials = credentials.clone();
             let h = host.clone();
             let u = uri.clone();
             let r = region.clone();
+            let http = http.clone();
+            let hs = default_headers.clone();
+            let metrics = metrics.clone();
 
             std::thread::spawn(move || loop {
                 let s3_client: Box<dyn S3Client> = match auth_type {
                     AuthType::AWS2 => Box::new(AWS2Client {
                         tls: secure,
-                        access_key: &akey,
-                        secret_key: &skey,
+                        credentials: credentials.clone(),
+                        clock_offset,
+                        http: http.clone(),
                     }),
                     AuthType::AWS4 => Box::new(AWS4Client {
                         tls: secure,
-                        access_key: &akey,
-                        secret_key: &skey,
-                        host: &h,
+                        credentials: credentials.clone(),
+                        host: h.clone(),
                         region: r.to_string(),
+                        clock_offset,
+                        http: http.clone(),
                     }),
                 };
                 let recv_end = a_ch_r2.lock().expect("worker recv end is expected");
-                let result_send_back_ch = acquire(&a_ch_result_s2);
+                let result_send_back_ch = match acquire(&a_ch_result_s2) {
+                    Ok(ch) => ch,
+                    Err(e) => {
+                        error!("worker can not send back result: {}", e);
+                        return;
+                    }
+                };
                 loop {
                     let p: Box<MultiUploadParameters> = match recv_end.recv() {
                         Ok(p) => p,
                         Err(e) => {
-                            let r = acquire(&a_ch_result_s2);
-                            r.send(Err(Error::RequestPoolError(format!("{:?}", e))))
-                                .ok();
-                            drop(r);
+                            if let Ok(r) = acquire(&a_ch_result_s2) {
+                                r.send(Err(Error::RequestPoolError(format!("{:?}", e))))
+                                    .ok();
+                            }
                             return;
                         }
                     };
@@ -100,7 +105,8 @@ impl UploadRequestPool {
                     }
 
                     info!("Part {} uploading ...", p.part_number);
-                    match s3_client.request(
+                    let start = Instant::now();
+                    let result = s3_client.request(
                         "PUT",
                         &h,
                         &u,
@@ -108,9 +114,26 @@ impl UploadRequestPool {
                             ("uploadId", upload.as_str()),
                             ("partNumber", p.part_number.to_string().as_str()),
                         ],
-                        &mut Vec::new(),
+                        &mut hs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
                         &p.payload,
-                    ) {
+                    );
+                    record_request(
+                        metrics.as_ref(),
+                        "PUT",
+                        &h,
+                        start,
+                        p.payload.len() as u64,
+                        &result,
+                    );
+                    match result {
+                        Ok((status, body, headers))
+                            if status.is_client_error() || status.is_server_error() =>
+                        {
+                            info!("Error on uploading Part {}: {}", p.part_number, status);
+                            result_send_back_ch
+                                .send(Err(s3_error(status, &headers, &body)))
+                                .expect("channel is full to handle messages");
+                        }
                         Ok(result) => {
                             let mut send_result =
                                 result_send_back_ch.send(Ok((p.part_number, result.2.clone())));
@@ -124,10 +147,10 @@ impl UploadRequestPool {
                         }
                         Err(err) => {
                             info!("Error on uploading Part {}: {}", p.part_number, err);
-                            let rs = acquire(&a_ch_result_s2);
-                            rs.send(Err(err))
+                            // the sender is already held by this worker
+                            result_send_back_ch
+                                .send(Err(err))
                                 .expect("channel is full to handle messages");
-                            drop(rs);
                         }
                     };
                 }
@@ -166,8 +189,11 @@ impl UploadRequestPool {
     pub fn wait(mut self) -> Result<String, Error> {
         let mut results = Vec::new();
         self.ch_data.take();
+        // no result will be sent back without any part, and S3 needs at least one part
+        if self.total_jobs == 0 {
+            return Err(Error::UserError("no part is uploaded"));
+        }
         loop {
-            thread::sleep(time::Duration::from_millis(1000));
             let result = self
                 .ch_result
                 .recv()
@@ -177,7 +203,7 @@ impl UploadRequestPool {
             info!("{} parts uploaded", results.len());
             if results.len() == self.total_jobs {
                 self.close();
-                let mut content = "<CompleteMultipartUpload>".to_string();
+                let mut parts = Vec::new();
                 for res in results {
                     debug!("{:?}", res);
                     let r = res?;
@@ -187,13 +213,9 @@ impl UploadRequestPool {
                         .expect("unexpected etag from server");
 
                     info!("part: {}, etag: {}", part, etag);
-                    content.push_str(&format!(
-                        "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
-                        part, etag
-                    ));
+                    parts.push((part, etag.to_string()));
                 }
-                content.push_str(&"</CompleteMultipartUpload>".to_string());
-                return Ok(content);
+                return Ok(complete_multipart_xml(&parts));
             }
         }
     }
@@ -0,0 +1,256 @@
+//! A blocking handler wraps the async `S3Pool` with a current thread runtime,
+//! such that the signing, parsing and multipart logic are shared with the async api.
+//! The `blocking` module is kept for a deprecation cycle.
+//! ```
+//! let config = s3handler::CredentialConfig{
+//!     host: "s3.us-east-1.amazonaws.com".to_string(),
+//!     access_key: "akey".to_string(),
+//!     secret_key: "skey".to_string(),
+//!     user: None,
+//!     region: None, // default is us-east-1
+//!     s3_type: None, // default will try to config as AWS S3 handler
+//!     secure: None, // dafault is false, because the integrity protect by HMAC
+//!     part_size: None, // default is automatic for downloads, 5MB for uploads
+//!     worker_count: None, // default is 10
+//! };
+//! let handler = s3handler::blocking2::Handler::from(&config);
+//! let _ = handler.la();
+//! ```
+
+use std::fs::{read, write};
+use std::path::Path;
+
+use mime_guess::from_path;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::blocking::{self, CredentialConfig};
+use crate::error::Error;
+use crate::tokio_async::primitives::S3Pool;
+use crate::tokio_async::traits::{DataPool, Filter};
+use crate::utils::S3Object;
+
+pub struct Handler {
+    pool: S3Pool,
+    runtime: Runtime,
+}
+
+impl Handler {
+    pub fn new(pool: S3Pool) -> Result<Self, Error> {
+        Ok(Handler {
+            pool,
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// The pool handling the requests
+    pub fn pool(&self) -> &S3Pool {
+        &self.pool
+    }
+
+    /// List all objects in all buckets
+    pub fn la(&self) -> Result<Vec<S3Object>, Error> {
+        let mut output = Vec::new();
+        for bucket in self.ls(None)? {
+            output.extend(self.list_objects(bucket, None)?);
+        }
+        Ok(output)
+    }
+
+    /// List all bucket of an account or List all object of an bucket
+    pub fn ls(&self, prefix: Option<&str>) -> Result<Vec<S3Object>, Error> {
+        let s3_object = S3Object::from(prefix.unwrap_or("s3://"));
+        match s3_object.bucket {
+            Some(_) => {
+                let prefix = s3_object
+                    .key
+                    .as_ref()
+                    .map(|k| Filter::Prefix(k.trim_start_matches('/').to_string()));
+                self.list_objects(
+                    S3Object {
+                        key: None,
+                        ..s3_object
+                    },
+                    prefix,
+                )
+            }
+            None => self.runtime.block_on(self.pool.list_buckets()),
+        }
+    }
+
+    fn list_objects(
+        &self,
+        bucket: S3Object,
+        filter: Option<Filter>,
+    ) -> Result<Vec<S3Object>, Error> {
+        self.runtime.block_on(async {
+            let mut output = Vec::new();
+            let mut folder = self.pool.list(Some(bucket), &filter).await?;
+            while let Some(obj) = folder.next_object().await? {
+                output.push(obj);
+            }
+            Ok(output)
+        })
+    }
+
+    /// Upload a file to a S3 bucket
+    pub fn put(&self, file: &str, dest: &str) -> Result<(), Error> {
+        if file.is_empty() || dest.is_empty() {
+            return Err(Error::UserError("please specify the file and the destiney"));
+        }
+        let (bucket, key) = match S3Object::from(dest) {
+            S3Object {
+                bucket: Some(bucket),
+                key: Some(key),
+                ..
+            } => (bucket, key),
+            S3Object {
+                bucket: Some(bucket),
+                key: None,
+                ..
+            } => (
+                bucket,
+                Path::new(file)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+                    .ok_or(Error::UserError("please specify the file and the destiney"))?,
+            ),
+            _ => return Err(Error::ModifyEmptyBucketError()),
+        };
+        let content = read(file)?;
+        self.runtime.block_on(self.pool.put_object(
+            &bucket,
+            &key,
+            content.into(),
+            from_path(Path::new(file)).first_raw(),
+        ))
+    }
+
+    /// Download an object from S3 service
+    pub fn get(&self, src: &str, file: Option<&str>) -> Result<(), Error> {
+        let (bucket, key) = bucket_and_key(src)?;
+        let fout = match file {
+            Some(fname) => fname,
+            None => Path::new(src)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or("s3download"),
+        };
+        let content = self.runtime.block_on(self.pool.get_object(&bucket, &key))?;
+        write(fout, content)?;
+        Ok(())
+    }
+
+    /// Delete an object
+    pub fn del(&self, src: &str) -> Result<(), Error> {
+        let (bucket, key) = bucket_and_key(src)?;
+        self.runtime
+            .block_on(self.pool.delete_object(&bucket, &key))
+    }
+
+    /// list all tags of an object
+    pub fn list_tag(&self, target: &str) -> Result<Vec<(String, String)>, Error> {
+        let (bucket, key) = bucket_and_key(target)?;
+        self.runtime.block_on(self.pool.get_tagging(&bucket, &key))
+    }
+
+    /// Put tags on an object
+    pub fn add_tag(&self, target: &str, tags: &[(&str, &str)]) -> Result<(), Error> {
+        let (bucket, key) = bucket_and_key(target)?;
+        self.runtime
+            .block_on(self.pool.put_tagging(&bucket, &key, tags))
+    }
+
+    /// Remove the tags from an object
+    pub fn del_tag(&self, target: &str) -> Result<(), Error> {
+        let (bucket, key) = bucket_and_key(target)?;
+        self.runtime
+            .block_on(self.pool.delete_tagging(&bucket, &key))
+    }
+}
+
+fn bucket_and_key(src: &str) -> Result<(String, String), Error> {
+    match S3Object::from(src) {
+        S3Object {
+            bucket: Some(bucket),
+            key: Some(key),
+            ..
+        } => Ok((bucket, key)),
+        _ => Err(Error::UserError("Please specific the object")),
+    }
+}
+
+impl From<&CredentialConfig> for Handler {
+    fn from(credential: &CredentialConfig) -> Self {
+        let handler = blocking::Handler::from(credential);
+        Handler::new(S3Pool::from(&handler)).expect("tokio runtime should be built")
+    }
+}
+
+impl From<S3Pool> for Handler {
+    fn from(pool: S3Pool) -> Self {
+        Handler::new(pool).expect("tokio runtime should be built")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::TestServer;
+
+    #[test]
+    fn test_blocking_facade() {
+        let server = TestServer::start();
+        let handler = Handler::from(S3Pool::new(server.host.clone()));
+
+        server.respond(200, &[], b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListAllMyBucketsResult><Buckets><Bucket><Name>bucket</Name><CreationDate>2020-01-31T14:58:45.000Z</CreationDate></Bucket></Buckets></ListAllMyBucketsResult>");
+        server.respond(200, &[], b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>key.txt</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>");
+        let objects = handler.la().unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].key.as_deref(), Some("/key.txt"));
+
+        let file = "/tmp/s3handler-blocking2-test.txt";
+        std::fs::write(file, b"content").unwrap();
+        handler.put(file, "s3://bucket").unwrap();
+
+        server.respond(200, &[("content-length", "7")], b"");
+        server.respond(200, &[], b"content");
+        handler.get("s3://bucket/key.txt", Some(file)).unwrap();
+        assert_eq!(std::fs::read(file).unwrap(), b"content");
+
+        handler
+            .add_tag("s3://bucket/key.txt", &[("k", "v")])
+            .unwrap();
+        server.respond(
+            200,
+            &[],
+            b"<Tagging><TagSet><Tag><Key>k</Key><Value>v</Value></Tag></TagSet></Tagging>",
+        );
+        assert_eq!(
+            handler.list_tag("s3://bucket/key.txt").unwrap(),
+            vec![("k".to_string(), "v".to_string())]
+        );
+        handler.del("s3://bucket/key.txt").unwrap();
+
+        let requests: Vec<(String, String)> = server
+            .requests()
+            .into_iter()
+            .map(|r| (r.method, r.path))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("GET".to_string(), "/".to_string()),
+                ("GET".to_string(), "/bucket/".to_string()),
+                (
+                    "PUT".to_string(),
+                    "/bucket/s3handler-blocking2-test.txt".to_string()
+                ),
+                ("HEAD".to_string(), "/bucket/key.txt".to_string()),
+                ("GET".to_string(), "/bucket/key.txt".to_string()),
+                ("PUT".to_string(), "/bucket/key.txt?tagging=".to_string()),
+                ("GET".to_string(), "/bucket/key.txt?tagging=".to_string()),
+                ("DELETE".to_string(), "/bucket/key.txt".to_string()),
+            ]
+        );
+    }
+}
@@ -4,12 +4,16 @@ pub enum Error {
     LoadError(std::io::Error),
     #[error("The response should be XML: {0:?}")]
     XMLParseError(quick_xml::Error),
+    #[error("The response should be JSON: {0:?}")]
+    JSONParseError(serde_json::Error),
     #[error("The field {0} not found in response")]
     FieldNotFound(&'static str),
     #[error("Unexpected input from user: {0}")]
     UserError(&'static str),
     #[error("Can not make a request: {0}")]
     ReqwestError(String),
+    #[error("Can not make a request with surf: {0}")]
+    SurfError(String),
     #[error("Error in RequestPool: {0}")]
     RequestPoolError(String),
     #[error("The resource with uncorrect scheme")]
@@ -26,6 +30,26 @@ pub enum Error {
     HeaderParsingError(),
     #[error("No object specified to move")]
     NoObject(),
+    #[error("Could not decompress the content: {0:?}")]
+    DecompressError(std::io::Error),
+    #[error("The precondition of the request is not met")]
+    PreconditionFailed(),
+    #[error("The payload hash is unknown, the body may be streamed")]
+    PayloadHashUnknown(),
+    #[error("The operation is not supported by the pool: {0}")]
+    Unsupported(&'static str),
+    #[error("The downloaded size {received} does not match the expected size {expected}")]
+    IntegrityError { expected: u64, received: u64 },
+    #[error("Could not load the credentials: {0}")]
+    CredentialError(String),
+    #[error("S3 error {status} {code}: {message} (request id: {request_id:?}, id 2: {host_id:?})")]
+    S3Error {
+        status: u16,
+        code: String,
+        message: String,
+        request_id: Option<String>,
+        host_id: Option<String>,
+    },
 }
 
 impl From<std::io::Error> for Error {
@@ -52,8 +76,48 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::JSONParseError(err)
+    }
+}
+
 impl From<reqwest::header::ToStrError> for Error {
     fn from(_err: reqwest::header::ToStrError) -> Self {
         Error::HeaderParsingError()
     }
 }
+
+#[cfg(feature = "async-std")]
+impl From<surf::Error> for Error {
+    fn from(err: surf::Error) -> Self {
+        Error::SurfError(err.to_string())
+    }
+}
+
+impl Error {
+    /// The http status of the response, if the error is produced from a response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::S3Error { status, .. } => Some(*status),
+            Error::PreconditionFailed() => Some(412),
+            _ => None,
+        }
+    }
+
+    /// The `x-amz-request-id` of the failed request, which is asked by AWS support
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Error::S3Error { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The `x-amz-id-2` of the failed request
+    pub fn extended_request_id(&self) -> Option<&str> {
+        match self {
+            Error::S3Error { host_id, .. } => host_id.as_deref(),
+            _ => None,
+        }
+    }
+}
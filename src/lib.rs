@@ -9,6 +9,8 @@
 //!     region: None, // default is us-east-1
 //!     s3_type: None, // default will try to config as AWS S3 handler
 //!     secure: None, // dafault is false, because the integrity protect by HMAC
+//!     part_size: None, // default is automatic for downloads, 5MB for uploads
+//!     worker_count: None, // default is 10
 //! };
 //! let mut handler = s3handler::blocking::Handler::from(&config);
 //! let _ = handler.la();
@@ -54,7 +56,7 @@
 //! let canal = bucket.toward("/path/to/another/folder").unwrap();
 //! // The canal bridges the two folder and ready to transfer data between bucket and folder
 //! assert!(canal.is_connect());
-//! // canal.sync().awit;
+//! // canal.sync(&Default::default()).await;
 //! ```
 
 #[cfg(feature = "blocking")]
@@ -62,8 +64,14 @@ pub mod blocking;
 #[cfg(feature = "blocking")]
 pub use blocking::*;
 
-// #[cfg(feature = "std-async")]
-// pub mod async_std;
+#[cfg(feature = "blocking2")]
+pub mod blocking2;
+
+#[cfg(any(feature = "tokio-async", feature = "async-std"))]
+mod async_common;
+
+#[cfg(feature = "async-std")]
+pub mod async_std;
 
 #[cfg(feature = "tokio-async")]
 pub mod tokio_async;
@@ -71,5 +79,5 @@ pub mod tokio_async;
 pub use tokio_async as none_blocking;
 
 pub mod error;
-pub use utils::{S3Convert, S3Object};
+pub use utils::{CredentialConfig, S3Convert, S3Object};
 pub mod utils;
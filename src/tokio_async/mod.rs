@@ -1,2 +1,2 @@
 pub mod primitives;
-pub mod traits;
+pub use crate::async_common::traits;
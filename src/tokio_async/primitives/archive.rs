@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{channel, Sender};
+
+use super::{Canal, PoolType};
+use crate::error::Error;
+use crate::tokio_async::traits::ByteStream;
+
+/// The size of chunk sent from the tar builder
+const CHUNK_SIZE: usize = 1048576;
+
+/// A writer cut the tar stream into chunks and send them to the uploader.
+/// The channel is bounded, so the tar builder will wait for the uploader,
+/// and the memory usage is bounded.
+struct ChunkWriter {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    sender: Sender<Result<Bytes, Error>>,
+}
+
+impl ChunkWriter {
+    fn new(chunk_size: usize, sender: Sender<Result<Bytes, Error>>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+            sender,
+        }
+    }
+
+    fn send_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.chunk_size));
+        self.sender
+            .blocking_send(Ok(chunk.into()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "uploader is closed"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= self.chunk_size {
+            self.send_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_chunk()
+    }
+}
+
+/// Build a tar stream of the `local_dir` in a blocking thread
+fn tar_stream(local_dir: &Path, chunk_size: usize) -> ByteStream {
+    let (sender, receiver) = channel(2);
+    let local_dir = local_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let error_sender = sender.clone();
+        let mut builder = tar::Builder::new(ChunkWriter::new(chunk_size, sender));
+        let r = builder
+            .append_dir_all(".", &local_dir)
+            .and_then(|_| builder.into_inner())
+            .and_then(|mut w| w.flush());
+        if let Err(e) = r {
+            error_sender.blocking_send(Err(e.into())).ok();
+        }
+    });
+    Box::pin(futures::stream::unfold(receiver, |mut r| async move {
+        r.recv().await.map(|chunk| (chunk, r))
+    }))
+}
+
+impl Canal {
+    /// Upload a local directory as a tar object without a temporary archive file.
+    /// The tar stream is built chunk by chunk and pushed into the first pool connected by the canal,
+    /// for a s3 pool the stream is uploaded part by part with multipart upload.
+    pub async fn upload_dir_as_tar(mut self, local_dir: &str, dest_key: &str) -> Result<(), Error> {
+        if !Path::new(local_dir).is_dir() {
+            return Err(Error::UserError("please specify a local directory"));
+        }
+        self = self._object(dest_key);
+        let (pool, object) = match self.default {
+            PoolType::UpPool => (self.up_pool, self.upstream_object),
+            PoolType::DownPool => (self.down_pool, self.downstream_object),
+        };
+        match (pool, object) {
+            (Some(pool), Some(object)) => {
+                pool.push_stream(object, tar_stream(Path::new(local_dir), CHUNK_SIZE))
+                    .await
+            }
+            (None, _) => Err(Error::PoolUninitializeError()),
+            (_, None) => Err(Error::NoObject()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio_async::primitives::FilePool;
+    use crate::tokio_async::traits::DataPool;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_tar_stream_in_chunks() {
+        let dir = "/tmp/s3handler-tar-stream-test";
+        std::fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        std::fs::write(format!("{}/a.txt", dir), vec![b'a'; 3000]).unwrap();
+        std::fs::write(format!("{}/sub/b.txt", dir), b"b").unwrap();
+
+        let mut stream = tar_stream(Path::new(dir), 1024);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            chunks.push(chunk.unwrap());
+        }
+        assert!(chunks.len() > 1);
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() >= 1024));
+
+        let data: Vec<u8> = chunks.concat();
+        let mut archive = tar::Archive::new(data.as_slice());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert!(paths.iter().any(|p| p.ends_with("a.txt")));
+        assert!(paths.iter().any(|p| p.ends_with("sub/b.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_upload_dir_as_tar() {
+        let dir = "/tmp/s3handler-upload-dir-test";
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(format!("{}/c.txt", dir), b"content").unwrap();
+
+        FilePool::default()
+            .target_to("/tmp")
+            .unwrap()
+            .upload_dir_as_tar(dir, "s3handler-upload-dir-test.tar")
+            .await
+            .unwrap();
+
+        let data = std::fs::read("/tmp/s3handler-upload-dir-test.tar").unwrap();
+        let mut archive = tar::Archive::new(data.as_slice());
+        assert!(archive
+            .entries()
+            .unwrap()
+            .any(|e| e.unwrap().path().unwrap().ends_with("c.txt")));
+    }
+}
@@ -0,0 +1,187 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Client;
+use url::Url;
+
+use super::s3::validate;
+use super::{Canal, PoolType};
+use crate::error::Error;
+use crate::tokio_async::traits::{DataPool, Filter, S3Folder};
+use crate::utils::{fill_meta, S3Object};
+
+/// A read-only pool for the public or pre-signed HTTP(S) urls.
+/// The host of the url is kept as the bucket, and the path with the query is kept as the key.
+/// ```
+/// use s3handler::none_blocking::primitives::HttpPool;
+///
+/// let canal = HttpPool::default()
+///     .url("https://cdn.example.com/file.tar.gz?token=abc")
+///     .unwrap()
+///     .toward("/path/to/file.tar.gz")
+///     .unwrap();
+/// assert!(canal.is_connect());
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpPool {
+    /// To use https or not, it is set up by the scheme of url
+    pub secure: bool,
+    client: Client,
+}
+
+impl Default for HttpPool {
+    fn default() -> Self {
+        Self {
+            secure: true,
+            client: Client::new(),
+        }
+    }
+}
+
+impl HttpPool {
+    pub fn url(mut self, url: &str) -> Result<Canal, Error> {
+        let url = Url::parse(url)?;
+        self.check_scheme(url.scheme())?;
+        self.secure = url.scheme() == "https";
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_string(),
+            (None, _) => return Err(Error::ResourceUrlError("url without host".to_string())),
+        };
+        let key = match url.query() {
+            Some(q) => format!("{}?{}", url.path(), q),
+            None => url.path().to_string(),
+        };
+        Ok(Canal {
+            up_pool: Some(Box::new(self)),
+            down_pool: None,
+            upstream_object: Some(S3Object {
+                bucket: Some(host),
+                key: Some(key),
+                ..Default::default()
+            }),
+            downstream_object: None,
+            default: PoolType::UpPool,
+            filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
+        })
+    }
+
+    fn endpoint(&self, desc: &S3Object) -> Result<String, Error> {
+        match desc {
+            S3Object {
+                bucket: Some(host),
+                key,
+                ..
+            } => Ok(format!(
+                "{}://{}{}",
+                if self.secure { "https" } else { "http" },
+                host,
+                key.as_deref().unwrap_or("/")
+            )),
+            _ => Err(Error::ResourceUrlError("url without host".to_string())),
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl DataPool for HttpPool {
+    async fn push(&self, _desc: S3Object, _object: Bytes) -> Result<(), Error> {
+        Err(Error::Unsupported("push on a http pool"))
+    }
+
+    async fn pull(&self, desc: S3Object) -> Result<Bytes, Error> {
+        let r = validate(self.client.get(&self.endpoint(&desc)?).send().await?).await?;
+        Ok(r.bytes().await?)
+    }
+
+    async fn list(
+        &self,
+        _index: Option<S3Object>,
+        _filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error> {
+        Err(Error::Unsupported("list on a http pool"))
+    }
+
+    async fn remove(&self, _desc: S3Object) -> Result<(), Error> {
+        Err(Error::Unsupported("remove on a http pool"))
+    }
+
+    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
+        let r = validate(self.client.head(&self.endpoint(desc)?).send().await?).await?;
+        fill_meta(desc, r.headers())
+    }
+
+    /// The metadata are read from the headers of the GET request
+    async fn pull_with_meta(&self, mut desc: S3Object) -> Result<(S3Object, Bytes), Error> {
+        let r = validate(self.client.get(&self.endpoint(&desc)?).send().await?).await?;
+        fill_meta(&mut desc, r.headers())?;
+        Ok((desc, r.bytes().await?))
+    }
+
+    fn check_scheme(&self, scheme: &str) -> Result<(), Error> {
+        match scheme.to_lowercase().as_str() {
+            "http" | "https" => Ok(()),
+            _ => Err(Error::SchemeError()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio_async::primitives::MemoryPool;
+    use crate::utils::test_server::TestServer;
+
+    #[tokio::test]
+    async fn test_pull_url_into_memory_pool() {
+        let server = TestServer::start();
+        let url = format!("http://{}/dir/file.txt?token=abc", server.host);
+
+        server.respond(200, &[("content-type", "text/plain")], b"content");
+        let memory = MemoryPool::default();
+        let mut canal = HttpPool::default().url(&url).unwrap();
+        canal.toward_pool(Box::new(memory.clone()));
+        let upstream_object = canal.upstream_object.clone().unwrap();
+        canal.pull().await.unwrap();
+        assert_eq!(
+            memory.pull(upstream_object.clone()).await.unwrap(),
+            Bytes::from_static(b"content")
+        );
+
+        server.respond(200, &[("content-length", "7"), ("etag", "\"abc\"")], b"");
+        let mut desc = upstream_object.clone();
+        let pool = HttpPool::default().url(&url).unwrap().up_pool.unwrap();
+        pool.fetch_meta(&mut desc).await.unwrap();
+        assert_eq!(desc.size, Some(7));
+        assert_eq!(desc.etag.as_deref(), Some("abc"));
+
+        server.respond(
+            404,
+            &[("x-amz-request-id", "request-id")],
+            b"<html>Not Found</html>",
+        );
+        let err = pool.pull(upstream_object.clone()).await.unwrap_err();
+        assert_eq!(err.status(), Some(404));
+        assert_eq!(err.request_id(), Some("request-id"));
+        assert!(matches!(
+            pool.list(None, &None).await,
+            Err(Error::Unsupported(_))
+        ));
+
+        let requests: Vec<(String, String)> = server
+            .requests()
+            .into_iter()
+            .map(|r| (r.method, r.path))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("GET".to_string(), "/dir/file.txt?token=abc".to_string()),
+                ("HEAD".to_string(), "/dir/file.txt?token=abc".to_string()),
+                ("GET".to_string(), "/dir/file.txt?token=abc".to_string()),
+            ]
+        );
+    }
+}
@@ -1,7 +1,12 @@
-pub use canal::{Canal, PoolType};
-pub use file::FilePool;
-pub use s3::S3Pool;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::async_common::primitives::FilePool;
+pub use crate::async_common::primitives::{
+    Canal, ListPages, ListSettings, MemoryFolder, MemoryPool, PoolType,
+};
+pub use http::HttpPool;
+pub use s3::{S3ObjectList, S3Pool};
 
-mod canal;
-mod file;
+#[cfg(all(feature = "archive", not(target_arch = "wasm32")))]
+mod archive;
+mod http;
 mod s3;
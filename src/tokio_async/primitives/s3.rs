@@ -1,186 +1,266 @@
 use async_trait::async_trait;
-use base64::encode;
 use bytes::{Bytes, BytesMut};
 use chrono::prelude::*;
-use dyn_clone::DynClone;
-use futures::future::join_all;
-use hmac::{Hmac, Mac};
+use chrono::Duration;
+use futures::{FutureExt, StreamExt};
+use log::warn;
 use reqwest::{
     header::{self, HeaderMap, HeaderName, HeaderValue},
-    Client, Method, Request, Response, Url,
+    Client, Method, Request, Response, StatusCode, Url,
 };
-use sha2::Digest;
-use sha2::Sha256 as sha2_256;
-use std::fmt;
-use url::form_urlencoded;
-
-use super::canal::{Canal, PoolType};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{Canal, ListPages, ListSettings, PoolType};
+use crate::async_common::signer::{DummySigner, Signer, V2AuthSigner, V4AuthSigner};
+#[cfg(feature = "blocking")]
 use crate::blocking::{AuthType, Handler};
 use crate::error::Error;
-use crate::tokio_async::traits::{DataPool, Filter, S3Folder};
+use crate::tokio_async::traits::{ByteStream, DataPool, Filter, S3Folder};
+use crate::utils::credentials::CredentialProvider;
+use crate::utils::metrics::{Metrics, NoopMetrics, Stopwatch};
+use crate::utils::sign::{PayloadHash, RecordedRequest};
 use crate::utils::{
-    s3object_list_xml_parser, upload_id_xml_parser, S3Convert, S3Object, UrlStyle, DEFAULT_REGION,
+    advance_marker, auto_part_size, buffer_size, clock_skew, content_md5, create_bucket_xml,
+    delete_errors_xml_parser, delete_objects_xml, escape_str, fill_meta, gunzip, is_gzip,
+    is_mutating, multipart_uploads_xml_parser, normalize_region, parts_xml_parser, region_endpoint,
+    s3_error, s3object_list_xml_parser, tagging_header, tagging_xml_parser, upload_id_xml_parser,
+    validate_bucket_name, versions_xml_parser, Action, ClientConfig, CredentialConfig, DuSummary,
+    PartInfo, S3Convert, S3Object, UploadInfo, UploadMarkers, UrlStyle, VersionEntry,
+    VersionMarkers, ACL_HEADER, CANNED_ACLS, DEFAULT_REGION, DELETE_BATCH_SIZE, METADATA_PREFIX,
+    REQUEST_PAYER_HEADER, STORAGE_CLASS_HEADER, TAGGING_HEADER,
 };
 
 type UTCTime = DateTime<Utc>;
 
-pub trait Signer: Send + Sync + DynClone + fmt::Debug {
-    /// This method will setup the header and put the authorize string
-    fn sign(&self, _request: &mut Request, _now: &UTCTime) {
-        unimplemented!()
+/// The minimum part size of multipart upload accepted by S3, except the last part
+const MIN_PART_SIZE: u64 = 5242880;
+
+/// The number of the parts transferred concurrently by default
+const DEFAULT_WORKER_COUNT: usize = 10;
+
+fn object_of(bucket: &str, key: &str) -> S3Object {
+    S3Convert::new(
+        Some(bucket.to_string()),
+        Some(key.to_string()),
+        None,
+        None,
+        None,
+        None,
+    )
+}
+
+/// Turn the failed response into `Error::S3Error` with the request ids for support
+pub(crate) async fn validate(r: Response) -> Result<Response, Error> {
+    let status = r.status();
+    if status.is_client_error() || status.is_server_error() {
+        let headers = r.headers().clone();
+        let body = r.bytes().await.unwrap_or_default();
+        Err(s3_error(status, &headers, &body))
+    } else {
+        Ok(r)
     }
+}
 
-    /// This method will be called once the resource change the region stored
-    fn update_region(&mut self, _region: String) {}
+/// The next chunk of the body
+#[cfg(not(target_arch = "wasm32"))]
+async fn next_chunk(res: &mut Option<Response>) -> Result<Option<Bytes>, Error> {
+    match res {
+        Some(r) => Ok(r.chunk().await?),
+        None => Ok(None),
+    }
 }
 
-dyn_clone::clone_trait_object!(Signer);
+/// The body is read at once on wasm32, the fetch api doesn't hand out the chunks
+#[cfg(target_arch = "wasm32")]
+async fn next_chunk(res: &mut Option<Response>) -> Result<Option<Bytes>, Error> {
+    match res.take() {
+        Some(r) => Ok(Some(r.bytes().await?)),
+        None => Ok(None),
+    }
+}
 
-/// A dummy signer if you do not want to sign any request to access a public resource
-#[derive(Clone, Debug)]
-pub struct DummySigner {}
+/// Chain the bodies of the part responses in order, and check the received size at the end
+fn part_stream(reqs: Vec<Result<Response, Error>>, expected: u64) -> ByteStream {
+    Box::pin(futures::stream::try_unfold(
+        (reqs.into_iter(), None, 0),
+        move |(mut reqs, mut current, mut received): (_, Option<Response>, u64)| async move {
+            loop {
+                if let Some(chunk) = next_chunk(&mut current).await? {
+                    received += chunk.len() as u64;
+                    return Ok(Some((chunk, (reqs, current, received))));
+                }
+                current = match reqs.next() {
+                    Some(res) => Some(validate(res?).await?),
+                    None if received == expected => return Ok(None),
+                    None => return Err(Error::IntegrityError { expected, received }),
+                };
+            }
+        },
+    ))
+}
 
-impl Signer for DummySigner {
-    fn sign(&self, _requests: &mut Request, _now: &UTCTime) {}
+fn set_content_type(headers: &mut HeaderMap, mime: Option<&str>) -> Result<(), Error> {
+    if let Some(mime) = mime {
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(mime).map_err(|_| Error::UserError("invalid content type"))?,
+        );
+    }
+    Ok(())
 }
 
-#[derive(Clone, Debug)]
-pub struct V2AuthSigner {
-    pub access_key: String,
-    pub secret_key: String,
-    pub auth_str: String,
-    pub special_header_prefix: String,
+fn set_tagging(headers: &mut HeaderMap, tagging: Option<&str>) -> Result<(), Error> {
+    if let Some(tagging) = tagging {
+        headers.insert(
+            TAGGING_HEADER,
+            HeaderValue::from_str(tagging).map_err(|_| Error::HeaderParsingError())?,
+        );
+    }
+    Ok(())
 }
 
-#[allow(dead_code)]
-impl V2AuthSigner {
-    /// new V2 auth signer compatible with AWS and CEPH
-    pub fn new(access_key: String, secret_key: String) -> Self {
-        V2AuthSigner {
-            access_key,
-            secret_key,
-            auth_str: "AWS".to_string(),
-            special_header_prefix: "x-amz".to_string(),
-        }
-    }
-    /// Setup the Auth string, if you are using customized S3
-    /// Default is "AWS"
-    pub fn auth_str(mut self, auth_str: String) -> Self {
-        self.auth_str = auth_str;
-        self
+/// The canned ACL and the request payer of the pushed object, and the storage class of the pool
+fn set_upload_options(
+    headers: &mut HeaderMap,
+    desc: &S3Object,
+    storage_class: Option<&str>,
+) -> Result<(), Error> {
+    if let Some(acl) = &desc.acl {
+        if !CANNED_ACLS.contains(&acl.as_str()) {
+            return Err(Error::UserError("please specific a valid canned ACL"));
+        }
+        headers.insert(
+            ACL_HEADER,
+            HeaderValue::from_str(acl).map_err(|_| Error::HeaderParsingError())?,
+        );
     }
+    if let Some(storage_class) = storage_class {
+        headers.insert(
+            STORAGE_CLASS_HEADER,
+            HeaderValue::from_str(storage_class).map_err(|_| Error::HeaderParsingError())?,
+        );
+    }
+    set_request_payer(headers, desc);
+    Ok(())
+}
 
-    /// Setup the Special header prefix, if you are using customized S3
-    /// Default is "x-amz"
-    pub fn special_header_prefix(mut self, special_header_prefix: String) -> Self {
-        self.special_header_prefix = special_header_prefix;
-        self
+/// The parts and the completion of a multipart upload also need the request payer
+fn set_request_payer(headers: &mut HeaderMap, desc: &S3Object) {
+    if desc.request_payer {
+        headers.insert(REQUEST_PAYER_HEADER, HeaderValue::from_static("requester"));
     }
 }
 
-impl Signer for V2AuthSigner {
-    fn sign(&self, request: &mut Request, _now: &UTCTime) {
-        let auth_string = format!(
-            "{} {}:{}",
-            self.auth_str,
-            self.access_key,
-            <Request as V2Signature>::sign(request, &self.secret_key)
+fn set_metadata(headers: &mut HeaderMap, metadata: &HashMap<String, String>) -> Result<(), Error> {
+    for (key, value) in metadata {
+        headers.insert(
+            HeaderName::from_bytes(format!("{}{}", METADATA_PREFIX, key).as_bytes())
+                .map_err(|_| Error::HeaderParsingError())?,
+            HeaderValue::from_str(value).map_err(|_| Error::HeaderParsingError())?,
         );
-        let headers = request.headers_mut();
-        headers.insert(header::AUTHORIZATION, auth_string.parse().unwrap());
     }
+    Ok(())
 }
 
-#[derive(Clone, Debug)]
-pub struct V4AuthSigner {
-    pub access_key: String,
-    pub secret_key: String,
-    pub region: String,
-    pub service: String,
-    pub action: String,
-    pub auth_str: String,
-    pub special_header_prefix: String,
+/// The header map of the name value pairs
+fn header_map_of(pairs: &[(String, String)]) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| Error::HeaderParsingError())?,
+            HeaderValue::from_str(value).map_err(|_| Error::HeaderParsingError())?,
+        );
+    }
+    Ok(headers)
 }
 
-#[allow(dead_code)]
-impl V4AuthSigner {
-    /// new V4 Auth signer for AWS and CEPH
-    pub fn new(access_key: String, secret_key: String, region: String) -> Self {
-        V4AuthSigner {
-            access_key,
-            secret_key,
-            region,
-            service: "s3".to_string(),
-            action: "aws4_request".to_string(),
-            auth_str: "AWS4-HMAC-SHA256".to_string(),
-            special_header_prefix: "x-amz".to_string(),
-        }
-    }
-    /// Default is "us-east-1"
-    pub fn region(mut self, region: String) -> Self {
-        self.region = region;
-        self
+/// The http client keeps the body as it stored in S3, and never decodes the content by itself
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(
+    not(any(feature = "native-tls", feature = "rustls")),
+    allow(unused_variables, unused_mut)
+)]
+fn new_client(client_config: &ClientConfig) -> Result<Client, Error> {
+    let mut builder = Client::builder().no_gzip();
+    if let Some(max) = client_config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
     }
-    /// Default is "s3"
-    pub fn service(mut self, service: String) -> Self {
-        self.service = service;
-        self
+    if let Some(timeout) = client_config.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
     }
-    /// Default is "aws4_request"
-    pub fn action(mut self, action: String) -> Self {
-        self.action = action;
-        self
+    if client_config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    } else if client_config.http2 == Some(false) {
+        builder = builder.http1_only();
     }
-    /// Setup the Auth string, if you are using customized S3
-    /// Default is "AWS4-HMAC-SHA256"
-    pub fn auth_str(mut self, auth_str: String) -> Self {
-        self.auth_str = auth_str;
-        self
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    {
+        builder = builder.danger_accept_invalid_certs(client_config.danger_accept_invalid_certs);
+        if let Some(pem) = &client_config.root_ca {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem)?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// The connections and the certificates are managed by the fetch api on wasm32
+#[cfg(target_arch = "wasm32")]
+fn new_client(_client_config: &ClientConfig) -> Result<Client, Error> {
+    Ok(Client::builder().build()?)
+}
+
+/// The multipart upload in progress, which is aborted in the background if it is dropped
+/// before completed, ex: a part failed or the future of the upload is cancelled.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+struct MultipartGuard {
+    /// The pool to abort the upload, None if the upload is kept
+    pool: Option<S3Pool>,
+    desc: S3Object,
+    multipart_id: String,
+}
+
+impl MultipartGuard {
+    fn new(pool: &S3Pool, desc: &S3Object, multipart_id: &str) -> Self {
+        MultipartGuard {
+            pool: pool.abort_on_drop.then(|| pool.clone()),
+            desc: desc.clone(),
+            multipart_id: multipart_id.to_string(),
+        }
     }
 
-    /// Setup the Special header prefix, if you are using customized S3
-    /// Default is "x-amz"
-    pub fn special_header_prefix(mut self, special_header_prefix: String) -> Self {
-        self.special_header_prefix = special_header_prefix;
-        self
+    /// The upload is completed, so nothing to abort
+    fn complete(mut self) {
+        self.pool = None;
     }
 }
 
-impl Signer for V4AuthSigner {
-    fn sign(&self, request: &mut Request, now: &UTCTime) {
-        let SignatureInfo {
-            signed_headers,
-            signature,
-        } = <Request as V4Signature>::sign(
-            request,
-            &self.auth_str,
-            now,
-            &self.secret_key,
-            &self.region,
-            &self.service,
-            &self.action,
-        );
-        let authorize_string = format!(
-            "{} Credential={}/{}/{}/{}/{}, SignedHeaders={}, Signature={}",
-            self.auth_str,
-            self.access_key,
-            now.format("%Y%m%d"),
-            self.region,
-            self.service,
-            self.action,
-            signed_headers,
-            signature
-        );
-        let headers = request.headers_mut();
-        headers.insert(header::AUTHORIZATION, authorize_string.parse().unwrap());
-    }
-    fn update_region(&mut self, region: String) {
-        self.region = region;
+impl Drop for MultipartGuard {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(pool), Ok(runtime)) = (self.pool.take(), tokio::runtime::Handle::try_current())
+        {
+            warn!("abort the multipart upload {}", self.multipart_id);
+            let desc = std::mem::take(&mut self.desc);
+            let multipart_id = std::mem::take(&mut self.multipart_id);
+            runtime.spawn(async move {
+                if let Err(e) = pool.abort_upload(desc, &multipart_id).await {
+                    warn!("fail to abort the multipart upload {}: {}", multipart_id, e);
+                }
+            });
+        }
     }
 }
+
 #[derive(Clone, Debug)]
 pub struct S3Pool {
     pub host: String,
+    /// The base domain of the buckets in virtual-host style, ex: a CNAME of the buckets,
+    /// default is the host.
+    pub base_domain: Option<String>,
     /// To use https or not, please note that integrity is secured by S3 protocol.
     /// If the confidentiality is not under concerned, the http is good.
     pub secure: bool,
@@ -191,16 +271,66 @@ pub struct S3Pool {
     /// The part size for multipart, default disabled.
     /// If Some the pull/push will check out the object size first and do mulitpart
     /// If None download and upload will be in one part
-    pub part_size: Option<usize>,
+    pub part_size: Option<u64>,
+
+    /// Pick the part size of downloads by the object size and the `worker_count`, default
+    /// enabled, and it is disabled once the `part_size` is pinned.
+    pub auto_part_size: bool,
+
+    /// The max number of the parts transferred concurrently, default 10.
+    pub worker_count: usize,
+
+    /// Decompress the object stored with `Content-Encoding: gzip` when pulling, default disabled.
+    pub decompress: bool,
+
+    /// Request `encoding-type=url` in the listings and decode the keys, default disabled.
+    pub encoding_type_url: bool,
+
+    /// Request `fetch-owner=true` in the V2 listings, default disabled.
+    pub fetch_owner: bool,
+
+    /// Stop the listings after the number of objects, default unlimited.
+    pub list_limit: Option<usize>,
+
+    /// Stop the listings after the number of requests, default unlimited.
+    pub list_max_requests: Option<usize>,
 
     client: Client,
 
+    /// The headers sent and signed with every request
+    default_headers: HeaderMap,
+
+    /// The hooks to record the requests
+    metrics: Arc<dyn Metrics>,
+
+    /// The settings to build the http client
+    client_config: ClientConfig,
+
     /// The signer to adapt different protocol of data source
     pub signer: Box<dyn Signer>,
 
-    objects: Vec<S3Object>,
-    filter: Option<Filter>,
-    is_truncated: bool,
+    /// The offset of the server clock in milliseconds, shared with the clones of the pool
+    clock_offset: Arc<AtomicI64>,
+
+    /// Skip the mutating requests and plan them as actions
+    dry_run: bool,
+
+    /// The actions planned in the dry-run mode, shared with the clones of the pool
+    planned: Arc<Mutex<Vec<Action>>>,
+
+    /// The regions of the buckets not in the region of the signer, which are registered or
+    /// discovered from the responses, shared with the clones of the pool
+    bucket_regions: Arc<Mutex<HashMap<String, String>>>,
+
+    /// Abort the multipart upload if the upload is dropped or failed before completed
+    abort_on_drop: bool,
+
+    /// The storage class of the pushed objects, ex: "STANDARD_IA", default decided by the server.
+    /// The storage class of the source object is not copied, it may not exist on this pool.
+    pub storage_class: Option<String>,
+
+    /// The signed requests recorded instead of being sent, shared with the clones of the pool
+    recorded: Option<Arc<Mutex<Vec<RecordedRequest>>>>,
 }
 
 impl S3Pool {
@@ -212,6 +342,8 @@ impl S3Pool {
             downstream_object: None,
             default: PoolType::UpPool,
             filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
         }
     }
 
@@ -223,23 +355,100 @@ impl S3Pool {
             downstream_object: None,
             default: PoolType::UpPool,
             filter: None,
+            preserve_mtime: false,
+            keep_prefix: false,
         }
     }
 
     pub fn new(host: String) -> Self {
         S3Pool {
             host,
+            base_domain: None,
             secure: false,
             url_style: UrlStyle::PATH,
-            client: Client::new(),
+            client: new_client(&ClientConfig::default()).expect("http client should be built"),
+            client_config: ClientConfig::default(),
+            default_headers: HeaderMap::new(),
+            metrics: Arc::new(NoopMetrics),
             signer: Box::new(DummySigner {}),
             part_size: None,
-            objects: Vec::with_capacity(1000),
-            filter: None,
-            is_truncated: false,
+            decompress: false,
+            encoding_type_url: false,
+            fetch_owner: false,
+            list_limit: None,
+            list_max_requests: None,
+            auto_part_size: true,
+            worker_count: DEFAULT_WORKER_COUNT,
+            clock_offset: Arc::new(AtomicI64::new(0)),
+            dry_run: false,
+            planned: Arc::default(),
+            bucket_regions: Arc::default(),
+            abort_on_drop: true,
+            storage_class: None,
+            recorded: None,
         }
     }
 
+    /// The pool of the AWS endpoint in the partition of the region,
+    /// ex: `s3.cn-north-1.amazonaws.com.cn` for `cn-north-1`
+    pub fn for_region(region: &str) -> Self {
+        S3Pool::new(region_endpoint(region))
+    }
+
+    /// Use https for the endpoints or not
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Accept the invalid certificates of https, ex: the self-signed certificate,
+    /// the connection is open to man-in-the-middle attacks, please prefer `root_ca`.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.client_config.danger_accept_invalid_certs = accept;
+        self.client = new_client(&self.client_config).expect("http client should be built");
+        self
+    }
+
+    /// Trust the root CA certificate in PEM, ex: the CA signing the certificate of a ceph cluster
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn root_ca(mut self, pem: &[u8]) -> Result<Self, Error> {
+        self.client_config.root_ca = Some(pem.to_vec());
+        self.client = new_client(&self.client_config)?;
+        Ok(self)
+    }
+
+    /// Keep at most `max` idle connections for a host
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Result<Self, Error> {
+        self.client_config.pool_max_idle_per_host = Some(max);
+        self.client = new_client(&self.client_config)?;
+        Ok(self)
+    }
+
+    /// Close the idle connections after the timeout, ex: 10 seconds for a haproxy dropping the
+    /// idle keep-alive connections after 15 seconds
+    pub fn pool_idle_timeout(mut self, timeout: std::time::Duration) -> Result<Self, Error> {
+        self.client_config.pool_idle_timeout = Some(timeout);
+        self.client = new_client(&self.client_config)?;
+        Ok(self)
+    }
+
+    /// Negotiate HTTP/2 with the server by ALPN, or use HTTP/1 only if disabled
+    pub fn http2(mut self, enable: bool) -> Result<Self, Error> {
+        self.client_config.http2 = Some(enable);
+        self.client_config.http2_prior_knowledge &= enable;
+        self.client = new_client(&self.client_config)?;
+        Ok(self)
+    }
+
+    /// Use HTTP/2 without the negotiation, only for the servers known to speak HTTP/2,
+    /// the requests to AWS S3 fail with it
+    pub fn http2_prior_knowledge(mut self, enable: bool) -> Result<Self, Error> {
+        self.client_config.http2_prior_knowledge = enable;
+        self.client = new_client(&self.client_config)?;
+        Ok(self)
+    }
+
     pub fn aws_v2(mut self, access_key: String, secret_key: String) -> Self {
         self.signer = Box::new(V2AuthSigner::new(access_key, secret_key));
         self.url_style = UrlStyle::PATH;
@@ -252,21 +461,39 @@ impl S3Pool {
         self
     }
 
+    /// Sign with AWS signature v2 by the credentials from the provider, ex: the rotated ones
+    pub fn aws_v2_with_provider(mut self, credentials: Arc<dyn CredentialProvider>) -> Self {
+        self.signer = Box::new(V2AuthSigner::with_provider(credentials));
+        self.url_style = UrlStyle::PATH;
+        self
+    }
+
+    /// Sign with AWS signature v4 by the credentials from the provider, ex: the rotated ones
+    pub fn aws_v4_with_provider(
+        mut self,
+        credentials: Arc<dyn CredentialProvider>,
+        region: String,
+    ) -> Self {
+        self.signer = Box::new(V4AuthSigner::with_provider(credentials, region));
+        self.url_style = UrlStyle::HOST;
+        self
+    }
+
     pub fn endpoint_and_virturalhost(&self, desc: S3Object) -> (String, Option<String>) {
-        let ((host, uri), virturalhost) = match self.url_style {
-            UrlStyle::PATH => (desc.path_style_links(self.host.clone()), None),
+        match self.url_style {
             UrlStyle::HOST => {
-                let (host, uri) = desc.virtural_host_style_links(self.host.clone());
-                ((host.clone(), uri), Some(host))
+                self.url_style
+                    .endpoint(self.virtual_host_domain(), self.secure, &desc)
             }
-        };
-        if self.secure {
-            (format!("https://{}{}", host, uri), virturalhost)
-        } else {
-            (format!("http://{}{}", host, uri), virturalhost)
+            UrlStyle::PATH => self.url_style.endpoint(&self.host, self.secure, &desc),
         }
     }
 
+    /// The domain of the buckets in virtual-host style
+    fn virtual_host_domain(&self) -> &str {
+        self.base_domain.as_deref().unwrap_or(&self.host)
+    }
+
     pub fn init_headers(
         &self,
         headers: &mut HeaderMap,
@@ -286,749 +513,2803 @@ impl S3Pool {
         } else {
             headers.insert(header::HOST, HeaderValue::from_str(&self.host).unwrap());
         }
+        for (name, value) in self.default_headers.iter() {
+            headers.insert(name, value.clone());
+        }
     }
 
-    fn handle_list_response(&mut self, body: String) -> Result<(), Error> {
-        (self.objects, self.is_truncated) = s3object_list_xml_parser(&body)?;
-        Ok(())
+    /// Record the requests, the retries and the transferred bytes with the hooks
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Add a header sent and signed with every request, ex: `x-tenant-id` for a storage gateway,
+    /// this can be called repeatedly for more headers.
+    pub fn default_header(mut self, name: &str, value: &str) -> Result<Self, Error> {
+        self.default_headers
+            .extend(header_map_of(&[(name.to_string(), value.to_string())])?);
+        Ok(self)
     }
 
-    pub fn part_size(mut self, s: usize) -> Self {
+    pub fn part_size(mut self, s: u64) -> Self {
         self.part_size = Some(s);
+        self.auto_part_size = false;
         self
     }
 
-    /// Init multipart upload session, and return `multipart_id`
-    async fn init_multipart_upload(
-        &self,
-        url: String,
-        virturalhost: Option<String>,
-    ) -> Result<String, Error> {
-        let url = format!("{}?uploads", url);
-        let mut request = self.client.post(&url).build()?;
-
-        let now = Utc::now();
-        self.init_headers(request.headers_mut(), &now, virturalhost);
-        self.signer.sign(&mut request, &now);
-
-        let r = self.client.execute(request).await?;
+    /// Pick the part size of downloads by the object size,
+    /// the object is split into the parts in 64MB ~ 512MB and downloaded concurrently.
+    pub fn auto_part_size(mut self) -> Self {
+        self.auto_part_size = true;
+        self
+    }
 
-        Ok(upload_id_xml_parser(&r.text().await?)?)
+    /// The max number of the parts transferred concurrently, which is also the concurrency to
+    /// pick the part size automatically.
+    pub fn worker_count(mut self, worker_count: usize) -> Self {
+        self.worker_count = worker_count.max(1);
+        self
     }
 
-    async fn generate_part_upload_requests(
-        &self,
-        desc: S3Object,
-        multipart_id: &str,
-        part_size: usize,
-        object: Bytes,
-    ) -> Result<Vec<Result<Response, reqwest::Error>>, Error> {
-        let mut part_number = 0;
-        let mut start = 0;
-        let mut req_list = vec![];
-        while start < object.len() {
-            part_number += 1;
-            let end = if start + part_size >= object.len() {
-                object.len()
-            } else {
-                start + part_size
-            };
-            let (endpoint, virtural_host) = self.endpoint_and_virturalhost(desc.clone());
-            let url = format!(
-                "{}?uploadId={}&partNumber={}",
-                endpoint, multipart_id, part_number
-            );
+    /// Pull the object with the metadata fetched, the size is checked and the content is
+    /// decompressed by the metadata
+    async fn pull_fetched(&self, desc: S3Object) -> Result<Bytes, Error> {
+        let gzip = self.decompress && is_gzip(desc.content_encoding.as_deref());
+        let expected_size = desc.size;
+        // an empty object, ex: the marker of a folder, is not requested again
+        if expected_size == Some(0) {
+            return Ok(Bytes::new());
+        }
+        let size = expected_size.unwrap_or_default();
+        let capacity = buffer_size(size)?;
+        let part_size = self.download_part_size(size);
+        let output = if part_size > 0 && part_size < size {
+            let reqs = self
+                .generate_part_download_requests(desc, part_size)
+                .await?;
+            self.complete_multi_part_download(reqs, capacity).await?
+        } else {
+            // TODO reuse the client setting and not only the reqest
+            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
+            let request = Request::new(Method::GET, Url::parse(&endpoint)?);
+            let r = self
+                .execute(request, virturalhost, &PayloadHash::empty())
+                .await?;
+            r.bytes().await?
+        };
+        // A truncated transfer should not be returned as a short object silently
+        if let Some(expected) = expected_size {
+            if output.len() as u64 != expected {
+                return Err(Error::IntegrityError {
+                    expected,
+                    received: output.len() as u64,
+                });
+            }
+        }
+        if gzip {
+            Ok(gunzip(&output)?.into())
+        } else {
+            Ok(output)
+        }
+    }
 
-            let mut request = self
-                .client
-                .put(&url)
-                .body(object.slice(start..end))
-                .build()?;
+    /// Pull the object as a stream with the metadata fetched, the object is pulled as a whole
+    /// if it is compressed or not larger than a part.
+    async fn pull_stream_fetched(&self, desc: S3Object) -> Result<ByteStream, Error> {
+        let gzip = self.decompress && is_gzip(desc.content_encoding.as_deref());
+        let size = desc.size.unwrap_or_default();
+        let part_size = self.download_part_size(size);
+        if gzip || part_size == 0 || part_size >= size {
+            let object = self.pull_fetched(desc).await?;
+            return Ok(Box::pin(futures::stream::once(async { Ok(object) })));
+        }
+        let reqs = self
+            .generate_part_download_requests(desc, part_size)
+            .await?;
+        Ok(part_stream(reqs, size))
+    }
 
-            let now = Utc::now();
-            self.init_headers(request.headers_mut(), &now, virtural_host);
-            self.signer.sign(&mut request, &now);
-            req_list.push(self.client.execute(request));
-            start += part_size
+    /// The part size to download the object in the size
+    fn download_part_size(&self, size: u64) -> u64 {
+        if self.auto_part_size {
+            auto_part_size(size, self.worker_count)
+        } else {
+            self.part_size.unwrap_or_default()
         }
-        Ok(join_all(req_list).await)
     }
 
-    async fn complete_multi_part_upload(
-        &self,
-        reqs: Vec<Result<Response, reqwest::Error>>,
-        desc: S3Object,
-        multipart_id: &str,
-    ) -> Result<Response, Error> {
-        let mut content = "<CompleteMultipartUpload>".to_string();
-        for (idx, res) in reqs.into_iter().enumerate() {
-            let r = res?;
-            let etag = r.headers()[reqwest::header::ETAG]
-                .to_str()
-                .expect("unexpected etag from server");
+    /// Decompress the object stored with `Content-Encoding: gzip` when pulling
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
 
-            content.push_str(&format!(
-                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
-                idx + 1,
-                etag
-            ));
-        }
-        content.push_str(&"</CompleteMultipartUpload>".to_string());
-        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
-        let url = format!("{}?uploadId={}", endpoint, multipart_id);
-        let mut request = self.client.post(&url).body(content.into_bytes()).build()?;
-        let now = Utc::now();
-        self.init_headers(request.headers_mut(), &now, virturalhost);
-        self.signer.sign(&mut request, &now);
-        let r = self.client.execute(request).await?;
-        Ok(r)
+    /// Request the keys url encoded in the listings, and the keys are decoded when parsing,
+    /// such that the keys with the characters invalid in XML, ex: `\n`, can be listed.
+    pub fn encoding_type_url(mut self, enable: bool) -> Self {
+        self.encoding_type_url = enable;
+        self
     }
 
-    async fn generate_part_download_requests(
-        &self,
-        desc: S3Object,
-        part_size: usize,
-    ) -> Result<Vec<Result<Response, reqwest::Error>>, Error> {
-        let mut start = 0;
-        let mut req_list = vec![];
-        while start < desc.size.unwrap() {
-            let end = if start + part_size >= desc.size.unwrap() {
-                desc.size.unwrap()
-            } else {
-                start + part_size
-            };
-            let (url, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+    /// Request the owner of the objects in the V2 listings, which are the pages after the first
+    pub fn fetch_owner(mut self, enable: bool) -> Self {
+        self.fetch_owner = enable;
+        self
+    }
 
-            let mut request = self.client.get(&url).build()?;
+    /// Stop the listings after the number of objects, ex: the first 200 keys,
+    /// and the pages are not requested after the limit is reached.
+    pub fn list_limit(mut self, limit: usize) -> Self {
+        self.list_limit = Some(limit);
+        self
+    }
 
-            let headers = request.headers_mut();
-            headers.insert(
-                header::RANGE,
-                HeaderValue::from_str(&format!("bytes={}-{}", start, end - 1)).unwrap(),
-            );
+    /// Stop the listings after the number of requests, ex: for the buckets with plenty of
+    /// delete markers, which may return the empty pages.
+    pub fn list_max_requests(mut self, max_requests: usize) -> Self {
+        self.list_max_requests = Some(max_requests);
+        self
+    }
 
-            let now = Utc::now();
-            self.init_headers(headers, &now, virturalhost);
-            self.signer.sign(&mut request, &now);
-            req_list.push(self.client.execute(request));
-            start += part_size
-        }
-        Ok(join_all(req_list).await)
+    /// Skip the mutating requests, ex: PUT, POST and DELETE, and plan them as actions,
+    /// the listings are still requested, so the plan is accurate.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
     }
 
-    async fn complete_multi_part_download(
-        &self,
-        reqs: Vec<Result<Response, reqwest::Error>>,
-    ) -> Result<Bytes, Error> {
-        let mut output = BytesMut::with_capacity(0);
-        for res in reqs.into_iter() {
-            let r = res?;
-            // TODO: no copy, check out a way of Bytes -> BytesMut then using unsplit
-            output.extend_from_slice(&r.bytes().await?);
-        }
-        Ok(output.into())
-    }
-
-    async fn update_list(&mut self) -> Result<S3Object, Error> {
-        let last_object = self.objects.remove(0);
-        let mut params = Vec::<(&str, String)>::new();
-        if let Some(key) = &last_object.key {
-            params.push(("list-type", "2".to_string()));
-            params.push((
-                "start-after",
-                key.to_string()
-                    .strip_prefix('/')
-                    .expect("key should start with /")
-                    .to_string(),
-            ));
-        }
+    /// Abort the in-progress multipart upload when the upload is failed or its future is dropped,
+    /// ex: the task is cancelled, so the uploaded parts are not left to be charged, default enabled.
+    /// Disable it to keep the parts and resume the upload later, the abort is not sent on wasm32.
+    pub fn abort_on_drop(mut self, abort: bool) -> Self {
+        self.abort_on_drop = abort;
+        self
+    }
 
-        let mut bucket_object = last_object.clone();
-        bucket_object.key = None;
-        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(bucket_object);
-        if let Some(Filter::Prefix(prefix)) = &self.filter {
-            params.push(("prefix", prefix.to_string()));
-        }
-        let url = if !params.is_empty() {
-            Url::parse_with_params(&endpoint, &params)?
-        } else {
-            Url::parse(&endpoint)?
-        };
-        let mut request = Request::new(Method::GET, url);
+    /// Set the base domain of the buckets in virtual-host style, ex: `cdn.example.com` for
+    /// `bucket.cdn.example.com` with CNAME, the host is still used in path style.
+    pub fn base_domain(mut self, domain: &str) -> Self {
+        self.base_domain = Some(domain.to_string());
+        self
+    }
 
-        let now = Utc::now();
-        self.init_headers(request.headers_mut(), &now, virturalhost);
-        self.signer.sign(&mut request, &now);
-        let body = self.client.execute(request).await?.text().await?;
-        // TODO: validate start-after
-        self.handle_list_response(body)?;
-        Ok(last_object)
+    /// Push the objects in the storage class, ex: "GLACIER_IR"
+    pub fn storage_class(mut self, storage_class: &str) -> Self {
+        self.storage_class = Some(storage_class.to_string());
+        self
     }
-}
 
-impl From<Handler<'_>> for S3Pool {
-    fn from(handler: Handler) -> Self {
-        let secure = handler.is_secure();
-        let Handler {
-            host,
-            access_key,
-            secret_key,
-            region,
-            auth_type,
-            url_style,
-            ..
-        } = handler;
-
-        let signer: Box<dyn Signer> = match auth_type {
-            AuthType::AWS4 => Box::new(V4AuthSigner::new(
-                access_key.into(),
-                secret_key.into(),
-                region.unwrap_or_else(|| DEFAULT_REGION.to_string()),
-            )),
-            AuthType::AWS2 => Box::new(V2AuthSigner::new(access_key.into(), secret_key.into())),
-        };
+    /// Sign the requests for the bucket in the region, so the buckets in different regions
+    /// can be accessed with one pool, ex: `.region_for_bucket("logs-eu", "eu-west-1")`
+    pub fn region_for_bucket(self, bucket: &str, region: &str) -> Self {
+        self.bucket_regions
+            .lock()
+            .expect("bucket regions should be accessible")
+            .insert(bucket.to_string(), normalize_region(region));
+        self
+    }
 
-        Self {
-            host: host.into(),
-            secure,
-            url_style,
-            client: Client::new(),
-            signer,
-            part_size: Some(5242880),
-            objects: Vec::with_capacity(1000),
-            filter: None,
-            is_truncated: false,
+    /// The bucket of the request
+    fn bucket_name<'u>(&self, url: &'u Url, virturalhost: Option<&'u str>) -> Option<&'u str> {
+        match virturalhost {
+            Some(host) => host
+                .strip_suffix(self.virtual_host_domain())?
+                .strip_suffix('.'),
+            None => url.path_segments()?.next(),
         }
     }
-}
 
-impl From<&Handler<'_>> for S3Pool {
-    fn from(handler: &Handler) -> Self {
-        let secure = handler.is_secure();
-        let Handler {
-            host,
-            access_key,
-            secret_key,
-            region,
-            auth_type,
-            url_style,
-            ..
-        } = handler;
-
-        let signer: Box<dyn Signer> = match auth_type {
-            AuthType::AWS4 => Box::new(V4AuthSigner::new(
-                access_key.to_string(),
-                secret_key.to_string(),
-                region.clone().unwrap_or_else(|| DEFAULT_REGION.to_string()),
-            )),
-            AuthType::AWS2 => Box::new(V2AuthSigner::new(
-                access_key.to_string(),
-                secret_key.to_string(),
-            )),
-        };
+    /// The region registered for the bucket of the request
+    fn bucket_region(&self, url: &Url, virturalhost: Option<&str>) -> Option<String> {
+        let bucket = self.bucket_name(url, virturalhost)?;
+        self.bucket_regions
+            .lock()
+            .expect("bucket regions should be accessible")
+            .get(bucket)
+            .cloned()
+    }
 
-        Self {
-            host: host.to_string(),
-            secure,
-            url_style: url_style.clone(),
-            client: Client::new(),
-            signer,
-            part_size: Some(5242880),
-            objects: Vec::with_capacity(1000),
-            filter: None,
-            is_truncated: false,
+    /// Remember the region of the bucket in `x-amz-bucket-region` of the redirect or the error,
+    /// and return true if the request should be signed again in the region
+    fn discover_region(
+        &self,
+        status: StatusCode,
+        url: &Url,
+        virturalhost: Option<&str>,
+        headers: &HeaderMap,
+    ) -> bool {
+        if !status.is_redirection() && !status.is_client_error() {
+            return false;
+        }
+        let (region, bucket) = match (
+            headers
+                .get("x-amz-bucket-region")
+                .and_then(|r| r.to_str().ok()),
+            self.bucket_name(url, virturalhost),
+        ) {
+            (Some(region), Some(bucket)) => (normalize_region(region), bucket),
+            _ => return false,
+        };
+        let mut regions = self
+            .bucket_regions
+            .lock()
+            .expect("bucket regions should be accessible");
+        let signed = regions
+            .get(bucket)
+            .map(|r| r.as_str())
+            .or_else(|| self.signer.default_region());
+        if signed.is_none() || signed == Some(region.as_str()) {
+            return false;
         }
+        warn!(
+            "the bucket {} is in {}, re-sign the request",
+            bucket, region
+        );
+        regions.insert(bucket.to_string(), region);
+        true
     }
-}
 
-#[async_trait]
-impl DataPool for S3Pool {
-    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error> {
-        let part_size = self.part_size.unwrap_or_default();
-        let _r = if part_size > 0 && part_size < object.len() {
-            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
-            let multipart_id = self.init_multipart_upload(endpoint, virturalhost).await?;
+    /// Take the actions planned in the dry-run mode
+    pub fn take_planned(&self) -> Vec<Action> {
+        std::mem::take(
+            &mut *self
+                .planned
+                .lock()
+                .expect("planned actions should be accessible"),
+        )
+    }
 
-            let reqs = self
-                .generate_part_upload_requests(desc.clone(), &multipart_id, part_size, object)
-                .await?;
-            self.complete_multi_part_upload(reqs, desc, &multipart_id)
-                .await?
+    /// Plan the mutating request in the dry-run mode, and return true if it should be skipped
+    fn plan(&self, method: &Method, desc: &S3Object, query: &[(&str, &str)]) -> bool {
+        if self.dry_run && is_mutating(method.as_str()) {
+            self.planned
+                .lock()
+                .expect("planned actions should be accessible")
+                .push(Action::new(method.as_str(), desc, query));
+            true
         } else {
-            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
-            let mut request = self.client.put(&endpoint).body(object).build()?;
+            false
+        }
+    }
 
-            let now = Utc::now();
-            self.init_headers(request.headers_mut(), &now, virturalhost);
-            self.signer.sign(&mut request, &now);
-            self.client.execute(request).await?
-        };
-        // TODO validate _r status code
-        Ok(())
+    /// The offset of the server clock to the local clock, which is detected when the server
+    /// rejects a request with `RequestTimeTooSkewed`, and added to the timestamp for signing.
+    pub fn clock_offset(&self) -> Duration {
+        Duration::milliseconds(self.clock_offset.load(Ordering::Relaxed))
     }
 
-    async fn pull(&self, mut desc: S3Object) -> Result<Bytes, Error> {
+    /// The time for signing, which is adjusted with the clock offset
+    fn now(&self) -> UTCTime {
+        Utc::now() + self.clock_offset()
+    }
+
+    /// Init the headers and sign the request in the region of the bucket
+    fn sign_request(
+        &self,
+        request: &mut Request,
+        virturalhost: Option<String>,
+        payload: &PayloadHash,
+    ) -> Result<(), Error> {
+        let now = self.now();
+        let region = self.bucket_region(request.url(), virturalhost.as_deref());
+        self.init_headers(request.headers_mut(), &now, virturalhost);
+        match region {
+            Some(region) => self.signer.sign_in_region(request, &now, payload, &region),
+            None => self.signer.sign(request, &now, payload),
+        }
+    }
+
+    /// Sign the request as the pool sends it and record it without sending, ex: to debug the
+    /// signatures with a customized signer or to snapshot the requests for the tests.
+    /// ```
+    /// use s3handler::none_blocking::primitives::S3Pool;
+    ///
+    /// let s3_pool = S3Pool::new("s3.us-east-1.amazonaws.com".to_string()).aws_v4(
+    ///     "akey".to_string(),
+    ///     "skey".to_string(),
+    ///     "us-east-1".to_string(),
+    /// );
+    /// let recorded = s3_pool
+    ///     .record(
+    ///         reqwest::Method::GET,
+    ///         s3handler::S3Object::from("s3://bucket/key"),
+    ///         &[("tagging", "")],
+    ///         bytes::Bytes::new(),
+    ///     )
+    ///     .unwrap();
+    /// println!("{:?}", recorded.header("authorization"));
+    /// ```
+    pub fn record(
+        &self,
+        method: Method,
+        desc: S3Object,
+        query: &[(&str, &str)],
+        body: Bytes,
+    ) -> Result<RecordedRequest, Error> {
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
+        let url = if query.is_empty() {
+            Url::parse(&endpoint)?
+        } else {
+            Url::parse_with_params(&endpoint, query)?
+        };
+        let payload = PayloadHash::of(&body);
+        let mut request = self.client.request(method, url).body(body).build()?;
+        self.sign_request(&mut request, virturalhost, &payload)?;
+        Ok(RecordedRequest::new(
+            request.method().as_str(),
+            request.url().as_str(),
+            request.headers(),
+            payload.as_str(),
+        ))
+    }
+
+    /// Sign the requests of the pool operations and record them instead of sending, every request
+    /// is answered with an empty 200 response, ex: to snapshot the requests for the tests.
+    /// The recorded requests are shared with the clones of the pool, and taken by `take_recorded`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recording(mut self, enable: bool) -> Self {
+        self.recorded = enable.then(Arc::default);
+        self
+    }
+
+    /// Take the requests recorded in the recording mode
+    pub fn take_recorded(&self) -> Vec<RecordedRequest> {
+        match &self.recorded {
+            Some(recorded) => std::mem::take(
+                &mut *recorded
+                    .lock()
+                    .expect("recorded requests should be accessible"),
+            ),
+            None => Vec::new(),
+        }
+    }
+
+    /// Sign and execute the request
+    async fn sign_and_execute(
+        &self,
+        mut request: Request,
+        virturalhost: Option<String>,
+        payload: &PayloadHash,
+    ) -> Result<Response, Error> {
+        self.sign_request(&mut request, virturalhost, payload)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(recorded) = &self.recorded {
+            recorded
+                .lock()
+                .expect("recorded requests should be accessible")
+                .push(RecordedRequest::new(
+                    request.method().as_str(),
+                    request.url().as_str(),
+                    request.headers(),
+                    payload.as_str(),
+                ));
+            return Ok(http::Response::new(Vec::<u8>::new()).into());
+        }
+        let method = request.method().clone();
+        let host = match request.url().port() {
+            Some(port) => format!("{}:{}", request.url().host_str().unwrap_or_default(), port),
+            None => request.url().host_str().unwrap_or_default().to_string(),
+        };
+        let sent = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| b.len() as u64)
+            .unwrap_or_default();
+        let start = Stopwatch::start();
+        let r = self.client.execute(request).await;
+        match &r {
+            Ok(r) => {
+                self.metrics.on_host_request(
+                    method.as_str(),
+                    &host,
+                    Some(r.status().as_u16()),
+                    start.elapsed(),
+                );
+                // The body is read by the caller after the request, so it is not counted, the
+                // content length is not the received size, ex: the one of a HEAD response
+                self.metrics.on_bytes(sent, 0);
+            }
+            Err(_) => self
+                .metrics
+                .on_host_request(method.as_str(), &host, None, start.elapsed()),
+        }
+        Ok(r?)
+    }
+
+    /// Sign and execute the request, the failed response will be turned into error.
+    /// If the request is rejected for the skewed clock, the clock offset is updated by the server
+    /// time, then the request is re-signed and retried once.
+    /// If the bucket is in another region, the region is cached for the following requests,
+    /// and the request is re-signed in the region and retried once.
+    async fn execute(
+        &self,
+        request: Request,
+        virturalhost: Option<String>,
+        payload: &PayloadHash,
+    ) -> Result<Response, Error> {
+        let retry = request.try_clone();
+        let url = request.url().clone();
+        let r = self
+            .sign_and_execute(request, virturalhost.clone(), payload)
+            .await?;
+        let headers = r.headers().clone();
+        let retry = match retry {
+            Some(request)
+                if self.discover_region(r.status(), &url, virturalhost.as_deref(), &headers) =>
+            {
+                self.metrics.on_retry(request.method().as_str());
+                return validate(
+                    self.sign_and_execute(request, virturalhost, payload)
+                        .await?,
+                )
+                .await;
+            }
+            retry => retry,
+        };
+        match (validate(r).await, retry) {
+            (Err(e), Some(request)) => match clock_skew(&e, &headers) {
+                Some(offset) => {
+                    warn!(
+                        "the clock is skewed {}ms from the server, re-sign the request",
+                        offset.num_milliseconds()
+                    );
+                    self.clock_offset
+                        .store(offset.num_milliseconds(), Ordering::Relaxed);
+                    self.metrics.on_retry(request.method().as_str());
+                    validate(
+                        self.sign_and_execute(request, virturalhost, payload)
+                            .await?,
+                    )
+                    .await
+                }
+                None => Err(e),
+            },
+            (r, _) => r,
+        }
+    }
+
+    /// Download an object without building a canal
+    pub async fn get_object(&self, bucket: &str, key: &str) -> Result<Bytes, Error> {
+        self.pull(object_of(bucket, key)).await
+    }
+
+    /// Take a quick look at an object with a single GET, the content is returned as it stored,
+    /// without the UTF-8 conversion and the decompression, and with the `Content-Type`.
+    pub async fn cat(&self, obj: S3Object) -> Result<(Bytes, Option<String>), Error> {
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(obj);
+        let request = Request::new(Method::GET, Url::parse(&endpoint)?);
+        let r = self
+            .execute(request, virturalhost, &PayloadHash::empty())
+            .await?;
+        let content_type = r
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .map(|v| v.to_str())
+            .transpose()?
+            .map(|v| v.to_string());
+        Ok((r.bytes().await?, content_type))
+    }
+
+    /// Upload an object without building a canal
+    pub async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        object: Bytes,
+        content_type: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut desc = object_of(bucket, key);
+        desc.mime = content_type.map(|t| t.to_string());
+        self.push(desc, object).await
+    }
+
+    /// Upload an object with the tags, which are set with the upload by `x-amz-tagging` header,
+    /// so the object is never untagged.  For multipart, the tags are set when initiating.
+    pub async fn put_object_with_tags(
+        &self,
+        bucket: &str,
+        key: &str,
+        object: Bytes,
+        content_type: Option<&str>,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let mut desc = object_of(bucket, key);
+        desc.mime = content_type.map(|t| t.to_string());
+        let tagging = if tags.is_empty() {
+            None
+        } else {
+            Some(tagging_header(tags))
+        };
+        self.upload(desc, object, tagging.as_deref()).await
+    }
+
+    /// Delete an object without building a canal
+    pub async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), Error> {
+        self.remove(object_of(bucket, key)).await
+    }
+
+    /// Summarize the count and the size of the objects under the prefix, and each sub-prefix split
+    /// by the delimiter, ex: `/`, the objects are counted page by page without keeping them.
+    pub async fn du(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: Option<&str>,
+    ) -> Result<DuSummary, Error> {
+        let prefix = prefix.trim_start_matches('/');
+        let mut folder = self
+            .list(
+                Some(bucket.into()),
+                &Some(Filter::Prefix(prefix.to_string())),
+            )
+            .await?;
+        let mut summary = DuSummary::default();
+        while let Some(obj) = folder.next_object().await? {
+            summary.add(&obj, prefix, delimiter);
+        }
+        Ok(summary)
+    }
+
+    /// Delete the objects under the prefix with the multi-object delete API,
+    /// and return the number of the objects removed
+    pub async fn remove_prefix(&self, bucket: &str, prefix: &str) -> Result<usize, Error> {
+        let mut folder = self
+            .list(
+                Some(bucket.into()),
+                &Some(Filter::Prefix(prefix.to_string())),
+            )
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(obj) = folder.next_object().await? {
+            keys.extend(obj.key);
+        }
+        let mut removed = 0;
+        for batch in keys.chunks(DELETE_BATCH_SIZE) {
+            if self.plan(&Method::POST, &bucket.into(), &[("delete", "")]) {
+                removed += batch.len();
+                continue;
+            }
+            let content = delete_objects_xml(batch);
+            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(bucket.into());
+            let url = Url::parse_with_params(&endpoint, &[("delete", "")])?;
+            let payload = PayloadHash::of(content.as_bytes());
+            let md5 = content_md5(content.as_bytes());
+            let mut request = self.client.post(url).body(content).build()?;
+            request.headers_mut().insert(
+                HeaderName::from_static("content-md5"),
+                HeaderValue::from_str(&md5).expect("base64 is a valid header value"),
+            );
+            let body = self
+                .execute(request, virturalhost, &payload)
+                .await?
+                .text()
+                .await?;
+            let failed = delete_errors_xml_parser(&body)?;
+            for (key, code) in failed.iter() {
+                warn!("fail to delete {}: {}", key, code);
+            }
+            removed += batch.len() - failed.len();
+        }
+        Ok(removed)
+    }
+
+    /// Create a bucket, the `CreateBucketConfiguration` is sent if the region is not the default
+    /// one.  The bucket is addressed in path style, because its subdomain does not exist yet.
+    pub async fn make_bucket(&self, bucket: &str, region: Option<&str>) -> Result<(), Error> {
+        let desc: S3Object = bucket.into();
+        validate_bucket_name(desc.bucket.as_deref().unwrap_or_default())?;
+        if self.plan(&Method::PUT, &desc, &[]) {
+            return Ok(());
+        }
+        let body = match region.map(normalize_region) {
+            Some(region) if region != DEFAULT_REGION => Bytes::from(create_bucket_xml(&region)),
+            _ => Bytes::new(),
+        };
+        self.send_path_style(Method::PUT, desc, body).await?;
+        Ok(())
+    }
+
+    /// Remove an empty bucket, the bucket is addressed in path style as `make_bucket`
+    pub async fn remove_bucket(&self, bucket: &str) -> Result<(), Error> {
+        let desc: S3Object = bucket.into();
+        if self.plan(&Method::DELETE, &desc, &[]) {
+            return Ok(());
+        }
+        self.send_path_style(Method::DELETE, desc, Bytes::new())
+            .await?;
+        Ok(())
+    }
+
+    /// Fetch the metadata of an object without building a canal
+    pub async fn head_object(&self, bucket: &str, key: &str) -> Result<S3Object, Error> {
+        let mut desc = object_of(bucket, key);
         self.fetch_meta(&mut desc).await?;
+        Ok(desc)
+    }
+
+    /// List all buckets of the account
+    pub async fn list_buckets(&self) -> Result<Vec<S3Object>, Error> {
+        let body = self
+            .send(Method::GET, S3Object::default(), &[], Bytes::new())
+            .await?
+            .text()
+            .await?;
+        Ok(s3object_list_xml_parser(&body)?.0)
+    }
+
+    /// Get the tags of an object
+    pub async fn get_tagging(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let body = self
+            .send(
+                Method::GET,
+                object_of(bucket, key),
+                &[("tagging", "")],
+                Bytes::new(),
+            )
+            .await?
+            .text()
+            .await?;
+        tagging_xml_parser(&body)
+    }
+
+    /// List the uploaded parts of an in-progress multipart upload
+    pub async fn list_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<PartInfo>, Error> {
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![("uploadId", upload_id)];
+            if let Some(marker) = &marker {
+                query.push(("part-number-marker", marker.as_str()));
+            }
+            let body = self
+                .send(Method::GET, object_of(bucket, key), &query, Bytes::new())
+                .await?
+                .text()
+                .await?;
+            let (page, next_marker) = parts_xml_parser(&body)?;
+            parts.extend(page);
+            match next_marker {
+                Some(m) if m.is_empty() => {
+                    return Err(Error::FieldNotFound("NextPartNumberMarker"))
+                }
+                Some(m) => advance_marker(&mut marker, Some(m))?,
+                None => return Ok(parts),
+            }
+        }
+    }
+
+    /// List the in-progress multipart uploads of a bucket, the uploads can be filtered by the
+    /// prefix of the keys.
+    pub async fn list_multipart_uploads(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<UploadInfo>, Error> {
+        let mut uploads = Vec::new();
+        let mut markers: Option<UploadMarkers> = None;
+        loop {
+            let mut query = vec![("uploads", "")];
+            if let Some(prefix) = prefix {
+                query.push(("prefix", prefix));
+            }
+            if let Some((key_marker, upload_id_marker)) = &markers {
+                query.push(("key-marker", key_marker.as_str()));
+                query.push(("upload-id-marker", upload_id_marker.as_str()));
+            }
+            let body = self
+                .send(Method::GET, bucket.into(), &query, Bytes::new())
+                .await?
+                .text()
+                .await?;
+            let (page, next_markers) = multipart_uploads_xml_parser(&body)?;
+            uploads.extend(page);
+            match next_markers {
+                Some((k, _)) if k.is_empty() => return Err(Error::FieldNotFound("NextKeyMarker")),
+                Some(m) => advance_marker(&mut markers, Some(m))?,
+                None => return Ok(uploads),
+            }
+        }
+    }
+
+    /// Abort a multipart upload, and the uploaded parts are removed
+    pub async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), Error> {
+        self.abort_upload(object_of(bucket, key), upload_id).await
+    }
+
+    async fn abort_upload(&self, desc: S3Object, upload_id: &str) -> Result<(), Error> {
+        if self.plan(&Method::DELETE, &desc, &[("uploadId", upload_id)]) {
+            return Ok(());
+        }
+        self.send(
+            Method::DELETE,
+            desc,
+            &[("uploadId", upload_id)],
+            Bytes::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// List the versions and the delete markers of the objects in a versioning bucket, the
+    /// entries can be filtered by the prefix of the keys.
+    pub async fn list_versions(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<VersionEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut markers: Option<VersionMarkers> = None;
+        loop {
+            let mut query = vec![("versions", "")];
+            if let Some(prefix) = prefix {
+                query.push(("prefix", prefix));
+            }
+            if let Some((key_marker, version_id_marker)) = &markers {
+                query.push(("key-marker", key_marker.as_str()));
+                query.push(("version-id-marker", version_id_marker.as_str()));
+            }
+            let body = self
+                .send(Method::GET, bucket.into(), &query, Bytes::new())
+                .await?
+                .text()
+                .await?;
+            let (page, next_markers) = versions_xml_parser(&body)?;
+            entries.extend(page);
+            match next_markers {
+                Some((k, _)) if k.is_empty() => return Err(Error::FieldNotFound("NextKeyMarker")),
+                Some(m) => advance_marker(&mut markers, Some(m))?,
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    /// Replace the tags of an object
+    pub async fn put_tagging(
+        &self,
+        bucket: &str,
+        key: &str,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let mut content = "<Tagging><TagSet>".to_string();
+        for tag in tags {
+            content.push_str(&format!(
+                "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+                escape_str(tag.0),
+                escape_str(tag.1)
+            ));
+        }
+        content.push_str("</TagSet></Tagging>");
+        let desc = object_of(bucket, key);
+        if self.plan(&Method::PUT, &desc, &[("tagging", "")]) {
+            return Ok(());
+        }
+        self.send(Method::PUT, desc, &[("tagging", "")], content.into())
+            .await?;
+        Ok(())
+    }
+
+    /// Remove all tags of an object
+    pub async fn delete_tagging(&self, bucket: &str, key: &str) -> Result<(), Error> {
+        let desc = object_of(bucket, key);
+        if self.plan(&Method::DELETE, &desc, &[("tagging", "")]) {
+            return Ok(());
+        }
+        self.send(Method::DELETE, desc, &[("tagging", "")], Bytes::new())
+            .await?;
+        Ok(())
+    }
+
+    /// Sign and send a request for the resource, the failed response will be turned into error
+    async fn send(
+        &self,
+        method: Method,
+        desc: S3Object,
+        query: &[(&str, &str)],
+        body: Bytes,
+    ) -> Result<Response, Error> {
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
+        let url = if query.is_empty() {
+            Url::parse(&endpoint)?
+        } else {
+            Url::parse_with_params(&endpoint, query)?
+        };
+        let payload = PayloadHash::of(&body);
+        let request = self.client.request(method, url).body(body).build()?;
+        self.execute(request, virturalhost, &payload).await
+    }
+
+    /// Sign and send a request for the resource in path style, whatever the url style of the pool
+    async fn send_path_style(
+        &self,
+        method: Method,
+        desc: S3Object,
+        body: Bytes,
+    ) -> Result<Response, Error> {
+        let (host, uri) = desc.path_style_links(self.host.clone());
+        let url = if self.secure {
+            Url::parse(&format!("https://{}{}", host, uri))?
+        } else {
+            Url::parse(&format!("http://{}{}", host, uri))?
+        };
+        let payload = PayloadHash::of(&body);
+        let request = self.client.request(method, url).body(body).build()?;
+        self.execute(request, None, &payload).await
+    }
+
+    /// Upload the object in one request, or in parts if it is larger than the part size
+    async fn upload(
+        &self,
+        desc: S3Object,
+        object: Bytes,
+        tagging: Option<&str>,
+    ) -> Result<(), Error> {
+        if self.plan(&Method::PUT, &desc, &[]) {
+            return Ok(());
+        }
         let part_size = self.part_size.unwrap_or_default();
-        if part_size > 0 && part_size < desc.size.unwrap_or_default() {
+        let _r = if part_size > 0 && part_size < object.len() as u64 {
+            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+            let multipart_id = self
+                .init_multipart_upload(endpoint, virturalhost, &desc, tagging)
+                .await?;
+            let guard = MultipartGuard::new(self, &desc, &multipart_id);
+
             let reqs = self
-                .generate_part_download_requests(desc, part_size)
+                .generate_part_upload_requests(desc.clone(), &multipart_id, part_size, object)
+                .await?;
+            let r = self
+                .complete_multi_part_upload(reqs, desc, &multipart_id)
                 .await?;
-            let output = self.complete_multi_part_download(reqs).await?;
+            guard.complete();
+            r
+        } else {
+            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+            let payload = PayloadHash::of(&object);
+            let mut request = self.client.put(&endpoint).body(object).build()?;
+            set_content_type(request.headers_mut(), desc.mime.as_deref())?;
+            set_metadata(request.headers_mut(), &desc.metadata)?;
+            set_tagging(request.headers_mut(), tagging)?;
+            set_upload_options(request.headers_mut(), &desc, self.storage_class.as_deref())?;
+            self.execute(request, virturalhost, &payload).await?
+        };
+        Ok(())
+    }
+
+    /// Init multipart upload session with the content type and metadata of the object,
+    /// and return `multipart_id`
+    async fn init_multipart_upload(
+        &self,
+        url: String,
+        virturalhost: Option<String>,
+        desc: &S3Object,
+        tagging: Option<&str>,
+    ) -> Result<String, Error> {
+        let url = format!("{}?uploads", url);
+        let mut request = self.client.post(&url).build()?;
+        set_content_type(request.headers_mut(), desc.mime.as_deref())?;
+        set_metadata(request.headers_mut(), &desc.metadata)?;
+        set_tagging(request.headers_mut(), tagging)?;
+        set_upload_options(request.headers_mut(), desc, self.storage_class.as_deref())?;
+        let r = self
+            .execute(request, virturalhost, &PayloadHash::empty())
+            .await?;
+
+        Ok(upload_id_xml_parser(&r.text().await?)?)
+    }
+
+    async fn generate_part_upload_requests(
+        &self,
+        desc: S3Object,
+        multipart_id: &str,
+        part_size: u64,
+        object: Bytes,
+    ) -> Result<Vec<Result<String, Error>>, Error> {
+        // The object is in memory, so the part larger than the address space is the whole object
+        let part_size = usize::try_from(part_size).unwrap_or(usize::MAX);
+        let mut part_number = 0;
+        let mut start = 0;
+        let mut req_list = vec![];
+        while start < object.len() {
+            part_number += 1;
+            let end = if start + part_size >= object.len() {
+                object.len()
+            } else {
+                start + part_size
+            };
+            // a panic in one part should fail the upload, rather than abort the whole task
+            let part = AssertUnwindSafe(self.upload_part(
+                desc.clone(),
+                multipart_id,
+                part_number,
+                object.slice(start..end),
+            ));
+            req_list.push(async move {
+                match part.catch_unwind().await {
+                    Ok(r) => r,
+                    Err(_) => Err(Error::RequestPoolError(format!(
+                        "the upload of part {} panicked",
+                        part_number
+                    ))),
+                }
+            });
+            start += part_size
+        }
+        Ok(futures::stream::iter(req_list)
+            .buffered(self.worker_count)
+            .collect()
+            .await)
+    }
+
+    /// Upload a part, and return the ETag of it
+    async fn upload_part(
+        &self,
+        desc: S3Object,
+        multipart_id: &str,
+        part_number: usize,
+        part: Bytes,
+    ) -> Result<String, Error> {
+        let (endpoint, virtural_host) = self.endpoint_and_virturalhost(desc.clone());
+        let url = format!(
+            "{}?uploadId={}&partNumber={}",
+            endpoint, multipart_id, part_number
+        );
+
+        let payload = PayloadHash::of(&part);
+        let mut request = self.client.put(&url).body(part).build()?;
+        set_request_payer(request.headers_mut(), &desc);
+        let r = validate(
+            self.sign_and_execute(request, virtural_host, &payload)
+                .await?,
+        )
+        .await?;
+        Ok(r.headers()
+            .get(reqwest::header::ETAG)
+            .ok_or(Error::FieldNotFound("ETag"))?
+            .to_str()?
+            .to_string())
+    }
+
+    async fn complete_multi_part_upload(
+        &self,
+        etags: Vec<Result<String, Error>>,
+        desc: S3Object,
+        multipart_id: &str,
+    ) -> Result<Response, Error> {
+        let mut content = "<CompleteMultipartUpload>".to_string();
+        for (idx, etag) in etags.into_iter().enumerate() {
+            let etag = etag?;
+            content.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                idx + 1,
+                etag
+            ));
+        }
+        content.push_str(&"</CompleteMultipartUpload>".to_string());
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+        let url = format!("{}?uploadId={}", endpoint, multipart_id);
+        let payload = PayloadHash::of(content.as_bytes());
+        let mut request = self.client.post(&url).body(content.into_bytes()).build()?;
+        set_request_payer(request.headers_mut(), &desc);
+        self.execute(request, virturalhost, &payload).await
+    }
+
+    async fn generate_part_download_requests(
+        &self,
+        desc: S3Object,
+        part_size: u64,
+    ) -> Result<Vec<Result<Response, Error>>, Error> {
+        let size = desc.size.unwrap_or_default();
+        if size == 0 || part_size == 0 {
+            return Ok(Vec::new());
+        }
+        let payload = PayloadHash::empty();
+        let mut start = 0;
+        let mut req_list = vec![];
+        while start < size {
+            let end = if start + part_size >= size {
+                size
+            } else {
+                start + part_size
+            };
+            let (url, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+
+            let mut request = self.client.get(&url).build()?;
+
+            request.headers_mut().insert(
+                header::RANGE,
+                HeaderValue::from_str(&format!("bytes={}-{}", start, end - 1)).unwrap(),
+            );
+            req_list.push(self.sign_and_execute(request, virturalhost, &payload));
+            start += part_size
+        }
+        Ok(futures::stream::iter(req_list)
+            .buffered(self.worker_count)
+            .collect()
+            .await)
+    }
+
+    /// Assemble the parts into a buffer allocated once for the whole object,
+    /// the chunks of each part are copied into the buffer as they arrive.
+    async fn complete_multi_part_download(
+        &self,
+        reqs: Vec<Result<Response, Error>>,
+        capacity: usize,
+    ) -> Result<Bytes, Error> {
+        let mut output = BytesMut::with_capacity(capacity);
+        for res in reqs.into_iter() {
+            let mut r = Some(validate(res?).await?);
+            while let Some(chunk) = next_chunk(&mut r).await? {
+                output.extend_from_slice(&chunk);
+            }
+        }
+        Ok(output.freeze())
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl From<Handler<'_>> for S3Pool {
+    fn from(handler: Handler) -> Self {
+        S3Pool::from(&handler)
+    }
+}
+
+/// The pool sends the requests to the same domain with the same settings of the handler,
+/// the response format is always XML for the pool.
+#[cfg(feature = "blocking")]
+impl From<&Handler<'_>> for S3Pool {
+    fn from(handler: &Handler) -> Self {
+        let client_config = handler.client_config().clone();
+        let signer: Box<dyn Signer> = match handler.auth_type {
+            AuthType::AWS4 => Box::new(V4AuthSigner::with_provider(
+                handler.credentials(),
+                handler
+                    .region
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            )),
+            AuthType::AWS2 => Box::new(V2AuthSigner::with_provider(handler.credentials())),
+        };
+
+        Self {
+            host: handler.host.to_string(),
+            base_domain: handler.base_domain().map(|d| d.to_string()),
+            secure: handler.is_secure(),
+            url_style: handler.url_style.clone(),
+            client: new_client(&client_config).expect("root CA is validated by the handler"),
+            client_config,
+            default_headers: header_map_of(handler.default_headers())
+                .expect("default headers should be valid header"),
+            metrics: handler.recorder(),
+            signer,
+            part_size: Some(handler.part_size()),
+            decompress: handler.is_decompress(),
+            encoding_type_url: handler.is_encoding_type_url(),
+            fetch_owner: false,
+            list_limit: None,
+            list_max_requests: None,
+            auto_part_size: handler.is_auto_part_size(),
+            worker_count: handler.worker_count(),
+            clock_offset: Arc::new(AtomicI64::new(handler.clock_offset().num_milliseconds())),
+            dry_run: handler.is_dry_run(),
+            planned: Arc::default(),
+            bucket_regions: Arc::default(),
+            abort_on_drop: true,
+            storage_class: None,
+            recorded: handler.recorded(),
+        }
+    }
+}
+
+/// The pool is set up by the config without a handler, the auth type and the url style are
+/// picked by the `s3_type` in the same way of the handler, and the signature is always v4.
+impl From<&CredentialConfig> for S3Pool {
+    fn from(config: &CredentialConfig) -> Self {
+        let mut pool = S3Pool::new(config.endpoint())
+            .secure(config.secure.unwrap_or(false))
+            .aws_v4(
+                config.access_key.clone(),
+                config.secret_key.clone(),
+                config
+                    .region
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            );
+        if config.s3_type.as_deref() != Some("aws") {
+            pool.url_style = UrlStyle::PATH;
+        }
+        if let Some(part_size) = config.part_size {
+            pool = pool.part_size(part_size);
+        }
+        pool.worker_count(config.worker_count.unwrap_or(DEFAULT_WORKER_COUNT))
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl DataPool for S3Pool {
+    async fn push(&self, desc: S3Object, object: Bytes) -> Result<(), Error> {
+        self.upload(desc, object, None).await
+    }
+
+    /// Upload the stream part by part, so only about one part is kept in memory.
+    /// The chunks from the stream are regrouped as the `part_size`,
+    /// which will not be less than the minimum part size of S3.
+    async fn push_stream(&self, desc: S3Object, mut object: ByteStream) -> Result<(), Error> {
+        if self.plan(&Method::PUT, &desc, &[]) {
+            return Ok(());
+        }
+        let part_size = buffer_size(std::cmp::max(
+            self.part_size.unwrap_or_default(),
+            MIN_PART_SIZE,
+        ))?;
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+        let multipart_id = self
+            .init_multipart_upload(endpoint, virturalhost, &desc, None)
+            .await?;
+        let guard = MultipartGuard::new(self, &desc, &multipart_id);
+
+        let mut buffer = BytesMut::with_capacity(part_size);
+        let mut reqs = vec![];
+        while let Some(chunk) = object.next().await {
+            buffer.extend_from_slice(&chunk?);
+            while buffer.len() >= part_size {
+                let part = buffer.split_to(part_size).freeze();
+                reqs.push(
+                    self.upload_part(desc.clone(), &multipart_id, reqs.len() + 1, part)
+                        .await,
+                );
+            }
+        }
+        if !buffer.is_empty() || reqs.is_empty() {
+            reqs.push(
+                self.upload_part(desc.clone(), &multipart_id, reqs.len() + 1, buffer.freeze())
+                    .await,
+            );
+        }
+        self.complete_multi_part_upload(reqs, desc, &multipart_id)
+            .await?;
+        guard.complete();
+        Ok(())
+    }
+
+    /// Pull the object in parts, the chunks of each part are yielded as they arrive,
+    /// so the whole object is never buffered.
+    async fn pull_stream(&self, mut desc: S3Object) -> Result<ByteStream, Error> {
+        self.fetch_meta(&mut desc).await?;
+        self.pull_stream_fetched(desc).await
+    }
+
+    /// The metadata from the HEAD request of the pull is returned without another request
+    async fn pull_stream_with_meta(
+        &self,
+        mut desc: S3Object,
+    ) -> Result<(S3Object, ByteStream), Error> {
+        self.fetch_meta(&mut desc).await?;
+        let object = self.pull_stream_fetched(desc.clone()).await?;
+        Ok((desc, object))
+    }
+
+    async fn pull(&self, mut desc: S3Object) -> Result<Bytes, Error> {
+        self.fetch_meta(&mut desc).await?;
+        self.pull_fetched(desc).await
+    }
+
+    /// The metadata from the HEAD request of the pull is returned without another request
+    async fn pull_with_meta(&self, mut desc: S3Object) -> Result<(S3Object, Bytes), Error> {
+        self.fetch_meta(&mut desc).await?;
+        let object = self.pull_fetched(desc.clone()).await?;
+        Ok((desc, object))
+    }
+
+    async fn list(
+        &self,
+        index: Option<S3Object>,
+        filter: &Option<Filter>,
+    ) -> Result<Box<dyn S3Folder>, Error> {
+        let mut index = index.unwrap_or_default();
+        // The key of the index is listed as the prefix, if there is no prefix filter
+        let filter = match (filter, index.key.take()) {
+            (Some(f), _) => Some(f.clone()),
+            (None, Some(key)) => Some(Filter::Prefix(key.trim_start_matches('/').to_string())),
+            (None, None) => None,
+        };
+        let list = S3ObjectList::new(self.clone(), index, filter)
+            .start()
+            .await?;
+        Ok(Box::new(list))
+    }
+
+    async fn remove(&self, desc: S3Object) -> Result<(), Error> {
+        if self.plan(&Method::DELETE, &desc, &[]) {
+            return Ok(());
+        }
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
+        let request = Request::new(Method::DELETE, Url::parse(&endpoint)?);
+        self.execute(request, virturalhost, &PayloadHash::empty())
+            .await?;
+        Ok(())
+    }
+
+    fn check_scheme(&self, scheme: &str) -> Result<(), Error> {
+        if scheme.to_lowercase() != "s3" {
+            Err(Error::SchemeError())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
+        let request = self.client.head(&endpoint).build()?;
+        let r = self
+            .execute(request, virturalhost, &PayloadHash::empty())
+            .await?;
+        fill_meta(desc, r.headers())
+
+        // TODO: check out it is correct or not that the storage class is absent here
+    }
+}
+
+/// The objects listed from a S3 pool, the listing keeps the state of the pagination and the
+/// pool is not changed.
+pub type S3ObjectList = crate::async_common::primitives::S3ObjectList<S3Pool>;
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl ListPages for S3Pool {
+    fn list_settings(&self) -> ListSettings {
+        ListSettings {
+            encoding_type_url: self.encoding_type_url,
+            fetch_owner: self.fetch_owner,
+            limit: self.list_limit,
+            max_requests: self.list_max_requests,
+        }
+    }
+
+    async fn list_page(&self, index: S3Object, query: &[(&str, &str)]) -> Result<String, Error> {
+        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(index);
+        let url = if !query.is_empty() {
+            Url::parse_with_params(&endpoint, query)?
+        } else {
+            Url::parse(&endpoint)?
+        };
+        let request = Request::new(Method::GET, url);
+        Ok(self
+            .execute(request, virturalhost, &PayloadHash::empty())
+            .await?
+            .text()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio_async::primitives::FilePool;
+    use crate::utils::glob::Rules;
+    use crate::utils::metrics::CounterMetrics;
+    use crate::utils::test_server::{list_page, TestServer, TEST_CA_PEM};
+
+    #[tokio::test]
+    async fn test_object_helpers() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+
+        pool.put_object(
+            "bucket",
+            "dir/key.txt",
+            Bytes::from_static(b"content"),
+            Some("text/plain"),
+        )
+        .await
+        .unwrap();
+
+        server.respond(
+            200,
+            &[("content-length", "7"), ("content-type", "text/plain")],
+            b"",
+        );
+        let desc = pool.head_object("bucket", "dir/key.txt").await.unwrap();
+        assert_eq!(desc.size, Some(7));
+        assert_eq!(desc.mime.as_deref(), Some("text/plain"));
+
+        server.respond(200, &[("content-length", "7")], b"");
+        server.respond(200, &[], b"content");
+        let object = pool.get_object("bucket", "dir/key.txt").await.unwrap();
+        assert_eq!(object, Bytes::from_static(b"content"));
+
+        pool.delete_object("bucket", "dir/key.txt").await.unwrap();
+
+        server.respond(404, &[("x-amz-request-id", "4442587FB7D0A2F9")], b"");
+        assert!(matches!(
+            pool.get_object("bucket", "missing").await,
+            Err(Error::S3Error { status: 404, .. })
+        ));
+
+        let requests = server.requests();
+        let methods: Vec<&str> = requests.iter().map(|r| r.method.as_str()).collect();
+        assert_eq!(
+            methods,
+            vec!["PUT", "HEAD", "HEAD", "GET", "DELETE", "HEAD"]
+        );
+        assert!(requests
+            .iter()
+            .all(|r| r.path == "/bucket/dir/key.txt" || r.path == "/bucket/missing"));
+        assert_eq!(requests[0].headers["content-type"], "text/plain");
+        assert_eq!(requests[0].body, b"content");
+    }
+
+    #[tokio::test]
+    async fn test_list_on_html_error_page() {
+        let server = TestServer::start();
+        server.respond(
+            503,
+            &[("content-type", "text/html")],
+            b"<html><body>Service Unavailable</body></html>",
+        );
+        let r = S3Pool::new(server.host.clone())
+            .bucket("bucket")
+            .list()
+            .await;
+        match r {
+            Err(Error::S3Error {
+                status, message, ..
+            }) => {
+                assert_eq!(status, 503);
+                assert!(message.contains("Service Unavailable"));
+            }
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_from_blocking_handle_to_s3_pool() {
+        let config = CredentialConfig {
+            host: "s3.us-east-1.amazonaws.com".to_string(),
+            access_key: "akey".to_string(),
+            secret_key: "skey".to_string(),
+            user: None,
+            region: None,       // default is us-east-1
+            s3_type: None,      // default will try to config as AWS S3 handler
+            secure: None,       // dafault is false, because the integrity protect by HMAC
+            part_size: None,    // default is automatic for downloads, 5MB for uploads
+            worker_count: None, // default is 10
+        };
+        let handler = Handler::from(&config);
+        let mut pool = S3Pool::from(&handler);
+        let s3_pool = S3Pool::new("s3.us-east-1.amazonaws.com".to_string());
+        assert_eq!(pool.host, s3_pool.host);
+
+        pool = handler.into();
+        let s3_pool = S3Pool::new("s3.us-east-1.amazonaws.com".to_string());
+        assert_eq!(pool.host, s3_pool.host);
+    }
+
+    #[test]
+    fn test_pool_for_region() {
+        let pool = S3Pool::for_region("cn-north-1").aws_v4(
+            "akey".to_string(),
+            "skey".to_string(),
+            "cn-north-1".to_string(),
+        );
+        assert_eq!(pool.host, "s3.cn-north-1.amazonaws.com.cn");
+        let (endpoint, virturalhost) = pool.endpoint_and_virturalhost("s3://bucket/key".into());
+        assert_eq!(endpoint, "http://bucket.s3.cn-north-1.amazonaws.com.cn/key");
+        assert_eq!(
+            virturalhost.as_deref(),
+            Some("bucket.s3.cn-north-1.amazonaws.com.cn")
+        );
+
+        let pool = S3Pool::for_region("us-gov-west-1");
+        assert_eq!(pool.host, "s3.us-gov-west-1.amazonaws.com");
+    }
+
+    #[test]
+    fn test_from_credential_config() {
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "s3.us-west-2.amazonaws.com", "access_key": "akey", "secret_key": "skey", "region": "us-west-2", "s3_type": "aws", "secure": true, "part_size": 10485760}"#,
+        )
+        .unwrap();
+        let pool = S3Pool::from(&config);
+        assert_eq!(pool.host, config.host);
+        assert!(pool.secure);
+        assert_eq!(pool.url_style, UrlStyle::HOST);
+        assert_eq!(pool.part_size, Some(10485760));
+        assert!(!pool.auto_part_size);
+        assert_eq!(pool.worker_count, DEFAULT_WORKER_COUNT);
+        assert_eq!(pool.signer.default_region(), Some("us-west-2"));
+
+        let config: CredentialConfig = serde_json::from_str(
+            r#"{"host": "ceph.lab:9000", "access_key": "akey", "secret_key": "skey", "s3_type": "ceph"}"#,
+        )
+        .unwrap();
+        let pool = S3Pool::from(&config);
+        assert_eq!(pool.host, "ceph.lab:9000");
+        assert!(!pool.secure);
+        assert_eq!(pool.url_style, UrlStyle::PATH);
+        assert!(pool.auto_part_size);
+        assert_eq!(pool.signer.default_region(), Some(DEFAULT_REGION));
+
+        // The part size is picked by the worker count
+        let pool = pool.worker_count(2);
+        assert_eq!(pool.download_part_size(1 << 30), 1 << 29);
+        assert!(pool.worker_count(0).worker_count > 0);
+    }
+
+    #[test]
+    fn test_from_handler_with_settings() {
+        let config = CredentialConfig {
+            host: "s3.us-east-1.amazonaws.com".to_string(),
+            access_key: "akey".to_string(),
+            secret_key: "skey".to_string(),
+            user: None,
+            region: Some("eu-west-1".to_string()),
+            s3_type: Some("ceph".to_string()),
+            secure: Some(true),
+            part_size: Some(10485760),
+            worker_count: Some(4),
+        };
+        let mut handler = Handler::from(&config);
+        assert!(!handler.is_auto_part_size());
+        handler.decompress(true);
+        handler.auto_part_size(true);
+        handler.dry_run(true);
+        handler.set_base_domain("cdn.example.com");
+
+        for pool in [S3Pool::from(&handler), S3Pool::from(handler)] {
+            assert_eq!(pool.host, "s3.us-east-1.amazonaws.com");
+            assert_eq!(pool.base_domain.as_deref(), Some("cdn.example.com"));
+            assert!(pool.secure);
+            assert!(matches!(pool.url_style, UrlStyle::PATH));
+            assert_eq!(pool.part_size, Some(10485760));
+            assert!(pool.decompress);
+            assert!(pool.auto_part_size);
+            assert_eq!(pool.worker_count, 4);
+            assert!(pool.dry_run);
+
+            // the base domain is only for the virtual-host style
+            let object = S3Object::from("s3://bucket/key");
+            assert_eq!(
+                pool.endpoint_and_virturalhost(object.clone()).0,
+                "https://s3.us-east-1.amazonaws.com/bucket/key"
+            );
+            let mut pool = pool;
+            pool.url_style = UrlStyle::HOST;
+            assert_eq!(
+                pool.endpoint_and_virturalhost(object),
+                (
+                    "https://bucket.cdn.example.com/key".to_string(),
+                    Some("bucket.cdn.example.com".to_string())
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_with_prefix_from_index() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        let index = S3Object::from("s3://bucket/photos/2021/");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated></ListBucketResult>");
+        pool.list(Some(index.clone()), &None).await.unwrap();
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated></ListBucketResult>");
+        pool.list(Some(index), &Some(Filter::Prefix("videos/".to_string())))
+            .await
+            .unwrap();
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/?prefix=photos%2F2021%2F".to_string(),
+                "/bucket/?prefix=videos%2F".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_with_empty_truncated_page() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>b.txt</NextMarker></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>c.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>");
+        let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+        let mut keys = Vec::new();
+        while let Some(obj) = folder.next_object().await.unwrap() {
+            keys.push(obj.key.unwrap());
+        }
+        assert_eq!(keys, vec!["/a.txt", "/c.txt"]);
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/",
+                "/bucket/?list-type=2&start-after=a.txt",
+                "/bucket/?list-type=2&start-after=b.txt",
+            ]
+        );
 
-            Ok(output)
-        } else {
-            // TODO reuse the client setting and not only the reqest
-            let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
-            let mut request = Request::new(Method::GET, Url::parse(&endpoint)?);
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker></ListBucketResult>");
+        let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+        assert_eq!(
+            folder.next_object().await.unwrap_err().to_string(),
+            "Error in RequestPool: listing did not advance"
+        );
+    }
 
-            let now = Utc::now();
-            self.init_headers(request.headers_mut(), &now, virturalhost);
-            self.signer.sign(&mut request, &now);
+    #[tokio::test]
+    async fn test_list_with_encoding_type_url() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone())
+            .encoding_type_url(true)
+            .fetch_owner(true);
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType><IsTruncated>true</IsTruncated><Contents><Key>a%0Ab.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>c+d.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType><IsTruncated>false</IsTruncated><Contents><Key>e.txt</Key><ETag>&quot;e&quot;</ETag><Size>7</Size><Owner><ID>owner-id</ID></Owner></Contents></ListBucketResult>");
+        let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+        let mut objects = Vec::new();
+        while let Some(obj) = folder.next_object().await.unwrap() {
+            objects.push((obj.key.unwrap(), obj.owner));
+        }
+        assert_eq!(
+            objects,
+            vec![
+                ("/a\nb.txt".to_string(), None),
+                ("/c d.txt".to_string(), None),
+                ("/e.txt".to_string(), Some("owner-id".to_string())),
+            ]
+        );
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/?encoding-type=url",
+                "/bucket/?list-type=2&start-after=c+d.txt&fetch-owner=true&encoding-type=url",
+            ]
+        );
+    }
 
-            let r = self.client.execute(request).await?;
-            // TODO validate status code
-            Ok(r.bytes().await?)
+    #[tokio::test]
+    async fn test_list_with_budget() {
+        let cases = [
+            (S3Pool::new(String::new()).list_limit(2500), 2500, 3),
+            (S3Pool::new(String::new()).list_limit(1000), 1000, 1),
+            (S3Pool::new(String::new()).list_limit(5000), 3000, 3),
+            (S3Pool::new(String::new()).list_max_requests(2), 2000, 2),
+        ];
+        for (pool, count, requests) in cases {
+            let server = TestServer::start();
+            for (start, truncated) in [(0, true), (1000, true), (2000, false)] {
+                server.respond(200, &[], list_page(start, truncated).as_bytes());
+            }
+            let pool = S3Pool {
+                host: server.host.clone(),
+                ..pool
+            };
+            let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+            let mut keys = Vec::new();
+            while let Some(obj) = folder.next_object().await.unwrap() {
+                keys.push(obj.key.unwrap());
+            }
+            assert_eq!(keys.len(), count);
+            assert_eq!(keys.last().unwrap(), &format!("/{:04}", count - 1));
+            assert_eq!(server.requests().len(), requests);
         }
     }
 
-    async fn list(
-        &self,
-        index: Option<S3Object>,
-        filter: &Option<Filter>,
-    ) -> Result<Box<dyn S3Folder>, Error> {
-        let mut pool = self.clone();
-        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(index.unwrap_or_default());
-        let url = if let Some(Filter::Prefix(prefix)) = filter {
-            Url::parse_with_params(&endpoint, &[("prefix", prefix)])?
-        } else {
-            Url::parse(&endpoint)?
-        };
-        let mut request = Request::new(Method::GET, url);
+    #[tokio::test]
+    async fn test_du() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>logs/2021/a.log</Key><ETag>&quot;a&quot;</ETag><Size>3000000000</Size><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>logs/2021/b.log</Key><ETag>&quot;b&quot;</ETag><Size>3000000000</Size><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/c.log</Key><ETag>&quot;c&quot;</ETag><Size>7</Size><StorageClass>GLACIER</StorageClass></Contents></ListBucketResult>");
+        let summary = pool.du("bucket", "/logs/", Some("/")).await.unwrap();
+        assert_eq!(summary.objects, 3);
+        assert_eq!(summary.total_bytes, 6_000_000_007);
+        assert_eq!(summary.by_storage_class["GLACIER"], 7);
+        assert_eq!(summary.sub_prefixes.len(), 1);
+        assert_eq!(summary.sub_prefixes["logs/2021/"].objects, 2);
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/?prefix=logs%2F",
+                "/bucket/?list-type=2&start-after=logs%2F2021%2Fb.log&prefix=logs%2F",
+            ]
+        );
+    }
 
-        let now = Utc::now();
-        pool.init_headers(request.headers_mut(), &now, virturalhost);
-        pool.signer.sign(&mut request, &now);
-        let body = pool.client.execute(request).await?.text().await?;
-        pool.handle_list_response(body)?;
+    #[tokio::test]
+    async fn test_list_with_glob() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/a.log</Key><ETag>&quot;a&quot;</ETag></Contents><Contents><Key>logs/a.log.gz</Key><ETag>&quot;b&quot;</ETag></Contents><Contents><Key>logs/2021/b.log</Key><ETag>&quot;c&quot;</ETag></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>a.log</Key><ETag>&quot;a&quot;</ETag></Contents><Contents><Key>b.txt</Key><ETag>&quot;b&quot;</ETag></Contents></ListBucketResult>");
+        let mut keys = Vec::new();
+        for filter in [Filter::glob("logs/*.log"), Filter::suffix(".log")] {
+            let mut folder = pool
+                .list(Some("bucket".into()), &Some(filter))
+                .await
+                .unwrap();
+            while let Some(obj) = folder.next_object().await.unwrap() {
+                keys.push(obj.key.unwrap());
+            }
+        }
+        assert_eq!(keys, vec!["/logs/a.log", "/a.log"]);
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, vec!["/bucket/?prefix=logs%2F", "/bucket/?prefix="]);
+    }
 
-        // passing filter if the list did not complete
-        if filter.is_some() && pool.is_truncated {
-            pool.filter = Some(filter.as_ref().unwrap().clone());
+    #[tokio::test]
+    async fn test_list_with_rules() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>web/app.js</Key><ETag>&quot;a&quot;</ETag></Contents><Contents><Key>web/cache/a.tmp</Key><ETag>&quot;b&quot;</ETag></Contents><Contents><Key>web/node_modules/x/index.js</Key><ETag>&quot;c&quot;</ETag></Contents><Contents><Key>web/src/node_modules/y.js</Key><ETag>&quot;d&quot;</ETag></Contents></ListBucketResult>");
+        let mut folder = pool
+            .bucket("bucket")
+            .prefix("web/")
+            .rules(Rules::default().exclude("*.tmp").exclude("node_modules/**"))
+            .list()
+            .await
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(obj) = folder.next_object().await.unwrap() {
+            keys.push(obj.key.unwrap());
         }
-        Ok(Box::new(pool))
+        assert_eq!(keys, vec!["/web/app.js", "/web/src/node_modules/y.js"]);
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, vec!["/bucket/?prefix=web%2F"]);
     }
 
-    async fn remove(&self, desc: S3Object) -> Result<(), Error> {
-        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc);
-        let mut request = Request::new(Method::DELETE, Url::parse(&endpoint)?);
+    #[tokio::test]
+    async fn test_list_modified_since() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>logs/a</Key><ETag>&quot;a&quot;</ETag><LastModified>2020-09-19T15:04:45.999Z</LastModified></Contents><Contents><Key>logs/b</Key><ETag>&quot;b&quot;</ETag><LastModified>2020-09-19T15:04:46.000Z</LastModified></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><Contents><Key>logs/c</Key><ETag>&quot;c&quot;</ETag><LastModified>2020-01-01T00:00:00.000Z</LastModified></Contents></ListBucketResult>");
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/d</Key><ETag>&quot;d&quot;</ETag><LastModified>2021-01-01T00:00:00.000Z</LastModified></Contents></ListBucketResult>");
+        let filter = Filter::ModifiedSince {
+            prefix: "logs/".to_string(),
+            since: "2020-09-19T15:04:46Z".parse().unwrap(),
+        };
+        let mut folder = pool
+            .list(Some("bucket".into()), &Some(filter))
+            .await
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(obj) = folder.next_object().await.unwrap() {
+            keys.push(obj.key.unwrap());
+        }
+        assert_eq!(keys, vec!["/logs/b", "/logs/d"]);
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/?prefix=logs%2F",
+                "/bucket/?list-type=2&start-after=logs%2Fb&prefix=logs%2F",
+                "/bucket/?list-type=2&start-after=logs%2Fc&prefix=logs%2F",
+            ]
+        );
+    }
 
-        let now = Utc::now();
-        self.init_headers(request.headers_mut(), &now, virturalhost);
-        self.signer.sign(&mut request, &now);
+    #[tokio::test]
+    async fn test_resign_on_clock_skew() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).aws_v2("akey".into(), "skey".into());
+        let server_time = (Utc::now() + Duration::hours(1)).to_rfc2822();
+        server.respond(
+            403,
+            &[("date", &server_time)],
+            b"<Error><Code>RequestTimeTooSkewed</Code><Message>The difference between the request time and the server's time is too large.</Message></Error>",
+        );
+        pool.put_object("bucket", "key", Bytes::from("content"), None)
+            .await
+            .unwrap();
+        assert!(
+            (pool.clock_offset() - Duration::hours(1))
+                .num_seconds()
+                .abs()
+                <= 1
+        );
 
-        let _r = self.client.execute(request).await?;
-        // TODO validate status code
-        Ok(())
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].body, b"content");
+        let signed_time = DateTime::parse_from_rfc2822(&requests[1].headers["date"]).unwrap();
+        assert!(
+            (signed_time.with_timezone(&Utc) - Utc::now() - Duration::hours(1))
+                .num_seconds()
+                .abs()
+                <= 2
+        );
+
+        // The offset is shared with the clones, and the request is retried only once
+        for _ in 0..2 {
+            server.respond(
+                403,
+                &[("date", &server_time)],
+                b"<Error><Code>RequestTimeTooSkewed</Code></Error>",
+            );
+        }
+        assert!(pool.clone().delete_object("bucket", "key").await.is_err());
+        assert_eq!(server.requests().len(), 4);
     }
 
-    fn check_scheme(&self, scheme: &str) -> Result<(), Error> {
-        if scheme.to_lowercase() != "s3" {
-            Err(Error::SchemeError())
-        } else {
-            Ok(())
+    #[tokio::test]
+    async fn test_multipart_download() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).part_size(7);
+        let object: Vec<u8> = (0..50u8).collect();
+        server.serve_object(&object);
+        server.respond(200, &[("content-length", "50")], b"");
+
+        let content = pool
+            .pull(S3Object::from("s3://bucket/object"))
+            .await
+            .unwrap();
+        assert_eq!(content.as_ref(), object.as_slice());
+
+        let mut ranges: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter_map(|r| r.headers.get("range").cloned())
+            .collect();
+        ranges.sort_by_key(|r| r[6..].split('-').next().unwrap().parse::<usize>().unwrap());
+        assert_eq!(ranges.len(), 8);
+        assert_eq!(ranges[0], "bytes=0-6");
+        assert_eq!(ranges[7], "bytes=49-49");
+    }
+
+    #[tokio::test]
+    async fn test_empty_object() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone()).part_size(7);
+        pool.url_style = UrlStyle::PATH;
+        // only the size is requested
+        server.respond(200, &[("content-length", "0")], b"");
+        let content = pool
+            .pull(S3Object::from("s3://bucket/_SUCCESS"))
+            .await
+            .unwrap();
+        assert!(content.is_empty());
+        assert_eq!(server.requests().len(), 1);
+
+        // the metadata is fetched once before streaming
+        server.respond(200, &[("content-length", "0")], b"");
+        let path = std::env::temp_dir().join("s3handler_test_empty_object_tokio");
+        std::fs::write(&path, b"stale").unwrap();
+        pool.clone()
+            .bucket("bucket")
+            .object("_SUCCESS")
+            .download_file(path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(server.requests().len(), 2);
+
+        pool.push(S3Object::from("s3://bucket/_SUCCESS"), Bytes::new())
+            .await
+            .unwrap();
+        let put = server.requests().pop().unwrap();
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.path, "/bucket/_SUCCESS");
+        assert!(put.body.is_empty());
+
+        // an empty stream is uploaded as one empty part
+        server.respond(
+            200,
+            &[],
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+        );
+        server.respond(200, &[("etag", "\"1\"")], b"");
+        pool.push_stream(
+            S3Object::from("s3://bucket/_SUCCESS"),
+            Box::pin(futures::stream::empty()),
+        )
+        .await
+        .unwrap();
+        let requests = server.requests();
+        let part = &requests[requests.len() - 2];
+        assert_eq!(part.method, "PUT");
+        assert!(part.path.contains("partNumber=1"));
+        assert!(part.body.is_empty());
+        assert_eq!(requests.last().unwrap().method, "POST");
+    }
+
+    #[tokio::test]
+    async fn test_dir_markers() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone());
+        pool.url_style = UrlStyle::PATH;
+        server.respond(200, &[], b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>photos/</Key><ETag>&quot;d41d8cd98f00b204e9800998ecf8427e&quot;</ETag><Size>0</Size></Contents><Contents><Key>photos/a.jpg</Key><ETag>&quot;a&quot;</ETag><Size>3</Size></Contents></ListBucketResult>");
+        // the metadata is fetched once before transferring
+        server.respond(200, &[("content-length", "3")], b"");
+        server.respond(200, &[], b"jpg");
+
+        let dir = std::env::temp_dir().join("s3handler_test_dir_markers");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut canal = pool.clone().bucket("bucket");
+        canal.toward_pool(Box::new(
+            FilePool::new(&format!("{}/", dir.to_string_lossy()))
+                .unwrap()
+                .bucket_as_directory(false),
+        ));
+        let mut folder = pool.list(Some("bucket".into()), &None).await.unwrap();
+        while let Some(obj) = folder.next_object().await.unwrap() {
+            canal.pull_obj(obj).await.unwrap();
         }
+        assert!(dir.join("photos").is_dir());
+        assert_eq!(std::fs::read(dir.join("photos/a.jpg")).unwrap(), b"jpg");
+        // the content of the marker is not requested
+        assert_eq!(server.requests().len(), 3);
+
+        // the marker of an empty folder is uploaded as an empty object
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        canal
+            .push_obj(S3Object::from("s3://bucket/empty/"))
+            .await
+            .unwrap();
+        let put = server.requests().pop().unwrap();
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.path, "/bucket/empty/");
+        assert!(put.body.is_empty());
+
+        // the marker is downloaded as a folder without the trailing `/` on the local path
+        let requests = server.requests().len();
+        pool.clone()
+            .bucket("bucket")
+            .object("photos/")
+            .download_file(dir.join("downloaded").to_str().unwrap())
+            .await
+            .unwrap();
+        assert!(dir.join("downloaded").is_dir());
+        assert_eq!(server.requests().len(), requests);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    async fn fetch_meta(&self, desc: &mut S3Object) -> Result<(), Error> {
-        let (endpoint, virturalhost) = self.endpoint_and_virturalhost(desc.clone());
-        let mut request = self.client.head(&endpoint).build()?;
+    #[tokio::test]
+    async fn test_multipart_download_larger_than_4gb() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).part_size(1 << 30);
+        server.respond(200, &[("content-length", "5368709120")], b"");
+        let stream = pool
+            .pull_stream(S3Object::from("s3://bucket/object"))
+            .await
+            .unwrap();
+        let ranges: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter_map(|r| r.headers.get("range").cloned())
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "bytes=0-1073741823",
+                "bytes=1073741824-2147483647",
+                "bytes=2147483648-3221225471",
+                "bytes=3221225472-4294967295",
+                "bytes=4294967296-5368709119",
+            ]
+        );
+        let chunks: Vec<Result<Bytes, Error>> = stream.collect().await;
+        assert!(matches!(
+            chunks.last(),
+            Some(Err(Error::IntegrityError {
+                expected: 5368709120,
+                received: 0
+            }))
+        ));
+    }
 
-        let now = Utc::now();
-        self.init_headers(request.headers_mut(), &now, virturalhost);
-        self.signer.sign(&mut request, &now);
-
-        let r = self.client.execute(request).await?;
-        let headers = r.headers();
-        desc.etag = if headers.contains_key(reqwest::header::ETAG) {
-            Some(
-                headers[reqwest::header::ETAG]
-                    .to_str()?
-                    .to_string()
-                    .replace('"', ""),
-            )
-        } else {
-            None
-        };
-        desc.mtime = if headers.contains_key(HeaderName::from_lowercase(b"last-modified").unwrap())
-        {
-            Some(
-                headers[HeaderName::from_lowercase(b"last-modified").unwrap()]
-                    .to_str()?
-                    .into(),
-            )
-        } else {
-            None
-        };
-        desc.size = if headers.contains_key(reqwest::header::CONTENT_LENGTH) {
-            Some(
-                headers[reqwest::header::CONTENT_LENGTH]
-                    .to_str()?
-                    .parse::<usize>()
-                    .unwrap_or_default(),
+    #[tokio::test]
+    async fn test_multipart_download_to_file() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).part_size(7);
+        let object: Vec<u8> = (0..50u8).collect();
+        server.serve_object(&object);
+        server.respond(200, &[("content-length", "50")], b"");
+        let stream = pool
+            .pull_stream(S3Object::from("s3://bucket/object"))
+            .await
+            .unwrap();
+
+        let dir = std::env::temp_dir();
+        let file_pool = FilePool::default();
+        file_pool
+            .push_stream(
+                S3Object {
+                    bucket: Some(dir.to_string_lossy()[1..].to_string()),
+                    key: Some("/s3handler_test_multipart_download_to_file".to_string()),
+                    ..Default::default()
+                },
+                stream,
             )
-        } else {
-            None
-        };
-
-        // TODO: check out it is correct or not that the storage class is absent here
+            .await
+            .unwrap();
+        let path = dir.join("s3handler_test_multipart_download_to_file");
+        assert_eq!(std::fs::read(&path).unwrap(), object);
+        std::fs::remove_file(path).unwrap();
+
+        server.serve_object(&[0; 45]);
+        server.respond(200, &[("content-length", "50")], b"");
+        let stream = pool
+            .pull_stream(S3Object::from("s3://bucket/object"))
+            .await
+            .unwrap();
+        let chunks: Vec<Result<Bytes, Error>> = stream.collect().await;
+        assert!(matches!(
+            chunks.last(),
+            Some(Err(Error::IntegrityError {
+                expected: 50,
+                received: 45
+            }))
+        ));
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_canal_keeps_content_type() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone());
+        pool.url_style = UrlStyle::PATH;
+        let meta = [
+            ("content-length", "7"),
+            ("content-type", "text/css"),
+            ("x-amz-meta-author", "anto"),
+        ];
+        // the metadata of the canal are fetched once before the download
+        server.respond(200, &meta, b"");
+        server.respond(200, &[], b"a{b:c}\n");
+        let mut canal = pool.clone().bucket("assets").object("site.css");
+        canal.toward_pool(Box::new(pool));
+        canal.toward_bucket("mirror");
+        canal.toward_object("site.css");
+        canal.pull().await.unwrap();
+
+        let requests = server.requests();
+        let put = requests.last().unwrap();
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.path, "/mirror/site.css");
+        assert_eq!(put.headers["content-type"], "text/css");
+        assert_eq!(put.headers["x-amz-meta-author"], "anto");
+        assert_eq!(put.body, b"a{b:c}\n");
     }
-}
 
-#[async_trait]
-impl S3Folder for S3Pool {
-    async fn next_object(&mut self) -> Result<Option<S3Object>, Error> {
-        loop {
-            if self.objects.is_empty() {
-                return Ok(None);
+    #[tokio::test]
+    async fn test_pull_obj_with_prefix() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        let prefix = "backups/testnet/rpc/2023-02-06T00:00:29Z/";
+        server.route(
+            &format!("/near-protocol-public/{}", prefix),
+            200,
+            &[],
+            b"content",
+        );
+        let listing = format!("<ListBucketResult><Name>near-protocol-public</Name><Prefix>{0}</Prefix><IsTruncated>false</IsTruncated><Contents><Key>{0}CURRENT</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>{0}data/000001.sst</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>", prefix);
+        let dir = std::env::temp_dir().join("s3handler-pull-obj-with-prefix");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        for keep_prefix in [false, true] {
+            server.respond(200, &[], listing.as_bytes());
+            let canal = pool
+                .clone()
+                .bucket("near-protocol-public")
+                .prefix(prefix)
+                .keep_prefix(keep_prefix);
+            let mut folder = canal.clone().list().await.unwrap();
+            let canal = canal.toward(&format!("{}/", dir.display())).unwrap();
+            let base = if keep_prefix {
+                dir.join("near-protocol-public").join(prefix)
             } else {
-                let obj = if self.is_truncated && self.objects.len() == 1 {
-                    let last = self.update_list().await?;
-                    last
-                } else {
-                    self.objects.remove(0)
-                };
+                dir.join("near-protocol-public")
+            };
+            std::fs::create_dir_all(base.join("data")).unwrap();
+            while let Some(obj) = folder.next_object().await.unwrap() {
                 if obj.key.is_some() {
-                    return Ok(Some(obj));
+                    canal.pull_obj(obj).await.unwrap();
                 }
             }
+            assert_eq!(std::fs::read(base.join("CURRENT")).unwrap(), b"content");
+            assert_eq!(
+                std::fs::read(base.join("data/000001.sst")).unwrap(),
+                b"content"
+            );
         }
+        let paths: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.path.contains("?prefix="))
+            .map(|r| r.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/near-protocol-public/?prefix=backups%2Ftestnet%2Frpc%2F2023-02-06T00%3A00%3A29Z%2F"; 2]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
-
-pub struct CanonicalHeadersInfo {
-    pub signed_headers: String,
-    pub canonical_headers: String,
-}
-
-pub struct CanonicalRequestInfo {
-    pub signed_headers: String,
-    pub canonical_request: String,
-}
-
-pub trait Canonical {
-    fn canonical_headers_info(&self) -> CanonicalHeadersInfo;
-    fn canonical_query_string(&self) -> String;
-    fn canonical_request_info(&self, payload_hash: &str) -> CanonicalRequestInfo;
-}
-
-impl Canonical for Request {
-    fn canonical_headers_info(&self) -> CanonicalHeadersInfo {
-        let mut canonical_headers = String::new();
-        let mut signed_headers = Vec::new();
 
-        let mut headers: Vec<(String, &str)> = self
-            .headers()
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default()))
-            .collect();
+    #[tokio::test]
+    async fn test_pull_obj_with_glob() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.route("/bucket/logs/app-1.txt", 200, &[], b"content");
+        let dir = std::env::temp_dir().join("s3handler-pull-obj-with-glob");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("bucket")).unwrap();
+
+        let mut canal = pool
+            .bucket("bucket")
+            .toward(&format!("{}/", dir.display()))
+            .unwrap();
+        canal.filter = Some(Filter::glob("logs/app*"));
+        canal
+            .pull_obj(S3Object::from("s3://bucket/logs/app-1.txt"))
+            .await
+            .unwrap();
+        // The literal part of the name is kept, only the folder is stripped
+        assert_eq!(
+            std::fs::read(dir.join("bucket/app-1.txt")).unwrap(),
+            b"content"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        headers.sort_by(|a, b| a.0.to_lowercase().as_str().cmp(b.0.to_lowercase().as_str()));
-        for h in headers {
-            canonical_headers.push_str(h.0.to_lowercase().as_str());
-            canonical_headers.push(':');
-            canonical_headers.push_str(h.1.trim());
-            canonical_headers.push('\n');
-            signed_headers.push(h.0.to_lowercase());
-        }
-        CanonicalHeadersInfo {
-            signed_headers: signed_headers.join(";"),
-            canonical_headers,
-        }
+    #[tokio::test]
+    async fn test_download_file_preserve_mtime() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone());
+        pool.url_style = UrlStyle::PATH;
+        let meta = [
+            ("content-length", "7"),
+            ("last-modified", "Sat, 19 Sep 2020 15:04:46 GMT"),
+        ];
+        // the metadata is fetched once, and reused by the pull
+        server.respond(200, &meta, b"");
+        server.respond(200, &[], b"content");
+        let path = std::env::temp_dir().join("s3handler_test_download_file_preserve_mtime");
+        pool.clone()
+            .bucket("bucket")
+            .object("object")
+            .preserve_mtime(true)
+            .download_file(path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"content");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600527886)
+        );
+        let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, vec!["HEAD", "GET"]);
+
+        // the modified time is recorded in the metadata when uploading
+        pool.bucket("bucket")
+            .object("object")
+            .preserve_mtime(true)
+            .upload_file(path.to_str().unwrap())
+            .await
+            .unwrap();
+        let put = server.requests().pop().unwrap();
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.headers["x-amz-meta-mtime"], "1600527886");
+        std::fs::remove_file(path).unwrap();
     }
 
-    fn canonical_query_string(&self) -> String {
-        let mut encoded = form_urlencoded::Serializer::new(String::new());
-        let mut qs: Vec<(String, String)> = self
-            .url()
-            .query_pairs()
-            .into_iter()
-            .map(|(k, v)| (k.as_ref().to_owned(), v.as_ref().to_owned()))
-            .collect();
+    #[tokio::test]
+    async fn test_truncated_download() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(200, &[("content-length", "50")], b"");
+        server.respond(200, &[], &[0; 20]);
+        match pool.pull(S3Object::from("s3://bucket/object")).await {
+            Err(Error::IntegrityError { expected, received }) => {
+                assert_eq!((expected, received), (50, 20))
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
 
-        qs.sort_by(|x, y| x.0.cmp(&y.0));
-
-        for (key, value) in qs {
-            encoded.append_pair(&key, &value);
-        }
-
-        // There is a `~` in upload id, should be treated in a tricky way.
-        //
-        // >>>
-        // In the concatenated string, period characters (.) are not escaped.
-        // RFC 3986 considers the period character an unreserved character,
-        // so it is **not** URL encoded.
-        // >>>
-        //
-        // ref:
-        // https://docs.aws.amazon.com/general/latest/gr/signature-version-2.html#create-canonical-string
-        encoded.finish().replace("%7E", "~")
-    }
-
-    fn canonical_request_info(&self, payload_hash: &str) -> CanonicalRequestInfo {
-        let CanonicalHeadersInfo {
-            signed_headers,
-            canonical_headers,
-        } = self.canonical_headers_info();
-        CanonicalRequestInfo {
-            signed_headers: signed_headers.clone(),
-            canonical_request: format!(
-                "{}\n{}\n{}\n{}\n{}\n{}",
-                self.method().as_str(),
-                self.url().path(),
-                self.canonical_query_string(),
-                canonical_headers,
-                signed_headers,
-                payload_hash
-            ),
+        let pool = pool.part_size(7);
+        server.serve_object(&[0; 45]);
+        server.respond(200, &[("content-length", "50")], b"");
+        match pool.pull(S3Object::from("s3://bucket/object")).await {
+            Err(Error::IntegrityError { expected, received }) => {
+                assert_eq!((expected, received), (50, 45))
+            }
+            r => panic!("unexpected result: {:?}", r),
         }
     }
-}
 
-pub trait V2Signature
-where
-    Self: Canonical,
-{
-    fn string_to_signed(&self) -> String;
-    fn sign(&self, sign_key: &str) -> String;
-}
+    #[test]
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn test_tls_config() {
+        assert!(S3Pool::new("127.0.0.1:9000".to_string())
+            .root_ca(b"-----BEGIN CERTIFICATE-----\nnot a certificate\n-----END CERTIFICATE-----\n")
+            .is_err());
+        let s3_pool = S3Pool::new("127.0.0.1:9000".to_string())
+            .danger_accept_invalid_certs(true)
+            .root_ca(TEST_CA_PEM.as_bytes())
+            .unwrap();
+        assert!(s3_pool.client_config.danger_accept_invalid_certs);
+        assert!(s3_pool.client_config.root_ca.is_some());
+    }
 
-impl V2Signature for Request {
-    fn string_to_signed(&self) -> String {
-        format!(
-            "{}\n\n\n{}\n{}{}",
-            self.method().as_str(),
-            self.headers().get(header::DATE).unwrap().to_str().unwrap(),
-            self.url().path(),
-            self.canonical_query_string()
-        )
+    #[tokio::test]
+    async fn test_connection_pool_config() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone())
+            .pool_max_idle_per_host(1)
+            .unwrap()
+            .pool_idle_timeout(std::time::Duration::from_secs(10))
+            .unwrap()
+            .http2(false)
+            .unwrap();
+        assert_eq!(pool.client_config.pool_max_idle_per_host, Some(1));
+        assert_eq!(
+            pool.client_config.pool_idle_timeout,
+            Some(std::time::Duration::from_secs(10))
+        );
+        assert_eq!(pool.client_config.http2, Some(false));
+        pool.delete_object("bucket", "key").await.unwrap();
+        assert_eq!(server.requests().len(), 1);
+
+        // HTTP/2 is negotiated, so the server speaking HTTP/1 only is still reached
+        let pool = S3Pool::new(server.host.clone()).http2(true).unwrap();
+        assert!(!pool.client_config.http2_prior_knowledge);
+        pool.delete_object("bucket", "key").await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+        let pool = pool
+            .http2_prior_knowledge(true)
+            .unwrap()
+            .http2(false)
+            .unwrap();
+        assert!(!pool.client_config.http2_prior_knowledge);
     }
-    fn sign(&self, sign_key: &str) -> String {
-        encode(&hmacsha1::hmac_sha1(
-            sign_key.as_bytes(),
-            <Request as V2Signature>::string_to_signed(self).as_bytes(),
-        ))
+
+    #[tokio::test]
+    async fn test_default_header() {
+        let server = TestServer::start();
+        assert!(S3Pool::new(server.host.clone())
+            .default_header("x tenant", "tenant")
+            .is_err());
+        let mut pool = S3Pool::new(server.host.clone())
+            .aws_v4("akey".into(), "skey".into(), "us-east-1".into())
+            .default_header("x-tenant-id", "tenant")
+            .unwrap();
+        pool.url_style = UrlStyle::PATH;
+        pool.remove(S3Object::from("s3://bucket/key"))
+            .await
+            .unwrap();
+        let request = &server.requests()[0];
+        assert_eq!(request.headers["x-tenant-id"], "tenant");
+        assert!(request.headers["authorization"].contains("x-tenant-id"));
     }
-}
 
-pub struct RequestHashInfo {
-    pub signed_headers: String,
-    pub sha256: String,
-}
+    #[tokio::test]
+    async fn test_cat() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(
+            200,
+            &[("Content-Type", "image/png")],
+            b"\x89PNG\r\n\x1a\n\xff",
+        );
+        server.respond(200, &[], b"plain");
+        let (content, content_type) = pool.cat(object_of("bucket", "a.png")).await.unwrap();
+        assert_eq!(content, Bytes::from_static(b"\x89PNG\r\n\x1a\n\xff"));
+        assert_eq!(content_type.as_deref(), Some("image/png"));
+        let (content, content_type) = pool.cat("s3://bucket/b.txt".into()).await.unwrap();
+        assert_eq!(content, Bytes::from_static(b"plain"));
+        assert_eq!(content_type, None);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/bucket/a.png");
+    }
 
-pub struct StringToSignInfo {
-    pub signed_headers: String,
-    pub string_to_signed: String,
-}
+    #[tokio::test]
+    async fn test_put_tagging() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        pool.put_tagging("bucket", "key", &[("team", "r&d"), ("<note>", "a \"b\"")])
+            .await
+            .unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/bucket/key?tagging=");
+        assert_eq!(
+            String::from_utf8_lossy(&requests[0].body),
+            "<Tagging><TagSet><Tag><Key>team</Key><Value>r&amp;d</Value></Tag><Tag><Key>&lt;note&gt;</Key><Value>a &quot;b&quot;</Value></Tag></TagSet></Tagging>"
+        );
+    }
 
-pub struct SignatureInfo {
-    pub signed_headers: String,
-    pub signature: String,
-}
+    #[tokio::test]
+    async fn test_put_object_with_tags() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone()).aws_v4(
+            "akey".into(),
+            "skey".into(),
+            "us-east-1".into(),
+        );
+        pool.url_style = UrlStyle::PATH;
+        pool.put_object_with_tags(
+            "bucket",
+            "key",
+            Bytes::from_static(b"content"),
+            None,
+            &[("project", "s3 handler"), ("team", "a&b")],
+        )
+        .await
+        .unwrap();
+
+        pool.part_size = Some(5);
+        server.respond(
+            200,
+            &[],
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+        );
+        server.respond(200, &[("etag", "\"1\"")], b"");
+        server.respond(200, &[("etag", "\"2\"")], b"");
+        pool.put_object_with_tags(
+            "bucket",
+            "key",
+            Bytes::from_static(b"content"),
+            None,
+            &[("project", "s3rs")],
+        )
+        .await
+        .unwrap();
 
-pub trait V4Signature
-where
-    Self: Canonical,
-{
-    fn string_to_signed(
-        &mut self,
-        auth_str: &str,
-        now: &UTCTime,
-        region: &str,
-        service: &str,
-        action: &str,
-    ) -> StringToSignInfo;
-    /// calculate hash mac and update header
-    fn payload_sha256(&mut self) -> String;
-    /// calculate hash mac and update header
-    fn request_sha256(&mut self) -> RequestHashInfo;
-    fn sign(
-        &mut self,
-        auth_str: &str,
-        now: &UTCTime,
-        sign_key: &str,
-        region: &str,
-        service: &str,
-        action: &str,
-    ) -> SignatureInfo;
-}
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].headers["x-amz-tagging"],
+            "project=s3%20handler&team=a%26b"
+        );
+        assert!(requests[0].headers["authorization"].contains("x-amz-tagging"));
+        assert_eq!(requests[1].path, "/bucket/key?uploads");
+        assert_eq!(requests[1].headers["x-amz-tagging"], "project=s3rs");
+        assert!(requests[1].headers["authorization"].contains("x-amz-tagging"));
+        assert!(requests[2..]
+            .iter()
+            .all(|r| !r.headers.contains_key("x-amz-tagging")));
+    }
 
-impl V4Signature for Request {
-    fn string_to_signed(
-        &mut self,
-        auth_str: &str,
-        now: &UTCTime,
-        region: &str,
-        service: &str,
-        action: &str,
-    ) -> StringToSignInfo {
-        let iso_8601_str = {
-            let mut s = now.to_rfc3339();
-            s.retain(|c| !['-', ':'].contains(&c));
-            format!("{}Z", &s[..15])
+    #[tokio::test]
+    async fn test_push_with_acl_and_request_payer() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone()).aws_v4(
+            "akey".into(),
+            "skey".into(),
+            "us-east-1".into(),
+        );
+        pool.url_style = UrlStyle::PATH;
+        let listed = S3Object {
+            storage_class: Some("GLACIER".to_string()),
+            ..S3Object::from("s3://bucket/key")
         };
-        let headers = self.headers_mut();
-        headers.insert(
-            header::HeaderName::from_static("x-amz-date"),
-            HeaderValue::from_str(&iso_8601_str).unwrap(),
-        );
-        let RequestHashInfo {
-            signed_headers,
-            sha256,
-        } = self.request_sha256();
-        StringToSignInfo {
-            signed_headers,
-            string_to_signed: format!(
-                "{}\n{}\n{}/{}/{}/{}\n{}",
-                auth_str,
-                iso_8601_str,
-                &iso_8601_str[..8],
-                region,
-                service,
-                action,
-                sha256
-            ),
+        pool.push(listed, Bytes::from_static(b"content"))
+            .await
+            .unwrap();
+        // The storage class of the source is not copied
+        assert!(!server.requests()[0]
+            .headers
+            .contains_key("x-amz-storage-class"));
+
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone())
+            .aws_v4("akey".into(), "skey".into(), "us-east-1".into())
+            .storage_class("STANDARD_IA");
+        pool.url_style = UrlStyle::PATH;
+        let desc = S3Object {
+            acl: Some("public-read".to_string()),
+            request_payer: true,
+            ..S3Object::from("s3://bucket/key")
+        };
+        pool.push(desc.clone(), Bytes::from_static(b"content"))
+            .await
+            .unwrap();
+
+        pool.part_size = Some(5);
+        server.respond(
+            200,
+            &[],
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+        );
+        server.respond(200, &[("etag", "\"1\"")], b"");
+        server.respond(200, &[("etag", "\"2\"")], b"");
+        pool.push(desc.clone(), Bytes::from_static(b"content"))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        for r in &requests[..2] {
+            assert_eq!(r.headers["x-amz-acl"], "public-read");
+            assert_eq!(r.headers["x-amz-storage-class"], "STANDARD_IA");
+            assert!(r.headers["authorization"].contains(
+                "x-amz-acl;x-amz-content-sha256;x-amz-date;x-amz-request-payer;x-amz-storage-class"
+            ));
         }
+        assert_eq!(requests[1].path, "/bucket/key?uploads");
+        for r in &requests {
+            assert_eq!(r.headers["x-amz-request-payer"], "requester");
+            assert!(r.headers["authorization"].contains("x-amz-request-payer"));
+        }
+        assert!(requests[2..]
+            .iter()
+            .all(|r| !r.headers.contains_key("x-amz-acl")));
+
+        let r = pool
+            .push(
+                S3Object {
+                    acl: Some("public".to_string()),
+                    ..desc
+                },
+                Bytes::from_static(b"content"),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::UserError(_))));
     }
 
-    fn payload_sha256(&mut self) -> String {
-        let mut sha = sha2_256::new();
-        sha.update(
-            self.body()
-                .map(|b| b.as_bytes())
-                .unwrap_or_default()
-                .unwrap_or_default(),
+    #[tokio::test]
+    async fn test_remove_prefix() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(
+            200,
+            &[],
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents><Contents><Key>logs/b.txt</Key><ETag>&quot;b&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
         );
-        let payload_hash = hex::encode(sha.finalize().as_slice());
-        let headers = self.headers_mut();
-        headers.insert(
-            header::HeaderName::from_static("x-amz-content-sha256"),
-            HeaderValue::from_str(&payload_hash).unwrap(),
+        server.respond(200, &[], b"<DeleteResult/>");
+        assert_eq!(pool.remove_prefix("bucket", "logs/").await.unwrap(), 2);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/bucket/?prefix=logs%2F");
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].path, "/bucket/?delete=");
+        assert_eq!(
+            String::from_utf8_lossy(&requests[1].body),
+            "<Delete><Quiet>true</Quiet><Object><Key>logs/a.txt</Key></Object><Object><Key>logs/b.txt</Key></Object></Delete>"
+        );
+        assert_eq!(
+            requests[1].headers["content-md5"],
+            content_md5(&requests[1].body)
         );
-        payload_hash
     }
 
-    fn request_sha256(&mut self) -> RequestHashInfo {
-        let paload_hash = self.payload_sha256();
-
-        let CanonicalRequestInfo {
-            signed_headers,
-            canonical_request,
-        } = self.canonical_request_info(&paload_hash);
-
-        let mut sha = sha2_256::new();
-        sha.update(canonical_request.as_str());
-        RequestHashInfo {
-            signed_headers,
-            sha256: hex::encode(sha.finalize().as_slice()),
+    #[tokio::test]
+    async fn test_make_and_remove_bucket() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).aws_v4(
+            "akey".into(),
+            "skey".into(),
+            "us-east-1".into(),
+        );
+        pool.make_bucket("logs-eu", Some("eu-west-1"))
+            .await
+            .unwrap();
+        pool.make_bucket("logs-us", Some("us-east-1"))
+            .await
+            .unwrap();
+        pool.remove_bucket("logs-eu").await.unwrap();
+        assert!(matches!(
+            pool.make_bucket("10.0.0.1", None).await,
+            Err(Error::UserError(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        for request in requests.iter() {
+            assert_eq!(request.headers["host"], server.host);
         }
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].path, "/logs-eu/");
+        assert!(String::from_utf8_lossy(&requests[0].body)
+            .contains("<LocationConstraint>eu-west-1</LocationConstraint>"));
+        assert_eq!(requests[1].path, "/logs-us/");
+        assert!(requests[1].body.is_empty());
+        assert_eq!(requests[2].method, "DELETE");
+        assert_eq!(requests[2].path, "/logs-eu/");
     }
 
-    fn sign(
-        &mut self,
-        auth_str: &str,
-        now: &UTCTime,
-        sign_key: &str,
-        region: &str,
-        service: &str,
-        action: &str,
-    ) -> SignatureInfo {
-        let StringToSignInfo {
-            signed_headers,
-            string_to_signed,
-        } = <Request as V4Signature>::string_to_signed(
-            self, auth_str, now, region, service, action,
-        );
-        let time_str = {
-            let mut s = now.to_rfc3339();
-            s.retain(|c| !['-', ':'].contains(&c));
-            &s[..8].to_string()
-        };
+    #[tokio::test]
+    async fn test_discover_bucket_region() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone()).aws_v4(
+            "akey".into(),
+            "skey".into(),
+            "us-east-1".into(),
+        );
+        pool.url_style = UrlStyle::PATH;
+        server.respond(
+            400,
+            &[("x-amz-bucket-region", "eu-west-1")],
+            b"<Error><Code>AuthorizationHeaderMalformed</Code><Message>the region 'us-east-1' is wrong; expecting 'eu-west-1'</Message></Error>",
+        );
+        pool.delete_object("logs-eu", "a").await.unwrap();
+        // the region is cached by the clones of the pool
+        pool.clone().delete_object("logs-eu", "b").await.unwrap();
+        pool.delete_object("logs-us", "c").await.unwrap();
 
-        let mut key: String = auth_str.split('-').next().unwrap_or_default().to_string();
-        key.push_str(sign_key);
+        let regions: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| {
+                r.headers["authorization"]
+                    .split('/')
+                    .nth(2)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        // the second request to the bucket skips the redirect
+        assert_eq!(
+            regions,
+            vec!["us-east-1", "eu-west-1", "eu-west-1", "us-east-1"]
+        );
 
-        let mut mac = Hmac::<sha2_256>::new_from_slice(key.as_str().as_bytes())
-            .expect("HMAC can take key of any size");
-        mac.update(time_str.as_bytes());
-        let result = mac.finalize();
-        let code_bytes = result.into_bytes();
+        // the error is returned if the region is already signed
+        server.respond(
+            403,
+            &[("x-amz-bucket-region", "eu-west-1")],
+            b"<Error><Code>AccessDenied</Code><Message>denied</Message></Error>",
+        );
+        assert!(matches!(
+            pool.delete_object("logs-eu", "d").await,
+            Err(Error::S3Error { status: 403, .. })
+        ));
+        assert_eq!(server.requests().len(), 5);
+    }
 
-        let mut mac1 =
-            Hmac::<sha2_256>::new_from_slice(&code_bytes).expect("HMAC can take key of any size");
-        mac1.update(region.as_bytes());
-        let result1 = mac1.finalize();
-        let code_bytes1 = result1.into_bytes();
+    #[tokio::test]
+    async fn test_region_for_bucket() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone())
+            .aws_v4("akey".into(), "skey".into(), "us-east-1".into())
+            .region_for_bucket("logs-eu", "eu-west-1")
+            .region_for_bucket("logs-ap", "AP-Northeast-1");
+        pool.url_style = UrlStyle::PATH;
+        for bucket in ["logs-eu", "logs-ap", "logs-us"] {
+            pool.delete_object(bucket, "key").await.unwrap();
+        }
+        let regions: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| {
+                r.headers["authorization"]
+                    .split('/')
+                    .nth(2)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(regions, vec!["eu-west-1", "ap-northeast-1", "us-east-1"]);
 
-        let mut mac2 =
-            Hmac::<sha2_256>::new_from_slice(&code_bytes1).expect("HMAC can take key of any size");
-        mac2.update(service.as_bytes());
-        let result2 = mac2.finalize();
-        let code_bytes2 = result2.into_bytes();
+        assert_eq!(
+            pool.bucket_region(
+                &Url::parse("http://logs-eu.s3.example.com/key").unwrap(),
+                Some(&format!("logs-eu.{}", pool.host))
+            ),
+            Some("eu-west-1".to_string())
+        );
+    }
 
-        let mut mac3 =
-            Hmac::<sha2_256>::new_from_slice(&code_bytes2).expect("HMAC can take key of any size");
-        mac3.update(action.as_bytes());
-        let result3 = mac3.finalize();
-        let code_bytes3 = result3.into_bytes();
+    #[tokio::test]
+    async fn test_dry_run() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone()).dry_run(true);
+        server.respond(
+            200,
+            &[],
+            b"<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated><Contents><Key>logs/a.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size></Contents></ListBucketResult>",
+        );
+        assert_eq!(pool.remove_prefix("bucket", "logs/").await.unwrap(), 1);
+        pool.delete_object("bucket", "b.txt").await.unwrap();
+        pool.put_object("bucket", "c.txt", Bytes::from_static(b"content"), None)
+            .await
+            .unwrap();
+        pool.delete_tagging("bucket", "c.txt").await.unwrap();
+
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+        let planned = pool.take_planned();
+        assert_eq!(
+            planned
+                .iter()
+                .map(|a| (a.method.as_str(), a.target.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("POST", "s3://bucket"),
+                ("DELETE", "s3://bucket/b.txt"),
+                ("PUT", "s3://bucket/c.txt"),
+                ("DELETE", "s3://bucket/c.txt"),
+            ]
+        );
+        assert_eq!(
+            planned[3].query,
+            vec![("tagging".to_string(), "".to_string())]
+        );
+        assert!(pool.take_planned().is_empty());
+    }
 
-        let mut mac4 =
-            Hmac::<sha2_256>::new_from_slice(&code_bytes3).expect("HMAC can take key of any size");
-        mac4.update(string_to_signed.as_bytes());
-        let result4 = mac4.finalize();
-        let code_bytes4 = result4.into_bytes();
+    #[tokio::test]
+    async fn test_multipart_upload_without_etag() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone());
+        pool.url_style = UrlStyle::PATH;
+        pool.part_size = Some(5);
+        server.respond(
+            200,
+            &[],
+            b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+        );
+        server.respond(200, &[("etag", "\"1\"")], b"");
+        server.respond(200, &[], b"");
+        let r = pool
+            .put_object("bucket", "key", Bytes::from_static(b"content"), None)
+            .await;
+        assert!(matches!(r, Err(Error::FieldNotFound("ETag"))));
+        assert!(server
+            .requests()
+            .iter()
+            .all(|r| r.method != "POST" || r.path != "/bucket/key?uploadId=upload-id"));
+    }
 
-        SignatureInfo {
-            signed_headers,
-            signature: format!("{code_bytes4:02x}"),
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_abort_dropped_multipart_upload() {
+        let server = TestServer::start();
+        for abort in [true, false] {
+            let mut pool = S3Pool::new(server.host.clone()).abort_on_drop(abort);
+            pool.url_style = UrlStyle::PATH;
+            server.respond(
+                200,
+                &[],
+                b"<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+            );
+            // the stream never ends, and it tells when the upload is initiated
+            let (tx, rx) = futures::channel::oneshot::channel();
+            let mut tx = Some(tx);
+            let stream = futures::stream::poll_fn(move |_| {
+                if let Some(tx) = tx.take() {
+                    tx.send(()).unwrap();
+                }
+                std::task::Poll::<Option<Result<Bytes, Error>>>::Pending
+            });
+            tokio::select! {
+                _ = pool.push_stream(object_of("bucket", "key"), Box::pin(stream)) => {
+                    panic!("the upload should not be completed")
+                }
+                _ = rx => {}
+            }
+        }
+        // the abort is sent in the background
+        for _ in 0..100 {
+            if server.requests().len() >= 3 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
         }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let requests: Vec<(String, String)> = server
+            .requests()
+            .into_iter()
+            .map(|r| (r.method, r.path))
+            .collect();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.contains(&(
+            "DELETE".to_string(),
+            "/bucket/key?uploadId=upload-id".to_string()
+        )));
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|(_, path)| path == "/bucket/key?uploads")
+                .count(),
+            2
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::blocking::CredentialConfig;
+    #[tokio::test]
+    async fn test_list_parts_and_uploads() {
+        let server = TestServer::start();
+        let pool = S3Pool::new(server.host.clone());
+        server.respond(
+            200,
+            &[],
+            b"<ListPartsResult><NextPartNumberMarker>1</NextPartNumberMarker><IsTruncated>true</IsTruncated><Part><PartNumber>1</PartNumber><Size>5</Size></Part></ListPartsResult>",
+        );
+        server.respond(
+            200,
+            &[],
+            b"<ListPartsResult><IsTruncated>false</IsTruncated><Part><PartNumber>2</PartNumber><Size>3</Size></Part></ListPartsResult>",
+        );
+        let parts = pool.list_parts("bucket", "key", "upload-id").await.unwrap();
+        assert_eq!(
+            parts.iter().map(|p| (p.number, p.size)).collect::<Vec<_>>(),
+            vec![(1, 5), (2, 3)]
+        );
+
+        server.respond(
+            200,
+            &[],
+            b"<ListMultipartUploadsResult><NextKeyMarker>a</NextKeyMarker><NextUploadIdMarker>1</NextUploadIdMarker><IsTruncated>true</IsTruncated><Upload><Key>a</Key><UploadId>1</UploadId></Upload></ListMultipartUploadsResult>",
+        );
+        server.respond(
+            200,
+            &[],
+            b"<ListMultipartUploadsResult><IsTruncated>false</IsTruncated><Upload><Key>b</Key><UploadId>2</UploadId></Upload></ListMultipartUploadsResult>",
+        );
+        let uploads = pool.list_multipart_uploads("bucket", None).await.unwrap();
+        assert_eq!(uploads.len(), 2);
+        assert_eq!(uploads[1].key, "b");
+
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/bucket/key?uploadId=upload-id",
+                "/bucket/key?uploadId=upload-id&part-number-marker=1",
+                "/bucket/?uploads=",
+                "/bucket/?uploads=&key-marker=a&upload-id-marker=1",
+            ]
+        );
+
+        // The truncated lists without the markers to advance are not continued
+        let repeated = b"<ListPartsResult><NextPartNumberMarker>1</NextPartNumberMarker><IsTruncated>true</IsTruncated></ListPartsResult>";
+        server.respond(200, &[], repeated);
+        server.respond(200, &[], repeated);
+        assert!(pool.list_parts("bucket", "key", "upload-id").await.is_err());
+        server.respond(
+            200,
+            &[],
+            b"<ListMultipartUploadsResult><IsTruncated>true</IsTruncated></ListMultipartUploadsResult>",
+        );
+        assert!(pool.list_multipart_uploads("bucket", None).await.is_err());
+        let repeated = b"<ListVersionsResult><IsTruncated>true</IsTruncated><NextKeyMarker>a</NextKeyMarker><NextVersionIdMarker>v1</NextVersionIdMarker></ListVersionsResult>";
+        server.respond(200, &[], repeated);
+        server.respond(200, &[], repeated);
+        assert!(pool.list_versions("bucket", None).await.is_err());
+        assert_eq!(server.requests().len(), 9);
+    }
 
     #[tokio::test]
-    async fn test_handle_list_response() {
-        let s = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>ant-lab</Name><Prefix></Prefix><Marker></Marker><MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated><Contents><Key>14M</Key><LastModified>2020-01-31T14:58:45.000Z</LastModified><ETag>&quot;8ff43d748637d249d80d6f45e15c7663-3&quot;</ETag><Size>14336000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>7M</Key><LastModified>2020-11-21T09:50:46.000Z</LastModified><ETag>&quot;cbe4f29b8b099989ae49afc02aa1c618-2&quot;</ETag><Size>7168000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>7M.json</Key><LastModified>2020-09-19T14:59:23.000Z</LastModified><ETag>&quot;d34bd3f9aff10629ac49353312a42b0f-2&quot;</ETag><Size>7168000</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>get</Key><LastModified>2020-08-11T06:10:11.000Z</LastModified><ETag>&quot;f895d74af5106ce0c3d6cb008fb3b98d&quot;</ETag><Size>304</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>t</Key><LastModified>2020-09-19T15:10:08.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>10</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>t.txt</Key><LastModified>2020-09-19T15:04:46.000Z</LastModified><ETag>&quot;5050ef3558233dc04b3fac50eff68de1&quot;</ETag><Size>10</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test-orig</Key><LastModified>2020-11-21T09:48:29.000Z</LastModified><ETag>&quot;c059dadd468de1835bc99dab6e3b2cee-3&quot;</ETag><Size>11534336</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test-s3handle</Key><LastModified>2020-11-21T10:09:39.000Z</LastModified><ETag>&quot;5dd39cab1c53c2c77cd352983f9641e1&quot;</ETag><Size>20</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents><Contents><Key>test.json</Key><LastModified>2020-08-11T09:54:42.000Z</LastModified><ETag>&quot;f895d74af5106ce0c3d6cb008fb3b98d&quot;</ETag><Size>304</Size><Owner><ID>54bbddd7c9c485b696f5b188467d4bec889b83d3862d0a6db526d9d17aadcee2</ID><DisplayName>yanganto</DisplayName></Owner><StorageClass>STANDARD</StorageClass></Contents></ListBucketResult>";
-        let mut pool = S3Pool::new("somewhere.in.the.world".to_string());
-        pool.handle_list_response(s.to_string()).unwrap();
-        assert!(!pool.objects.is_empty());
-        assert!(!pool.is_truncated);
+    async fn test_metrics() {
+        let server = TestServer::start();
+        let metrics = Arc::new(CounterMetrics::default());
+        let pool = S3Pool::new(server.host.clone()).metrics(metrics.clone());
+        pool.put_object("bucket", "key", Bytes::from_static(b"content"), None)
+            .await
+            .unwrap();
+        server.respond(200, &[("content-length", "7")], b"");
+        server.respond(200, &[], b"content");
+        pool.get_object("bucket", "key").await.unwrap();
+        // The bodies of the responses are not counted by the async pool
+        assert_eq!(metrics.counts(), (3, 7, 0, 0));
+
+        server.respond(
+            403,
+            &[("date", &Utc::now().to_rfc2822())],
+            b"<Error><Code>RequestTimeTooSkewed</Code></Error>",
+        );
+        pool.remove(S3Object::from("s3://bucket/key"))
+            .await
+            .unwrap();
+        assert_eq!(metrics.counts().0, 5);
+        assert_eq!(metrics.counts().3, 1);
+        assert_eq!(metrics.errors.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.latency_histogram().iter().sum::<usize>(), 5);
+        assert_eq!(metrics.hosts()[&server.host], 5);
     }
 
     #[test]
-    fn test_from_blocking_handle_to_s3_pool() {
-        let config = CredentialConfig {
-            host: "s3.us-east-1.amazonaws.com".to_string(),
-            access_key: "akey".to_string(),
-            secret_key: "skey".to_string(),
-            user: None,
-            region: None,  // default is us-east-1
-            s3_type: None, // default will try to config as AWS S3 handler
-            secure: None,  // dafault is false, because the integrity protect by HMAC
-        };
-        let handler = Handler::from(&config);
-        let mut pool = S3Pool::from(&handler);
-        let s3_pool = S3Pool::new("s3.us-east-1.amazonaws.com".to_string());
-        assert_eq!(pool.host, s3_pool.host);
+    fn test_record() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone()).aws_v4(
+            "akey".into(),
+            "skey".into(),
+            "us-east-1".into(),
+        );
+        pool.url_style = UrlStyle::PATH;
+        let recorded = pool
+            .record(
+                Method::PUT,
+                S3Object::from("s3://bucket/key"),
+                &[("tagging", "")],
+                Bytes::from_static(b"<Tagging/>"),
+            )
+            .unwrap();
+        assert_eq!(recorded.method, "PUT");
+        assert_eq!(
+            recorded.url,
+            format!("http://{}/bucket/key?tagging=", server.host)
+        );
+        assert_eq!(
+            recorded.payload_hash,
+            PayloadHash::of(b"<Tagging/>").as_str()
+        );
+        assert_eq!(
+            recorded.header("x-amz-content-sha256"),
+            Some(recorded.payload_hash.as_str())
+        );
+        assert_eq!(recorded.header("host"), Some(server.host.as_str()));
+        assert!(recorded
+            .header("authorization")
+            .unwrap()
+            .starts_with("AWS4-HMAC-SHA256 Credential=akey/"));
+        assert!(server.requests().is_empty());
+    }
 
-        pool = handler.into();
-        let s3_pool = S3Pool::new("s3.us-east-1.amazonaws.com".to_string());
-        assert_eq!(pool.host, s3_pool.host);
+    #[tokio::test]
+    async fn test_recording() {
+        let server = TestServer::start();
+        let mut pool = S3Pool::new(server.host.clone())
+            .aws_v4("akey".into(), "skey".into(), "us-east-1".into())
+            .default_header("x-amz-meta-city", "Zürich")
+            .unwrap()
+            .recording(true);
+        pool.url_style = UrlStyle::PATH;
+        pool.push(
+            S3Object::from("s3://bucket/key"),
+            Bytes::from_static(b"content"),
+        )
+        .await
+        .unwrap();
+        // the clone of the pool records into the same requests
+        pool.clone()
+            .remove(S3Object::from("s3://bucket/key"))
+            .await
+            .unwrap();
+        let recorded = pool.take_recorded();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].method, "PUT");
+        assert_eq!(
+            recorded[0].payload_hash,
+            PayloadHash::of(b"content").as_str()
+        );
+        assert!(recorded[0]
+            .header("authorization")
+            .unwrap()
+            .starts_with("AWS4-HMAC-SHA256 Credential=akey/"));
+        // the header value out of ASCII is kept
+        assert_eq!(recorded[0].header("x-amz-meta-city"), Some("Zürich"));
+        assert_eq!(recorded[1].method, "DELETE");
+        assert!(pool.take_recorded().is_empty());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn test_secure_endpoints() {
+        for (secure, scheme) in [(false, "http"), (true, "https")] {
+            let s3_pool = S3Pool::new("127.0.0.1:9000".to_string()).secure(secure);
+            assert_eq!(
+                s3_pool.endpoint_and_virturalhost(S3Object::from("s3://bucket/key")),
+                (format!("{}://127.0.0.1:9000/bucket/key", scheme), None)
+            );
+            let s3_pool = s3_pool.aws_v4("akey".into(), "skey".into(), "us-east-1".into());
+            assert_eq!(
+                s3_pool.endpoint_and_virturalhost(S3Object::from("s3://bucket/key")),
+                (
+                    format!("{}://bucket.127.0.0.1:9000/key", scheme),
+                    Some("bucket.127.0.0.1:9000".to_string())
+                )
+            );
+        }
     }
 }
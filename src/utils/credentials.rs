@@ -0,0 +1,577 @@
+//! The providers of the credentials for signing, the signers ask the provider for every request,
+//! so the rotated credentials, ex: the ones of an instance profile, are picked up without
+//! rebuilding the pools or the handler.
+//! ```
+//! use std::sync::Arc;
+//! use s3handler::utils::credentials::{CredentialProvider, EnvProvider, StaticProvider};
+//!
+//! let provider: Arc<dyn CredentialProvider> = Arc::new(StaticProvider::new("akey", "skey"));
+//! assert_eq!(provider.credentials().unwrap().access_key, "akey");
+//! // or take the credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+//! let provider: Arc<dyn CredentialProvider> = Arc::new(EnvProvider);
+//! ```
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+use crate::error::Error;
+
+/// The credentials to sign the requests
+/// - session_token - the token of the temporary credentials, sent as `x-amz-security-token`
+/// - expiry - the credentials should be fetched again after this time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl Credentials {
+    pub fn new(access_key: String, secret_key: String) -> Self {
+        Credentials {
+            access_key,
+            secret_key,
+            session_token: None,
+            expiry: None,
+        }
+    }
+
+    /// The credentials are expired or will be expired in the margin
+    pub fn is_expired(&self, margin: chrono::Duration) -> bool {
+        match self.expiry {
+            Some(expiry) => expiry - margin <= Utc::now(),
+            None => false,
+        }
+    }
+}
+
+/// The source of the credentials, which is queried when a request is signed.
+/// The provider refreshes the credentials by itself if they can be rotated.
+pub trait CredentialProvider: Send + Sync + fmt::Debug {
+    fn credentials(&self) -> Result<Credentials, Error>;
+}
+
+/// The fixed credentials, ex: the keys from the config
+#[derive(Debug, Clone)]
+pub struct StaticProvider(Credentials);
+
+impl StaticProvider {
+    pub fn new(access_key: &str, secret_key: &str) -> Self {
+        StaticProvider(Credentials::new(
+            access_key.to_string(),
+            secret_key.to_string(),
+        ))
+    }
+}
+
+impl From<Credentials> for StaticProvider {
+    fn from(credentials: Credentials) -> Self {
+        StaticProvider(credentials)
+    }
+}
+
+impl CredentialProvider for StaticProvider {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// The credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`,
+/// the variables are read for every request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvProvider;
+
+impl CredentialProvider for EnvProvider {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        let var = |name: &str| {
+            env::var(name).map_err(|_| Error::CredentialError(format!("{} is not set", name)))
+        };
+        Ok(Credentials {
+            access_key: var("AWS_ACCESS_KEY_ID")?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            expiry: None,
+        })
+    }
+}
+
+/// The credentials of a profile in the shared credentials file, the file is read for every
+/// request, so the credentials rewritten by another tool are picked up.
+/// The file is `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials`, and the profile is
+/// `AWS_PROFILE` or `default`, if they are not specified.
+#[derive(Debug, Clone)]
+pub struct ProfileProvider {
+    path: PathBuf,
+    profile: String,
+}
+
+impl Default for ProfileProvider {
+    fn default() -> Self {
+        let path = match env::var("AWS_SHARED_CREDENTIALS_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => PathBuf::from(env::var("HOME").unwrap_or_default())
+                .join(".aws")
+                .join("credentials"),
+        };
+        ProfileProvider {
+            path,
+            profile: env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string()),
+        }
+    }
+}
+
+impl ProfileProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the credentials from the file
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Use the credentials of the profile
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = profile.to_string();
+        self
+    }
+}
+
+impl CredentialProvider for ProfileProvider {
+    fn credentials(&self) -> Result<Credentials, Error> {
+        let content = std::fs::read_to_string(&self.path).map_err(|e| {
+            Error::CredentialError(format!("can not read {}: {}", self.path.display(), e))
+        })?;
+        let mut in_profile = false;
+        let mut access_key = None;
+        let mut secret_key = None;
+        let mut session_token = None;
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_profile = section.trim() == self.profile;
+            } else if let (true, Some((key, value))) = (in_profile, line.split_once('=')) {
+                let value = Some(value.trim().to_string());
+                match key.trim() {
+                    "aws_access_key_id" => access_key = value,
+                    "aws_secret_access_key" => secret_key = value,
+                    "aws_session_token" => session_token = value,
+                    _ => {}
+                }
+            }
+        }
+        match (access_key, secret_key) {
+            (Some(access_key), Some(secret_key)) => Ok(Credentials {
+                access_key,
+                secret_key,
+                session_token,
+                expiry: None,
+            }),
+            _ => Err(Error::CredentialError(format!(
+                "the keys of profile {} are not found in {}",
+                self.profile,
+                self.path.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+pub use imds::{ContainerProvider, ImdsProvider};
+
+#[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+mod imds {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use chrono::{DateTime, Utc};
+    use reqwest::blocking::Client;
+
+    use super::{CredentialProvider, Credentials};
+    use crate::error::Error;
+
+    const DEFAULT_ENDPOINT: &str = "http://169.254.169.254";
+    const TOKEN_TTL_HEADER: &str = "x-aws-ec2-metadata-token-ttl-seconds";
+    const TOKEN_HEADER: &str = "x-aws-ec2-metadata-token";
+    const CONTAINER_ENDPOINT: &str = "http://169.254.170.2";
+    /// The credentials without the expiration are fetched again after a while, rather than being
+    /// cached forever
+    const DEFAULT_TTL_MINUTES: i64 = 15;
+
+    /// The credentials are fetched again 5 minutes before they are expired, the lock is not held
+    /// while fetching, so the other callers are not blocked by the metadata service, and the
+    /// credentials fetched last are kept if they are fetched at the same time.
+    pub(super) fn cached_or_fetch<F>(
+        cached: &Mutex<Option<Credentials>>,
+        fetch: F,
+    ) -> Result<Credentials, Error>
+    where
+        F: FnOnce() -> Result<Credentials, Error> + Send + 'static,
+    {
+        let lock = || {
+            cached
+                .lock()
+                .map_err(|e| Error::CredentialError(e.to_string()))
+        };
+        if let Some(credentials) = lock()?
+            .as_ref()
+            .filter(|c| !c.is_expired(chrono::Duration::minutes(5)))
+        {
+            return Ok(credentials.clone());
+        }
+        let credentials = fetch_blocking(fetch)?;
+        *lock()? = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    /// Fetch in another thread, because the blocking client can not be used in the thread of an
+    /// async runtime, and the worker of a multi-thread tokio runtime is handed over to the other
+    /// tasks while waiting.
+    fn fetch_blocking<F>(fetch: F) -> Result<Credentials, Error>
+    where
+        F: FnOnce() -> Result<Credentials, Error> + Send + 'static,
+    {
+        let wait = move || {
+            std::thread::spawn(fetch)
+                .join()
+                .map_err(|_| Error::CredentialError("fetching panicked".to_string()))?
+        };
+        #[cfg(feature = "tokio-async")]
+        if tokio::runtime::Handle::try_current()
+            .is_ok_and(|h| h.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread)
+        {
+            return tokio::task::block_in_place(wait);
+        }
+        wait()
+    }
+
+    /// The credentials document of the metadata services of EC2 and ECS
+    pub(super) fn parse_credentials(document: &str) -> Result<Credentials, Error> {
+        let document: serde_json::Value =
+            serde_json::from_str(document).map_err(|e| Error::CredentialError(e.to_string()))?;
+        let field = |name: &str| {
+            document[name]
+                .as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| {
+                    Error::CredentialError(format!("{} is not in the credentials", name))
+                })
+        };
+        Ok(Credentials {
+            access_key: field("AccessKeyId")?,
+            secret_key: field("SecretAccessKey")?,
+            session_token: field("Token").ok(),
+            expiry: Some(match field("Expiration") {
+                Ok(e) => e.parse::<DateTime<Utc>>().map_err(|err| {
+                    Error::CredentialError(format!("invalid Expiration {}: {}", e, err))
+                })?,
+                Err(_) => Utc::now() + chrono::Duration::minutes(DEFAULT_TTL_MINUTES),
+            }),
+        })
+    }
+
+    fn client() -> Result<Client, Error> {
+        Ok(Client::builder().timeout(Duration::from_secs(5)).build()?)
+    }
+
+    /// The credentials of the instance profile from the EC2 instance metadata service (IMDSv2),
+    /// the credentials are cached and fetched again 5 minutes before they are expired.
+    #[derive(Debug)]
+    pub struct ImdsProvider {
+        endpoint: String,
+        cached: Mutex<Option<Credentials>>,
+    }
+
+    impl Default for ImdsProvider {
+        fn default() -> Self {
+            ImdsProvider {
+                endpoint: DEFAULT_ENDPOINT.to_string(),
+                cached: Mutex::new(None),
+            }
+        }
+    }
+
+    impl ImdsProvider {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// The endpoint of the metadata service, ex: `http://[fd00:ec2::254]` for IPv6
+        pub fn endpoint(mut self, endpoint: &str) -> Self {
+            self.endpoint = endpoint.trim_end_matches('/').to_string();
+            self
+        }
+
+        fn fetch(endpoint: &str) -> Result<Credentials, Error> {
+            let client = client()?;
+            let token = client
+                .put(format!("{}/latest/api/token", endpoint))
+                .header(TOKEN_TTL_HEADER, "21600")
+                .send()?
+                .error_for_status()?
+                .text()?;
+            let get = |path: &str| {
+                client
+                    .get(format!(
+                        "{}/latest/meta-data/iam/security-credentials/{}",
+                        endpoint, path
+                    ))
+                    .header(TOKEN_HEADER, token.as_str())
+                    .send()?
+                    .error_for_status()?
+                    .text()
+            };
+            let roles = get("")?;
+            let role = roles
+                .lines()
+                .next()
+                .ok_or_else(|| Error::CredentialError("no instance profile".to_string()))?;
+            parse_credentials(&get(role)?)
+        }
+    }
+
+    impl CredentialProvider for ImdsProvider {
+        fn credentials(&self) -> Result<Credentials, Error> {
+            let endpoint = self.endpoint.clone();
+            cached_or_fetch(&self.cached, move || Self::fetch(&endpoint))
+        }
+    }
+
+    /// The credentials of the task role from the ECS container metadata endpoint,
+    /// the uri is `AWS_CONTAINER_CREDENTIALS_FULL_URI` or `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI`
+    /// on 169.254.170.2, and `AWS_CONTAINER_AUTHORIZATION_TOKEN` is sent if it is set.
+    #[derive(Debug)]
+    pub struct ContainerProvider {
+        uri: String,
+        authorization: Option<String>,
+        cached: Mutex<Option<Credentials>>,
+    }
+
+    impl Default for ContainerProvider {
+        fn default() -> Self {
+            let uri = std::env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI").unwrap_or_else(|_| {
+                format!(
+                    "{}{}",
+                    CONTAINER_ENDPOINT,
+                    std::env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI").unwrap_or_default()
+                )
+            });
+            ContainerProvider {
+                uri,
+                authorization: std::env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN").ok(),
+                cached: Mutex::new(None),
+            }
+        }
+    }
+
+    impl ContainerProvider {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// The full uri of the credentials
+        pub fn uri(mut self, uri: &str) -> Self {
+            self.uri = uri.to_string();
+            self
+        }
+
+        /// The value of the `Authorization` header
+        pub fn authorization(mut self, token: &str) -> Self {
+            self.authorization = Some(token.to_string());
+            self
+        }
+
+        fn fetch(uri: &str, authorization: Option<&str>) -> Result<Credentials, Error> {
+            let mut request = client()?.get(uri);
+            if let Some(token) = authorization {
+                request = request.header(reqwest::header::AUTHORIZATION, token);
+            }
+            parse_credentials(&request.send()?.error_for_status()?.text()?)
+        }
+    }
+
+    impl CredentialProvider for ContainerProvider {
+        fn credentials(&self) -> Result<Credentials, Error> {
+            let uri = self.uri.clone();
+            let authorization = self.authorization.clone();
+            cached_or_fetch(&self.cached, move || {
+                Self::fetch(&uri, authorization.as_deref())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_provider() {
+        let path = std::env::temp_dir().join("s3handler_test_profile_provider");
+        std::fs::write(
+            &path,
+            "[default]\naws_access_key_id = akey\naws_secret_access_key = skey\n\n# rotated\n[ci]\naws_access_key_id=ci-akey\naws_secret_access_key=ci-skey\naws_session_token=token\n",
+        )
+        .unwrap();
+        let provider = ProfileProvider::new().path(&path);
+        assert_eq!(
+            provider.credentials().unwrap(),
+            Credentials::new("akey".to_string(), "skey".to_string())
+        );
+        let credentials = provider.clone().profile("ci").credentials().unwrap();
+        assert_eq!(credentials.access_key, "ci-akey");
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert!(provider.profile("prod").credentials().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_credentials_expiry() {
+        let mut credentials = Credentials::new("akey".to_string(), "skey".to_string());
+        assert!(!credentials.is_expired(chrono::Duration::minutes(5)));
+        credentials.expiry = Some(Utc::now() + chrono::Duration::minutes(3));
+        assert!(credentials.is_expired(chrono::Duration::minutes(5)));
+        assert!(!credentials.is_expired(chrono::Duration::zero()));
+    }
+
+    #[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_parse_credentials() {
+        let credentials = imds::parse_credentials(
+            r#"{"AccessKeyId":"akey","SecretAccessKey":"skey","Expiration":"2100-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.expiry,
+            Some("2100-01-01T00:00:00Z".parse().unwrap())
+        );
+        // The credentials without the expiration are not cached forever
+        let credentials =
+            imds::parse_credentials(r#"{"AccessKeyId":"akey","SecretAccessKey":"skey"}"#).unwrap();
+        assert!(credentials.expiry.unwrap() < Utc::now() + chrono::Duration::hours(1));
+        assert!(imds::parse_credentials(
+            r#"{"AccessKeyId":"akey","SecretAccessKey":"skey","Expiration":"tomorrow"}"#
+        )
+        .is_err());
+    }
+
+    #[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_cached_or_fetch() {
+        use std::sync::{mpsc, Arc, Mutex};
+
+        let cached = Arc::new(Mutex::new(None));
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let fetching = {
+            let cached = cached.clone();
+            std::thread::spawn(move || {
+                imds::cached_or_fetch(&cached, move || {
+                    started.send(()).unwrap();
+                    wait_release.recv().unwrap();
+                    Ok(Credentials::new("akey".to_string(), "skey".to_string()))
+                })
+            })
+        };
+        wait_started.recv().unwrap();
+        // The lock is not held while fetching
+        assert!(cached.try_lock().unwrap().is_none());
+        release.send(()).unwrap();
+        assert_eq!(fetching.join().unwrap().unwrap().access_key, "akey");
+        let credentials =
+            imds::cached_or_fetch(&cached, || panic!("the credentials are cached")).unwrap();
+        assert_eq!(credentials.access_key, "akey");
+    }
+
+    #[cfg(all(feature = "imds", feature = "tokio-async", not(target_arch = "wasm32")))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_imds_provider_in_runtime() {
+        use crate::utils::test_server::TestServer;
+
+        let server = TestServer::start();
+        server.respond(200, &[], b"imds-token");
+        server.respond(200, &[], b"role\n");
+        server.respond(
+            200,
+            &[],
+            br#"{"AccessKeyId":"akey","SecretAccessKey":"skey","Token":"token","Expiration":"2100-01-01T00:00:00Z"}"#,
+        );
+        let provider = ImdsProvider::new().endpoint(&format!("http://{}", server.host));
+        assert_eq!(provider.credentials().unwrap().access_key, "akey");
+    }
+
+    #[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_imds_provider() {
+        use crate::utils::test_server::TestServer;
+
+        let server = TestServer::start();
+        server.respond(200, &[], b"imds-token");
+        server.respond(200, &[], b"role\n");
+        server.respond(
+            200,
+            &[],
+            br#"{"AccessKeyId":"akey","SecretAccessKey":"skey","Token":"token","Expiration":"2100-01-01T00:00:00Z"}"#,
+        );
+        let provider = ImdsProvider::new().endpoint(&format!("http://{}", server.host));
+        let credentials = provider.credentials().unwrap();
+        assert_eq!(credentials.access_key, "akey");
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        // the cached credentials are not expired
+        provider.credentials().unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].headers["x-aws-ec2-metadata-token-ttl-seconds"],
+            "21600"
+        );
+        assert_eq!(
+            requests[2].path,
+            "/latest/meta-data/iam/security-credentials/role"
+        );
+        assert_eq!(
+            requests[2].headers["x-aws-ec2-metadata-token"],
+            "imds-token"
+        );
+    }
+
+    #[cfg(all(feature = "imds", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_container_provider() {
+        use crate::utils::test_server::TestServer;
+
+        let server = TestServer::start();
+        let expiry = (Utc::now() + chrono::Duration::minutes(3)).to_rfc3339();
+        for key in ["akey", "akey2"] {
+            server.respond(
+                200,
+                &[],
+                format!(
+                    r#"{{"AccessKeyId":"{}","SecretAccessKey":"skey","Token":"token","Expiration":"{}"}}"#,
+                    key, expiry
+                )
+                .as_bytes(),
+            );
+        }
+        let provider = ContainerProvider::new()
+            .uri(&format!("http://{}/v2/credentials/id", server.host))
+            .authorization("auth-token");
+        assert_eq!(provider.credentials().unwrap().access_key, "akey");
+        // the credentials are going to expire, so they are fetched again
+        assert_eq!(provider.credentials().unwrap().access_key, "akey2");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/v2/credentials/id");
+        assert_eq!(requests[0].headers["authorization"], "auth-token");
+    }
+}
@@ -0,0 +1,270 @@
+/// The pattern of the keys, which is compiled once and checked on every listed object.
+/// - `*` - any characters in a folder, ex: `logs/*.log`
+/// - `?` - one character in a folder
+/// - `**` - any characters across the folders, ex: `**/*.parquet`, `**/` also matches no folder
+///
+/// The keys are matched without the leading `/`, and the literal part before the first wildcard
+/// is listed as the prefix by the server.
+/// ```
+/// use s3handler::utils::glob::Glob;
+///
+/// let glob = Glob::new("data/**/*.parquet");
+/// assert_eq!(glob.prefix(), "data/");
+/// assert!(glob.is_match("data/2021/01/part-0.parquet"));
+/// assert!(glob.is_match("data/part-0.parquet"));
+/// assert!(!glob.is_match("data/part-0.parquet.crc"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    pattern: String,
+    prefix: String,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    One,
+    /// `*`
+    Any,
+    /// `**`
+    Recursive,
+    /// `**/`
+    Folders,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '?' => Token::One,
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        Token::Folders
+                    } else {
+                        Token::Recursive
+                    }
+                }
+                '*' => Token::Any,
+                c => Token::Literal(c),
+            });
+        }
+        let prefix = tokens
+            .iter()
+            .map_while(|t| match t {
+                Token::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect();
+        Glob {
+            pattern: pattern.to_string(),
+            prefix,
+            tokens,
+        }
+    }
+
+    /// The keys end with the suffix in any folder, ex: `.log`
+    pub fn suffix(suffix: &str) -> Self {
+        let mut tokens = vec![Token::Recursive];
+        tokens.extend(suffix.chars().map(Token::Literal));
+        Glob {
+            pattern: format!("**{}", suffix),
+            prefix: String::new(),
+            tokens,
+        }
+    }
+
+    /// The literal part before the first wildcard
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The key is matched without the leading `/`, ex: `/logs/a.log` and `logs/a.log` are the same
+    pub fn is_match(&self, key: &str) -> bool {
+        let key: Vec<char> = key.strip_prefix('/').unwrap_or(key).chars().collect();
+        matches(&self.tokens, &key)
+    }
+}
+
+/// Include or exclude the keys matching the glob, a pattern without `/` is matched with the name
+/// in any folder, ex: `*.tmp`, else it is matched with the whole key, ex: `node_modules/**`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Include(Glob),
+    Exclude(Glob),
+}
+
+impl Rule {
+    pub fn is_match(&self, key: &str) -> bool {
+        let glob = match self {
+            Rule::Include(glob) | Rule::Exclude(glob) => glob,
+        };
+        if glob.as_str().contains('/') {
+            glob.is_match(key)
+        } else {
+            glob.is_match(key.rsplit('/').next().unwrap_or(key))
+        }
+    }
+}
+
+/// The ordered include and exclude rules of the recursive operations, the rules are checked in
+/// order and the first matched one decides, and the keys matching none of them are included.
+///
+/// Please note the later filter takes precedence in aws-cli, so `--exclude "*" --include "*.txt"`
+/// is written in the reversed order.
+/// ```
+/// use s3handler::utils::glob::Rules;
+///
+/// let rules = Rules::default().include("*.txt").exclude("*");
+/// assert!(rules.is_included("docs/a.txt"));
+/// assert!(!rules.is_included("docs/a.jpg"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.rules.push(Rule::Include(Glob::new(pattern)));
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.rules.push(Rule::Exclude(Glob::new(pattern)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The key, which is relative to the folder or the prefix of the operation, is kept or not
+    pub fn is_included(&self, key: &str) -> bool {
+        !matches!(
+            self.rules.iter().find(|r| r.is_match(key)),
+            Some(Rule::Exclude(_))
+        )
+    }
+}
+
+/// The tokens are matched with the keys in a table of the suffixes, rather than backtracking,
+/// such that the patterns with many wildcards are checked in `O(tokens * key)` time.
+/// `next[k]` is true if the tokens after the current one match `key[k..]`.
+fn matches(tokens: &[Token], key: &[char]) -> bool {
+    let n = key.len();
+    let mut next: Vec<bool> = (0..=n).map(|k| k == n).collect();
+    for token in tokens.iter().rev() {
+        let mut current = vec![false; n + 1];
+        // any suffix after a `/` from k is matched by the rest, for `**/`
+        let mut after_folder = false;
+        for k in (0..=n).rev() {
+            let c = key.get(k);
+            current[k] = match token {
+                Token::Literal(l) => c == Some(l) && next[k + 1],
+                Token::One => c.is_some_and(|c| *c != '/') && next[k + 1],
+                Token::Any => next[k] || (c.is_some_and(|c| *c != '/') && current[k + 1]),
+                Token::Recursive => next[k] || (c.is_some() && current[k + 1]),
+                Token::Folders => {
+                    after_folder |= c == Some(&'/') && next[k + 1];
+                    next[k] || after_folder
+                }
+            };
+        }
+        next = current;
+    }
+    next[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let glob = Glob::new("logs/*/app-?.log");
+        assert_eq!(glob.prefix(), "logs/");
+        assert!(glob.is_match("/logs/2021/app-1.log"));
+        assert!(!glob.is_match("logs/2021/01/app-1.log"));
+        assert!(!glob.is_match("logs/2021/app-10.log"));
+
+        let glob = Glob::new("**/*.parquet");
+        assert_eq!(glob.prefix(), "");
+        assert!(glob.is_match("a.parquet"));
+        assert!(glob.is_match("data/2021/a.parquet"));
+        assert!(!glob.is_match("data/a.parquet/_SUCCESS"));
+
+        let glob = Glob::new("data/**");
+        assert!(glob.is_match("data/a/b"));
+
+        // the wildcards are not backtracked exponentially
+        let glob = Glob::new(&"*a".repeat(30));
+        assert!(!glob.is_match(&format!("{}b", "a".repeat(60))));
+        assert!(glob.is_match(&"a".repeat(60)));
+        assert!(Glob::new(&"**a".repeat(30)).is_match(&"a".repeat(200)));
+        assert!(!glob.is_match("other/a"));
+
+        let glob = Glob::new("data/report.csv");
+        assert_eq!(glob.prefix(), "data/report.csv");
+        assert!(glob.is_match("data/report.csv"));
+        assert!(!glob.is_match("data/report.csv.gz"));
+
+        let glob = Glob::suffix(".log");
+        assert_eq!(glob.prefix(), "");
+        assert!(glob.is_match("/a/b/c.log"));
+        assert!(!glob.is_match("/a/b/c.log.gz"));
+    }
+
+    #[test]
+    fn test_rules() {
+        // The folder in the examples of aws-cli
+        let files = [
+            ".git/config",
+            ".git/description",
+            "foo.txt",
+            "bar.txt",
+            "baz.jpg",
+        ];
+        let included = |rules: &Rules| -> Vec<&str> {
+            files
+                .iter()
+                .copied()
+                .filter(|f| rules.is_included(f))
+                .collect()
+        };
+
+        assert_eq!(included(&Rules::default()), files.to_vec());
+        // --exclude ".git/*"
+        assert_eq!(
+            included(&Rules::default().exclude(".git/*")),
+            vec!["foo.txt", "bar.txt", "baz.jpg"]
+        );
+        // --exclude "*" --include "*.txt"
+        assert_eq!(
+            included(&Rules::default().include("*.txt").exclude("*")),
+            vec!["foo.txt", "bar.txt"]
+        );
+        // --include "*.txt" --exclude "*"
+        assert!(included(&Rules::default().exclude("*").include("*.txt")).is_empty());
+        // --exclude "*.txt"
+        assert_eq!(
+            included(&Rules::default().exclude("*.txt")),
+            vec![".git/config", ".git/description", "baz.jpg"]
+        );
+
+        let rules = Rules::default().exclude("*.tmp").exclude("node_modules/**");
+        assert!(rules.is_included("src/main.js"));
+        assert!(!rules.is_included("/src/cache/a.tmp"));
+        assert!(!rules.is_included("node_modules/left-pad/index.js"));
+        assert!(rules.is_included("src/node_modules/index.js"));
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(target_arch = "wasm32")]
+use chrono::{DateTime, Utc};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+/// The hooks for the observability of the requests, ex: a Prometheus recorder.
+/// All hooks are no-op by default, please implement the ones you need.
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use s3handler::utils::metrics::Metrics;
+///
+/// #[derive(Debug, Default)]
+/// struct Counter(AtomicUsize);
+///
+/// impl Metrics for Counter {
+///     fn on_request(&self, _method: &str, _status: Option<u16>, _latency: std::time::Duration) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait Metrics: Send + Sync + fmt::Debug {
+    /// A request is finished, the status is None if there is no response, ex: connection failed
+    fn on_request(&self, _method: &str, _status: Option<u16>, _latency: Duration) {}
+
+    /// A request to the host is finished, ex: to label the metrics by the endpoints,
+    /// it is passed to `on_request` by default.
+    fn on_host_request(&self, method: &str, _host: &str, status: Option<u16>, latency: Duration) {
+        self.on_request(method, status, latency)
+    }
+
+    /// The bytes of the request body sent and the response body received,
+    /// the streaming body is not counted when sending, and the response body is not counted
+    /// by the async pools, which read the body after the request is finished.
+    fn on_bytes(&self, _sent: u64, _received: u64) {}
+
+    /// A request is retried, ex: re-signed for the skewed clock
+    fn on_retry(&self, _method: &str) {}
+
+    /// A http client is built with a new pool of the connections, the idle connections in the
+    /// pool are reused by the later requests of the client, ex: to check the connections are
+    /// not opened for every request.
+    fn on_client(&self) {}
+}
+
+/// The default metrics without recording anything
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// The upper bounds of the latency buckets in milliseconds, the last bucket is unbounded
+pub const LATENCY_BUCKETS: [u64; 6] = [10, 50, 100, 500, 1000, 5000];
+
+/// The metrics kept in the atomic counters, ex: for the tests or to be scraped by an exporter
+/// ```
+/// use std::sync::Arc;
+/// use s3handler::utils::metrics::CounterMetrics;
+///
+/// let metrics = Arc::new(CounterMetrics::default());
+/// let s3_pool = s3handler::none_blocking::primitives::S3Pool::new("somewhere.in.the.world".to_string())
+///     .metrics(metrics.clone());
+/// let (requests, sent, received, retries) = metrics.counts();
+/// ```
+#[derive(Debug, Default)]
+pub struct CounterMetrics {
+    pub requests: AtomicUsize,
+    /// The requests without a response or with a status of 4xx and 5xx
+    pub errors: AtomicUsize,
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    pub retries: AtomicUsize,
+    /// The http clients built, each of them has its own pool of the connections
+    pub clients: AtomicUsize,
+    /// The requests in the latency buckets of `LATENCY_BUCKETS`
+    pub latency: [AtomicUsize; LATENCY_BUCKETS.len() + 1],
+    hosts: Mutex<HashMap<String, usize>>,
+}
+
+impl Metrics for CounterMetrics {
+    fn on_request(&self, _method: &str, status: Option<u16>, latency: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_none_or(|s| s >= 400) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|b| ms <= *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_host_request(&self, method: &str, host: &str, status: Option<u16>, latency: Duration) {
+        *self
+            .hosts
+            .lock()
+            .expect("hosts should be accessible")
+            .entry(host.to_string())
+            .or_default() += 1;
+        self.on_request(method, status, latency)
+    }
+    fn on_bytes(&self, sent: u64, received: u64) {
+        self.sent.fetch_add(sent, Ordering::Relaxed);
+        self.received.fetch_add(received, Ordering::Relaxed);
+    }
+    fn on_retry(&self, _method: &str) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_client(&self) {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl CounterMetrics {
+    /// The requests, sent bytes, received bytes and retries
+    pub fn counts(&self) -> (usize, u64, u64, usize) {
+        (
+            self.requests.load(Ordering::Relaxed),
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+            self.retries.load(Ordering::Relaxed),
+        )
+    }
+
+    /// The requests in each latency bucket
+    pub fn latency_histogram(&self) -> Vec<usize> {
+        self.latency
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// The requests to each host
+    pub fn hosts(&self) -> HashMap<String, usize> {
+        self.hosts
+            .lock()
+            .expect("hosts should be accessible")
+            .clone()
+    }
+}
+
+/// The stopwatch for the latency of a request, `Instant` panics on wasm32,
+/// so the clock from `chrono` backed by `js_sys::Date` is used there.
+#[cfg_attr(not(feature = "tokio-async"), allow(dead_code))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
+    #[cfg(target_arch = "wasm32")]
+    start: DateTime<Utc>,
+}
+
+#[cfg_attr(not(feature = "tokio-async"), allow(dead_code))]
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: Instant::now(),
+            #[cfg(target_arch = "wasm32")]
+            start: Utc::now(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn elapsed(&self) -> Duration {
+        (Utc::now() - self.start).to_std().unwrap_or_default()
+    }
+}
@@ -1,11 +1,221 @@
-use quick_xml::{events::Event, Reader};
-use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::Read;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use quick_xml::{escape::escape, events::Event, Reader};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde_derive::Deserialize;
 use url::Url;
 
 use crate::error::Error;
 
+pub mod credentials;
+pub mod glob;
+pub mod metrics;
+pub mod notification;
+pub mod sign;
+#[cfg(all(feature = "sts", not(target_arch = "wasm32")))]
+pub mod sts;
+pub mod sync;
+#[cfg(test)]
+pub(crate) mod test_server;
+pub mod walk;
+
 pub const DEFAULT_REGION: &str = "us-east-1";
 
+/// The minimum part size picked by `auto_part_size`
+pub const AUTO_PART_SIZE_MIN: u64 = 64 * 1024 * 1024;
+/// The maximum part size picked by `auto_part_size`
+pub const AUTO_PART_SIZE_MAX: u64 = 512 * 1024 * 1024;
+
+/// Pick the part size of a download by the object size, the object is split for the
+/// concurrency, and each part is bounded in 64MB ~ 512MB and rounded up to MB.
+/// The object smaller than the minimum is downloaded in one request.
+pub fn auto_part_size(object_size: u64, concurrency: usize) -> u64 {
+    let concurrency = concurrency.max(1) as u64;
+    let part_size = object_size.div_ceil(concurrency);
+    let part_size = part_size.clamp(AUTO_PART_SIZE_MIN, AUTO_PART_SIZE_MAX);
+    (part_size + (1 << 20) - 1) >> 20 << 20
+}
+
+/// The size of a buffer in memory, the object larger than the address space, ex: 4GB on the
+/// 32-bit targets, can not be buffered and should be streamed.
+pub fn buffer_size(size: u64) -> Result<usize, Error> {
+    usize::try_from(size)
+        .map_err(|_| Error::UserError("the object is too large to buffer in memory"))
+}
+
+/// Check the bucket name with the DNS-compatible naming rules before creating the bucket,
+/// the existing buckets are not checked, because the legacy buckets in us-east-1 may have
+/// the uppercase letters or the underscores.
+/// ```
+/// use s3handler::utils::validate_bucket_name;
+///
+/// assert!(validate_bucket_name("my-logs.2021").is_ok());
+/// assert!(validate_bucket_name("My_Logs").is_err());
+/// ```
+pub fn validate_bucket_name(name: &str) -> Result<(), Error> {
+    if name.len() < 3 || name.len() > 63 {
+        return Err(Error::UserError(
+            "the bucket name should be 3 to 63 characters long",
+        ));
+    }
+    if !name
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'.' || b == b'-')
+    {
+        return Err(Error::UserError(
+            "the bucket name should only contain lowercase letters, numbers, dots and hyphens",
+        ));
+    }
+    let is_alphanumeric = |b: Option<&u8>| b.is_some_and(|b| b.is_ascii_alphanumeric());
+    if !is_alphanumeric(name.as_bytes().first()) || !is_alphanumeric(name.as_bytes().last()) {
+        return Err(Error::UserError(
+            "the bucket name should begin and end with a letter or a number",
+        ));
+    }
+    if name.contains("..") {
+        return Err(Error::UserError(
+            "the bucket name should not contain two adjacent dots",
+        ));
+    }
+    if name.parse::<std::net::Ipv4Addr>().is_ok() {
+        return Err(Error::UserError(
+            "the bucket name should not be formatted as an IP address",
+        ));
+    }
+    Ok(())
+}
+
+/// The region in the signature is case sensitive, ex: " US-East-1 " is signed as "us-east-1"
+pub fn normalize_region(region: &str) -> String {
+    region.trim().to_lowercase()
+}
+
+/// The partitions of AWS, the regions in a partition share the same suffix of the endpoints,
+/// ex: `amazonaws.com.cn` for the regions in China.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    Aws,
+    /// `cn-north-1` and `cn-northwest-1`
+    China,
+    /// `us-gov-west-1` and `us-gov-east-1`
+    GovCloud,
+}
+
+impl Partition {
+    /// The partition of the region
+    pub fn of(region: &str) -> Self {
+        let region = normalize_region(region);
+        if region.starts_with("cn-") {
+            Partition::China
+        } else if region.starts_with("us-gov-") {
+            Partition::GovCloud
+        } else {
+            Partition::Aws
+        }
+    }
+
+    /// The partition in the ARNs, ex: `arn:aws-cn:s3:::bucket`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Partition::Aws => "aws",
+            Partition::China => "aws-cn",
+            Partition::GovCloud => "aws-us-gov",
+        }
+    }
+
+    pub fn dns_suffix(&self) -> &'static str {
+        match self {
+            Partition::Aws | Partition::GovCloud => "amazonaws.com",
+            Partition::China => "amazonaws.com.cn",
+        }
+    }
+}
+
+/// The S3 endpoint of the region in its partition, ex: `s3.cn-north-1.amazonaws.com.cn`
+pub fn region_endpoint(region: &str) -> String {
+    let region = normalize_region(region);
+    format!("s3.{}.{}", region, Partition::of(&region).dns_suffix())
+}
+
+/// # The struct for credential config for each S3 cluster
+/// - host is a parameter for the server you want to link
+///     - it can be s3.us-east-1.amazonaws.com or a ip, ex 10.1.1.100, for a ceph node
+///     - if host is empty, the AWS endpoint of the region is used, ex: s3.cn-north-1.amazonaws.com.cn
+/// - user name is not required, because it only show in the prompt of shell
+/// - access_key and secret_key are keys to connect to the cluster providing S3
+/// - region is a paramter for the S3 cluster location
+///     - if region is not specified, it will take default value us-east-1
+/// - s3 type is a shortcut to set up auth type, format, url style for aws or ceph
+///     - if s3_type is not specified, it will take aws as default value, aws
+/// - secure is the request will send via https or not.  The integrity of requests is provided by
+/// HMAC, and the https requests can provid the confidentiality.
+/// - part_size is the chunk size in bytes for multipart upload and download
+///     - if part_size is not specified, the downloads pick the part size by the object size,
+///       and the uploads take default value 5MB
+/// - worker_count is the max number of workers for multipart transfer
+///     - if worker_count is not specified, it will take default value 10
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct CredentialConfig {
+    pub host: String,
+    pub user: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+    pub region: Option<String>,
+    pub s3_type: Option<String>,
+    pub secure: Option<bool>,
+    pub part_size: Option<u64>,
+    pub worker_count: Option<usize>,
+}
+
+impl CredentialConfig {
+    /// The host, or the AWS endpoint in the partition of the region if the host is not given
+    pub fn endpoint(&self) -> String {
+        if self.host.is_empty() {
+            region_endpoint(self.region.as_deref().unwrap_or(DEFAULT_REGION))
+        } else {
+            self.host.clone()
+        }
+    }
+}
+
+/// The settings of the http clients
+/// - the TLS settings for the S3 services with self-signed certificates, ex: the ceph cluster in
+///   a lab
+/// - the connection pool settings for the load balancers dropping the idle keep-alive
+///   connections, ex: haproxy
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// Skip the certificate verification, the connection is open to man-in-the-middle attacks
+    pub danger_accept_invalid_certs: bool,
+    /// The root CA certificate in PEM trusted additionally, this is the safer way
+    pub root_ca: Option<Vec<u8>>,
+    /// The maximum idle connections kept for a host, default is unlimited
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Close the idle connections after the timeout, default is 90 seconds, please keep it
+    /// shorter than the idle timeout of the load balancer
+    pub pool_idle_timeout: Option<std::time::Duration>,
+    /// Negotiate HTTP/2 with the server by ALPN or use HTTP/1 only, default is negotiated
+    pub http2: Option<bool>,
+    /// Use HTTP/2 without the negotiation, only for the servers known to speak HTTP/2 in plain
+    /// text, ex: the RGW without a TLS terminator, AWS S3 refuses the requests
+    pub http2_prior_knowledge: bool,
+}
+
+/// The max size of the source object copied in one request, the larger one is copied in parts
+pub const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The max number of parts in a multipart upload
+pub const MAX_PART_COUNT: u64 = 10_000;
+
+/// The max length of the body kept in the error, if the error page is not from S3
+const ERROR_SNIPPET_LENGTH: usize = 256;
+
 /// # Flexible S3 format parser
 /// - bucket - the objeck belonge to which
 /// - key - the object key
@@ -13,6 +223,11 @@ pub const DEFAULT_REGION: &str = "us-east-1";
 /// - etag - the etag calculated by server (MD5 in general)
 /// - storage_class - the storage class of this object
 /// - size - the size of the object
+/// - mime - the content type of the object
+/// - content_encoding - the content encoding of the object, ex: gzip
+/// - request_id - the `x-amz-request-id` of the response fetching the metadata
+/// - host_id - the `x-amz-id-2` of the response fetching the metadata
+/// - metadata - the user metadata, ex: `x-amz-meta-author` is kept as `author`
 /// ```
 /// use s3handler::{S3Object, S3Convert};
 ///
@@ -37,12 +252,93 @@ pub struct S3Object {
     pub mtime: Option<String>, // TODO: use some datetime type
     pub etag: Option<String>,
     pub storage_class: Option<String>,
-    pub size: Option<usize>,
+    pub size: Option<u64>,
     pub mime: Option<String>,
+    pub content_encoding: Option<String>,
+    pub request_id: Option<String>,
+    pub host_id: Option<String>,
+    pub metadata: HashMap<String, String>,
+    /// The canonical user ID of the owner in the listing
+    pub owner: Option<String>,
+    /// The canned ACL set by `x-amz-acl` when the object is pushed, ex: "public-read"
+    pub acl: Option<String>,
+    /// Agree to pay for the requests in a requester-pays bucket by `x-amz-request-payer`
+    pub request_payer: bool,
+}
+
+impl S3Object {
+    /// The 0-byte object with a key ending in `/` created by the tools as the marker of a folder
+    pub fn is_dir_marker(&self) -> bool {
+        self.key.as_deref().is_some_and(|k| k.ends_with('/'))
+    }
+
+    /// The object is modified at or after the time, the `LastModified` of a listing and the
+    /// `Last-Modified` header are both accepted, the object without a valid mtime, ex: a bucket,
+    /// is treated as modified, such that it is not missed by an incremental backup.
+    pub fn is_modified_since(&self, since: &DateTime<Utc>) -> bool {
+        match self.last_modified() {
+            Some(mtime) => mtime >= *since,
+            None => true,
+        }
+    }
+
+    /// The modified time of the file kept in the `mtime` user metadata when it was uploaded,
+    /// or the `Last-Modified` of the object
+    pub fn modified_time(&self) -> Option<DateTime<Utc>> {
+        self.metadata
+            .get(MTIME_METADATA)
+            .and_then(|t| parse_mtime_metadata(t))
+            .or_else(|| self.last_modified())
+    }
+
+    fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.mtime.as_deref().and_then(|t| {
+            DateTime::parse_from_rfc3339(t)
+                .or_else(|_| DateTime::parse_from_rfc2822(t))
+                .ok()
+                .map(|t| t.with_timezone(&Utc))
+        })
+    }
+}
+
+/// The user metadata keeping the modified time of the file in seconds since the epoch,
+/// which is the same as s3cmd and rclone, ex: `1600527886.5`
+pub const MTIME_METADATA: &str = "mtime";
+
+/// The `mtime` user metadata of the time, the fraction of a second is kept if there is one
+pub fn mtime_metadata(time: &DateTime<Utc>) -> String {
+    match time.timestamp_subsec_nanos() {
+        0 => time.timestamp().to_string(),
+        nanos => format!("{}.{:09}", time.timestamp(), nanos)
+            .trim_end_matches('0')
+            .to_string(),
+    }
+}
+
+fn parse_mtime_metadata(mtime: &str) -> Option<DateTime<Utc>> {
+    let (secs, fraction) = mtime.trim().split_once('.').unwrap_or((mtime.trim(), ""));
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        format!("{:0<9}", &fraction[..fraction.len().min(9)])
+            .parse()
+            .ok()?
+    };
+    Utc.timestamp_opt(secs.parse().ok()?, nanos).single()
 }
 
 impl From<&str> for S3Object {
     fn from(s3_path: &str) -> Self {
+        // The path pasted from shell may be padded with spaces or quoted
+        let s3_path = s3_path.trim();
+        let s3_path = ['"', '\'']
+            .iter()
+            .find_map(|q| s3_path.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(s3_path)
+            .trim();
         if let Ok(url_parser) = Url::parse(s3_path) {
             let bucket = match url_parser.host_str() {
                 Some(h) if !h.is_empty() => Some(h.to_string()),
@@ -57,6 +353,13 @@ impl From<&str> for S3Object {
                     storage_class: None,
                     size: None,
                     mime: None,
+                    content_encoding: None,
+                    request_id: None,
+                    host_id: None,
+                    metadata: HashMap::new(),
+                    owner: None,
+                    acl: None,
+                    request_payer: false,
                 },
                 _ => S3Object {
                     bucket,
@@ -66,6 +369,13 @@ impl From<&str> for S3Object {
                     storage_class: None,
                     size: None,
                     mime: None,
+                    content_encoding: None,
+                    request_id: None,
+                    host_id: None,
+                    metadata: HashMap::new(),
+                    owner: None,
+                    acl: None,
+                    request_payer: false,
                 },
             }
         } else {
@@ -96,7 +406,7 @@ pub trait S3Convert {
         mtime: Option<String>,
         etag: Option<String>,
         storage_class: Option<String>,
-        size: Option<usize>,
+        size: Option<u64>,
     ) -> Self;
 }
 
@@ -125,30 +435,18 @@ impl S3Convert for S3Object {
         }
     }
 
+    /// The first segment is the bucket and the rest is the key, ex: `bucket/prefix`,
+    /// the empty path or `/` is the root without bucket.
     fn new_from_uri(uri: &str) -> S3Object {
-        let re = Regex::new(r#"/?(?P<bucket>[A-Za-z0-9\-\._]+)(?P<object>[A-Za-z0-9\-\._/]*)\s*"#)
-            .unwrap();
-        let caps = re.captures(uri).expect("S3 object uri format error.");
-        if caps["object"].is_empty() || &caps["object"] == "/" {
-            S3Object {
-                bucket: Some(caps["bucket"].to_string()),
-                key: None,
-                mtime: None,
-                etag: None,
-                storage_class: None,
-                size: None,
-                mime: None,
-            }
-        } else {
-            S3Object {
-                bucket: Some(caps["bucket"].to_string()),
-                key: Some(caps["object"].to_string()),
-                mtime: None,
-                etag: None,
-                storage_class: None,
-                size: None,
-                mime: None,
-            }
+        let uri = uri.trim();
+        let uri = uri.strip_prefix('/').unwrap_or(uri);
+        let (bucket, key) = uri.split_once('/').unwrap_or((uri, ""));
+        S3Object {
+            bucket: Some(bucket.to_string()).filter(|b| !b.is_empty()),
+            key: Some(key)
+                .filter(|k| !k.is_empty())
+                .map(|k| format!("/{}", k)),
+            ..Default::default()
         }
     }
 
@@ -158,7 +456,7 @@ impl S3Convert for S3Object {
         mtime: Option<String>,
         etag: Option<String>,
         storage_class: Option<String>,
-        size: Option<usize>,
+        size: Option<u64>,
     ) -> S3Object {
         let key = match object {
             None => None,
@@ -179,12 +477,19 @@ impl S3Convert for S3Object {
             storage_class,
             size,
             mime: None,
+            content_encoding: None,
+            request_id: None,
+            host_id: None,
+            metadata: HashMap::new(),
+            owner: None,
+            acl: None,
+            request_payer: false,
         }
     }
 }
 
 /// The request URL style
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum UrlStyle {
     /// Path style URL
     /// The bucket name will be listed in the URI
@@ -200,6 +505,164 @@ impl Default for UrlStyle {
     }
 }
 
+impl UrlStyle {
+    /// The endpoint of the object in the style, and the virtual host which is set as the host
+    /// header, there is no virtual host in the path style.
+    pub fn endpoint(&self, host: &str, secure: bool, desc: &S3Object) -> (String, Option<String>) {
+        let ((host, uri), virturalhost) = match self {
+            UrlStyle::PATH => (desc.path_style_links(host.to_string()), None),
+            UrlStyle::HOST => {
+                let (host, uri) = desc.virtural_host_style_links(host.to_string());
+                ((host.clone(), uri), Some(host))
+            }
+        };
+        if secure {
+            (format!("https://{}{}", host, uri), virturalhost)
+        } else {
+            (format!("http://{}{}", host, uri), virturalhost)
+        }
+    }
+}
+
+impl std::str::FromStr for UrlStyle {
+    type Err = Error;
+
+    /// Parse `path` or `host`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "path" => Ok(UrlStyle::PATH),
+            "host" => Ok(UrlStyle::HOST),
+            _ => Err(Error::UserError("url style should be path or host")),
+        }
+    }
+}
+
+/// Check the content encoding is gzip or not
+pub fn is_gzip(content_encoding: Option<&str>) -> bool {
+    content_encoding
+        .map(|e| e.split(',').any(|e| e.trim().eq_ignore_ascii_case("gzip")))
+        .unwrap_or_default()
+}
+
+/// Decompress the content encoded with gzip
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    GzDecoder::new(data)
+        .read_to_end(&mut output)
+        .map_err(Error::DecompressError)?;
+    Ok(output)
+}
+
+/// Take the `x-amz-request-id` and `x-amz-id-2` from the response headers,
+/// which are asked by the support of AWS.
+pub fn request_ids(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let get = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    (get("x-amz-request-id"), get("x-amz-id-2"))
+}
+
+/// The prefix of the headers carrying the user metadata of an object
+pub const METADATA_PREFIX: &str = "x-amz-meta-";
+
+/// Fill the metadata of the object from the headers of a HEAD or GET response
+#[cfg(any(feature = "tokio-async", feature = "async-std"))]
+pub(crate) fn fill_meta(desc: &mut S3Object, headers: &HeaderMap) -> Result<(), Error> {
+    let get = |name| -> Result<Option<String>, Error> {
+        Ok(headers
+            .get(name)
+            .map(|v| v.to_str())
+            .transpose()?
+            .map(|v| v.to_string()))
+    };
+    desc.size = get(reqwest::header::CONTENT_LENGTH)?.and_then(|l| l.parse::<u64>().ok());
+    desc.etag = get(reqwest::header::ETAG)?.map(|e| e.replace('"', ""));
+    desc.mtime = get(reqwest::header::LAST_MODIFIED)?;
+    desc.mime = get(reqwest::header::CONTENT_TYPE)?;
+    desc.content_encoding = get(reqwest::header::CONTENT_ENCODING)?;
+    desc.metadata = user_metadata(headers);
+    (desc.request_id, desc.host_id) = request_ids(headers);
+    Ok(())
+}
+
+/// Take the user metadata from the `x-amz-meta-*` headers, the prefix is stripped from the keys
+pub fn user_metadata(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(k, v)| {
+            let key = k.as_str().strip_prefix(METADATA_PREFIX)?;
+            Some((key.to_string(), v.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
+
+/// The error code of the request rejected for the difference between the request time and the
+/// server time is too large.
+pub const REQUEST_TIME_TOO_SKEWED: &str = "RequestTimeTooSkewed";
+
+/// The offset of the server clock to the local clock, if the request is rejected with
+/// `RequestTimeTooSkewed`.  The server time is taken from the `Date` header of the response,
+/// and the offset should be added to the timestamp for signing.
+pub fn clock_skew(error: &Error, headers: &HeaderMap) -> Option<Duration> {
+    match error {
+        Error::S3Error { code, .. } if code == REQUEST_TIME_TOO_SKEWED => {
+            let date = headers.get(reqwest::header::DATE)?.to_str().ok()?;
+            let server_time = DateTime::parse_from_rfc2822(date).ok()?;
+            Some(server_time.with_timezone(&Utc) - Utc::now())
+        }
+        _ => None,
+    }
+}
+
+/// Build `Error::S3Error` from a failed response,
+/// the request ids in headers are preferred than the ones in the XML body
+pub fn s3_error(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Error {
+    let (mut request_id, mut host_id) = request_ids(headers);
+    let mut code = String::new();
+    let mut message = String::new();
+    let mut reader = Reader::from_reader(body);
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => current_tag = e.name().to_vec(),
+            Ok(Event::End(_)) => current_tag.clear(),
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                match current_tag.as_slice() {
+                    b"Code" => code = text,
+                    b"Message" => message = text,
+                    b"RequestId" if request_id.is_none() => request_id = Some(text),
+                    b"HostId" if host_id.is_none() => host_id = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    if code.is_empty() && message.is_empty() {
+        // The error page may be returned by a proxy or WAF, keep a snippet for debugging
+        message = String::from_utf8_lossy(body)
+            .chars()
+            .take(ERROR_SNIPPET_LENGTH)
+            .collect::<String>()
+            .trim()
+            .to_string();
+    }
+    Error::S3Error {
+        status: status.as_u16(),
+        code,
+        message,
+        request_id,
+        host_id,
+    }
+}
+
 pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Error> {
     let mut reader = Reader::from_str(body);
     let mut output = Vec::new();
@@ -210,7 +673,10 @@ pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Err
     let mut in_storage_class_tag = false;
     let mut in_size_tag = false;
     let mut in_truncated_tag = false;
+    let mut in_owner_id_tag = false;
+    let mut in_owner_tag = false;
     let mut bucket = String::new();
+    let mut owner = None;
     let mut key = String::new();
     let mut mtime = String::new();
     let mut etag = String::new();
@@ -228,6 +694,8 @@ pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Err
                 b"StorageClass" => in_storage_class_tag = true,
                 b"Size" => in_size_tag = true,
                 b"IsTruncated" => in_truncated_tag = true,
+                b"Owner" => in_owner_tag = true,
+                b"ID" => in_owner_id_tag = in_owner_tag,
                 _ => {}
             },
             Ok(Event::End(ref e)) => match e.name() {
@@ -239,14 +707,18 @@ pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Err
                     None,
                     None,
                 )),
-                b"Contents" => output.push(S3Convert::new(
-                    Some(bucket.clone()),
-                    Some(key.clone()),
-                    Some(mtime.clone()),
-                    Some(etag[1..etag.len() - 1].to_string()),
-                    Some(storage_class.clone()),
-                    Some(size),
-                )),
+                b"Contents" => output.push(S3Object {
+                    owner: owner.take(),
+                    ..S3Convert::new(
+                        Some(bucket.clone()),
+                        Some(key.clone()),
+                        Some(mtime.clone()),
+                        Some(etag[1..etag.len() - 1].to_string()),
+                        Some(storage_class.clone()),
+                        Some(size),
+                    )
+                }),
+                b"Owner" => in_owner_tag = false,
                 _ => {}
             },
             Ok(Event::Text(e)) => {
@@ -270,11 +742,18 @@ pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Err
                     bucket = e.unescape_and_decode(&reader).unwrap();
                     in_name_tag = false;
                 }
+                if in_owner_id_tag {
+                    owner = Some(
+                        e.unescape_and_decode(&reader)
+                            .map_err(Error::XMLParseError)?,
+                    );
+                    in_owner_id_tag = false;
+                }
                 if in_size_tag {
                     size = e
                         .unescape_and_decode(&reader)
                         .unwrap()
-                        .parse::<usize>()
+                        .parse::<u64>()
                         .unwrap_or_default();
                     in_size_tag = false;
                 }
@@ -293,29 +772,864 @@ pub fn s3object_list_xml_parser(body: &str) -> Result<(Vec<S3Object>, bool), Err
         }
         buf.clear();
     }
+    if is_url_encoded(body) {
+        for obj in output.iter_mut() {
+            obj.key = obj.key.as_deref().map(url_decode);
+        }
+    }
     Ok((output, is_truncated))
 }
 
+/// Parse a page of the JSON listing of Ceph RGW, and return the objects, the listing is truncated
+/// or not, and the marker to continue if it is truncated, which is the `NextMarker` or the last
+/// key of the page as the XML listing.  A truncated page may have no marker to continue.
+/// The fields of each object are listed without names in the `Contents` array, and the `Contents`
+/// is repeated for every object, so the page is visited entry by entry instead of as a map.
+pub fn s3object_list_json_parser(
+    body: &[u8],
+) -> Result<(Vec<S3Object>, bool, Option<String>), Error> {
+    let JsonListing {
+        name,
+        encoding_type,
+        is_truncated,
+        next_marker,
+        contents,
+    } = serde_json::from_slice(body)?;
+    let url_encoded = encoding_type.as_deref() == Some("url");
+    let decode = |k: String| if url_encoded { url_decode(&k) } else { k };
+    // The listing without `IsTruncated` is continued by the `NextMarker` only
+    let truncated =
+        is_truncated.unwrap_or_else(|| next_marker.as_ref().is_some_and(|m| !m.is_empty()));
+    let next_marker = match is_truncated {
+        Some(false) => None,
+        _ => next_marker
+            .filter(|m| !m.is_empty())
+            .map(decode)
+            .or_else(|| {
+                is_truncated
+                    .and_then(|_| contents.last()?.key.clone())
+                    .map(decode)
+            }),
+    };
+    let objects = contents
+        .into_iter()
+        .map(|c| S3Object {
+            owner: c.owner,
+            ..S3Convert::new(
+                name.clone(),
+                c.key.map(decode),
+                c.mtime,
+                c.etag.map(|e| e.trim_matches('"').to_string()),
+                c.storage_class,
+                c.size,
+            )
+        })
+        .collect();
+    Ok((objects, truncated, next_marker))
+}
+
+#[derive(Default)]
+struct JsonListing {
+    name: Option<String>,
+    encoding_type: Option<String>,
+    is_truncated: Option<bool>,
+    next_marker: Option<String>,
+    contents: Vec<JsonContent>,
+}
+
+#[derive(Default)]
+struct JsonContent {
+    key: Option<String>,
+    mtime: Option<String>,
+    etag: Option<String>,
+    size: Option<u64>,
+    storage_class: Option<String>,
+    owner: Option<String>,
+}
+
+impl JsonContent {
+    /// The fields are named in the newer RGW, ex: `{"Key": "a.txt", ...}`, else they are in the
+    /// order of the XML listing, ex: `["a.txt", "2021-01-01T00:00:00.000Z", "\"etag\"", 3, ...]`
+    fn from_value(value: serde_json::Value) -> Result<Self, Error> {
+        let size = |v: &serde_json::Value| {
+            v.as_u64()
+                .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+        };
+        let text = |v: &serde_json::Value| v.as_str().map(|s| s.to_string());
+        match value {
+            serde_json::Value::Object(o) => Ok(JsonContent {
+                key: o.get("Key").and_then(text),
+                mtime: o.get("LastModified").and_then(text),
+                etag: o.get("ETag").and_then(text),
+                size: o.get("Size").and_then(size),
+                storage_class: o.get("StorageClass").and_then(text),
+                owner: o.get("Owner").and_then(|o| o.get("ID")).and_then(text),
+            }),
+            serde_json::Value::Array(a) => Ok(JsonContent {
+                key: a.first().and_then(text),
+                mtime: a.get(1).and_then(text),
+                etag: a.get(2).and_then(text),
+                size: a.get(3).and_then(size),
+                storage_class: a.get(4).and_then(text),
+                owner: a.get(5).and_then(|o| o.get("ID")).and_then(text),
+            }),
+            _ => Err(Error::FieldNotFound("Contents")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for JsonListing {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ListingVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ListingVisitor {
+            type Value = JsonListing;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a ListBucketResult")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<JsonListing, A::Error> {
+                let mut page = JsonListing::default();
+                while let Some(field) = map.next_key::<String>()? {
+                    match field.as_str() {
+                        "Name" => page.name = map.next_value()?,
+                        "EncodingType" => page.encoding_type = map.next_value()?,
+                        "NextMarker" => page.next_marker = map.next_value()?,
+                        "IsTruncated" => {
+                            // The flag may be quoted as the text of the XML listing
+                            page.is_truncated = match map.next_value()? {
+                                serde_json::Value::Bool(b) => Some(b),
+                                serde_json::Value::String(s) => s.parse().ok(),
+                                _ => None,
+                            }
+                        }
+                        "Contents" => {
+                            let value: serde_json::Value = map.next_value()?;
+                            // A list of the named objects, or the fields of one object
+                            let values = match value {
+                                serde_json::Value::Array(a) if a.iter().all(|v| v.is_object()) => a,
+                                v => vec![v],
+                            };
+                            for v in values {
+                                page.contents.push(
+                                    JsonContent::from_value(v).map_err(serde::de::Error::custom)?,
+                                );
+                            }
+                        }
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(page)
+            }
+        }
+
+        deserializer.deserialize_map(ListingVisitor)
+    }
+}
+
+/// The keys in the listing are url encoded, if `encoding-type=url` is requested
+fn is_url_encoded(body: &str) -> bool {
+    first_tag_xml_parser(body, "EncodingType").is_ok_and(|e| e == "url")
+}
+
+/// Encode the key in the header, ex: `x-amz-copy-source`, the `/` and the unreserved characters
+/// are kept
+pub fn url_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decode the url encoded key in the listing, the space is encoded as `+` by S3
+pub fn url_decode(encoded: &str) -> String {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [chars.next(), chars.next()];
+                match hex {
+                    [Some(h), Some(l)] => match u8::from_str_radix(
+                        std::str::from_utf8(&[h, l]).unwrap_or_default(),
+                        16,
+                    ) {
+                        Ok(v) => bytes.push(v),
+                        Err(_) => bytes.extend_from_slice(&[b'%', h, l]),
+                    },
+                    _ => {
+                        bytes.push(b'%');
+                        bytes.extend(hex.iter().flatten());
+                    }
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+pub(crate) fn escape_str(s: &str) -> Cow<'_, str> {
+    match escape(s.as_bytes()) {
+        Cow::Borrowed(_) => Cow::Borrowed(s),
+        Cow::Owned(v) => Cow::Owned(String::from_utf8(v).unwrap_or_default()),
+    }
+}
+
+/// A mutating request which is not sent in the dry-run mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Action {
+    /// The http method, ex: DELETE
+    pub method: String,
+    /// The bucket or the object, ex: s3://bucket/key
+    pub target: String,
+    /// The query of the sub-resource, ex: tagging
+    pub query: Vec<(String, String)>,
+}
+
+impl Action {
+    pub fn new(method: &str, desc: &S3Object, query: &[(&str, &str)]) -> Self {
+        Action {
+            method: method.to_string(),
+            target: format!(
+                "s3://{}{}",
+                desc.bucket.as_deref().unwrap_or_default(),
+                desc.key.as_deref().unwrap_or_default()
+            ),
+            query: query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// The request with the method changes the data, and it is skipped in the dry-run mode
+pub fn is_mutating(method: &str) -> bool {
+    matches!(method, "PUT" | "POST" | "DELETE")
+}
+
+/// The max number of keys in a request of the multi-object delete API
+pub const DELETE_BATCH_SIZE: usize = 1000;
+
+/// The payload of the multi-object delete API in quiet mode, only the failed keys are responded
+pub fn delete_objects_xml(keys: &[String]) -> String {
+    let mut content = "<Delete><Quiet>true</Quiet>".to_string();
+    for key in keys {
+        content.push_str(&format!(
+            "<Object><Key>{}</Key></Object>",
+            escape_str(key.trim_start_matches('/'))
+        ));
+    }
+    content.push_str("</Delete>");
+    content
+}
+
+/// The payload of the create bucket API to locate the bucket out of the default region
+pub fn create_bucket_xml(region: &str) -> String {
+    format!(
+        "<CreateBucketConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><LocationConstraint>{}</LocationConstraint></CreateBucketConfiguration>",
+        region
+    )
+}
+
+/// The `Content-MD5` header required by the multi-object delete API
+pub fn content_md5(payload: &[u8]) -> String {
+    base64::encode(md5::compute(payload).0)
+}
+
+/// The ETag of the object uploaded in parts, which is the MD5 of the concatenated MD5s of the
+/// parts with the part count, ex: `b9a4d6e74e6be4117e4726aa450242ec-3`, such that a local file
+/// can be compared with the remote object without downloading.
+pub fn multipart_etag(path: &str, part_size: u64) -> Result<String, Error> {
+    if part_size == 0 {
+        return Err(Error::UserError("part size should be larger than 0"));
+    }
+    let mut file = std::fs::File::open(path)?;
+    let mut digests = Vec::new();
+    let mut part = Vec::with_capacity(buffer_size(part_size)?);
+    loop {
+        part.clear();
+        (&mut file).take(part_size).read_to_end(&mut part)?;
+        // The empty object is uploaded in one empty part
+        if part.is_empty() && !digests.is_empty() {
+            break;
+        }
+        digests.extend_from_slice(&md5::compute(&part).0);
+        if (part.len() as u64) < part_size {
+            break;
+        }
+    }
+    Ok(format!(
+        "{:x}-{}",
+        md5::compute(&digests),
+        digests.len() / 16
+    ))
+}
+
+/// The same as `multipart_etag()` for the content in memory
+pub fn multipart_etag_of_bytes(content: &[u8], part_size: u64) -> Result<String, Error> {
+    if part_size == 0 {
+        return Err(Error::UserError("part size should be larger than 0"));
+    }
+    let mut digests = Vec::new();
+    let mut part_count = 0;
+    for part in content.chunks(usize::try_from(part_size).unwrap_or(usize::MAX)) {
+        digests.extend_from_slice(&md5::compute(part).0);
+        part_count += 1;
+    }
+    if part_count == 0 {
+        digests.extend_from_slice(&md5::compute(b"").0);
+        part_count = 1;
+    }
+    Ok(format!("{:x}-{}", md5::compute(&digests), part_count))
+}
+
+/// Parse the keys failed to delete and the error codes from the multi-object delete response
+pub fn delete_errors_xml_parser(body: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut reader = Reader::from_str(body);
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut in_error = false;
+    let mut key = String::new();
+    let mut code = String::new();
+    let mut output = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                current_tag = e.name().to_vec();
+                if current_tag == b"Error" {
+                    in_error = true;
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if e.name() == b"Error" {
+                    output.push((std::mem::take(&mut key), std::mem::take(&mut code)));
+                    in_error = false;
+                }
+                current_tag.clear();
+            }
+            Ok(Event::Text(e)) if in_error => match current_tag.as_slice() {
+                b"Key" => {
+                    key = e
+                        .unescape_and_decode(&reader)
+                        .map_err(Error::XMLParseError)?
+                }
+                b"Code" => {
+                    code = e
+                        .unescape_and_decode(&reader)
+                        .map_err(Error::XMLParseError)?
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(output)
+}
+
+/// The header to tag the object when it is uploaded
+pub const TAGGING_HEADER: &str = "x-amz-tagging";
+
+/// The header of the canned ACL
+pub const ACL_HEADER: &str = "x-amz-acl";
+
+/// The canned ACLs can be set with `x-amz-acl` header
+#[cfg(any(feature = "blocking", feature = "tokio-async"))]
+pub(crate) const CANNED_ACLS: [&str; 8] = [
+    "private",
+    "public-read",
+    "public-read-write",
+    "authenticated-read",
+    "aws-exec-read",
+    "bucket-owner-read",
+    "bucket-owner-full-control",
+    "log-delivery-write",
+];
+
+/// The header of the storage class, ex: "STANDARD_IA"
+pub const STORAGE_CLASS_HEADER: &str = "x-amz-storage-class";
+
+/// The header to access a requester-pays bucket, the only value is "requester"
+pub const REQUEST_PAYER_HEADER: &str = "x-amz-request-payer";
+
+/// Encode the tags as the value of `x-amz-tagging` header, ex: `k1=v1&k2=v2`
+pub fn tagging_header(tags: &[(&str, &str)]) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(tags)
+        .finish()
+        .replace('+', "%20")
+}
+
+pub fn tagging_xml_parser(body: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = Vec::new();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut key = String::new();
+    let mut value = String::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => current_tag = e.name().to_vec(),
+            Ok(Event::End(ref e)) => {
+                if e.name() == b"Tag" {
+                    output.push((std::mem::take(&mut key), std::mem::take(&mut value)));
+                }
+                current_tag.clear();
+            }
+            Ok(Event::Text(e)) => match current_tag.as_slice() {
+                b"Key" => key = e.unescape_and_decode(&reader).unwrap_or_default(),
+                b"Value" => value = e.unescape_and_decode(&reader).unwrap_or_default(),
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(output)
+}
+
 pub fn upload_id_xml_parser(res: &str) -> Result<String, Error> {
+    first_tag_xml_parser(res, "UploadId")
+}
+
+/// The ETag of the part copied by `UploadPartCopy`, the quotes are kept for completing the upload
+pub fn copy_part_etag_xml_parser(res: &str) -> Result<String, Error> {
+    first_tag_xml_parser(res, "ETag")
+}
+
+/// The `CompleteMultipartUpload` body of the part numbers and the ETags of the parts
+pub fn complete_multipart_xml(parts: &[(usize, String)]) -> String {
+    let mut content = "<CompleteMultipartUpload>".to_string();
+    for (part, etag) in parts {
+        content.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+            part, etag
+        ));
+    }
+    content.push_str("</CompleteMultipartUpload>");
+    content
+}
+
+/// The text of the first tag found in the response
+pub(crate) fn first_tag_xml_parser(res: &str, tag: &'static str) -> Result<String, Error> {
     let mut reader = Reader::from_str(res);
     let mut in_tag = false;
     let mut buf = Vec::new();
 
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == tag.as_bytes() => in_tag = true,
+            Ok(Event::End(ref e)) if e.name() == tag.as_bytes() => in_tag = false,
+            Ok(Event::Text(e)) if in_tag => {
+                return e.unescape_and_decode(&reader).map_err(Error::XMLParseError);
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Err(Error::FieldNotFound(tag))
+}
+
+/// # The disk usage of the objects under a prefix
+/// - objects - the count of the objects
+/// - total_bytes - the total size of the objects
+/// - by_storage_class - the total size of the objects in each storage class, ex: "STANDARD"
+/// - sub_prefixes - the usage of each sub-prefix, if it is summarized with a delimiter,
+///   ex: `logs/2021/` for `logs/2021/01/a.log` under `logs/` with `/`, like `du -d1`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DuSummary {
+    pub objects: u64,
+    pub total_bytes: u64,
+    pub by_storage_class: HashMap<String, u64>,
+    pub sub_prefixes: BTreeMap<String, DuSummary>,
+}
+
+impl DuSummary {
+    /// Count the listed object under the prefix, the object without key, ex: a bucket, is skipped
+    pub fn add(&mut self, obj: &S3Object, prefix: &str, delimiter: Option<&str>) {
+        let key = match obj.key.as_deref() {
+            Some(k) => k.strip_prefix('/').unwrap_or(k),
+            None => return,
+        };
+        let size = obj.size.unwrap_or_default();
+        let storage_class = obj
+            .storage_class
+            .as_deref()
+            .filter(|c| !c.is_empty())
+            .unwrap_or("STANDARD");
+        self.count(size, storage_class);
+        if let Some(delimiter) = delimiter.filter(|d| !d.is_empty()) {
+            if let Some(i) = key
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.find(delimiter))
+            {
+                self.sub_prefixes
+                    .entry(key[..prefix.len() + i + delimiter.len()].to_string())
+                    .or_default()
+                    .count(size, storage_class);
+            }
+        }
+    }
+
+    fn count(&mut self, size: u64, storage_class: &str) {
+        self.objects += 1;
+        self.total_bytes += size;
+        *self
+            .by_storage_class
+            .entry(storage_class.to_string())
+            .or_default() += size;
+    }
+}
+
+/// # The uploaded part of an in-progress multipart upload
+/// - number - the part number
+/// - size - the size of the part
+/// - etag - the etag of the part without quotes
+/// - last_modified - the time the part uploaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartInfo {
+    pub number: usize,
+    pub size: u64,
+    pub etag: String,
+    pub last_modified: String,
+}
+
+/// # The in-progress multipart upload
+/// - key - the object key of the upload
+/// - upload_id - the id of the upload
+/// - initiated - the time the upload initiated
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UploadInfo {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: String,
+}
+
+/// # An entry of ListObjectVersions
+/// - Object - a version of the object, the etag and the size are only for the object
+/// - DeleteMarker - the placeholder of a deleted object, which is the latest version if the
+///   object is deleted and not restored
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionEntry {
+    Object {
+        key: String,
+        version_id: String,
+        is_latest: bool,
+        last_modified: String,
+        etag: String,
+        size: u64,
+    },
+    DeleteMarker {
+        key: String,
+        version_id: String,
+        is_latest: bool,
+        last_modified: String,
+    },
+}
+
+/// Parse a page of ListParts, and return the parts with the `NextPartNumberMarker` if the list
+/// is truncated.
+pub fn parts_xml_parser(body: &str) -> Result<(Vec<PartInfo>, Option<String>), Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = Vec::new();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut part = PartInfo::default();
+    let mut is_truncated = false;
+    let mut next_marker = None;
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => current_tag = e.name().to_vec(),
+            Ok(Event::End(ref e)) => {
+                if e.name() == b"Part" {
+                    output.push(std::mem::take(&mut part));
+                }
+                current_tag.clear();
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                match current_tag.as_slice() {
+                    b"PartNumber" => part.number = text.parse().unwrap_or_default(),
+                    b"Size" => part.size = text.parse().unwrap_or_default(),
+                    b"ETag" => part.etag = text.replace('"', ""),
+                    b"LastModified" => part.last_modified = text,
+                    b"IsTruncated" => is_truncated = text == "true",
+                    b"NextPartNumberMarker" => next_marker = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    // An empty marker is returned if the list is truncated without a marker to continue
+    let next_marker = if is_truncated {
+        Some(next_marker.unwrap_or_default())
+    } else {
+        None
+    };
+    Ok((output, next_marker))
+}
+
+/// # The attributes of an object from GetObjectAttributes
+/// - etag - the etag without quotes
+/// - checksums - the algorithm and the base64 checksum, ex: `("SHA256", "...")`
+/// - total_parts_count - the number of parts if the object is uploaded by multipart
+/// - parts - the parts of the page, which are only listed if the parts are checksummed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectAttributes {
+    pub etag: Option<String>,
+    pub object_size: Option<u64>,
+    pub storage_class: Option<String>,
+    pub checksums: Vec<(String, String)>,
+    pub total_parts_count: Option<usize>,
+    pub parts: Vec<ObjectPart>,
+}
+
+/// # The part in the attributes of an object
+/// - number - the part number
+/// - size - the size of the part
+/// - checksums - the algorithm and the base64 checksum of the part
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectPart {
+    pub number: usize,
+    pub size: u64,
+    pub checksums: Vec<(String, String)>,
+}
+
+/// Parse the response of GetObjectAttributes, the checksums of the object and the ones of the
+/// parts are told by the `Part` they are in.
+pub fn object_attributes_xml_parser(body: &str) -> Result<ObjectAttributes, Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = ObjectAttributes::default();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut part: Option<ObjectPart> = None;
     loop {
         match reader.read_event(&mut buf) {
             Ok(Event::Start(ref e)) => {
-                if e.name() == b"UploadId" {
-                    in_tag = true;
+                current_tag = e.name().to_vec();
+                if e.name() == b"Part" {
+                    part = Some(ObjectPart::default());
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if e.name() == b"Part" {
+                    output.parts.extend(part.take());
+                }
+                current_tag.clear();
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                let checksum = current_tag
+                    .strip_prefix(b"Checksum")
+                    .filter(|a| !a.is_empty())
+                    .map(|a| (String::from_utf8_lossy(a).to_string(), text.clone()));
+                match (part.as_mut(), current_tag.as_slice()) {
+                    (Some(part), b"PartNumber") => part.number = text.parse().unwrap_or_default(),
+                    (Some(part), b"Size") => part.size = text.parse().unwrap_or_default(),
+                    (Some(part), _) => part.checksums.extend(checksum),
+                    (None, b"ETag") => output.etag = Some(text.replace('"', "")),
+                    (None, b"ObjectSize") => output.object_size = text.parse().ok(),
+                    (None, b"StorageClass") => output.storage_class = Some(text),
+                    (None, b"TotalPartsCount") => output.total_parts_count = text.parse().ok(),
+                    (None, _) => output.checksums.extend(checksum),
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(output)
+}
+
+/// Parse the `NextMarker` of a listing page
+pub fn next_marker_xml_parser(body: &str) -> Result<Option<String>, Error> {
+    let output = match first_tag_xml_parser(body, "NextMarker") {
+        Ok(output) => output,
+        Err(Error::FieldNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if output.is_empty() {
+        Ok(None)
+    } else if is_url_encoded(body) {
+        Ok(Some(url_decode(&output)))
+    } else {
+        Ok(Some(output))
+    }
+}
+
+/// Move the marker of a listing to the next page.  Some RGW versions return the same marker
+/// again when many delete markers exist, the listing will fail instead of spinning forever.
+pub fn advance_marker<T: PartialEq>(
+    marker: &mut Option<T>,
+    next_marker: Option<T>,
+) -> Result<(), Error> {
+    if next_marker == *marker {
+        return Err(Error::RequestPoolError(
+            "listing did not advance".to_string(),
+        ));
+    }
+    *marker = next_marker;
+    Ok(())
+}
+
+/// The marker to continue a truncated page of the XML listing, which is the `NextMarker`, or the
+/// last key of the page, because AWS leaves out the `NextMarker` without a delimiter.
+pub(crate) fn xml_next_marker(body: &str, page: &[S3Object]) -> Result<Option<String>, Error> {
+    Ok(next_marker_xml_parser(body)?.or_else(|| {
+        page.iter()
+            .rev()
+            .find_map(|o| o.key.as_ref())
+            .map(|k| k.trim_start_matches('/').to_string())
+    }))
+}
+
+/// Parse a page of the XML listing, and move the marker to the next page if it is truncated.
+/// A truncated page may have no content but a `NextMarker` to continue.
+pub(crate) fn xml_list_page(
+    body: &str,
+    marker: &mut Option<String>,
+) -> Result<(Vec<S3Object>, bool), Error> {
+    let (page, is_truncated) = s3object_list_xml_parser(body)?;
+    if is_truncated {
+        let next_marker = xml_next_marker(body, &page)?.or_else(|| marker.clone());
+        advance_marker(marker, next_marker)?;
+    }
+    Ok((page, is_truncated))
+}
+
+/// The `NextKeyMarker` and `NextUploadIdMarker` to continue a ListMultipartUploads
+pub type UploadMarkers = (String, String);
+
+/// Parse a page of ListMultipartUploads, and return the uploads with the markers if the list is
+/// truncated.
+pub fn multipart_uploads_xml_parser(
+    body: &str,
+) -> Result<(Vec<UploadInfo>, Option<UploadMarkers>), Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = Vec::new();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut upload = UploadInfo::default();
+    let mut is_truncated = false;
+    let mut next_key_marker = String::new();
+    let mut next_upload_id_marker = String::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => current_tag = e.name().to_vec(),
+            Ok(Event::End(ref e)) => {
+                if e.name() == b"Upload" {
+                    output.push(std::mem::take(&mut upload));
                 }
+                current_tag.clear();
             }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                match current_tag.as_slice() {
+                    b"Key" => upload.key = text,
+                    b"UploadId" => upload.upload_id = text,
+                    b"Initiated" => upload.initiated = text,
+                    b"IsTruncated" => is_truncated = text == "true",
+                    b"NextKeyMarker" => next_key_marker = text,
+                    b"NextUploadIdMarker" => next_upload_id_marker = text,
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    let next_markers = if is_truncated {
+        Some((next_key_marker, next_upload_id_marker))
+    } else {
+        None
+    };
+    Ok((output, next_markers))
+}
+
+/// The `NextKeyMarker` and `NextVersionIdMarker` to continue a ListObjectVersions
+pub type VersionMarkers = (String, String);
+
+/// Parse a page of ListObjectVersions, the versions and the delete markers are returned in the
+/// order of the response with the markers if the list is truncated.
+pub fn versions_xml_parser(
+    body: &str,
+) -> Result<(Vec<VersionEntry>, Option<VersionMarkers>), Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = Vec::new();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut key = String::new();
+    let mut version_id = String::new();
+    let mut is_latest = false;
+    let mut last_modified = String::new();
+    let mut etag = String::new();
+    let mut size = 0;
+    let mut is_truncated = false;
+    let mut next_key_marker = String::new();
+    let mut next_version_id_marker = String::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => current_tag = e.name().to_vec(),
             Ok(Event::End(ref e)) => {
-                if e.name() == b"UploadId" {
-                    in_tag = false;
+                match e.name() {
+                    b"Version" => output.push(VersionEntry::Object {
+                        key: std::mem::take(&mut key),
+                        version_id: std::mem::take(&mut version_id),
+                        is_latest: std::mem::take(&mut is_latest),
+                        last_modified: std::mem::take(&mut last_modified),
+                        etag: std::mem::take(&mut etag),
+                        size: std::mem::take(&mut size),
+                    }),
+                    b"DeleteMarker" => output.push(VersionEntry::DeleteMarker {
+                        key: std::mem::take(&mut key),
+                        version_id: std::mem::take(&mut version_id),
+                        is_latest: std::mem::take(&mut is_latest),
+                        last_modified: std::mem::take(&mut last_modified),
+                    }),
+                    _ => {}
                 }
+                current_tag.clear();
             }
             Ok(Event::Text(e)) => {
-                if in_tag {
-                    return Ok(e.unescape_and_decode(&reader).unwrap());
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                match current_tag.as_slice() {
+                    b"Key" => key = text,
+                    b"VersionId" => version_id = text,
+                    b"IsLatest" => is_latest = text == "true",
+                    b"LastModified" => last_modified = text,
+                    b"ETag" => etag = text.replace('"', ""),
+                    b"Size" => size = text.parse().unwrap_or_default(),
+                    b"IsTruncated" => is_truncated = text == "true",
+                    b"NextKeyMarker" => next_key_marker = text,
+                    b"NextVersionIdMarker" => next_version_id_marker = text,
+                    _ => {}
                 }
             }
             Ok(Event::Eof) => break,
@@ -324,13 +1638,297 @@ pub fn upload_id_xml_parser(res: &str) -> Result<String, Error> {
         }
         buf.clear();
     }
-    Err(Error::FieldNotFound("upload_id"))
+    let next_markers = if is_truncated {
+        Some((next_key_marker, next_version_id_marker))
+    } else {
+        None
+    };
+    Ok((output, next_markers))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_auto_part_size() {
+        const MB: u64 = 1 << 20;
+        const GB: u64 = 1 << 30;
+        // a small object is in one part
+        assert_eq!(auto_part_size(5 * MB, 10), AUTO_PART_SIZE_MIN);
+        assert_eq!(auto_part_size(100 * MB, 10), 64 * MB);
+        // split for the concurrency and rounded up to MB
+        assert_eq!(auto_part_size(GB, 10), 103 * MB);
+        assert_eq!(auto_part_size(GB, 4), 256 * MB);
+        // a huge object is bounded by the maximum part size
+        assert_eq!(auto_part_size(50 * GB, 10), AUTO_PART_SIZE_MAX);
+        assert_eq!(auto_part_size(GB, 0), AUTO_PART_SIZE_MAX);
+    }
+
+    #[test]
+    fn test_delete_objects_xml() {
+        assert_eq!(
+            delete_objects_xml(&["/logs/a.txt".to_string(), "/logs/a&b.txt".to_string()]),
+            "<Delete><Quiet>true</Quiet><Object><Key>logs/a.txt</Key></Object><Object><Key>logs/a&amp;b.txt</Key></Object></Delete>"
+        );
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+        let response = "<DeleteResult><Deleted><Key>logs/a.txt</Key></Deleted><Error><Key>logs/a&amp;b.txt</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error></DeleteResult>";
+        assert_eq!(
+            delete_errors_xml_parser(response).unwrap(),
+            vec![("logs/a&b.txt".to_string(), "AccessDenied".to_string())]
+        );
+        assert!(delete_errors_xml_parser("<DeleteResult/>")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tagging_header() {
+        assert_eq!(
+            tagging_header(&[("project", "s3 handler"), ("team", "a&b=c")]),
+            "project=s3%20handler&team=a%26b%3Dc"
+        );
+    }
+
+    #[test]
+    fn test_versions_xml_parser() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListVersionsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01">
+  <Name>bucket</Name>
+  <Prefix>my</Prefix>
+  <KeyMarker/>
+  <VersionIdMarker/>
+  <NextKeyMarker>my-third-image.jpg</NextKeyMarker>
+  <NextVersionIdMarker>03jpff543dhffds434rfdsFDN943fdsFkdmqnh892</NextVersionIdMarker>
+  <MaxKeys>3</MaxKeys>
+  <IsTruncated>true</IsTruncated>
+  <DeleteMarker>
+    <Key>my-image.jpg</Key>
+    <VersionId>ffd2c9d45ba26fd2</VersionId>
+    <IsLatest>true</IsLatest>
+    <LastModified>2009-11-12T17:50:30.000Z</LastModified>
+    <Owner>
+      <ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID>
+      <DisplayName>mtd@amazon.com</DisplayName>
+    </Owner>
+  </DeleteMarker>
+  <Version>
+    <Key>my-image.jpg</Key>
+    <VersionId>3/L4kqtJl40Nr8X8gdRQBpUMLUo</VersionId>
+    <IsLatest>false</IsLatest>
+    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+    <ETag>"fba9dede5f27731c9771645a39863328"</ETag>
+    <Size>434234</Size>
+    <StorageClass>STANDARD</StorageClass>
+    <Owner>
+      <ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID>
+      <DisplayName>mtd@amazon.com</DisplayName>
+    </Owner>
+  </Version>
+  <Version>
+    <Key>my-third-image.jpg</Key>
+    <VersionId>03jpff543dhffds434rfdsFDN943fdsFkdmqnh892</VersionId>
+    <IsLatest>true</IsLatest>
+    <LastModified>2009-10-15T17:50:30.000Z</LastModified>
+    <ETag>"396fefef536d5ce46c7537ecf978a360"</ETag>
+    <Size>64</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Version>
+</ListVersionsResult>"#;
+        let (entries, next_markers) = versions_xml_parser(body).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                VersionEntry::DeleteMarker {
+                    key: "my-image.jpg".to_string(),
+                    version_id: "ffd2c9d45ba26fd2".to_string(),
+                    is_latest: true,
+                    last_modified: "2009-11-12T17:50:30.000Z".to_string(),
+                },
+                VersionEntry::Object {
+                    key: "my-image.jpg".to_string(),
+                    version_id: "3/L4kqtJl40Nr8X8gdRQBpUMLUo".to_string(),
+                    is_latest: false,
+                    last_modified: "2009-10-12T17:50:30.000Z".to_string(),
+                    etag: "fba9dede5f27731c9771645a39863328".to_string(),
+                    size: 434234,
+                },
+                VersionEntry::Object {
+                    key: "my-third-image.jpg".to_string(),
+                    version_id: "03jpff543dhffds434rfdsFDN943fdsFkdmqnh892".to_string(),
+                    is_latest: true,
+                    last_modified: "2009-10-15T17:50:30.000Z".to_string(),
+                    etag: "396fefef536d5ce46c7537ecf978a360".to_string(),
+                    size: 64,
+                },
+            ]
+        );
+        assert_eq!(
+            next_markers,
+            Some((
+                "my-third-image.jpg".to_string(),
+                "03jpff543dhffds434rfdsFDN943fdsFkdmqnh892".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parts_xml_parser() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListPartsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>example-bucket</Bucket>
+  <Key>example-object</Key>
+  <UploadId>XXBsb2FkIElEIGZvciBlbHZpbmcncyVcdS1tb3ZpZS5tMnRzEEEwbG9hZA</UploadId>
+  <PartNumberMarker>1</PartNumberMarker>
+  <NextPartNumberMarker>3</NextPartNumberMarker>
+  <MaxParts>2</MaxParts>
+  <IsTruncated>true</IsTruncated>
+  <Part>
+    <PartNumber>2</PartNumber>
+    <LastModified>2010-11-10T20:48:34.000Z</LastModified>
+    <ETag>"7778aef83f66abc1fa1e8477f296d394"</ETag>
+    <Size>10485760</Size>
+  </Part>
+  <Part>
+    <PartNumber>3</PartNumber>
+    <LastModified>2010-11-10T20:48:33.000Z</LastModified>
+    <ETag>"aaaa18db4cc2f85cedef654fccc4a4x8"</ETag>
+    <Size>10485760</Size>
+  </Part>
+</ListPartsResult>"#;
+        let (parts, next_marker) = parts_xml_parser(body).unwrap();
+        assert_eq!(next_marker, Some("3".to_string()));
+        assert_eq!(
+            parts[0],
+            PartInfo {
+                number: 2,
+                size: 10485760,
+                etag: "7778aef83f66abc1fa1e8477f296d394".to_string(),
+                last_modified: "2010-11-10T20:48:34.000Z".to_string(),
+            }
+        );
+        assert_eq!(parts[1].number, 3);
+
+        let body = body.replace("<IsTruncated>true", "<IsTruncated>false");
+        assert_eq!(parts_xml_parser(&body).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_object_attributes_xml_parser() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<GetObjectAttributesResponse xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <ETag>"6c9a0c1fda1a4d8f5a2a8e3f36bb0b3c-2"</ETag>
+  <Checksum>
+    <ChecksumSHA256>J2fQ+hWGm3nCtMVXz8WG7HmhvKkfq8WL+MmQxz8bCsE=</ChecksumSHA256>
+  </Checksum>
+  <ObjectParts>
+    <TotalPartsCount>2</TotalPartsCount>
+    <PartNumberMarker>0</PartNumberMarker>
+    <NextPartNumberMarker>2</NextPartNumberMarker>
+    <MaxParts>1000</MaxParts>
+    <IsTruncated>false</IsTruncated>
+    <Part>
+      <ChecksumSHA256>mLlqNRWhZZIdJx2YlOZtJdFLyS7I6rxXJQ0VEdSQt8E=</ChecksumSHA256>
+      <PartNumber>1</PartNumber>
+      <Size>8388608</Size>
+    </Part>
+    <Part>
+      <ChecksumSHA256>Nq8HD3jXUe4YhXbvCv5Xm8kHe6b0WJdQsvbnmsHLtbI=</ChecksumSHA256>
+      <PartNumber>2</PartNumber>
+      <Size>1048576</Size>
+    </Part>
+  </ObjectParts>
+  <StorageClass>STANDARD</StorageClass>
+  <ObjectSize>9437184</ObjectSize>
+</GetObjectAttributesResponse>"#;
+        let attributes = object_attributes_xml_parser(body).unwrap();
+        assert_eq!(
+            attributes.etag.as_deref(),
+            Some("6c9a0c1fda1a4d8f5a2a8e3f36bb0b3c-2")
+        );
+        assert_eq!(attributes.object_size, Some(9437184));
+        assert_eq!(attributes.storage_class.as_deref(), Some("STANDARD"));
+        assert_eq!(
+            attributes.checksums,
+            vec![(
+                "SHA256".to_string(),
+                "J2fQ+hWGm3nCtMVXz8WG7HmhvKkfq8WL+MmQxz8bCsE=".to_string()
+            )]
+        );
+        assert_eq!(attributes.total_parts_count, Some(2));
+        assert_eq!(
+            attributes.parts[1],
+            ObjectPart {
+                number: 2,
+                size: 1048576,
+                checksums: vec![(
+                    "SHA256".to_string(),
+                    "Nq8HD3jXUe4YhXbvCv5Xm8kHe6b0WJdQsvbnmsHLtbI=".to_string()
+                )],
+            }
+        );
+        assert_eq!(attributes.parts.len(), 2);
+
+        let attributes = object_attributes_xml_parser(
+            "<GetObjectAttributesResponse><ObjectSize>7</ObjectSize></GetObjectAttributesResponse>",
+        )
+        .unwrap();
+        assert_eq!(attributes.object_size, Some(7));
+        assert!(attributes.etag.is_none() && attributes.parts.is_empty());
+    }
+
+    #[test]
+    fn test_multipart_uploads_xml_parser() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListMultipartUploadsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>bucket</Bucket>
+  <KeyMarker></KeyMarker>
+  <UploadIdMarker></UploadIdMarker>
+  <NextKeyMarker>my-movie.m2ts</NextKeyMarker>
+  <NextUploadIdMarker>YW55IGlkZWEgd2h5IGVsdmluZydzIHVwbG9hZCBmYWlsZWQ</NextUploadIdMarker>
+  <MaxUploads>2</MaxUploads>
+  <IsTruncated>true</IsTruncated>
+  <Upload>
+    <Key>my-divisor</Key>
+    <UploadId>XMgbGlrZSBlbHZpbmcncyBub3QgaGF2aW5nIG11Y2ggbHVjaw</UploadId>
+    <Initiator>
+      <ID>arn:aws:iam::111122223333:user/user1-11111a31-17b5-4fb7-9df5-b111111f13de</ID>
+      <DisplayName>user1-11111a31-17b5-4fb7-9df5-b111111f13de</DisplayName>
+    </Initiator>
+    <StorageClass>STANDARD</StorageClass>
+    <Initiated>2010-11-10T20:48:33.000Z</Initiated>
+  </Upload>
+  <Upload>
+    <Key>my-movie.m2ts</Key>
+    <UploadId>YW55IGlkZWEgd2h5IGVsdmluZydzIHVwbG9hZCBmYWlsZWQ</UploadId>
+    <Initiated>2010-11-10T20:48:34.000Z</Initiated>
+  </Upload>
+</ListMultipartUploadsResult>"#;
+        let (uploads, next_markers) = multipart_uploads_xml_parser(body).unwrap();
+        assert_eq!(
+            next_markers,
+            Some((
+                "my-movie.m2ts".to_string(),
+                "YW55IGlkZWEgd2h5IGVsdmluZydzIHVwbG9hZCBmYWlsZWQ".to_string()
+            ))
+        );
+        assert_eq!(
+            uploads,
+            vec![
+                UploadInfo {
+                    key: "my-divisor".to_string(),
+                    upload_id: "XMgbGlrZSBlbHZpbmcncyBub3QgaGF2aW5nIG11Y2ggbHVjaw".to_string(),
+                    initiated: "2010-11-10T20:48:33.000Z".to_string(),
+                },
+                UploadInfo {
+                    key: "my-movie.m2ts".to_string(),
+                    upload_id: "YW55IGlkZWEgd2h5IGVsdmluZydzIHVwbG9hZCBmYWlsZWQ".to_string(),
+                    initiated: "2010-11-10T20:48:34.000Z".to_string(),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_upload_id() {
         let response = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<InitiateMultipartUploadResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Bucket>ant-lab</Bucket><Key>test-s3handle-big-v4-async-1611237128</Key><UploadId>6lxsB3W3e.Gf6D2mXrDpscWxHeVNloGTDMPUmomjmRYbQ5j4K31mMTcSdzWTHY6cSnA_S36J6GKY.aAxAkjcTXGb3btEB_O9XSpIy9mFRIlYAo0DH_Oyg9KF6D5fppQzPfYBy_OZTIncT6zK_zQIyQ--</UploadId></InitiateMultipartUploadResult>";
@@ -338,4 +1936,369 @@ mod tests {
         assert!(upload_id.is_ok());
         assert_eq!(upload_id.unwrap(), "6lxsB3W3e.Gf6D2mXrDpscWxHeVNloGTDMPUmomjmRYbQ5j4K31mMTcSdzWTHY6cSnA_S36J6GKY.aAxAkjcTXGb3btEB_O9XSpIy9mFRIlYAo0DH_Oyg9KF6D5fppQzPfYBy_OZTIncT6zK_zQIyQ--");
     }
+
+    #[test]
+    fn test_clock_skew() {
+        let body = b"<Error><Code>RequestTimeTooSkewed</Code><Message>The difference between the request time and the server's time is too large.</Message></Error>";
+        let mut headers = HeaderMap::new();
+        let server_time = Utc::now() + Duration::hours(1);
+        headers.insert(
+            reqwest::header::DATE,
+            server_time.to_rfc2822().parse().unwrap(),
+        );
+        let error = s3_error(StatusCode::FORBIDDEN, &headers, body);
+        let offset = clock_skew(&error, &headers).unwrap();
+        assert!((offset - Duration::hours(1)).num_seconds().abs() <= 1);
+
+        assert!(clock_skew(&error, &HeaderMap::new()).is_none());
+        let error = s3_error(
+            StatusCode::FORBIDDEN,
+            &headers,
+            b"<Error><Code>AccessDenied</Code></Error>",
+        );
+        assert!(clock_skew(&error, &headers).is_none());
+    }
+
+    #[test]
+    fn test_s3_error() {
+        let body = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>NoSuchKey</Code><Message>The resource you requested does not exist</Message><Resource>/mybucket/myfoto.jpg</Resource><RequestId>4442587FB7D0A2F9</RequestId><HostId>body-host-id</HostId></Error>";
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amz-id-2", "header-host-id".parse().unwrap());
+        let error = s3_error(StatusCode::NOT_FOUND, &headers, body);
+        assert_eq!(error.status(), Some(404));
+        assert_eq!(error.request_id(), Some("4442587FB7D0A2F9"));
+        assert_eq!(error.extended_request_id(), Some("header-host-id"));
+        match error {
+            Error::S3Error {
+                status,
+                code,
+                message,
+                request_id,
+                host_id,
+            } => {
+                assert_eq!(status, 404);
+                assert_eq!(code, "NoSuchKey");
+                assert_eq!(message, "The resource you requested does not exist");
+                assert_eq!(request_id, Some("4442587FB7D0A2F9".to_string()));
+                assert_eq!(host_id, Some("header-host-id".to_string()));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_du_summary() {
+        let object = |key: &str, size: u64, storage_class: &str| S3Object {
+            bucket: Some("bucket".to_string()),
+            key: Some(key.to_string()),
+            size: Some(size),
+            storage_class: Some(storage_class.to_string()),
+            ..Default::default()
+        };
+        let mut summary = DuSummary::default();
+        for obj in [
+            object("/logs/a.log", 10, "STANDARD"),
+            object("/logs/2021/01/b.log", 20, "GLACIER"),
+            object("/logs/2021/c.log", 30, ""),
+            object("/logs/2022/d.log", 40, "STANDARD"),
+        ] {
+            summary.add(&obj, "logs/", Some("/"));
+        }
+        summary.add(&S3Object::from("s3://bucket"), "logs/", Some("/"));
+        assert_eq!(summary.objects, 4);
+        assert_eq!(summary.total_bytes, 100);
+        assert_eq!(summary.by_storage_class["STANDARD"], 80);
+        assert_eq!(summary.by_storage_class["GLACIER"], 20);
+        assert_eq!(
+            summary
+                .sub_prefixes
+                .iter()
+                .map(|(p, s)| (p.as_str(), s.objects, s.total_bytes))
+                .collect::<Vec<_>>(),
+            vec![("logs/2021/", 2, 50), ("logs/2022/", 1, 40)]
+        );
+    }
+
+    #[test]
+    fn test_multipart_etag() {
+        let content: Vec<u8> = (0..=255).cycle().take(10240).collect();
+        let file = std::env::temp_dir().join("s3handler_test_multipart_etag");
+        std::fs::write(&file, &content).unwrap();
+        assert_eq!(
+            multipart_etag(file.to_str().unwrap(), 4096).unwrap(),
+            "b9a4d6e74e6be4117e4726aa450242ec-3"
+        );
+        // the file fit in the parts exactly
+        assert_eq!(
+            multipart_etag(file.to_str().unwrap(), 5120).unwrap(),
+            multipart_etag_of_bytes(&content, 5120).unwrap()
+        );
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            multipart_etag_of_bytes(&content, 4096).unwrap(),
+            "b9a4d6e74e6be4117e4726aa450242ec-3"
+        );
+        assert_eq!(
+            multipart_etag_of_bytes(b"", 4096).unwrap(),
+            "59adb24ef3cdbe0297f05b395827453f-1"
+        );
+        assert!(multipart_etag_of_bytes(&content, 0).is_err());
+    }
+
+    #[test]
+    fn test_region_endpoint() {
+        assert_eq!(region_endpoint("us-east-1"), "s3.us-east-1.amazonaws.com");
+        assert_eq!(
+            region_endpoint("cn-north-1"),
+            "s3.cn-north-1.amazonaws.com.cn"
+        );
+        assert_eq!(
+            region_endpoint(" CN-Northwest-1 "),
+            "s3.cn-northwest-1.amazonaws.com.cn"
+        );
+        assert_eq!(
+            region_endpoint("us-gov-west-1"),
+            "s3.us-gov-west-1.amazonaws.com"
+        );
+        assert_eq!(Partition::of("cn-north-1").name(), "aws-cn");
+        assert_eq!(Partition::of("us-gov-east-1"), Partition::GovCloud);
+        assert_eq!(Partition::of("eu-west-1"), Partition::Aws);
+    }
+
+    #[test]
+    fn test_modified_time() {
+        let mut obj = S3Object {
+            mtime: Some("Sat, 19 Sep 2020 15:04:46 GMT".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            obj.modified_time().unwrap().to_rfc3339(),
+            "2020-09-19T15:04:46+00:00"
+        );
+        obj.metadata
+            .insert(MTIME_METADATA.to_string(), "1600527886.5".to_string());
+        let mtime = obj.modified_time().unwrap();
+        assert_eq!(mtime.timestamp(), 1600527886);
+        assert_eq!(mtime.timestamp_subsec_millis(), 500);
+        assert_eq!(mtime_metadata(&mtime), "1600527886.5");
+        assert_eq!(
+            mtime_metadata(&Utc.timestamp_opt(1600527886, 0).unwrap()),
+            "1600527886"
+        );
+        obj.metadata
+            .insert(MTIME_METADATA.to_string(), "invalid".to_string());
+        assert_eq!(obj.modified_time().unwrap().timestamp(), 1600527886);
+        // the multibyte fraction is invalid rather than sliced in the middle of a character
+        obj.metadata
+            .insert(MTIME_METADATA.to_string(), "1.12345678é".to_string());
+        assert_eq!(obj.modified_time().unwrap().timestamp(), 1600527886);
+    }
+
+    #[test]
+    fn test_is_modified_since() {
+        let since = "2020-09-19T15:04:46Z".parse::<DateTime<Utc>>().unwrap();
+        let object = |mtime: &str| S3Object {
+            mtime: Some(mtime.to_string()),
+            ..Default::default()
+        };
+        assert!(object("2020-09-19T15:04:46.000Z").is_modified_since(&since));
+        assert!(object("2020-09-19T15:04:46.001Z").is_modified_since(&since));
+        assert!(!object("2020-09-19T15:04:45.999Z").is_modified_since(&since));
+        assert!(object("Sat, 19 Sep 2020 15:04:46 GMT").is_modified_since(&since));
+        assert!(!object("Sat, 19 Sep 2020 15:04:45 GMT").is_modified_since(&since));
+        assert!(S3Object::default().is_modified_since(&since));
+    }
+
+    #[test]
+    fn test_validate_bucket_name() {
+        for name in ["abc", "my-logs.2021", "1st-bucket", &"a".repeat(63)] {
+            assert!(validate_bucket_name(name).is_ok(), "{}", name);
+        }
+        for (name, reason) in [
+            ("ab", "3 to 63 characters"),
+            (&"a".repeat(64), "3 to 63 characters"),
+            ("My-Bucket", "lowercase letters"),
+            ("my_bucket", "lowercase letters"),
+            ("-bucket", "begin and end"),
+            ("bucket.", "begin and end"),
+            ("my..bucket", "adjacent dots"),
+            ("192.168.5.4", "IP address"),
+        ] {
+            assert!(
+                matches!(validate_bucket_name(name), Err(Error::UserError(e)) if e.contains(reason)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_url_encoded_listing() {
+        let body = "<ListBucketResult><Name>bucket</Name><EncodingType>url</EncodingType><IsTruncated>true</IsTruncated><NextMarker>c%2Bd.txt</NextMarker><Contents><Key>a+b%0Ac%25.txt</Key><ETag>&quot;a&quot;</ETag><Size>7</Size><Owner><ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID><DisplayName>webfile</DisplayName></Owner></Contents><Contents><Key>c%2Bd.txt</Key><ETag>&quot;c&quot;</ETag><Size>7</Size></Contents></ListBucketResult>";
+        let (objects, truncated) = s3object_list_xml_parser(body).unwrap();
+        assert!(truncated);
+        assert_eq!(objects[1].key.as_deref(), Some("/a b\nc%.txt"));
+        assert_eq!(
+            objects[1].owner.as_deref(),
+            Some("75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a")
+        );
+        assert_eq!(objects[2].key.as_deref(), Some("/c+d.txt"));
+        assert_eq!(objects[2].owner, None);
+        assert_eq!(
+            next_marker_xml_parser(body).unwrap().as_deref(),
+            Some("c+d.txt")
+        );
+        assert!(
+            next_marker_xml_parser("<ListBucketResult></Next><NextMarker>a</NextMarker>").is_err()
+        );
+
+        // The keys are kept as they are without the encoding type
+        let (objects, _) =
+            s3object_list_xml_parser(&body.replace("<EncodingType>url</EncodingType>", ""))
+                .unwrap();
+        assert_eq!(objects[2].key.as_deref(), Some("/c%2Bd.txt"));
+
+        // The owner can not be unescaped
+        assert!(matches!(
+            s3object_list_xml_parser(&body.replace("<ID>75aa", "<ID>&bogus;75aa")),
+            Err(Error::XMLParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_url_encode() {
+        assert_eq!(url_encode("/bucket/logs/a.txt"), "/bucket/logs/a.txt");
+        assert_eq!(
+            url_encode("/bucket/a b+c?d%é.txt"),
+            "/bucket/a%20b%2Bc%3Fd%25%C3%A9.txt"
+        );
+        assert_eq!(url_decode(&url_encode("/a b+c é")), "/a b+c é");
+    }
+
+    #[test]
+    fn test_s3object_list_json_parser() {
+        // The listing of Ceph RGW with `format=json`, the `Contents` is repeated for each object
+        let body = br#"{"Name":"bucket","Prefix":"","Marker":"","NextMarker":"logs/b.gz","MaxKeys":2,"IsTruncated":true,"Contents":["logs/a.gz","2021-03-01T08:12:44.193Z","\"0b1c3a6a1e4d4c4e2ba1a3bb1b1b9f55\"",1048576,"STANDARD_IA",{"ID":"tester","DisplayName":"Tester"},"Normal"],"Contents":["logs/b.gz","2021-03-02T08:12:44.193Z","\"6c8f5b7a3c4d2e1f0a9b8c7d6e5f4a3b-2\"",10485760,"STANDARD",{"ID":"tester","DisplayName":"Tester"},"Normal"]}"#;
+        let (objects, truncated, next_marker) = s3object_list_json_parser(body).unwrap();
+        assert!(truncated);
+        assert_eq!(next_marker.as_deref(), Some("logs/b.gz"));
+        assert_eq!(objects.len(), 2);
+        let a = &objects[0];
+        assert_eq!(a.bucket.as_deref(), Some("bucket"));
+        assert_eq!(a.key.as_deref(), Some("/logs/a.gz"));
+        assert_eq!(a.mtime.as_deref(), Some("2021-03-01T08:12:44.193Z"));
+        assert_eq!(a.etag.as_deref(), Some("0b1c3a6a1e4d4c4e2ba1a3bb1b1b9f55"));
+        assert_eq!(a.storage_class.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(a.size, Some(1048576));
+        assert_eq!(a.owner.as_deref(), Some("tester"));
+        assert_eq!(objects[1].size, Some(10485760));
+        assert_eq!(objects[1].storage_class.as_deref(), Some("STANDARD"));
+
+        // The named fields in any order and with the spaces, and the last page without a marker
+        let body = br#"{
+            "Name": "bucket",
+            "IsTruncated": false,
+            "Contents": [
+                {"Size": 7, "Key": "a b.txt", "StorageClass": "STANDARD", "ETag": "\"5050ef3558233dc04b3fac50eff68de1\"", "LastModified": "2020-09-19T15:10:08.000Z"}
+            ]
+        }"#;
+        let (objects, truncated, next_marker) = s3object_list_json_parser(body).unwrap();
+        assert!(!truncated);
+        assert_eq!(next_marker, None);
+        assert_eq!(objects[0].key.as_deref(), Some("/a b.txt"));
+        assert_eq!(
+            objects[0].etag.as_deref(),
+            Some("5050ef3558233dc04b3fac50eff68de1")
+        );
+        assert_eq!(objects[0].size, Some(7));
+        assert_eq!(
+            objects[0].mtime.as_deref(),
+            Some("2020-09-19T15:10:08.000Z")
+        );
+
+        // The truncated page without a marker is continued after the last key
+        let body = br#"{"Name":"bucket","IsTruncated":"true","Contents":[{"Key":"a.txt"},{"Key":"b.txt"}]}"#;
+        let (_, truncated, next_marker) = s3object_list_json_parser(body).unwrap();
+        assert!(truncated);
+        assert_eq!(next_marker.as_deref(), Some("b.txt"));
+        let body = br#"{"Name":"bucket","IsTruncated":false,"NextMarker":"b.txt","Contents":[{"Key":"b.txt"}]}"#;
+        let (_, truncated, next_marker) = s3object_list_json_parser(body).unwrap();
+        assert!(!truncated);
+        assert_eq!(next_marker, None);
+
+        // The truncated page without a content and a marker can not be continued
+        let body = br#"{"Name":"bucket","IsTruncated":true,"Contents":[]}"#;
+        let (objects, truncated, next_marker) = s3object_list_json_parser(body).unwrap();
+        assert!(objects.is_empty());
+        assert!(truncated);
+        assert_eq!(next_marker, None);
+
+        assert!(s3object_list_json_parser(b"<ListBucketResult/>").is_err());
+    }
+
+    #[test]
+    fn test_s3_object_from_padded_path() {
+        for path in [
+            "s3://bucket/key ",
+            " s3://bucket/key\n",
+            "\"s3://bucket/key\"",
+            "'s3://bucket/key '",
+            "\"bucket/key\" ",
+            "/bucket/key\t",
+        ] {
+            let s3_object = S3Object::from(path);
+            assert_eq!(s3_object.bucket, Some("bucket".to_string()));
+            assert_eq!(s3_object.key, Some("/key".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_s3_error_from_html_page() {
+        let body = b"<html>\r\n<head><title>502 Bad Gateway</title></head>\r\n<body><center><h1>502 Bad Gateway</h1></center></body></html>";
+        match s3_error(StatusCode::BAD_GATEWAY, &HeaderMap::new(), body) {
+            Error::S3Error {
+                status,
+                code,
+                message,
+                ..
+            } => {
+                assert_eq!(status, 502);
+                assert!(code.is_empty());
+                assert!(message.contains("502 Bad Gateway"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match s3_error(StatusCode::BAD_GATEWAY, &HeaderMap::new(), &[b'x'; 1024]) {
+            Error::S3Error { message, .. } => assert_eq!(message.len(), ERROR_SNIPPET_LENGTH),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_tagging() {
+        let response = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Tagging xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><TagSet><Tag><Key>tag1</Key><Value>val1</Value></Tag><Tag><Key>tag2</Key><Value>a&amp;b</Value></Tag></TagSet></Tagging>";
+        assert_eq!(
+            tagging_xml_parser(response).unwrap(),
+            vec![
+                ("tag1".to_string(), "val1".to_string()),
+                ("tag2".to_string(), "a&b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gunzip() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"This is a test file\n").unwrap();
+        let data = encoder.finish().unwrap();
+        assert_eq!(gunzip(&data).unwrap(), b"This is a test file\n");
+        assert!(gunzip(b"not gzip").is_err());
+        assert!(is_gzip(Some("gzip")));
+        assert!(is_gzip(Some("identity, GZIP")));
+        assert!(!is_gzip(Some("br")));
+        assert!(!is_gzip(None));
+    }
 }
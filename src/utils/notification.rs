@@ -0,0 +1,260 @@
+use quick_xml::{events::Event, Reader};
+
+use super::escape_str;
+use crate::error::Error;
+
+/// The destination of the notification
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotificationTarget {
+    /// The ARN of a SNS topic
+    Topic(String),
+    /// The ARN of a SQS queue
+    Queue(String),
+    /// The ARN of a Lambda function
+    CloudFunction(String),
+}
+
+impl NotificationTarget {
+    fn config_tag(&self) -> &'static str {
+        match self {
+            NotificationTarget::Topic(_) => "TopicConfiguration",
+            NotificationTarget::Queue(_) => "QueueConfiguration",
+            NotificationTarget::CloudFunction(_) => "CloudFunctionConfiguration",
+        }
+    }
+
+    fn arn_tag(&self) -> &'static str {
+        match self {
+            NotificationTarget::Topic(_) => "Topic",
+            NotificationTarget::Queue(_) => "Queue",
+            NotificationTarget::CloudFunction(_) => "CloudFunction",
+        }
+    }
+
+    fn arn(&self) -> &str {
+        match self {
+            NotificationTarget::Topic(arn)
+            | NotificationTarget::Queue(arn)
+            | NotificationTarget::CloudFunction(arn) => arn,
+        }
+    }
+}
+
+/// # A notification rule of a bucket
+/// - id - the optional id of the rule
+/// - target - where the notification will be sent
+/// - events - the event types, ex: "s3:ObjectCreated:*"
+/// - prefix, suffix - only notify on the object keys match the filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationRule {
+    pub id: Option<String>,
+    pub target: NotificationTarget,
+    pub events: Vec<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+/// # The notification configuration of a bucket
+/// ```
+/// use s3handler::utils::notification::*;
+///
+/// let config = NotificationConfiguration {
+///     rules: vec![NotificationRule {
+///         id: Some("new-images".to_string()),
+///         target: NotificationTarget::Queue("arn:aws:sqs:us-east-1:123456789012:queue".to_string()),
+///         events: vec!["s3:ObjectCreated:*".to_string()],
+///         prefix: Some("images/".to_string()),
+///         suffix: None,
+///     }],
+/// };
+/// assert_eq!(config, notification_xml_parser(&config.to_xml()).unwrap());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotificationConfiguration {
+    pub rules: Vec<NotificationRule>,
+}
+
+impl NotificationConfiguration {
+    /// Serialize into the XML payload of the notification API
+    pub fn to_xml(&self) -> String {
+        let mut content = "<NotificationConfiguration>".to_string();
+        for rule in self.rules.iter() {
+            content.push_str(&format!("<{}>", rule.target.config_tag()));
+            if let Some(id) = &rule.id {
+                content.push_str(&format!("<Id>{}</Id>", escape_str(id)));
+            }
+            content.push_str(&format!(
+                "<{0}>{1}</{0}>",
+                rule.target.arn_tag(),
+                escape_str(rule.target.arn())
+            ));
+            for event in rule.events.iter() {
+                content.push_str(&format!("<Event>{}</Event>", escape_str(event)));
+            }
+            if rule.prefix.is_some() || rule.suffix.is_some() {
+                content.push_str("<Filter><S3Key>");
+                for (name, value) in [("prefix", &rule.prefix), ("suffix", &rule.suffix)] {
+                    if let Some(value) = value {
+                        content.push_str(&format!(
+                            "<FilterRule><Name>{}</Name><Value>{}</Value></FilterRule>",
+                            name,
+                            escape_str(value)
+                        ));
+                    }
+                }
+                content.push_str("</S3Key></Filter>");
+            }
+            content.push_str(&format!("</{}>", rule.target.config_tag()));
+        }
+        content.push_str("</NotificationConfiguration>");
+        content
+    }
+}
+
+pub fn notification_xml_parser(body: &str) -> Result<NotificationConfiguration, Error> {
+    let mut reader = Reader::from_str(body);
+    let mut output = NotificationConfiguration::default();
+    let mut buf = Vec::new();
+    let mut current_tag = Vec::new();
+    let mut id = None;
+    let mut arn = String::new();
+    let mut events = Vec::new();
+    let mut prefix = None;
+    let mut suffix = None;
+    let mut filter_name = String::new();
+    let mut filter_value = String::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                current_tag = e.name().to_vec();
+                match e.name() {
+                    // The fields are not carried over from the previous configuration
+                    b"TopicConfiguration"
+                    | b"QueueConfiguration"
+                    | b"CloudFunctionConfiguration" => {
+                        id = None;
+                        arn.clear();
+                        events.clear();
+                        prefix = None;
+                        suffix = None;
+                    }
+                    b"FilterRule" => {
+                        filter_name.clear();
+                        filter_value.clear();
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                let target = match e.name() {
+                    b"TopicConfiguration" => Some(NotificationTarget::Topic(arn.clone())),
+                    b"QueueConfiguration" => Some(NotificationTarget::Queue(arn.clone())),
+                    b"CloudFunctionConfiguration" => {
+                        Some(NotificationTarget::CloudFunction(arn.clone()))
+                    }
+                    b"FilterRule" => {
+                        match filter_name.to_lowercase().as_str() {
+                            "prefix" => prefix = Some(filter_value.clone()),
+                            "suffix" => suffix = Some(filter_value.clone()),
+                            _ => {}
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    output.rules.push(NotificationRule {
+                        id: id.take(),
+                        target,
+                        events: std::mem::take(&mut events),
+                        prefix: prefix.take(),
+                        suffix: suffix.take(),
+                    });
+                }
+                current_tag.clear();
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(&reader).unwrap_or_default();
+                match current_tag.as_slice() {
+                    b"Id" => id = Some(text),
+                    b"Topic" | b"Queue" | b"CloudFunction" => arn = text,
+                    b"Event" => events.push(text),
+                    b"Name" => filter_name = text,
+                    b"Value" => filter_value = text,
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::XMLParseError(e)),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notification_configuration() {
+        let response = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<NotificationConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><TopicConfiguration><Id>ceph-kafka</Id><Topic>arn:aws:sns:default::kafka-topic</Topic><Event>s3:ObjectCreated:*</Event><Event>s3:ObjectRemoved:*</Event><Filter><S3Key><FilterRule><Name>prefix</Name><Value>logs/</Value></FilterRule><FilterRule><Name>suffix</Name><Value>.gz</Value></FilterRule></S3Key></Filter></TopicConfiguration><CloudFunctionConfiguration><CloudFunction>arn:aws:lambda:us-west-2:35667example:function:CreateThumbnail</CloudFunction><Event>s3:ObjectCreated:Put</Event></CloudFunctionConfiguration></NotificationConfiguration>";
+        let config = notification_xml_parser(response).unwrap();
+        assert_eq!(
+            config.rules,
+            vec![
+                NotificationRule {
+                    id: Some("ceph-kafka".to_string()),
+                    target: NotificationTarget::Topic(
+                        "arn:aws:sns:default::kafka-topic".to_string()
+                    ),
+                    events: vec![
+                        "s3:ObjectCreated:*".to_string(),
+                        "s3:ObjectRemoved:*".to_string()
+                    ],
+                    prefix: Some("logs/".to_string()),
+                    suffix: Some(".gz".to_string()),
+                },
+                NotificationRule {
+                    id: None,
+                    target: NotificationTarget::CloudFunction(
+                        "arn:aws:lambda:us-west-2:35667example:function:CreateThumbnail"
+                            .to_string()
+                    ),
+                    events: vec!["s3:ObjectCreated:Put".to_string()],
+                    prefix: None,
+                    suffix: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_notification_configuration_to_xml() {
+        let config = NotificationConfiguration {
+            rules: vec![NotificationRule {
+                id: None,
+                target: NotificationTarget::Queue("arn:aws:sqs:us-east-1:1:queue".to_string()),
+                events: vec!["s3:ObjectRemoved:Delete".to_string()],
+                prefix: None,
+                suffix: Some("a&b".to_string()),
+            }],
+        };
+        assert_eq!(
+            config.to_xml(),
+            "<NotificationConfiguration><QueueConfiguration><Queue>arn:aws:sqs:us-east-1:1:queue</Queue><Event>s3:ObjectRemoved:Delete</Event><Filter><S3Key><FilterRule><Name>suffix</Name><Value>a&amp;b</Value></FilterRule></S3Key></Filter></QueueConfiguration></NotificationConfiguration>"
+        );
+        assert_eq!(notification_xml_parser(&config.to_xml()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_parse_configuration_without_arn() {
+        let response = "<NotificationConfiguration><QueueConfiguration><Queue>arn:aws:sqs:us-east-1:1:queue</Queue><Event>s3:ObjectRemoved:Delete</Event></QueueConfiguration><TopicConfiguration><Event>s3:ObjectCreated:*</Event></TopicConfiguration></NotificationConfiguration>";
+        let config = notification_xml_parser(response).unwrap();
+        assert_eq!(
+            config.rules[1].target,
+            NotificationTarget::Topic(String::new())
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Compare the objects of the source and the destination of a sync by the keys relative to the
+//! folder or the prefix, and plan the keys to transfer and the keys to prune.
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+use crate::error::Error;
+use crate::utils::S3Object;
+
+/// # The settings of a sync
+/// - delete - remove the files or the objects only on the destination
+/// - checksum - compare by the ETags instead of the sizes and the modified times,
+///   the ETags of the local files are calculated with the part size of the handler
+/// - dry_run - plan the keys without transferring or removing anything
+/// ```
+/// let options = s3handler::utils::sync::SyncOptions {
+///     delete: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    pub delete: bool,
+    pub checksum: bool,
+    pub dry_run: bool,
+}
+
+/// # The keys planned by a sync, which are relative to the folder or the prefix
+/// - transfer - the keys missing or different on the destination
+/// - delete - the keys only on the destination, which are pruned if `delete` is set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncPlan {
+    pub transfer: Vec<String>,
+    pub delete: Vec<String>,
+}
+
+impl SyncPlan {
+    /// Plan the keys in order, the objects are compared by the ETags if `checksum` is set and
+    /// the ETags of both sides are known, else by the size and the modified time.
+    pub fn new(
+        src: &BTreeMap<String, S3Object>,
+        dest: &BTreeMap<String, S3Object>,
+        checksum: bool,
+        delete: bool,
+    ) -> Self {
+        let transfer = src
+            .iter()
+            .filter(|(k, o)| dest.get(*k).is_none_or(|d| is_changed(o, d, checksum)))
+            .map(|(k, _)| k.clone())
+            .collect();
+        let delete = if delete {
+            dest.keys()
+                .filter(|k| !src.contains_key(*k))
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        SyncPlan { transfer, delete }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfer.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// Check the relative key listed by a sync stays under the folder or the prefix, the key with
+/// a parent, a root or a prefix component is refused, ex: `../.bashrc`
+pub fn check_key(key: &str) -> Result<(), Error> {
+    if Path::new(key)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(())
+    } else {
+        Err(Error::UserError("the key escapes the folder of the sync"))
+    }
+}
+
+/// The source is changed if the size is different or it is modified after the destination,
+/// the time is not compared if it is unknown on either side.
+fn is_changed(src: &S3Object, dest: &S3Object, checksum: bool) -> bool {
+    if let (true, Some(s), Some(d)) = (checksum, &src.etag, &dest.etag) {
+        return s.trim_matches('"') != d.trim_matches('"');
+    }
+    if src.size != dest.size {
+        return true;
+    }
+    match (src.modified_time(), dest.modified_time()) {
+        (Some(s), Some(d)) => s > d,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(size: u64, mtime: &str, etag: &str) -> S3Object {
+        S3Object {
+            size: Some(size),
+            mtime: Some(mtime.to_string()),
+            etag: Some(etag.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sync_plan() {
+        let src: BTreeMap<String, S3Object> = vec![
+            ("same.txt", object(7, "2021-01-01T00:00:00Z", "a")),
+            ("newer.txt", object(7, "2021-01-02T00:00:00Z", "b")),
+            ("resized.txt", object(8, "2021-01-01T00:00:00Z", "c")),
+            ("missing.txt", object(7, "2021-01-01T00:00:00Z", "d")),
+        ]
+        .into_iter()
+        .map(|(k, o)| (k.to_string(), o))
+        .collect();
+        let dest: BTreeMap<String, S3Object> = vec![
+            ("same.txt", object(7, "2021-01-01T08:00:00Z", "a")),
+            ("newer.txt", object(7, "2021-01-01T00:00:00Z", "b")),
+            ("resized.txt", object(7, "2021-01-02T00:00:00Z", "x")),
+            ("extra.txt", object(7, "2021-01-01T00:00:00Z", "e")),
+        ]
+        .into_iter()
+        .map(|(k, o)| (k.to_string(), o))
+        .collect();
+
+        let plan = SyncPlan::new(&src, &dest, false, false);
+        assert_eq!(
+            plan.transfer,
+            vec!["missing.txt", "newer.txt", "resized.txt"]
+        );
+        assert!(plan.delete.is_empty());
+
+        // The touched file with the same content is not transferred by the checksum
+        let plan = SyncPlan::new(&src, &dest, true, true);
+        assert_eq!(plan.transfer, vec!["missing.txt", "resized.txt"]);
+        assert_eq!(plan.delete, vec!["extra.txt"]);
+
+        assert!(SyncPlan::new(&src, &src, false, true).is_empty());
+    }
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("a.txt").is_ok());
+        assert!(check_key("sub/./b.txt").is_ok());
+        assert!(check_key("../a.txt").is_err());
+        assert!(check_key("sub/../../a.txt").is_err());
+        assert!(check_key("/etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! A tiny HTTP server for the tests of pools, which records the requests and replies the
+//! prepared responses in order, if there is no prepared response, an empty 200 response will be
+//! replied.  If an object is served, the ranged GET requests are replied with the parts of it.
+//! The requests sent in parallel can be replied by their paths with the routes.
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The request received by the test server, the header names are in lowercase
+#[derive(Debug, Clone)]
+pub(crate) struct TestRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// A self-signed root CA certificate for the TLS settings
+pub(crate) const TEST_CA_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBjjCCATWgAwIBAgIUeg2nPaXWW85Nm+IX32T6yayvpxgwCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRczNoYW5kbGVyIHRlc3QgQ0EwIBcNMjYxMDE2MTIzNzA4WhgP
+MjEyNjA5MjIxMjM3MDhaMBwxGjAYBgNVBAMMEXMzaGFuZGxlciB0ZXN0IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEFGyeVswEdiDHFHlHffygF6nn31cjhYE1
++nhO7CQuhChJAAsmVcH4lyY8btKwH8XYA/3wCpoLLHrbrUkT8hbYJKNTMFEwHQYD
+VR0OBBYEFOoMj29wJ8DsB9kACbhUKNAEEHVBMB8GA1UdIwQYMBaAFOoMj29wJ8Ds
+B9kACbhUKNAEEHVBMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDRwAwRAIg
+GAcBnDIShydT1z/nB+SPzPcbvG1RpV4GPRYljzkrFRECIBXyriqcvRB0dOjkRTcE
+lbyD1bJLTBBGhODiqT0aEWem
+-----END CERTIFICATE-----
+";
+
+/// A page of 1000 objects in the XML listing, the keys are numbered from the start, ex: `0000`
+pub(crate) fn list_page(start: usize, truncated: bool) -> String {
+    let mut body = format!(
+        "<ListBucketResult><Name>bucket</Name><IsTruncated>{}</IsTruncated>",
+        truncated
+    );
+    for i in start..start + 1000 {
+        body.push_str(&format!(
+            "<Contents><Key>{:04}</Key><ETag>&quot;e&quot;</ETag><Size>1</Size></Contents>",
+            i
+        ));
+    }
+    body.push_str("</ListBucketResult>");
+    body
+}
+
+type TestResponse = (u16, Vec<(String, String)>, Vec<u8>);
+
+pub(crate) struct TestServer {
+    pub host: String,
+    responses: Arc<Mutex<VecDeque<TestResponse>>>,
+    requests: Arc<Mutex<Vec<TestRequest>>>,
+    object: Arc<Mutex<Option<Vec<u8>>>>,
+    routes: Arc<Mutex<Vec<(String, TestResponse)>>>,
+}
+
+/// The part of the object for the `range` header, ex: bytes=0-9
+fn range_of(object: &[u8], range: &str) -> Option<Vec<u8>> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse::<usize>().ok()?;
+    let end = end.parse::<usize>().ok()?.min(object.len() - 1);
+    object.get(start..=end).map(|part| part.to_vec())
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let responses = Arc::new(Mutex::new(VecDeque::<TestResponse>::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let object = Arc::new(Mutex::new(None::<Vec<u8>>));
+        let routes = Arc::new(Mutex::new(Vec::<(String, TestResponse)>::new()));
+        let (server_responses, server_requests, server_object, server_routes) = (
+            responses.clone(),
+            requests.clone(),
+            object.clone(),
+            routes.clone(),
+        );
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap_or_default();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+                let mut headers = HashMap::new();
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line).unwrap_or_default() == 0
+                        || line.trim().is_empty()
+                    {
+                        break;
+                    }
+                    if let Some((k, v)) = line.split_once(':') {
+                        headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .and_then(|l| l.parse::<usize>().ok())
+                    .unwrap_or_default();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap_or_default();
+                let part = match (server_object.lock().unwrap().as_ref(), headers.get("range")) {
+                    (Some(object), Some(range)) if method == "GET" => range_of(object, range),
+                    _ => None,
+                };
+                let route = server_routes
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+                    .map(|(_, response)| response.clone());
+                server_requests.lock().unwrap().push(TestRequest {
+                    method: method.clone(),
+                    path,
+                    headers,
+                    body,
+                });
+
+                let (status, headers, body) = match (part, route) {
+                    (Some(part), _) => (206, Vec::new(), part),
+                    (None, Some(response)) => response,
+                    (None, None) => server_responses.lock().unwrap().pop_front().unwrap_or((
+                        200,
+                        Vec::new(),
+                        Vec::new(),
+                    )),
+                };
+                let mut response = format!("HTTP/1.1 {} TEST\r\nconnection: close\r\n", status);
+                if !headers.iter().any(|(k, _)| k == "content-length") {
+                    response.push_str(&format!("content-length: {}\r\n", body.len()));
+                }
+                for (k, v) in headers {
+                    response.push_str(&format!("{}: {}\r\n", k, v));
+                }
+                response.push_str("\r\n");
+                let mut response = response.into_bytes();
+                if method != "HEAD" {
+                    response.extend_from_slice(&body);
+                }
+                stream.write_all(&response).unwrap_or_default();
+            }
+        });
+        Self {
+            host,
+            responses,
+            requests,
+            object,
+            routes,
+        }
+    }
+
+    /// Serve the object for the ranged GET requests
+    pub fn serve_object(&self, object: &[u8]) {
+        *self.object.lock().unwrap() = Some(object.to_vec());
+    }
+
+    /// Always reply the requests with the path prefix with the response
+    pub fn route(&self, prefix: &str, status: u16, headers: &[(&str, &str)], body: &[u8]) {
+        self.routes.lock().unwrap().push((
+            prefix.to_string(),
+            (
+                status,
+                headers
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                body.to_vec(),
+            ),
+        ));
+    }
+
+    pub fn respond(&self, status: u16, headers: &[(&str, &str)], body: &[u8]) {
+        self.responses.lock().unwrap().push_back((
+            status,
+            headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body.to_vec(),
+        ));
+    }
+
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,152 @@
+//! Walk a local directory recursively for the uploads, the symbolic links are handled by the
+//! `SymlinkPolicy`, and the error of an entry, ex: a dangling link, is reported with its path
+//! instead of aborting the whole walk.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// The user metadata keeping the target of a symbolic link, the link is uploaded as an empty
+/// object with it in the `Preserve` policy
+pub const SYMLINK_METADATA: &str = "symlink-target";
+
+/// The way to handle the symbolic links in a directory
+/// - Follow - read the file or the folder the link points to, this is the default,
+///   and the cycles of the folders are detected
+/// - Skip - skip the links
+/// - Preserve - keep the link as an empty object with the target in the `symlink-target` metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SymlinkPolicy {
+    #[default]
+    Follow,
+    Skip,
+    Preserve,
+}
+
+/// The entry to upload in the directory
+#[derive(Debug, PartialEq)]
+pub enum LocalEntry {
+    File(PathBuf),
+    /// The target of the link, which is kept as it is, ex: a relative path
+    Symlink(PathBuf),
+}
+
+/// Walk the directory recursively, the entries are in the order of the paths under the directory,
+/// and the links are named by themselves, not by their targets.
+#[allow(clippy::type_complexity)]
+pub fn walk_dir(
+    dir: &Path,
+    policy: SymlinkPolicy,
+) -> Result<Vec<(PathBuf, Result<LocalEntry, Error>)>, Error> {
+    let mut entries = Vec::new();
+    let mut ancestors = vec![fs::canonicalize(dir)?];
+    walk(dir, policy, &mut ancestors, &mut entries)?;
+    Ok(entries)
+}
+
+fn walk(
+    dir: &Path,
+    policy: SymlinkPolicy,
+    ancestors: &mut Vec<PathBuf>,
+    entries: &mut Vec<(PathBuf, Result<LocalEntry, Error>)>,
+) -> Result<(), Error> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    for path in paths {
+        if fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            match policy {
+                SymlinkPolicy::Follow => {}
+                SymlinkPolicy::Skip => continue,
+                SymlinkPolicy::Preserve => {
+                    let target = fs::read_link(&path).map(LocalEntry::Symlink);
+                    entries.push((path, target.map_err(Error::from)));
+                    continue;
+                }
+            }
+        }
+        // The metadata of the target, which is missing for a dangling link
+        match fs::metadata(&path).and_then(|m| Ok((m, fs::canonicalize(&path)?))) {
+            Ok((m, real)) if m.is_dir() => {
+                if ancestors.contains(&real) {
+                    entries.push((path, Err(Error::UserError("a cycle of symbolic links"))));
+                } else {
+                    ancestors.push(real);
+                    if let Err(e) = walk(&path, policy, ancestors, entries) {
+                        entries.push((path, Err(e)));
+                    }
+                    ancestors.pop();
+                }
+            }
+            Ok(_) => entries.push((path.clone(), Ok(LocalEntry::File(path)))),
+            Err(e) => entries.push((path, Err(e.into()))),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_walk_dir() {
+        let dir = std::env::temp_dir().join("s3handler-walk-dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"a").unwrap();
+        fs::write(dir.join("sub/b.txt"), b"b").unwrap();
+        symlink("a.txt", dir.join("link.txt")).unwrap();
+        symlink("missing.txt", dir.join("dangling.txt")).unwrap();
+        symlink("..", dir.join("sub/cycle")).unwrap();
+
+        let walked = |policy| -> Vec<(String, Result<LocalEntry, Error>)> {
+            walk_dir(&dir, policy)
+                .unwrap()
+                .into_iter()
+                .map(|(p, e)| (p.strip_prefix(&dir).unwrap().display().to_string(), e))
+                .collect()
+        };
+
+        let entries = walked(SymlinkPolicy::Follow);
+        let names: Vec<&str> = entries.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "a.txt",
+                "dangling.txt",
+                "link.txt",
+                "sub/b.txt",
+                "sub/cycle"
+            ]
+        );
+        assert_eq!(
+            entries[0].1.as_ref().unwrap(),
+            &LocalEntry::File(dir.join("a.txt"))
+        );
+        assert!(matches!(entries[1].1, Err(Error::LoadError(_))));
+        assert_eq!(
+            entries[2].1.as_ref().unwrap(),
+            &LocalEntry::File(dir.join("link.txt"))
+        );
+        assert!(matches!(entries[4].1, Err(Error::UserError(_))));
+
+        let entries = walked(SymlinkPolicy::Skip);
+        let names: Vec<&str> = entries.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "sub/b.txt"]);
+
+        let entries = walked(SymlinkPolicy::Preserve);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(
+            entries[1].1.as_ref().unwrap(),
+            &LocalEntry::Symlink("missing.txt".into())
+        );
+        assert_eq!(
+            entries[4].1.as_ref().unwrap(),
+            &LocalEntry::Symlink("..".into())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -101,6 +101,8 @@ fn test_v2_sync_operation() {
         region: None,
         s3_type: None,
         secure: None,
+        part_size: None,
+        worker_count: None,
     };
     let mut handler = s3handler::blocking::Handler::from(&config);
     handler.change_auth_type("aws2");
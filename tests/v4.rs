@@ -114,7 +114,7 @@ async fn test_v4_async_operation() {
             env::var("SECRET_KEY").unwrap(),
             env::var("REGION").unwrap(),
         )
-        .part_size(env::var("PART_SIZE").unwrap().parse::<usize>().unwrap());
+        .part_size(env::var("PART_SIZE").unwrap().parse::<u64>().unwrap());
     let obj = s3_pool
         .bucket(&env::var("BUCKET_NAME").unwrap())
         .object(&env::var("BIG_OBJECT_NAME").unwrap());
@@ -127,7 +127,7 @@ async fn test_v4_async_operation() {
             env::var("SECRET_KEY").unwrap(),
             env::var("REGION").unwrap(),
         )
-        .part_size(env::var("PART_SIZE").unwrap().parse::<usize>().unwrap())
+        .part_size(env::var("PART_SIZE").unwrap().parse::<u64>().unwrap())
         .bucket(&env::var("BUCKET_NAME").unwrap())
         .object(&new_object);
     obj.upload_file(temp_test_file).await.unwrap();
@@ -144,6 +144,79 @@ async fn test_v4_async_operation() {
     obj.remove().await.unwrap();
 }
 
+/// The operations shared by the blocking handlers
+macro_rules! sync_operation {
+    ($handler:expr, $temp_test_file:expr, $new_object:expr) => {{
+        use std::env;
+        use std::fs::File;
+        use std::io::prelude::*;
+
+        // Download
+        $handler
+            .get(
+                &format!(
+                    "/{}/{}",
+                    env::var("BUCKET_NAME").unwrap(),
+                    env::var("OBJECT_NAME").unwrap()
+                ),
+                Some(&$temp_test_file),
+            )
+            .unwrap();
+        let mut file = File::open($temp_test_file).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, env::var("EXPECT_CONTENT").unwrap());
+
+        // Upload
+        $handler
+            .put(
+                &$temp_test_file,
+                &format!("/{}/{}", env::var("BUCKET_NAME").unwrap(), &$new_object),
+            )
+            .unwrap();
+
+        // Delete
+        $handler
+            .del(&format!(
+                "/{}/{}",
+                env::var("BUCKET_NAME").unwrap(),
+                &$new_object
+            ))
+            .unwrap();
+    }};
+}
+
+fn sync_test_config() -> s3handler::blocking::CredentialConfig {
+    use std::env;
+
+    s3handler::blocking::CredentialConfig {
+        host: env::var("S3_HOST").unwrap(),
+        access_key: env::var("ACCESS_KEY").unwrap(),
+        secret_key: env::var("SECRET_KEY").unwrap(),
+        user: None,
+        region: env::var("REGION").ok(),
+        s3_type: None,
+        secure: None,
+        part_size: None,
+        worker_count: None,
+    }
+}
+
+fn new_sync_object(tag: &str) -> String {
+    use std::env;
+    use std::time::SystemTime;
+
+    format!(
+        "{}-{}-{}",
+        env::var("OBJECT_NAME").unwrap(),
+        tag,
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    )
+}
+
 #[test_with::env(
     ACCESS_KEY,
     SECRET_KEY,
@@ -154,66 +227,35 @@ async fn test_v4_async_operation() {
 )]
 #[test]
 fn test_v4_sync_operation() {
-    use std::env;
-    use std::fs::File;
-    use std::io::prelude::*;
-    use std::time::SystemTime;
-
-    let akey = env::var("ACCESS_KEY").unwrap();
-    println!("use access key: {} for sync testing", akey);
-
-    // TODO: use tmpfile crate to test on different OS
-    let temp_test_file = "/tmp/sync-test";
-    let new_object = format!(
-        "{}-sync-{}",
-        env::var("OBJECT_NAME").unwrap(),
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs()
+    println!(
+        "use access key: {} for sync testing",
+        std::env::var("ACCESS_KEY").unwrap()
     );
 
-    // Download
-    let config = s3handler::blocking::CredentialConfig {
-        host: env::var("S3_HOST").unwrap(),
-        access_key: env::var("ACCESS_KEY").unwrap(),
-        secret_key: env::var("SECRET_KEY").unwrap(),
-        user: None,
-        region: env::var("REGION").ok(),
-        s3_type: None,
-        secure: None,
-    };
+    // TODO: use tmpfile crate to test on different OS
+    let config = sync_test_config();
     let mut handler = s3handler::blocking::Handler::from(&config);
     handler.change_auth_type("aws4");
-    handler
-        .get(
-            &format!(
-                "/{}/{}",
-                env::var("BUCKET_NAME").unwrap(),
-                env::var("OBJECT_NAME").unwrap()
-            ),
-            Some(&temp_test_file),
-        )
-        .unwrap();
-    let mut file = File::open(temp_test_file).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    assert_eq!(contents, env::var("EXPECT_CONTENT").unwrap());
+    sync_operation!(handler, "/tmp/sync-test", new_sync_object("sync"));
+}
 
-    // Upload
-    handler
-        .put(
-            &temp_test_file,
-            &format!("/{}/{}", env::var("BUCKET_NAME").unwrap(), &new_object),
-        )
-        .unwrap();
+#[cfg(feature = "blocking2")]
+#[test_with::env(
+    ACCESS_KEY,
+    SECRET_KEY,
+    S3_HOST,
+    BUCKET_NAME,
+    OBJECT_NAME,
+    EXPECT_CONTENT
+)]
+#[test]
+fn test_v4_blocking2_operation() {
+    println!(
+        "use access key: {} for blocking2 testing",
+        std::env::var("ACCESS_KEY").unwrap()
+    );
 
-    // Delete
-    handler
-        .del(&format!(
-            "/{}/{}",
-            env::var("BUCKET_NAME").unwrap(),
-            &new_object
-        ))
-        .unwrap();
+    let config = sync_test_config();
+    let handler = s3handler::blocking2::Handler::from(&config);
+    sync_operation!(handler, "/tmp/blocking2-test", new_sync_object("blocking2"));
 }
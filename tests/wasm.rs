@@ -0,0 +1,53 @@
+//! The signing on wasm32
+//!
+//! Run in node with wasm-pack
+//! ```bash
+//! wasm-pack test --node -- --no-default-features --features tokio-async --test wasm
+//! ```
+#![cfg(target_arch = "wasm32")]
+
+use chrono::{DateTime, Utc};
+use s3handler::utils::sign::{amz_date, hash_payload, v4_sign, Scope, V4Request};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn test_v4_sign_get_object_example() {
+    // https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+    let time = DateTime::parse_from_rfc3339("2013-05-24T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let payload_hash = hash_payload(b"");
+    let date = amz_date(&time);
+    let signature = v4_sign(
+        &V4Request {
+            method: "GET",
+            uri: "/test.txt",
+            query_strings: &[],
+            headers: &[
+                ("host", "examplebucket.s3.amazonaws.com"),
+                ("range", "bytes=0-9"),
+                ("x-amz-content-sha256", &payload_hash),
+                ("x-amz-date", &date),
+            ],
+            payload_hash: &payload_hash,
+        },
+        "AWS4-HMAC-SHA256",
+        "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+        &time,
+        &Scope::s3("us-east-1"),
+    );
+    assert_eq!(
+        signature.signed_headers,
+        "host;range;x-amz-content-sha256;x-amz-date"
+    );
+    assert_eq!(
+        signature.signature,
+        "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+    );
+}
+
+#[wasm_bindgen_test]
+fn test_now_from_js_date() {
+    // The clock of std panics on wasm32, chrono takes the time from `js_sys::Date`
+    assert_eq!(amz_date(&Utc::now()).len(), "20130524T000000Z".len());
+}